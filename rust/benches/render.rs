@@ -0,0 +1,59 @@
+//! Rendering benchmark. Run with:
+//!
+//!     cargo bench --bench render
+
+use std::time::Instant;
+use ultrafxr::error::ErrorHandler;
+use ultrafxr::render::{compile_program, render_graph, RenderParams};
+use ultrafxr::sourcepos::Span;
+
+struct Errors;
+
+impl ErrorHandler for Errors {
+    fn handle(&mut self, _pos: Span, message: &str) {
+        panic!("{}", message);
+    }
+}
+
+const PATCHES: &[(&str, &str)] = &[
+    ("sine", "(sine (oscillator (note 0)))"),
+    (
+        "filtered_saw",
+        "(lowPass2 (sawtooth (oscillator (note 0))) (frequency (envelope (set 0.5))) 2.0)",
+    ),
+    (
+        "constant_inputs",
+        "(mix 0.5 (lowPass2 (noise) 1kHz 0.7) \
+              0.5 (highPass 30Hz (lowPass4 (* 0.5 (noise)) 2kHz 0.7)) \
+              0.5 (* 0.25 (sine (oscillator 220Hz))))",
+    ),
+    (
+        "dense",
+        "(mix 0.5 (saturate (* 4 (sawtooth (oscillator (note 0))))) \
+              0.5 (highPass 30Hz (lowPass4 (noise) (frequency (envelope (set 0.25))) 0.7)) \
+              0.25 (crush (square (oscillator (note 7))) 6 8kHz))",
+    ),
+];
+
+/// Length of audio to render for each patch, in samples.
+const LENGTH: usize = 48000 * 10;
+
+fn main() {
+    let params = RenderParams {
+        max_length: LENGTH,
+        ..Default::default()
+    };
+    for &(name, source) in PATCHES.iter() {
+        let (graph, root) = compile_program(&mut Errors, source).unwrap();
+        let start = Instant::now();
+        let samples = render_graph(&graph, root, &params).unwrap();
+        let elapsed = start.elapsed().as_secs_f64();
+        let audio = samples.len() as f64 / params.parameters.sample_rate;
+        println!(
+            "{:<16} {:8.2} ns/sample {:8.1}x realtime",
+            name,
+            elapsed * 1e9 / samples.len() as f64,
+            audio / elapsed
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Benchmark for looking up columns in a very long line. Run with:
+//!
+//!     cargo bench --bench sourcetext
+
+use std::time::Instant;
+use ultrafxr::sourcetext::{SourceText, TextPos};
+
+/// Length of the line, in bytes.
+const LENGTH: usize = 4 << 20;
+
+/// Number of lookups to perform.
+const LOOKUPS: u32 = 100000;
+
+fn main() {
+    let pattern = "(sine (oscillator (note 0))) \u{e9}\u{2014} ".as_bytes();
+    let input: Vec<u8> = pattern.iter().cycle().take(LENGTH).cloned().collect();
+    let text = SourceText::new("<bench>", &input).unwrap();
+    let start = Instant::now();
+    let mut total = 0u64;
+    for n in 0..LOOKUPS {
+        let byte = n.wrapping_mul(2654435761) % LENGTH as u32;
+        total += text.column(TextPos { line: 0, byte }) as u64;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{:<16} {:8.2} us/lookup (checksum {})",
+        "column",
+        elapsed * 1e6 / LOOKUPS as f64,
+        total
+    );
+}
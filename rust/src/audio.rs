@@ -0,0 +1,511 @@
+use crate::render::{BlockRenderer, RenderParams};
+use crate::signal::graph::{Graph, SignalRef};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, BuildStreamError, Device, FromSample, Sample, SampleFormat, SampleRate,
+    SizedSample, Stream, StreamConfig, StreamError, SupportedBufferSize,
+};
+use std::convert::TryFrom;
+use std::error;
+use std::io::{Error as IOError, ErrorKind, Result as IOResult};
+use std::mem;
+use std::sync::mpsc::{
+    sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Renders a synthesizer program for playback, optionally looping it.
+pub struct Player<'a> {
+    graph: &'a Graph,
+    root: SignalRef,
+    params: &'a RenderParams,
+    renderer: BlockRenderer,
+    do_loop: bool,
+}
+
+impl<'a> Player<'a> {
+    /// Create a new player for the given graph, which plays the chords in
+    /// the parameters one after another.
+    pub fn new(
+        graph: &'a Graph,
+        root: SignalRef,
+        params: &'a RenderParams,
+        do_loop: bool,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Player {
+            graph,
+            root,
+            params,
+            renderer: BlockRenderer::new(graph, root, params)?,
+            do_loop,
+        })
+    }
+
+    /// Render the next buffer of audio. Returns None when playback is done,
+    /// which only happens if the player is not looping.
+    pub fn next(&mut self) -> Result<Option<&[f32]>, Box<dyn error::Error>> {
+        if self.renderer.is_done() {
+            if !self.do_loop {
+                return Ok(None);
+            }
+            self.renderer = BlockRenderer::new(self.graph, self.root, self.params)?;
+        }
+        Ok(self.renderer.next_block())
+    }
+}
+
+/// A stream which sends audio to an output device.
+pub trait Output {
+    /// Write samples to the device, blocking until they are accepted.
+    fn write(&mut self, data: &[f32]) -> IOResult<()>;
+
+    /// Finish playback, blocking until all samples have played.
+    fn finish(self: Box<Self>) -> IOResult<()>;
+}
+
+/// Number of blocks which can be queued for the output device before writes
+/// block.
+const QUEUE_LENGTH: usize = 4;
+
+/// Time to wait after the last samples are sent to the device, so they can
+/// finish playing before the stream is closed.
+const DRAIN_TIME: Duration = Duration::from_millis(200);
+
+/// How often to check for stream errors while waiting for the device.
+const POLL_TIME: Duration = Duration::from_millis(10);
+
+/// Time to wait for the device beyond the length of the queued audio, before
+/// deciding that it has stopped.
+const STALL_TIME: Duration = Duration::from_secs(2);
+
+/// Feeds queued blocks of samples to the output device's callback.
+struct Feeder {
+    blocks: Receiver<Vec<f32>>,
+    // Played blocks are sent back to the writer, so buffers are never freed
+    // by the callback.
+    free: SyncSender<Vec<f32>>,
+    block: Vec<f32>,
+    pos: usize,
+    // Signaled once all blocks have been played and the queue is closed.
+    done: Option<SyncSender<()>>,
+}
+
+impl Feeder {
+    /// Fill a buffer from the device with interleaved frames, copying each
+    /// sample to every channel. If no samples are queued, the buffer is
+    /// filled with silence.
+    fn fill<T: Sample + FromSample<f32>>(&mut self, data: &mut [T], channels: usize) {
+        for frame in data.chunks_mut(channels) {
+            if self.pos == self.block.len() {
+                match self.blocks.try_recv() {
+                    Ok(block) => {
+                        let played = mem::replace(&mut self.block, block);
+                        let _ = self.free.try_send(played);
+                        self.pos = 0;
+                    }
+                    Err(e) => {
+                        if e == TryRecvError::Disconnected {
+                            if let Some(done) = self.done.take() {
+                                let _ = done.try_send(());
+                            }
+                        }
+                        frame.fill(T::EQUILIBRIUM);
+                        continue;
+                    }
+                }
+                if self.block.is_empty() {
+                    frame.fill(T::EQUILIBRIUM);
+                    continue;
+                }
+            }
+            frame.fill(T::from_sample(self.block[self.pos]));
+            self.pos += 1;
+        }
+    }
+}
+
+/// Wait until a condition is true. Fails with the first error reported by the
+/// stream, or if the condition is still false after the timeout.
+fn wait(
+    errors: &Receiver<StreamError>,
+    timeout: Duration,
+    mut ready: impl FnMut() -> bool,
+) -> IOResult<()> {
+    let start = Instant::now();
+    while !ready() {
+        match errors.recv_timeout(POLL_TIME) {
+            Ok(e) => return Err(IOError::other(e)),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(IOError::other("audio stream stopped"))
+            }
+        }
+        if start.elapsed() > timeout {
+            return Err(IOError::new(
+                ErrorKind::TimedOut,
+                "audio device stopped requesting samples",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Audio output which streams samples to a device.
+struct StreamOutput {
+    blocks: SyncSender<Vec<f32>>,
+    free: Receiver<Vec<f32>>,
+    done: Receiver<()>,
+    errors: Receiver<StreamError>,
+    // Time to wait for the device before deciding it has stopped.
+    timeout: Duration,
+    stream: Stream,
+}
+
+impl Output for StreamOutput {
+    fn write(&mut self, data: &[f32]) -> IOResult<()> {
+        let mut block = self.free.try_recv().unwrap_or_default();
+        block.clear();
+        block.extend_from_slice(data);
+        let mut block = Some(block);
+        let mut stopped = false;
+        wait(&self.errors, self.timeout, || {
+            match self.blocks.try_send(block.take().unwrap()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(b)) => {
+                    block = Some(b);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    stopped = true;
+                    true
+                }
+            }
+        })?;
+        if stopped {
+            return Err(IOError::other("audio stream stopped"));
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> IOResult<()> {
+        let StreamOutput {
+            blocks,
+            free,
+            done,
+            errors,
+            timeout,
+            stream,
+        } = *self;
+        drop(blocks);
+        wait(&errors, timeout, || {
+            !matches!(done.try_recv(), Err(TryRecvError::Empty))
+        })?;
+        thread::sleep(DRAIN_TIME);
+        let result = match errors.try_recv() {
+            Ok(e) => Err(IOError::other(e)),
+            Err(_) => Ok(()),
+        };
+        // Close the stream while the free list is open, so the callback never
+        // frees a buffer.
+        drop(stream);
+        drop(free);
+        result
+    }
+}
+
+/// Create an output stream on a device with the given sample type.
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &Device,
+    config: &StreamConfig,
+    mut feeder: Feeder,
+    errors: SyncSender<StreamError>,
+) -> Result<Stream, BuildStreamError> {
+    let channels = config.channels as usize;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| feeder.fill(data, channels),
+        move |e| {
+            let _ = errors.try_send(e);
+        },
+        None,
+    )
+}
+
+/// Rank sample formats by preference, or return None for unsupported formats.
+fn format_rank(format: SampleFormat) -> Option<u8> {
+    match format {
+        SampleFormat::F32 => Some(0),
+        SampleFormat::I16 => Some(1),
+        SampleFormat::U16 => Some(2),
+        _ => None,
+    }
+}
+
+/// Get the device buffer size to use for the requested size. The requested
+/// size is clamped to the range the device supports. If the range is unknown,
+/// the device's default is used.
+fn device_buffer_size(requested: usize, supported: &SupportedBufferSize) -> BufferSize {
+    match *supported {
+        SupportedBufferSize::Range { min, max } => {
+            let size = u32::try_from(requested).unwrap_or(u32::MAX);
+            BufferSize::Fixed(size.min(max).max(min))
+        }
+        SupportedBufferSize::Unknown => BufferSize::Default,
+    }
+}
+
+/// Open the default audio output device. Mono audio is played on every
+/// channel of the device. The buffer size is the number of frames the device
+/// requests at a time, if the device supports it.
+pub fn open_default(sample_rate: u32, buffer_size: usize) -> IOResult<Box<dyn Output>> {
+    let device = match cpal::default_host().default_output_device() {
+        Some(device) => device,
+        None => return Err(IOError::other("no audio output device found")),
+    };
+    let rate = SampleRate(sample_rate);
+    let config = device
+        .supported_output_configs()
+        .map_err(IOError::other)?
+        .filter(|c| format_rank(c.sample_format()).is_some())
+        .filter_map(|c| c.try_with_sample_rate(rate))
+        .min_by_key(|c| (format_rank(c.sample_format()), c.channels()));
+    let config = match config {
+        Some(config) => config,
+        None => {
+            return Err(IOError::other(format!(
+                "the audio output device does not support a sample rate of {} Hz",
+                sample_rate
+            )))
+        }
+    };
+    let (blocks, receiver) = sync_channel(QUEUE_LENGTH);
+    // Every buffer is either queued, being played, being written, or free, so
+    // the free list never fills up.
+    let (free_sender, free) = sync_channel(QUEUE_LENGTH + 2);
+    let (done_sender, done) = sync_channel(1);
+    let (error_sender, errors) = sync_channel(1);
+    let feeder = Feeder {
+        blocks: receiver,
+        free: free_sender,
+        block: Vec::new(),
+        pos: 0,
+        done: Some(done_sender),
+    };
+    let format = config.sample_format();
+    let mut stream_config = config.config();
+    stream_config.buffer_size = device_buffer_size(buffer_size, config.buffer_size());
+    let device_frames = match stream_config.buffer_size {
+        BufferSize::Fixed(size) => size as usize,
+        BufferSize::Default => 0,
+    };
+    // Allow time for the whole queue to play, plus one device buffer.
+    let queued = (QUEUE_LENGTH + 1) * buffer_size + device_frames;
+    let timeout = STALL_TIME + Duration::from_secs_f64(queued as f64 / sample_rate as f64);
+    let stream = match format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, feeder, error_sender),
+        SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, feeder, error_sender),
+        _ => build_stream::<u16>(&device, &stream_config, feeder, error_sender),
+    }
+    .map_err(IOError::other)?;
+    stream.play().map_err(IOError::other)?;
+    Ok(Box::new(StreamOutput {
+        blocks,
+        free,
+        done,
+        errors,
+        timeout,
+        stream,
+    }))
+}
+
+/// Play audio until the player is done.
+pub fn play(player: &mut Player, output: &mut dyn Output) -> Result<(), Box<dyn error::Error>> {
+    while let Some(data) = player.next()? {
+        output.write(data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::NoteEvent;
+    use crate::signal::envelope::{Envelope, Segment};
+    use crate::signal::ops;
+
+    /// Output which records the samples written to it.
+    struct Recorder(Vec<f32>);
+
+    impl Output for Recorder {
+        fn write(&mut self, data: &[f32]) -> IOResult<()> {
+            self.0.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn finish(self: Box<Self>) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    fn test_graph() -> (Graph, SignalRef) {
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(ops::Note { offset: 0 }));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let wave = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sine,
+        }));
+        let env = graph.add(Box::new(Envelope {
+            segments: Box::from(&[Segment::Set { value: 1.0 }, Segment::Gate, Segment::Stop][..]),
+        }));
+        let out = graph.add(Box::new(ops::Multiply {
+            inputs: [wave, env],
+        }));
+        (graph, out)
+    }
+
+    /// Parameters for playing the given chords, with a short gate.
+    fn params(notes: &[&[f32]]) -> RenderParams {
+        let mut params = RenderParams {
+            notes: notes
+                .iter()
+                .map(|chord| chord.iter().map(|&note| NoteEvent::new(note)).collect())
+                .collect(),
+            gate: 1000,
+            max_length: usize::MAX,
+            ..Default::default()
+        };
+        params.parameters.buffer_size = 256;
+        params
+    }
+
+    #[test]
+    fn play_samples() {
+        let (graph, root) = test_graph();
+        let params = params(&[&[69.0]]);
+        let mut player = Player::new(&graph, root, &params, false).unwrap();
+        let mut output = Recorder(Vec::new());
+        play(&mut player, &mut output).unwrap();
+        assert_eq!(output.0.len(), 1000);
+        assert!(output.0.iter().any(|&x| x != 0.0));
+    }
+
+    #[test]
+    fn play_loop() {
+        let (graph, root) = test_graph();
+        let params = params(&[&[69.0]]);
+        let mut player = Player::new(&graph, root, &params, true).unwrap();
+        let mut output = Recorder(Vec::new());
+        while output.0.len() < 3000 {
+            let data = player.next().unwrap().expect("looping player stopped");
+            output.write(data).unwrap();
+        }
+        assert_eq!(&output.0[..1000], &output.0[1000..2000]);
+    }
+
+    #[test]
+    fn stream_callback() {
+        let (graph, root) = test_graph();
+        let params = params(&[&[69.0]]);
+        let mut player = Player::new(&graph, root, &params, false).unwrap();
+        let (blocks, receiver) = sync_channel(QUEUE_LENGTH);
+        let (free_sender, free) = sync_channel(QUEUE_LENGTH + 2);
+        let (done_sender, done) = sync_channel(1);
+        let mut feeder = Feeder {
+            blocks: receiver,
+            free: free_sender,
+            block: Vec::new(),
+            pos: 0,
+            done: Some(done_sender),
+        };
+        // Nothing is queued yet, so the device gets silence.
+        let mut data = vec![1.0f32; 64];
+        feeder.fill(&mut data, 2);
+        assert!(data.iter().all(|&x| x == 0.0));
+        // The player's output is copied to both channels.
+        let expect = player.next().unwrap().unwrap().to_vec();
+        blocks.send(expect.clone()).unwrap();
+        drop(blocks);
+        let mut data = vec![0.0f32; expect.len() * 2 + 64];
+        feeder.fill(&mut data, 2);
+        assert!(data.iter().any(|&x| x != 0.0));
+        for (n, (frame, &x)) in data.chunks(2).zip(expect.iter()).enumerate() {
+            assert_eq!(frame, &[x, x][..], "frame {}", n);
+        }
+        assert!(data[expect.len() * 2..].iter().all(|&x| x == 0.0));
+        // The output is finished once the queue is closed and played.
+        assert_eq!(done.try_recv(), Ok(()));
+        // Played buffers are returned to the writer instead of being freed.
+        // The first one is the empty buffer the feeder started with.
+        assert_eq!(free.try_recv(), Ok(Vec::new()));
+        assert!(free.try_recv().is_err());
+        // Integer formats are converted.
+        let mut feeder = Feeder {
+            blocks: {
+                let (blocks, receiver) = sync_channel(1);
+                blocks.send(vec![0.5, -0.5]).unwrap();
+                receiver
+            },
+            free: sync_channel(1).0,
+            block: Vec::new(),
+            pos: 0,
+            done: None,
+        };
+        let mut data = [0i16; 3];
+        feeder.fill(&mut data, 1);
+        assert_eq!(data, [16384, -16384, 0]);
+    }
+
+    #[test]
+    fn stream_wait() {
+        let (error_sender, errors) = sync_channel(1);
+        let timeout = Duration::from_millis(50);
+        assert!(wait(&errors, timeout, || true).is_ok());
+        let mut count = 0;
+        let ready = || {
+            count += 1;
+            count > 2
+        };
+        assert!(wait(&errors, timeout, ready).is_ok());
+        // A device which stops calling back does not block forever.
+        match wait(&errors, timeout, || false) {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+            r => panic!("expected timeout, got {:?}", r),
+        }
+        // Errors from the stream are reported.
+        error_sender.send(StreamError::DeviceNotAvailable).unwrap();
+        match wait(&errors, timeout, || false) {
+            Err(e) => assert_ne!(e.kind(), ErrorKind::TimedOut),
+            r => panic!("expected stream error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn buffer_size() {
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        for &(requested, expect) in [(256, 256), (16, 64), (8192, 4096)].iter() {
+            assert_eq!(
+                device_buffer_size(requested, &range),
+                BufferSize::Fixed(expect),
+                "{}",
+                requested
+            );
+        }
+        assert_eq!(
+            device_buffer_size(256, &SupportedBufferSize::Unknown),
+            BufferSize::Default
+        );
+    }
+
+    #[test]
+    fn play_notes() {
+        let (graph, root) = test_graph();
+        let params = params(&[&[57.0], &[69.0]]);
+        let mut player = Player::new(&graph, root, &params, false).unwrap();
+        let mut output = Recorder(Vec::new());
+        play(&mut player, &mut output).unwrap();
+        assert_eq!(output.0.len(), 2000);
+        assert_ne!(&output.0[..1000], &output.0[1000..]);
+    }
+}
@@ -1,31 +1,200 @@
-use crate::consolelogger::ConsoleLogger;
-use crate::error::Failed;
-use crate::evaluate::evaluate_program;
-use crate::note::Note;
-use crate::parseargs::{Arg, Args, UsageError};
-use crate::parser::{ParseResult, Parser};
+#[cfg(feature = "audio")]
+use crate::audio;
+use crate::consolelogger::{self, ConsoleLogger};
+use crate::error::{ErrorHandler, Failed, Severity};
+use crate::evaluate::{evaluate_program, evaluate_statements_only};
+#[cfg(feature = "flacenc")]
+use crate::flac;
+use crate::format::{self, format_source};
+use crate::include::expand_includes;
+use crate::note::{Note, Temperament, Tuning, DEFAULT_TUNING};
+use crate::parseargs::{Arg, Args, ShortOptions, UsageError};
+use crate::parser::{ParseResult, Parser, ParserConfig};
+use crate::raw::{self, Endian};
+use crate::render::{normalize, BlockRenderer, Fade, Limiter, Normalize};
+use crate::render::{NoteEvent, RenderParams, DEFAULT_MAX_VOICES, DEFAULT_VELOCITY};
+use crate::sexpr::{Content, SExpr};
 use crate::shell::quote_os;
 use crate::signal::graph::{Graph, SignalRef};
-use crate::signal::program::{Input as PInput, Parameters, Program};
+use crate::signal::program::{Parameters, Program};
+use crate::sourcepos::Span;
 use crate::token::Tokenizer;
 use crate::wave;
+use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::{stdout, Error as IOError, Read, Write};
-use std::path::PathBuf;
+use std::io::{
+    stderr, stdin, stdout, BufRead, Error as IOError, ErrorKind, IsTerminal, Read, Write,
+};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 const DEFAULT_SAMPLE_RATE: u32 = 48000;
 const MIN_SAMPLE_RATE: u32 = 8000;
 const MAX_SAMPLE_RATE: u32 = 192000;
+
+/// Environment variable which sets the sample rate, if -sample-rate is not
+/// given.
+const SAMPLE_RATE_VAR: &str = "ULTRAFXR_SAMPLE_RATE";
 const DEFAULT_BUFFER_SIZE: usize = 1024;
 const MIN_BUFFER_SIZE: usize = 32;
 const MAX_BUFFER_SIZE: usize = 8192;
+const MAX_CONTROL_RATE: usize = 256;
+const MAX_VOICES: usize = 64;
+const MIN_TUNING: f64 = 400.0;
+const MAX_TUNING: f64 = 480.0;
+
+/// Usage summary printed by -help.
+pub const HELP: &str = "\
+Usage: ultrafxr [<option>...] <file>...
+       ultrafxr [<option>...] -script <script>
+
+Renders sound effects from synthesizer scripts. Use \"-\" as the file to read
+the script from standard input.
+
+Options:
+  -help, -h           Show this help and exit.
+  -version, -V        Show the version and exit.
+  -script <script>    Evaluate the script given on the command line.
+  -write-wav          Write the output to a WAVE file, named after the input.
+  -wav-out <file>, -o <file>
+                      Path of the WAVE file to write, used with -write-wav.
+  -format <format>    Output file format: wav, flac, or raw for headerless
+                      16-bit PCM (default wav, or chosen by the extension of
+                      .flac, .raw, and .pcm files).
+  -endian <order>     Byte order of raw output: little or big (default
+                      little).
+  -force, -f          Overwrite existing output files.
+  -out-dir <dir>      Write a WAVE file for each input to this directory.
+  -play, -p           Play the output on the default audio device.
+  -loop               Play the output repeatedly, used with -play.
+  -notes <notes>      Comma-separated notes to play in sequence, like
+                      C4,A#3,m60 (default A4). A note may be followed by a
+                      velocity from 0 to 127, like C4@100 (default 64).
+                      Notes joined with + play together as a chord, like
+                      C4+E4+G4.
+  -voices <n>         Maximum number of notes in a chord, up to 64 (default
+                      8). Extra notes at the end of a chord are dropped.
+  -tempo <bpm>        Tempo, in beats per minute.
+  -gate <time>        Gate length.
+  -tuning <hz>        Frequency of A4 (default 440).
+  -temperament <name> Tuning system: equal, just, or pythagorean.
+  -tonic <note>       Tonic for just and Pythagorean temperaments.
+  -param <name>=<value>
+                      Set the value of a control read by (param <name>). May
+                      be given more than once. Unset controls are zero.
+  -antialias <on|off> Band-limit sawtooth and pulse waves (default on).
+  -oversample <n>     Oversample nonlinear operations by 2, 4, or 8.
+  -control-rate <n>   Compute envelopes and the arithmetic on them once every
+                      n samples, a power of two up to 256, and interpolate
+                      (default 1, every sample).
+  -normalize <mode>   Scale the WAVE output so its peak or rms level is the
+                      target level.
+  -target-db <db>     Target level for -normalize, in dBFS (default 0).
+  -limit              Apply a peak limiter to the WAVE output so it does not
+                      clip.
+  -fade-in <ms>       Fade in the start of the WAVE output.
+  -fade-out <ms>      Fade out the end of the WAVE output.
+  -embed-source       Store the script and render settings in the WAVE file's
+                      metadata.
+  -sample-rate <hz>   Sample rate of the output (default 48000, or the
+                      ULTRAFXR_SAMPLE_RATE environment variable). May also be
+                      a preset: cd (44100), dvd or pro (48000), hires (96000).
+  -buffer-size <n>    Size of the buffers used when rendering.
+  -repl               Read and evaluate expressions interactively.
+  -check              Check that the inputs compile, without writing or playing
+                      audio.
+  -disassemble        Print the compiled program.
+  -dump-syntax        Print the parsed syntax tree.
+  -format-source      Rewrite each input file in a standard layout, keeping
+                      comments. Scripts from standard input or -script are
+                      written to standard output.
+  -dump-graph         Print the signal graph.
+  -dump-dot           Print the signal graph in Graphviz DOT format.
+  -verbose, -v        Print more information.
+  -quiet, -q          Do not print warnings or progress. Errors are still
+                      printed.
+  -werror             Treat warnings as errors.
+  -warn-inexact       Warn about numbers which cannot be stored exactly, like
+                      0.1.
+
+Options may also be written with two dashes and with \"=\", like
+--sample-rate=44100. Short options may be combined, like -vp.
+";
+
+/// Short aliases for long options.
+const SHORT_OPTIONS: ShortOptions = &[
+    ('h', "help"),
+    ('V', "version"),
+    ('v', "verbose"),
+    ('q', "quiet"),
+    ('p', "play"),
+    ('o', "wav-out"),
+    ('f', "force"),
+];
+
+/// Get the version string printed by -version.
+pub fn version() -> String {
+    format!("ultrafxr {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Action requested by the command-line arguments.
+#[derive(Debug)]
+pub enum Action {
+    Run(Box<Command>),
+    Help,
+    Version,
+}
 
 #[derive(Debug, Clone)]
 pub enum Input {
     File(OsString),
     String(String),
+    Stdin,
+}
+
+/// Format of an output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Wave,
+    /// FLAC, which requires the flacenc feature.
+    Flac,
+    /// Headerless samples.
+    Raw,
+}
+
+impl OutputFormat {
+    /// Get the format for an output path from its extension.
+    fn from_path(path: &OsStr) -> Self {
+        match Path::new(path).extension().and_then(OsStr::to_str) {
+            Some("flac") => OutputFormat::Flac,
+            Some("raw") | Some("pcm") => OutputFormat::Raw,
+            _ => OutputFormat::Wave,
+        }
+    }
+
+    /// Get the extension used to name output files.
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wave => "wav",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Raw => "raw",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "wav" | "wave" => OutputFormat::Wave,
+            "flac" => OutputFormat::Flac,
+            "raw" => OutputFormat::Raw,
+            _ => return Err(()),
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,24 +207,296 @@ pub struct File {
 pub struct Command {
     pub files: Vec<File>,
     pub play: bool,
-    pub notes: Option<Vec<Note>>,
+    pub force: bool,
+    /// Chords to play, with the MIDI velocity of each note.
+    pub notes: Option<Vec<Vec<(Note, u8)>>>,
+    pub max_voices: usize,
     pub tempo: Option<f32>,
     pub gate: Option<f32>,
     pub disassemble: bool,
     pub do_loop: bool,
     pub verbose: bool,
+    pub quiet: bool,
+    pub werror: bool,
+    pub warn_inexact: bool,
     pub dump_syntax: bool,
     pub dump_graph: bool,
+    pub dump_dot: bool,
+    pub check: bool,
+    pub format_source: bool,
+    pub repl: bool,
     pub sample_rate: Option<u32>,
     pub buffer_size: Option<usize>,
+    pub tuning: Option<f64>,
+    pub temperament: Option<Temperament>,
+    pub tonic: Option<Note>,
+    pub controls: HashMap<String, f64>,
+    pub antialias: bool,
+    pub oversample: usize,
+    pub control_rate: usize,
+    pub normalize: Option<Normalize>,
+    pub target_db: f64,
+    pub limit: bool,
+    pub fade_in: f64,
+    pub fade_out: f64,
+    pub embed_source: bool,
+    pub format: Option<OutputFormat>,
+    pub endian: Endian,
 }
 
-fn parse_notes(arg: &str) -> Option<Vec<Note>> {
+/// Parse a list of chords, like "C4,E4@100+G4". Each note may have a MIDI
+/// velocity after "@".
+fn parse_notes(arg: &str) -> Result<Vec<Vec<(Note, u8)>>, String> {
     let mut result = Vec::new();
-    for s in arg.split(',') {
-        result.push(s.parse::<Note>().ok()?);
+    for chord in arg.split(',') {
+        let mut notes = Vec::new();
+        for s in chord.split('+') {
+            let (name, velocity) = match s.find('@') {
+                Some(i) => (&s[..i], Some(&s[i + 1..])),
+                None => (s, None),
+            };
+            let note = match name.parse::<Note>() {
+                Ok(note) => note,
+                Err(e) => return Err(format!("{:?}: {}", s, e)),
+            };
+            let velocity = match velocity.map(|v| v.parse::<u8>()) {
+                None => DEFAULT_VELOCITY,
+                Some(Ok(v)) if v <= 127 => v,
+                Some(_) => return Err(format!("{:?}: velocity must be from 0 to 127", s)),
+            };
+            notes.push((note, velocity));
+        }
+        result.push(notes);
+    }
+    Ok(result)
+}
+
+/// Parse a control assignment, like "velocity=0.5".
+fn parse_control(arg: &str) -> Result<(String, f64), String> {
+    let (name, value) = match arg.find('=') {
+        Some(idx) => (&arg[..idx], &arg[idx + 1..]),
+        None => return Err(format!("{:?}: expected <name>=<value>", arg)),
+    };
+    if name.is_empty() {
+        return Err(format!("{:?}: empty name", arg));
+    }
+    match value.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok((name.to_string(), value)),
+        _ => Err(format!("{:?}: invalid value {:?}", arg, value)),
+    }
+}
+
+/// Named sample rates, accepted by -sample-rate.
+const SAMPLE_RATE_PRESETS: &[(&str, u32)] = &[
+    ("cd", 44100),
+    ("dvd", 48000),
+    ("pro", 48000),
+    ("hires", 96000),
+];
+
+/// Parse a sample rate, in Hz or as the name of a preset.
+fn parse_sample_rate(arg: &str) -> Result<u32, String> {
+    if let Ok(rate) = arg.parse::<u32>() {
+        return Ok(rate);
+    }
+    match SAMPLE_RATE_PRESETS.iter().find(|&&(name, _)| name == arg) {
+        Some(&(_, rate)) => Ok(rate),
+        None => {
+            let names: Vec<&str> = SAMPLE_RATE_PRESETS.iter().map(|&(name, _)| name).collect();
+            Err(format!(
+                "{:?}: expected a rate in Hz or one of: {}",
+                arg,
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+/// Check that a requested sample rate is supported, or get the default rate
+/// if none was requested.
+pub fn normalize_sample_rate(
+    requested: Option<u32>,
+    handler: &mut dyn ErrorHandler,
+) -> Result<u32, Failed> {
+    match requested {
+        Some(rate) => {
+            if rate < MIN_SAMPLE_RATE {
+                handler.handle(
+                    Span::none(),
+                    &format!(
+                        "sample rate {} is too low, acceptable rates are {}-{}",
+                        rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+                    ),
+                );
+                Err(Failed)
+            } else if rate > MAX_SAMPLE_RATE {
+                handler.handle(
+                    Span::none(),
+                    &format!(
+                        "sample rate {} is too high, acceptable rates are {}-{}",
+                        rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+                    ),
+                );
+                Err(Failed)
+            } else {
+                Ok(rate)
+            }
+        }
+        None => Ok(DEFAULT_SAMPLE_RATE),
+    }
+}
+
+/// Get the buffer size to use for a requested size, or the default size if
+/// none was requested. Sizes are clamped to the supported range and rounded
+/// up to a power of two, with a warning.
+pub fn normalize_buffer_size(requested: Option<usize>, handler: &mut dyn ErrorHandler) -> usize {
+    let size = match requested {
+        Some(size) => size,
+        None => return DEFAULT_BUFFER_SIZE,
+    };
+    let (nsize, problem) = if size < MIN_BUFFER_SIZE {
+        (MIN_BUFFER_SIZE, "is too low")
+    } else if size > MAX_BUFFER_SIZE {
+        (MAX_BUFFER_SIZE, "is too high")
+    } else {
+        (size.next_power_of_two(), "is not a power of two")
+    };
+    if nsize != size {
+        handler.warning(
+            Span::none(),
+            &format!("buffer size {} {}, using {}", size, problem, nsize),
+        );
+    }
+    nsize
+}
+
+/// Error handler for diagnostics which do not refer to source text, which
+/// prints them to stderr.
+struct Console;
+
+impl ErrorHandler for Console {
+    fn handle(&mut self, _pos: Span, message: &str) {
+        error!("{}", message);
+    }
+
+    fn warning(&mut self, _pos: Span, message: &str) {
+        warning!("{}", message);
+    }
+}
+
+/// Parse a fade length, in milliseconds.
+fn parse_fade(arg: &str) -> Option<f64> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|&x| x.is_finite() && x >= 0.0)
+}
+
+/// Magic bytes at the start of a gzip stream.
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// Read the input file and return its name and its contents. Files and
+/// standard input compressed with gzip are decompressed.
+fn read_input(file: &File, stdin: &mut dyn Read) -> Result<(String, Box<[u8]>), Failed> {
+    match file.input {
+        Input::File(ref path) => {
+            let filename = quote_os(path);
+            let mut text = Vec::new();
+            match fs::File::open(path).and_then(|mut f| f.read_to_end(&mut text)) {
+                Ok(_) => {}
+                Err(e) => {
+                    error!("could not read {}: {}", filename, e);
+                    return Err(Failed);
+                }
+            }
+            let text = decompress(&filename, text)?;
+            Ok((filename, Box::from(text)))
+        }
+        Input::String(ref s) => Ok(("<arg>".to_string(), Box::from(s.as_bytes()))),
+        Input::Stdin => {
+            let filename = "<stdin>".to_string();
+            let mut text = Vec::new();
+            if let Err(e) = stdin.read_to_end(&mut text) {
+                error!("could not read {}: {}", filename, e);
+                return Err(Failed);
+            }
+            let text = decompress(&filename, text)?;
+            Ok((filename, Box::from(text)))
+        }
+    }
+}
+
+/// Write a formatted script back to its input file, or to the output if it
+/// was not read from a file. Files are only written if the formatting changed.
+fn write_formatted(
+    file: &File,
+    filename: &str,
+    text: &[u8],
+    formatted: &[u8],
+    output: &mut dyn Write,
+) -> Result<(), Failed> {
+    match file.input {
+        Input::File(ref path) => {
+            if formatted == text {
+                return Ok(());
+            }
+            if let Err(e) = fs::write(path, formatted) {
+                error!("could not write {}: {}", filename, e);
+                return Err(Failed);
+            }
+        }
+        _ => {
+            if let Err(e) = output.write_all(formatted) {
+                error!("could not write output: {}", e);
+                return Err(Failed);
+            }
+        }
     }
-    Some(result)
+    Ok(())
+}
+
+/// Decompress input if it is compressed with gzip, otherwise return it
+/// unchanged.
+#[cfg(feature = "flate2")]
+fn decompress(filename: &str, data: Vec<u8>) -> Result<Vec<u8>, Failed> {
+    use flate2::read::MultiGzDecoder;
+    if !data.starts_with(GZIP_MAGIC) {
+        return Ok(data);
+    }
+    let mut text = Vec::new();
+    match MultiGzDecoder::new(&data[..]).read_to_end(&mut text) {
+        Ok(_) => Ok(text),
+        Err(e) => {
+            error!("could not decompress {}: {}", filename, e);
+            Err(Failed)
+        }
+    }
+}
+
+/// Decompress input if it is compressed with gzip, otherwise return it
+/// unchanged.
+#[cfg(not(feature = "flate2"))]
+fn decompress(filename: &str, data: Vec<u8>) -> Result<Vec<u8>, Failed> {
+    if !data.starts_with(GZIP_MAGIC) {
+        return Ok(data);
+    }
+    error!(
+        "{} is compressed with gzip, which requires the flate2 feature",
+        filename
+    );
+    Err(Failed)
+}
+
+/// Create an output file. Fails if the file already exists, unless force is
+/// true.
+fn create_output(path: &Path, force: bool) -> Result<fs::File, IOError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    options.open(path)
 }
 
 fn unwrap_write<T>(filename: &str, result: Result<T, IOError>) -> Result<T, Failed> {
@@ -68,24 +509,222 @@ fn unwrap_write<T>(filename: &str, result: Result<T, IOError>) -> Result<T, Fail
     }
 }
 
+/// Shows the progress of a render on standard error, if it is a terminal.
+/// The progress line is cleared when this is dropped.
+struct Progress {
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(renderer: &mut BlockRenderer, filename: &str, show: bool) -> Self {
+        let enabled = show && renderer.length().is_some() && stderr().is_terminal();
+        if enabled {
+            let filename = filename.to_string();
+            let mut last = None;
+            renderer.set_progress(move |pos, length| {
+                let percent = pos as u64 * 100 / length.max(1) as u64;
+                if last != Some(percent) {
+                    last = Some(percent);
+                    eprint!("\rrendering {}: {}%", filename, percent);
+                }
+            });
+        }
+        Progress { enabled }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.enabled {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// Measures how long each stage of processing takes, for -verbose.
+struct Timer {
+    enabled: bool,
+    start: Instant,
+    stages: Vec<(&'static str, Duration)>,
+    /// Length of the rendered audio, in seconds.
+    audio_length: Option<f64>,
+}
+
+impl Timer {
+    fn new(enabled: bool) -> Self {
+        Timer {
+            enabled,
+            start: Instant::now(),
+            stages: Vec::new(),
+            audio_length: None,
+        }
+    }
+
+    /// Start timing the next stage now.
+    fn reset(&mut self) {
+        self.start = Instant::now();
+    }
+
+    /// Record the end of a stage, and start timing the next stage.
+    fn stage(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.stages.push((name, now - self.start));
+        self.start = now;
+    }
+
+    /// Write the times for each stage, if timing is enabled.
+    fn report(&self, out: &mut impl Write) -> Result<(), IOError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        for &(name, time) in self.stages.iter() {
+            writeln!(out, "{}: {:.1} ms", name, ms(time))?;
+        }
+        if let (Some(length), Some(&(_, time))) = (
+            self.audio_length,
+            self.stages.iter().find(|&&(name, _)| name == "render"),
+        ) {
+            writeln!(
+                out,
+                "rendered {:.2} seconds of audio in {:.1} ms ({:.1}x realtime)",
+                length,
+                ms(time),
+                length / time.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writer for any output file format.
+enum SampleWriter<'a> {
+    Wave(wave::Writer<'a>),
+    #[cfg(feature = "flacenc")]
+    Flac(flac::Writer<'a>),
+    Raw(raw::Writer<'a>),
+}
+
+impl<'a> SampleWriter<'a> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), IOError> {
+        match self {
+            SampleWriter::Wave(w) => w.write(samples),
+            #[cfg(feature = "flacenc")]
+            SampleWriter::Flac(w) => w.write(samples),
+            SampleWriter::Raw(w) => w.write(samples),
+        }
+    }
+
+    fn finish(self) -> Result<(), IOError> {
+        match self {
+            SampleWriter::Wave(w) => w.finish(),
+            #[cfg(feature = "flacenc")]
+            SampleWriter::Flac(w) => w.finish(),
+            SampleWriter::Raw(w) => w.finish(),
+        }
+    }
+}
+
+/// Final processing of output samples before they are written to a file.
+struct OutputStage<'a> {
+    writer: SampleWriter<'a>,
+    limiter: Option<Limiter>,
+    fade: Option<Fade>,
+    limited: Vec<f32>,
+    faded: Vec<f32>,
+}
+
+impl<'a> OutputStage<'a> {
+    /// Process and write samples.
+    fn write(&mut self, samples: &[f32]) -> Result<(), IOError> {
+        let mut samples = samples;
+        if let Some(ref mut limiter) = self.limiter {
+            self.limited.clear();
+            limiter.process(samples, &mut self.limited);
+            samples = &self.limited;
+        }
+        if let Some(ref mut fade) = self.fade {
+            self.faded.clear();
+            fade.process(samples, &mut self.faded);
+            samples = &self.faded;
+        }
+        self.writer.write(samples)
+    }
+
+    /// Write any samples which are still being processed, and return the
+    /// writer.
+    fn finish(mut self) -> Result<SampleWriter<'a>, IOError> {
+        self.limited.clear();
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.finish(&mut self.limited);
+        }
+        self.faded.clear();
+        match self.fade {
+            None => self.writer.write(&self.limited)?,
+            Some(ref mut fade) => {
+                fade.process(&self.limited, &mut self.faded);
+                if !fade.finish(&mut self.faded) {
+                    warning!("fade is longer than the output, shortening it");
+                }
+                self.writer.write(&self.faded)?;
+            }
+        }
+        Ok(self.writer)
+    }
+}
+
 impl Command {
-    pub fn from_args(args: env::ArgsOs) -> Result<Command, UsageError> {
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> Result<Action, UsageError> {
+        Command::from_args_env(args, |name| env::var_os(name))
+    }
+
+    /// Parse command-line arguments, using a function to get environment
+    /// variables.
+    fn from_args_env(
+        args: impl IntoIterator<Item = OsString>,
+        getenv: impl Fn(&str) -> Option<OsString>,
+    ) -> Result<Action, UsageError> {
         let mut inputs = Vec::new();
         let mut script = None;
         let mut do_write_wave = false;
         let mut wave_file = None;
+        let mut out_dir = None;
         let mut play = false;
+        let mut force = false;
         let mut notes = None;
+        let mut max_voices = DEFAULT_MAX_VOICES;
         let mut tempo = None;
         let mut gate = None;
         let mut disassemble = false;
         let mut do_loop = false;
         let mut verbose = false;
+        let mut quiet = false;
+        let mut werror = false;
+        let mut warn_inexact = false;
         let mut dump_syntax = false;
         let mut dump_graph = false;
+        let mut dump_dot = false;
+        let mut check = false;
+        let mut format_source = false;
+        let mut repl = false;
         let mut sample_rate = None;
         let mut buffer_size = None;
-        let mut args = Args::from_args(args);
+        let mut tuning = None;
+        let mut temperament = None;
+        let mut tonic = None;
+        let mut controls = HashMap::new();
+        let mut antialias = true;
+        let mut oversample = 1;
+        let mut control_rate = 1;
+        let mut normalize = None;
+        let mut target_db = 0.0;
+        let mut limit = false;
+        let mut embed_source = false;
+        let mut format = None;
+        let mut endian = Endian::Little;
+        let mut fade_in = 0.0;
+        let mut fade_out = 0.0;
+        let mut args = Args::from_args(args).with_shorts(SHORT_OPTIONS);
         loop {
             args = match args.next()? {
                 Arg::End => break,
@@ -94,24 +733,62 @@ impl Command {
                     rest
                 }
                 Arg::Named(option) => match option.name() {
+                    "help" => {
+                        option.no_value()?;
+                        return Ok(Action::Help);
+                    }
+                    "version" => {
+                        option.no_value()?;
+                        return Ok(Action::Version);
+                    }
                     "write-wav" => {
                         do_write_wave = true;
                         option.no_value()?.1
                     }
                     "wav-out" => {
-                        let (_, value, rest) = option.value_osstr()?;
+                        let (_, value, rest) =
+                            option.parse_osstr(|s| Some(s.to_owned()).filter(|s| !s.is_empty()))?;
                         wave_file = Some(value);
                         rest
                     }
+                    "format" => {
+                        let (_, value, rest) = option.parse_str(|s| s.parse().ok())?;
+                        format = Some(value);
+                        rest
+                    }
+                    "endian" => {
+                        let (_, value, rest) = option.parse_str(|s| s.parse().ok())?;
+                        endian = value;
+                        rest
+                    }
+                    "out-dir" => {
+                        let (_, value, rest) =
+                            option.parse_osstr(|s| Some(s.to_owned()).filter(|s| !s.is_empty()))?;
+                        out_dir = Some(value);
+                        rest
+                    }
+                    "force" => {
+                        force = true;
+                        option.no_value()?.1
+                    }
                     "play" => {
                         play = true;
                         option.no_value()?.1
                     }
                     "notes" => {
-                        let (_, value, rest) = option.parse_str(parse_notes)?;
+                        let (_, value, rest) = option.try_parse_str(parse_notes)?;
                         notes = Some(value);
                         rest
                     }
+                    "voices" => {
+                        let (_, value, rest) = option.parse_str(|s| {
+                            s.parse::<usize>()
+                                .ok()
+                                .filter(|n| (1..=MAX_VOICES).contains(n))
+                        })?;
+                        max_voices = value;
+                        rest
+                    }
                     "tempo" => {
                         let (_, value, rest) = option.parse_str(|s| s.parse::<f32>().ok())?;
                         tempo = Some(value);
@@ -122,6 +799,18 @@ impl Command {
                         gate = Some(value);
                         rest
                     }
+                    "repl" => {
+                        repl = true;
+                        option.no_value()?.1
+                    }
+                    "check" => {
+                        check = true;
+                        option.no_value()?.1
+                    }
+                    "format-source" => {
+                        format_source = true;
+                        option.no_value()?.1
+                    }
                     "disassemble" => {
                         disassemble = true;
                         option.no_value()?.1
@@ -134,6 +823,18 @@ impl Command {
                         verbose = true;
                         option.no_value()?.1
                     }
+                    "quiet" => {
+                        quiet = true;
+                        option.no_value()?.1
+                    }
+                    "werror" => {
+                        werror = true;
+                        option.no_value()?.1
+                    }
+                    "warn-inexact" => {
+                        warn_inexact = true;
+                        option.no_value()?.1
+                    }
                     "dump-syntax" => {
                         dump_syntax = true;
                         option.no_value()?.1
@@ -142,8 +843,12 @@ impl Command {
                         dump_graph = true;
                         option.no_value()?.1
                     }
+                    "dump-dot" => {
+                        dump_dot = true;
+                        option.no_value()?.1
+                    }
                     "sample-rate" => {
-                        let (_, value, rest) = option.parse_str(|s| s.parse::<u32>().ok())?;
+                        let (_, value, rest) = option.try_parse_str(parse_sample_rate)?;
                         sample_rate = Some(value);
                         rest
                     }
@@ -152,6 +857,82 @@ impl Command {
                         buffer_size = Some(value);
                         rest
                     }
+                    "tuning" => {
+                        let (_, value, rest) = option.parse_str(|s| {
+                            s.parse::<f64>().ok().filter(|&x| x.is_finite() && x > 0.0)
+                        })?;
+                        tuning = Some(value);
+                        rest
+                    }
+                    "temperament" => {
+                        let (_, value, rest) = option.parse_str(|s| s.parse().ok())?;
+                        temperament = Some(value);
+                        rest
+                    }
+                    "tonic" => {
+                        let (_, value, rest) = option.try_parse_str(|s| s.parse::<Note>())?;
+                        tonic = Some(value);
+                        rest
+                    }
+                    "param" => {
+                        let (_, (name, value), rest) = option.try_parse_str(parse_control)?;
+                        controls.insert(name, value);
+                        rest
+                    }
+                    "antialias" => {
+                        let (_, value, rest) = option.parse_str(|s| match s {
+                            "on" => Some(true),
+                            "off" => Some(false),
+                            _ => None,
+                        })?;
+                        antialias = value;
+                        rest
+                    }
+                    "oversample" => {
+                        let (_, value, rest) = option.parse_str(|s| {
+                            s.parse::<usize>().ok().filter(|n| [1, 2, 4, 8].contains(n))
+                        })?;
+                        oversample = value;
+                        rest
+                    }
+                    "control-rate" => {
+                        let (_, value, rest) = option.parse_str(|s| {
+                            s.parse::<usize>()
+                                .ok()
+                                .filter(|n| n.is_power_of_two() && *n <= MAX_CONTROL_RATE)
+                        })?;
+                        control_rate = value;
+                        rest
+                    }
+                    "normalize" => {
+                        let (_, value, rest) = option.parse_str(|s| s.parse().ok())?;
+                        normalize = Some(value);
+                        rest
+                    }
+                    "target-db" => {
+                        let (_, value, rest) = option
+                            .parse_str(|s| s.parse::<f64>().ok().filter(|x| x.is_finite()))?;
+                        target_db = value;
+                        rest
+                    }
+                    "limit" => {
+                        limit = true;
+                        option.no_value()?.1
+                    }
+                    "embed-source" => {
+                        embed_source = true;
+                        option.no_value()?.1
+                    }
+                    "fade-in" => {
+                        let (_, value, rest) = option.parse_str(parse_fade)?;
+                        fade_in = value;
+                        rest
+                    }
+                    "fade-out" => {
+                        let (_, value, rest) = option.parse_str(parse_fade)?;
+                        fade_out = value;
+                        rest
+                    }
                     "script" => {
                         let (_, value, rest) = option.value_str()?;
                         script = Some(value);
@@ -161,7 +942,34 @@ impl Command {
                 },
             };
         }
+        if sample_rate.is_none() {
+            if let Some(value) = getenv(SAMPLE_RATE_VAR).filter(|value| !value.is_empty()) {
+                match value.to_str().map(parse_sample_rate) {
+                    Some(Ok(rate)) => sample_rate = Some(rate),
+                    Some(Err(e)) => {
+                        return Err(UsageError::Custom {
+                            text: format!("invalid {}: {}", SAMPLE_RATE_VAR, e),
+                        })
+                    }
+                    None => {
+                        return Err(UsageError::Custom {
+                            text: format!("invalid {}: {}", SAMPLE_RATE_VAR, quote_os(&value)),
+                        })
+                    }
+                }
+            }
+        }
         let mut files = Vec::new();
+        // Name of the output file for scripts and standard input.
+        let default_output = format!(
+            "ultrafxr.{}",
+            format.unwrap_or(OutputFormat::Wave).extension()
+        );
+        if repl && (script.is_some() || !inputs.is_empty()) {
+            return Err(UsageError::Custom {
+                text: "cannot specify inputs with -repl".to_string(),
+            });
+        }
         match script {
             Some(input) => {
                 if !inputs.is_empty() {
@@ -171,23 +979,55 @@ impl Command {
                 }
                 let input = Input::String(input);
                 let mut output_wave = wave_file;
-                if output_wave.is_none() && do_write_wave {
-                    output_wave = Some(OsString::from("ultrafxr.wav"));
+                if let Some(dir) = out_dir {
+                    if output_wave.is_some() {
+                        return Err(UsageError::Custom {
+                            text: "cannot specify both -out-dir and -wav-out".to_string(),
+                        });
+                    }
+                    output_wave = Some(PathBuf::from(dir).join(&default_output).into());
+                } else if output_wave.is_none() && do_write_wave {
+                    output_wave = Some(OsString::from(default_output));
                 }
                 files.push(File { input, output_wave });
             }
             None => {
-                if inputs.is_empty() {
+                if inputs.is_empty() && !repl {
                     return Err(UsageError::Custom {
                         text: format!("no inputs"),
                     });
                 }
+                let mut has_stdin = false;
                 for input in inputs.drain(..) {
+                    let input = if input == "-" {
+                        if has_stdin {
+                            return Err(UsageError::Custom {
+                                text: "standard input \"-\" can only be read once".to_string(),
+                            });
+                        }
+                        has_stdin = true;
+                        Input::Stdin
+                    } else {
+                        Input::File(input)
+                    };
                     files.push(File {
-                        input: Input::File(input),
+                        input,
                         output_wave: None,
                     });
                 }
+                if out_dir.is_some() {
+                    if wave_file.is_some() {
+                        return Err(UsageError::Custom {
+                            text: "cannot specify both -out-dir and -wav-out".to_string(),
+                        });
+                    }
+                    do_write_wave = true;
+                }
+                if format_source && (do_write_wave || play) {
+                    return Err(UsageError::Custom {
+                        text: "-format-source cannot be used with -write-wav or -play".to_string(),
+                    });
+                }
                 if do_write_wave {
                     match wave_file {
                         Some(path) => {
@@ -200,21 +1040,53 @@ impl Command {
                             files[0].output_wave = Some(path);
                         }
                         None => {
+                            let mut outputs: Vec<PathBuf> = Vec::with_capacity(files.len());
                             for file in files.iter_mut() {
                                 let path = match &file.input {
-                                    &Input::File(ref path) => path,
+                                    Input::File(path) => {
+                                        let input = PathBuf::from(path.clone());
+                                        let mut path = match &out_dir {
+                                            Some(dir) => match input.file_name() {
+                                                Some(name) => PathBuf::from(dir).join(name),
+                                                None => {
+                                                    return Err(UsageError::Custom {
+                                                        text: format!(
+                                                            "cannot name output for input {}",
+                                                            quote_os(&input)
+                                                        ),
+                                                    })
+                                                }
+                                            },
+                                            None => input.clone(),
+                                        };
+                                        path.set_extension(
+                                            format.unwrap_or(OutputFormat::Wave).extension(),
+                                        );
+                                        if path == input {
+                                            return Err(UsageError::Custom {
+                                                text: format!(
+                                                    "refusing to overwrite input file {}",
+                                                    quote_os(&path)
+                                                ),
+                                            });
+                                        }
+                                        path
+                                    }
+                                    Input::Stdin => match &out_dir {
+                                        Some(dir) => PathBuf::from(dir).join(&default_output),
+                                        None => PathBuf::from(&default_output),
+                                    },
                                     _ => panic!("expected file"),
                                 };
-                                let mut path = PathBuf::from(path.clone());
-                                if path.extension() == Some(OsStr::new("wav")) {
+                                if outputs.contains(&path) {
                                     return Err(UsageError::Custom {
                                         text: format!(
-                                            "refusing to overwrite input file {}",
+                                            "multiple inputs would write to {}",
                                             quote_os(&path)
                                         ),
                                     });
                                 }
-                                path.set_extension("wav");
+                                outputs.push(path.clone());
                                 file.output_wave = Some(OsString::from(path))
                             }
                         }
@@ -222,39 +1094,86 @@ impl Command {
                 }
             }
         }
-        Ok(Command {
+        Ok(Action::Run(Box::new(Command {
             files,
             play,
+            force,
             notes,
+            max_voices,
             tempo,
             gate,
             disassemble,
             do_loop,
             verbose,
+            quiet,
+            werror,
+            warn_inexact,
             dump_syntax,
             dump_graph,
+            dump_dot,
+            check,
+            format_source,
+            repl,
             sample_rate,
             buffer_size,
-        })
+            tuning,
+            temperament,
+            tonic,
+            controls,
+            antialias,
+            oversample,
+            control_rate,
+            normalize,
+            target_db,
+            limit,
+            fade_in,
+            fade_out,
+            embed_source,
+            format,
+            endian,
+        })))
     }
 
     pub fn run(&self) -> Result<(), Failed> {
-        let mut failures: usize = 0;
-        for file in self.files.iter() {
-            match self.run_file(file) {
-                Ok(()) => (),
-                Err(Failed) => failures += 1,
+        consolelogger::set_min_severity(self.min_severity());
+        consolelogger::set_warnings_as_errors(self.werror);
+        // Options are normalized once, so their warnings are only printed once.
+        let params = self.render_params()?;
+        if self.repl {
+            self.repl(&mut stdin().lock(), &mut stdout(), &params)?;
+        } else {
+            let mut failures: usize = 0;
+            for file in self.files.iter() {
+                match self.run_file(file, &params) {
+                    Ok(()) => (),
+                    Err(Failed) => failures += 1,
+                }
+            }
+            if failures > 0 {
+                if self.files.len() > 1 {
+                    error!("failed on {} of {} inputs", failures, self.files.len());
+                }
+                return Err(Failed);
             }
         }
-        if failures > 0 && self.files.len() > 1 {
-            error!("failed on {} of {} inputs", failures, self.files.len());
+        // Warnings from the command line are only checked here, after they
+        // have been printed.
+        if consolelogger::promoted_warnings() {
+            return Err(Failed);
         }
         Ok(())
     }
 
-    fn run_file(&self, file: &File) -> Result<(), Failed> {
-        let (filename, text) = self.read_input(file)?;
+    fn run_file(&self, file: &File, params: &RenderParams) -> Result<(), Failed> {
+        let mut timer = Timer::new(self.verbose);
+        let (filename, text) = read_input(file, &mut stdin())?;
         let mut err_handler = ConsoleLogger::from_text(filename.as_ref(), text.as_ref());
+        err_handler.set_min_severity(self.min_severity());
+        err_handler.set_warnings_as_errors(self.werror);
+        if self.format_source {
+            let formatted = format_source(&mut err_handler, &text, format::DEFAULT_WIDTH)?;
+            return write_formatted(file, &filename, &text, &formatted, &mut stdout());
+        }
         let exprs = {
             let mut exprs = Vec::new();
             let mut toks = match Tokenizer::new(text.as_ref()) {
@@ -264,7 +1183,8 @@ impl Command {
                     return Err(Failed);
                 }
             };
-            let mut parser = Parser::new();
+            timer.stage("tokenize");
+            let mut parser = Parser::new().with_config(self.parser_config());
             loop {
                 match parser.parse(&mut err_handler, &mut toks) {
                     ParseResult::None => break,
@@ -275,148 +1195,1454 @@ impl Command {
                     ParseResult::Error => return Err(Failed),
                     ParseResult::Value(expr) => {
                         if self.dump_syntax {
-                            eprintln!("Syntax: {}", expr.print());
+                            eprintln!("Syntax:\n{}", expr.pretty(80));
                         }
                         exprs.push(expr);
                     }
                 }
             }
-            exprs
+            timer.stage("parse");
+            let path = match file.input {
+                Input::File(ref path) => Some(Path::new(path)),
+                _ => None,
+            };
+            expand_includes(&mut err_handler, path, exprs, self.parser_config())?
         };
-        let (graph, root) = evaluate_program(&mut err_handler, exprs.as_ref())?;
+        let (mut graph, root) = evaluate_program(&mut err_handler, exprs.as_ref())?;
+        if err_handler.promoted_warnings() {
+            return Err(Failed);
+        }
+        timer.stage("evaluate");
         if self.dump_graph {
             let mut stdout = stdout();
             graph.dump(&mut stdout);
-            writeln!(&mut stdout, "root = {:?}", root).unwrap();
+            writeln!(&mut stdout, "root = #{}", root.0).unwrap();
+        }
+        if self.dump_dot {
+            graph.dump_dot(&mut stdout(), root);
+        }
+        graph.fold_constants();
+        graph.dedup();
+        if self.disassemble {
+            self.disassemble(&graph, root, &params.parameters)?;
         }
-        match file.output_wave {
-            Some(ref path) => self.write_wave(path, &graph, root)?,
-            None => {}
+        if self.check {
+            return self.check(&graph, root, &params.parameters);
+        }
+        if let Some(ref path) = file.output_wave {
+            timer.reset();
+            let length = self.write_wave(path, &text, &graph, root, params)?;
+            timer.stage("render");
+            timer.audio_length = Some(length as f64 / params.parameters.sample_rate);
+        }
+        timer.report(&mut stderr()).unwrap();
+        if self.play {
+            self.play(&graph, root, params)?;
         }
         Ok(())
     }
 
-    /// Read the input file and return its name and its contents.
-    fn read_input(&self, file: &File) -> Result<(String, Box<[u8]>), Failed> {
-        match file.input {
-            Input::File(ref path) => {
-                let filename = quote_os(path);
-                let mut text = Vec::new();
-                match fs::File::open(path).and_then(|mut f| f.read_to_end(&mut text)) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("could not read {}: {}", filename, e);
-                        return Err(Failed);
-                    }
+    /// Read expressions from the input and evaluate them until the input ends.
+    /// Definitions are kept for the rest of the session, and a summary of the
+    /// graph is printed for each other expression. Errors are reported and the
+    /// session continues. With -werror, an expression with warnings fails like
+    /// one with errors, and the session fails when the input ends.
+    fn repl(
+        &self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+        params: &RenderParams,
+    ) -> Result<(), Failed> {
+        // Definitions which evaluated successfully. They are evaluated again
+        // for each expression, so they are always in scope.
+        let mut definitions: Vec<SExpr> = Vec::new();
+        let mut text = String::new();
+        // True if any warning was reported as an error.
+        let mut promoted = false;
+        loop {
+            let prompt = if text.is_empty() { "> " } else { "... " };
+            write!(output, "{}", prompt).unwrap();
+            output.flush().unwrap();
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) => {
+                    writeln!(output).unwrap();
+                    return if promoted { Err(Failed) } else { Ok(()) };
                 }
-                Ok((filename, Box::from(text)))
-            }
-            Input::String(ref s) => Ok(("<arg>".to_string(), Box::from(s.as_bytes()))),
-        }
-    }
-
-    /// Write output wave file.
-    fn write_wave(&self, path: &OsStr, graph: &Graph, signal: SignalRef) -> Result<(), Failed> {
-        let filename = quote_os(path);
-        let sample_rate = match self.sample_rate {
-            Some(rate) => {
-                if rate < MIN_SAMPLE_RATE {
-                    error!(
-                        "sample rate {} is too low, acceptable rates are {}-{}",
-                        rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
-                    );
-                    return Err(Failed);
-                } else if rate > MAX_SAMPLE_RATE {
-                    error!(
-                        "sample rate {} is too high, acceptable rates are {}-{}",
-                        rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
-                    );
+                Ok(_) => text.push_str(&line),
+                Err(e) => {
+                    error!("could not read input: {}", e);
                     return Err(Failed);
-                } else {
-                    rate
                 }
             }
-            None => DEFAULT_SAMPLE_RATE,
-        };
-        let buffer_size = match self.buffer_size {
-            Some(size) => {
-                if size < MIN_BUFFER_SIZE {
-                    warning!("buffer size {} is too low, using {}", size, MIN_BUFFER_SIZE);
-                    MIN_BUFFER_SIZE
-                } else if size > MAX_BUFFER_SIZE {
-                    warning!(
-                        "buffer size {} is too high, using {}",
-                        size,
-                        MAX_BUFFER_SIZE
-                    );
-                    MAX_BUFFER_SIZE
-                } else {
-                    let nsize = size.next_power_of_two();
-                    if nsize != size {
-                        warning!(
-                            "buffer size {} is not a power of two, using {}",
-                            size,
-                            nsize
-                        );
+            let mut err_handler = ConsoleLogger::from_text("<input>", text.as_bytes());
+            err_handler.set_min_severity(self.min_severity());
+            err_handler.set_warnings_as_errors(self.werror);
+            let mut toks = match Tokenizer::new(text.as_bytes()) {
+                Ok(toks) => toks,
+                Err(e) => {
+                    error!("could not parse input: {}", e);
+                    text.clear();
+                    continue;
+                }
+            };
+            let mut parser = Parser::new().with_config(self.parser_config());
+            let mut exprs = Vec::new();
+            let complete = loop {
+                match parser.parse(&mut err_handler, &mut toks) {
+                    ParseResult::None => break true,
+                    ParseResult::Incomplete => break false,
+                    ParseResult::Error => {
+                        exprs.clear();
+                        break true;
                     }
-                    nsize
+                    ParseResult::Value(expr) => exprs.push(expr),
                 }
+            };
+            if !complete {
+                // Read more lines to finish the expression.
+                continue;
             }
-            None => DEFAULT_BUFFER_SIZE,
-        };
-        let note = self
-            .notes
-            .as_ref()
-            .and_then(|x| x.first().copied())
-            .unwrap_or(Note(69));
-        let program = Program::new(
-            &graph,
-            signal,
-            &Parameters {
-                sample_rate: sample_rate as f64,
-                buffer_size,
-            },
-        );
-        let mut program = match program {
+            if err_handler.take_promoted_warnings() {
+                promoted = true;
+                text.clear();
+                continue;
+            }
+            for expr in exprs.drain(..) {
+                let is_definition = match &expr.content {
+                    Content::List(items) => match items.first().map(|item| &item.content) {
+                        Some(Content::Symbol(name)) => {
+                            ["define", "defun", "defmacro"].contains(&name.as_str())
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                definitions.push(expr);
+                if is_definition {
+                    let result = evaluate_statements_only(&mut err_handler, &definitions);
+                    let failed = err_handler.take_promoted_warnings();
+                    promoted |= failed;
+                    if result.is_err() || failed {
+                        definitions.pop();
+                    }
+                    continue;
+                }
+                let result = evaluate_program(&mut err_handler, &definitions);
+                definitions.pop();
+                if err_handler.take_promoted_warnings() {
+                    promoted = true;
+                    continue;
+                }
+                let (mut graph, root) = match result {
+                    Ok(x) => x,
+                    Err(Failed) => continue,
+                };
+                graph.fold_constants();
+                graph.dedup();
+                if self.dump_graph {
+                    graph.dump(output);
+                }
+                writeln!(
+                    output,
+                    "{} nodes, output #{} ({})",
+                    graph.nodes().len(),
+                    root.0,
+                    graph.nodes()[root.0 as usize].op_name()
+                )
+                .unwrap();
+                if self.play {
+                    // Playback errors are already reported.
+                    let _ = self.play(&graph, root, params);
+                }
+            }
+            text.clear();
+        }
+    }
+
+    /// Get the options for parsing scripts.
+    fn parser_config(&self) -> ParserConfig {
+        ParserConfig::default().warn_inexact(self.warn_inexact)
+    }
+
+    /// Get the minimum severity of diagnostics to print.
+    fn min_severity(&self) -> Severity {
+        if self.quiet {
+            Severity::Error
+        } else {
+            Severity::Warning
+        }
+    }
+
+    /// Get the sample rate to render at.
+    fn sample_rate(&self) -> Result<u32, Failed> {
+        normalize_sample_rate(self.sample_rate, &mut Console)
+    }
+
+    /// Get the size of the buffers to render.
+    fn buffer_size(&self) -> usize {
+        normalize_buffer_size(self.buffer_size, &mut Console)
+    }
+
+    /// Get the conversion from notes to frequencies.
+    fn tuning(&self) -> Tuning {
+        let temperament = self.temperament.unwrap_or(Temperament::Equal);
+        if temperament == Temperament::Equal && self.tonic.is_some() {
+            warning!("-tonic has no effect with equal temperament");
+        }
+        Tuning {
+            reference: self.reference_frequency(),
+            temperament,
+            tonic: self.tonic.map_or(0, |note| note.chromaticity()),
+        }
+    }
+
+    /// Get the frequency of A4, in Hz.
+    fn reference_frequency(&self) -> f64 {
+        match self.tuning {
+            Some(tuning) => {
+                if !(MIN_TUNING..=MAX_TUNING).contains(&tuning) {
+                    warning!(
+                        "tuning {} Hz is unusual, typical tunings are {}-{} Hz",
+                        tuning,
+                        MIN_TUNING,
+                        MAX_TUNING
+                    );
+                }
+                tuning
+            }
+            None => DEFAULT_TUNING,
+        }
+    }
+
+    /// Get the parameters for creating programs. This prints warnings for
+    /// unusual options, so it is only called once.
+    fn parameters(&self) -> Result<Parameters, Failed> {
+        let buffer_size = self.buffer_size();
+        Ok(Parameters {
+            sample_rate: self.sample_rate()? as f64,
+            buffer_size,
+            tuning: self.tuning(),
+            controls: self.controls.clone(),
+            antialias: self.antialias,
+            oversample: self.oversample,
+            // The control period must divide the buffer.
+            control_rate: self.control_rate.min(buffer_size),
+        })
+    }
+
+    /// Get the parameters for rendering the notes. Like parameters, this
+    /// prints warnings, so it is only called once.
+    fn render_params(&self) -> Result<RenderParams, Failed> {
+        let parameters = self.parameters()?;
+        let notes = match &self.notes {
+            Some(notes) => notes
+                .iter()
+                .map(|chord| {
+                    if chord.len() > self.max_voices {
+                        warning!(
+                            "chord has {} notes, only the first {} are played",
+                            chord.len(),
+                            self.max_voices
+                        );
+                    }
+                    chord
+                        .iter()
+                        .map(|&(note, velocity)| NoteEvent::with_velocity(note.0 as f32, velocity))
+                        .collect()
+                })
+                .collect(),
+            None => vec![vec![NoteEvent::new(69.0)]],
+        };
+        Ok(RenderParams {
+            gate: (parameters.sample_rate / 2.0) as usize,
+            parameters,
+            notes,
+            max_length: usize::MAX,
+            max_voices: self.max_voices,
+        })
+    }
+
+    /// Check that a program can be created from the graph, without rendering
+    /// it.
+    fn check(
+        &self,
+        graph: &Graph,
+        signal: SignalRef,
+        parameters: &Parameters,
+    ) -> Result<(), Failed> {
+        match Program::new(graph, signal, parameters) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("could not create program: {}", e);
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Print the compiled program to stdout.
+    fn disassemble(
+        &self,
+        graph: &Graph,
+        signal: SignalRef,
+        parameters: &Parameters,
+    ) -> Result<(), Failed> {
+        let program = Program::new(graph, signal, parameters);
+        let program = match program {
             Ok(p) => p,
             Err(e) => {
                 error!("could not create program: {}", e);
                 return Err(Failed);
             }
         };
-        let mut file = match fs::File::create(&path) {
+        let mut stdout = stdout();
+        program.disassemble(graph, &mut stdout).unwrap();
+        Ok(())
+    }
+
+    /// Render audio and write it to a WAVE file. Returns the number of samples
+    /// written. The source is the script text, which is embedded in the file
+    /// if requested.
+    fn write_wave(
+        &self,
+        path: &OsStr,
+        source: &[u8],
+        graph: &Graph,
+        signal: SignalRef,
+        params: &RenderParams,
+    ) -> Result<usize, Failed> {
+        let filename = quote_os(path);
+        let format = self.format.unwrap_or_else(|| OutputFormat::from_path(path));
+        if cfg!(not(feature = "flacenc")) && format == OutputFormat::Flac {
+            error!(
+                "could not write {}: FLAC output requires the flacenc feature",
+                filename
+            );
+            return Err(Failed);
+        }
+        if self.embed_source && format != OutputFormat::Wave {
+            warning!("-embed-source is only supported for WAVE output, ignoring it");
+        }
+        let sample_rate = params.parameters.sample_rate as u32;
+        let mut renderer = match BlockRenderer::new(graph, signal, params) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed);
+            }
+        };
+        let mut file = match create_output(path.as_ref(), self.force) {
             Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                error!(
+                    "could not create {}: file exists, use -force to overwrite",
+                    filename
+                );
+                return Err(Failed);
+            }
             Err(e) => {
                 error!("could not create {}: {}", filename, e);
                 return Err(Failed);
             }
         };
-        let mut writer = wave::Writer::from_stream(
-            &mut file,
-            &wave::Parameters {
-                channel_count: 1,
-                sample_rate,
+        let wave_params = wave::Parameters {
+            channel_count: 1,
+            sample_rate,
+        };
+        let writer = match format {
+            OutputFormat::Wave => {
+                let mut writer = wave::Writer::from_stream(&mut file, &wave_params);
+                if self.embed_source {
+                    // The settings are written as a comment, so the embedded
+                    // script can still be run as-is.
+                    let mut comment = format!("; sample rate: {}\n", sample_rate).into_bytes();
+                    comment.extend_from_slice(source);
+                    writer.add_info(*b"ISFT", version().as_bytes());
+                    writer.add_info(*b"ICMT", &comment);
+                }
+                SampleWriter::Wave(writer)
+            }
+            #[cfg(feature = "flacenc")]
+            OutputFormat::Flac => {
+                SampleWriter::Flac(flac::Writer::from_stream(&mut file, &wave_params))
+            }
+            #[cfg(not(feature = "flacenc"))]
+            OutputFormat::Flac => unreachable!(),
+            OutputFormat::Raw => {
+                SampleWriter::Raw(raw::Writer::from_stream(&mut file, self.endian))
+            }
+        };
+        let mut output = OutputStage {
+            writer,
+            limiter: if self.limit {
+                Some(Limiter::new(sample_rate as f64))
+            } else {
+                None
+            },
+            fade: if self.fade_in > 0.0 || self.fade_out > 0.0 {
+                let samples = |ms: f64| (ms * 0.001 * sample_rate as f64).round() as usize;
+                Some(Fade::new(samples(self.fade_in), samples(self.fade_out)))
+            } else {
+                None
             },
+            limited: Vec::new(),
+            faded: Vec::new(),
+        };
+        let progress = Progress::new(&mut renderer, &filename, !self.quiet);
+        let mut length = 0;
+        match self.normalize {
+            None => {
+                while let Some(samples) = renderer.next_block() {
+                    length += samples.len();
+                    unwrap_write(&filename, output.write(samples))?;
+                }
+            }
+            Some(mode) => {
+                // Normalizing needs the whole output to measure its level.
+                let mut samples = Vec::new();
+                while let Some(block) = renderer.next_block() {
+                    samples.extend_from_slice(block);
+                }
+                normalize(&mut samples, mode, self.target_db);
+                length = samples.len();
+                unwrap_write(&filename, output.write(&samples))?;
+            }
+        }
+        drop(progress);
+        let writer = unwrap_write(&filename, output.finish())?;
+        unwrap_write(&filename, writer.finish())?;
+        unwrap_write(&filename, file.sync_all())?;
+        Ok(length)
+    }
+
+    /// Play the audio on the default output device.
+    #[cfg(feature = "audio")]
+    fn play(&self, graph: &Graph, signal: SignalRef, params: &RenderParams) -> Result<(), Failed> {
+        let sample_rate = params.parameters.sample_rate as u32;
+        let mut player = match audio::Player::new(graph, signal, params, self.do_loop) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("{}", e);
+                return Err(Failed);
+            }
+        };
+        let mut output = match audio::open_default(sample_rate, params.parameters.buffer_size) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("could not open audio output: {}", e);
+                return Err(Failed);
+            }
+        };
+        if let Err(e) = audio::play(&mut player, output.as_mut()) {
+            error!("playback failed: {}", e);
+            return Err(Failed);
+        }
+        match output.finish() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("playback failed: {}", e);
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Play the audio on the default output device.
+    #[cfg(not(feature = "audio"))]
+    fn play(
+        &self,
+        _graph: &Graph,
+        _signal: SignalRef,
+        _params: &RenderParams,
+    ) -> Result<(), Failed> {
+        error!("audio playback is not available, ultrafxr was built without the \"audio\" feature");
+        Err(Failed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::program::Input as PInput;
+    use crate::sourcepos::{Pos, Span};
+    use crate::sourcetext::{SourceText, TextPos};
+
+    /// Error handler which records errors and warnings.
+    #[derive(Default)]
+    struct Messages {
+        errors: Vec<String>,
+        warnings: Vec<String>,
+    }
+
+    impl ErrorHandler for Messages {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            self.errors.push(message.to_owned());
+        }
+
+        fn warning(&mut self, _pos: Span, message: &str) {
+            self.warnings.push(message.to_owned());
+        }
+    }
+
+    #[test]
+    fn buffer_size() {
+        let cases: &[(Option<usize>, usize, Option<&str>)] = &[
+            (None, DEFAULT_BUFFER_SIZE, None),
+            (Some(256), 256, None),
+            (Some(MIN_BUFFER_SIZE), MIN_BUFFER_SIZE, None),
+            (Some(MAX_BUFFER_SIZE), MAX_BUFFER_SIZE, None),
+            (Some(4), MIN_BUFFER_SIZE, Some("too low")),
+            (Some(100000), MAX_BUFFER_SIZE, Some("too high")),
+            (Some(300), 512, Some("not a power of two")),
+        ];
+        for &(requested, expected, warning) in cases.iter() {
+            let mut messages = Messages::default();
+            assert_eq!(
+                normalize_buffer_size(requested, &mut messages),
+                expected,
+                "{:?}",
+                requested
+            );
+            assert!(messages.errors.is_empty());
+            match warning {
+                None => assert!(messages.warnings.is_empty(), "{:?}", messages.warnings),
+                Some(warning) => {
+                    assert_eq!(messages.warnings.len(), 1, "{:?}", messages.warnings);
+                    assert!(
+                        messages.warnings[0].contains(warning),
+                        "{:?}",
+                        messages.warnings
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sample_rate() {
+        let mut messages = Messages::default();
+        assert_eq!(
+            normalize_sample_rate(None, &mut messages),
+            Ok(DEFAULT_SAMPLE_RATE)
         );
-        let mut pos: usize = 0;
-        let end = (sample_rate / 2) as usize;
-        loop {
-            let output = program.render(&PInput {
-                gate: if pos < end && end - pos < buffer_size {
-                    Some(end - pos)
-                } else {
-                    None
-                },
-                note: note.0 as f32,
-            });
-            let output = match output {
-                Some(x) => x,
-                None => break,
+        assert_eq!(normalize_sample_rate(Some(44100), &mut messages), Ok(44100));
+        assert!(messages.errors.is_empty());
+        assert!(normalize_sample_rate(Some(4000), &mut messages).is_err());
+        assert!(normalize_sample_rate(Some(400000), &mut messages).is_err());
+        assert_eq!(messages.errors.len(), 2, "{:?}", messages.errors);
+        assert!(messages.errors[0].contains("too low"));
+        assert!(messages.errors[1].contains("too high"));
+        assert!(messages.warnings.is_empty());
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn read_gzip() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        let text = "(define x (noise))\n(* x 0.5)\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+        assert!(data.starts_with(GZIP_MAGIC));
+        let path = env::temp_dir().join(format!("ultrafxr-gzip-{}.ufx.gz", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let file = File {
+            input: Input::File(path.clone().into()),
+            output_wave: None,
+        };
+        let result = read_input(&file, &mut &b""[..]);
+        fs::remove_file(&path).unwrap();
+        let (_, decompressed) = result.unwrap();
+        let parse = |text: &[u8]| -> Vec<SExpr> {
+            let mut toks = Tokenizer::new(text).unwrap();
+            let mut parser = Parser::new();
+            let mut exprs = Vec::new();
+            while let ParseResult::Value(expr) = parser.parse(&mut Messages::default(), &mut toks) {
+                exprs.push(expr);
+            }
+            exprs
+        };
+        let expected = parse(text.as_bytes());
+        let exprs = parse(&decompressed);
+        assert_eq!(exprs.len(), 2);
+        assert!(exprs
+            .iter()
+            .map(SExpr::by_value)
+            .eq(expected.iter().map(SExpr::by_value)));
+        // Input which is not compressed is unchanged.
+        let file = File {
+            input: Input::Stdin,
+            output_wave: None,
+        };
+        let (_, plain) = read_input(&file, &mut text.as_bytes()).unwrap();
+        assert_eq!(&plain[..], text.as_bytes());
+    }
+
+    #[cfg(not(feature = "flate2"))]
+    #[test]
+    fn read_gzip_unsupported() {
+        let file = File {
+            input: Input::Stdin,
+            output_wave: None,
+        };
+        let mut stdin: &[u8] = b"\x1f\x8b\x08\x00";
+        assert!(read_input(&file, &mut stdin).is_err());
+    }
+
+    #[test]
+    fn read_stdin() {
+        let file = File {
+            input: Input::Stdin,
+            output_wave: None,
+        };
+        let mut stdin: &[u8] = b"(define x 1)\n(noise)\n";
+        let (filename, text) = read_input(&file, &mut stdin).unwrap();
+        assert_eq!(filename, "<stdin>");
+        assert_eq!(&text[..], b"(define x 1)\n(noise)\n");
+        let source = SourceText::new(&filename, &text).unwrap();
+        assert_eq!(source.filename(), "<stdin>");
+        assert_eq!(source.line(1), b"(noise)");
+        let span = source
+            .span(Span {
+                start: Pos(14),
+                end: Pos(21),
+            })
+            .unwrap();
+        assert_eq!(span.start, TextPos { line: 1, byte: 0 });
+        assert_eq!(span.end, TextPos { line: 1, byte: 7 });
+    }
+
+    fn parse(args: &[&str]) -> Result<Action, UsageError> {
+        Command::from_args_env(args.iter().map(OsString::from), |_| None)
+    }
+
+    #[test]
+    fn help() {
+        for &arg in ["-help", "--help", "-h"].iter() {
+            match parse(&[arg]) {
+                Ok(Action::Help) => (),
+                r => panic!("{}: {:?}", arg, r),
+            }
+        }
+        match parse(&["in.lisp", "-play", "--help", "-unknown"]) {
+            Ok(Action::Help) => (),
+            r => panic!("{:?}", r),
+        }
+        assert!(!HELP.is_empty());
+        for &option in ["-write-wav", "-wav-out", "-play", "-notes", "-script"].iter() {
+            assert!(HELP.contains(option), "help missing {}", option);
+        }
+    }
+
+    #[test]
+    fn version() {
+        for &arg in ["-version", "--version", "-V"].iter() {
+            match parse(&[arg]) {
+                Ok(Action::Version) => (),
+                r => panic!("{}: {:?}", arg, r),
+            }
+        }
+        assert!(super::version().contains(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn sample_rate_env() {
+        let parse_env = |args: &[&str], value: &str| {
+            let value = OsString::from(value);
+            Command::from_args_env(args.iter().map(OsString::from), |name| {
+                assert_eq!(name, SAMPLE_RATE_VAR);
+                Some(value.clone())
+            })
+        };
+        match parse_env(&["in.lisp"], "44100") {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.sample_rate, Some(44100)),
+            r => panic!("{:?}", r),
+        }
+        match parse_env(&["-sample-rate", "96000", "in.lisp"], "44100") {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.sample_rate, Some(96000)),
+            r => panic!("{:?}", r),
+        }
+        match parse_env(&["in.lisp"], "") {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.sample_rate, None),
+            r => panic!("{:?}", r),
+        }
+        match parse_env(&["in.lisp"], "fast") {
+            Err(UsageError::Custom { text }) => assert!(text.contains(SAMPLE_RATE_VAR), "{}", text),
+            r => panic!("{:?}", r),
+        }
+        // Rates from the environment are checked like rates from arguments.
+        match parse_env(&["in.lisp"], "1000") {
+            Ok(Action::Run(cmd)) => assert!(cmd.sample_rate().is_err()),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn sample_rate_presets() {
+        for &(name, rate) in [("cd", 44100), ("pro", 48000), ("hires", 96000)].iter() {
+            match parse(&["-sample-rate", name, "in.lisp"]) {
+                Ok(Action::Run(cmd)) => assert_eq!(cmd.sample_rate, Some(rate), "{}", name),
+                r => panic!("{}: {:?}", name, r),
+            }
+        }
+        match parse(&["-sample-rate", "vinyl", "in.lisp"]) {
+            Err(UsageError::Custom { text }) => {
+                assert!(text.contains("vinyl"), "{}", text);
+                for &(name, _) in SAMPLE_RATE_PRESETS.iter() {
+                    assert!(text.contains(name), "{}", text);
+                }
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn option_values() {
+        match parse(&["--sample-rate=44100", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.sample_rate, Some(44100)),
+            r => panic!("{:?}", r),
+        }
+        match parse(&["--write-wav", "--wav-out=out.wav", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => {
+                assert_eq!(cmd.files[0].output_wave, Some(OsString::from("out.wav")))
+            }
+            r => panic!("{:?}", r),
+        }
+        match parse(&["--wav-out=", "in.lisp"]) {
+            Err(UsageError::OptionInvalidValue { option, .. }) => assert_eq!(option, "wav-out"),
+            r => panic!("{:?}", r),
+        }
+        match parse(&["--play=x", "in.lisp"]) {
+            Err(UsageError::OptionUnexpectedParameter { option }) => assert_eq!(option, "play"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn short_options() {
+        match parse(&["-vp", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert!(cmd.verbose && cmd.play),
+            r => panic!("{:?}", r),
+        }
+        for &args in [
+            &["-write-wav", "-o", "out.wav", "in.lisp"][..],
+            &["-write-wav", "-vo", "out.wav", "in.lisp"][..],
+            &["-write-wav", "-o=out.wav", "in.lisp"][..],
+        ]
+        .iter()
+        {
+            match parse(args) {
+                Ok(Action::Run(cmd)) => {
+                    assert_eq!(cmd.files.len(), 1, "{:?}", args);
+                    assert_eq!(cmd.files[0].output_wave, Some(OsString::from("out.wav")));
+                }
+                r => panic!("{:?}: {:?}", args, r),
+            }
+        }
+        match parse(&["-qp", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => {
+                assert!(cmd.quiet && cmd.play);
+                assert_eq!(cmd.min_severity(), Severity::Error);
+            }
+            r => panic!("{:?}", r),
+        }
+        // Long options with a single dash are not bundles.
+        match parse(&["-play", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert!(cmd.play && !cmd.verbose),
+            r => panic!("{:?}", r),
+        }
+        match parse(&["-vx", "in.lisp"]) {
+            Err(UsageError::UnknownOption { option }) => assert_eq!(option, "vx"),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    fn output_paths(args: &[&str]) -> Vec<Option<OsString>> {
+        match parse(args) {
+            Ok(Action::Run(cmd)) => cmd.files.iter().map(|f| f.output_wave.clone()).collect(),
+            r => panic!("{:?}: {:?}", args, r),
+        }
+    }
+
+    #[test]
+    fn batch() {
+        assert_eq!(
+            output_paths(&["-write-wav", "a.lisp", "dir/b.lisp"]),
+            [
+                Some(OsString::from("a.wav")),
+                Some(OsString::from("dir/b.wav"))
+            ]
+        );
+        assert_eq!(
+            output_paths(&["-out-dir", "out", "a.lisp", "dir/b.lisp"]),
+            [
+                Some(OsString::from("out/a.wav")),
+                Some(OsString::from("out/b.wav"))
+            ]
+        );
+        assert_eq!(
+            output_paths(&["-out-dir", "out", "in.wav"]),
+            [Some(OsString::from("out/in.wav"))]
+        );
+        for &args in [
+            &["-write-wav", "in.wav"][..],
+            &["-out-dir", "out", "a/x.lisp", "b/x.lisp"][..],
+            &["-out-dir", "out", "-wav-out", "x.wav", "a.lisp"][..],
+        ]
+        .iter()
+        {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn batch_failure() {
+        let dir = env::temp_dir();
+        let good = dir.join(format!("ultrafxr-good-{}.lisp", std::process::id()));
+        let bad = dir.join(format!("ultrafxr-bad-{}.lisp", std::process::id()));
+        fs::write(&good, "(sine (oscillator (note 0)))").unwrap();
+        fs::write(&bad, "(sine").unwrap();
+        let run = |paths: &[&PathBuf]| {
+            let mut args = vec!["-check", "-quiet"];
+            args.extend(paths.iter().map(|p| p.to_str().unwrap()));
+            match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd.run(),
+                r => panic!("{:?}", r),
+            }
+        };
+        // A failure in one input does not stop the others, but the command
+        // still fails.
+        let results = [run(&[&good]), run(&[&bad]), run(&[&bad, &good])];
+        fs::remove_file(&good).unwrap();
+        fs::remove_file(&bad).unwrap();
+        assert_eq!(results, [Ok(()), Err(Failed), Err(Failed)]);
+    }
+
+    #[test]
+    fn param() {
+        use crate::signal::ops::Parameter;
+        let cmd = match parse(&["-param", "x=0.75", "--param=y=2", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(Parameter {
+            name: Box::from("x"),
+        }));
+        let z = graph.add(Box::new(Parameter {
+            name: Box::from("z"),
+        }));
+        let parameters = cmd.parameters().unwrap();
+        for &(root, expect) in [(x, 0.75), (z, 0.0)].iter() {
+            let mut program = Program::new(&graph, root, &parameters).unwrap();
+            let output = program
+                .render(&PInput {
+                    gate: None,
+                    note: 69.0,
+                    velocity: 1.0,
+                })
+                .unwrap();
+            assert_eq!(output[0], expect);
+        }
+        assert_eq!(cmd.controls.get("y"), Some(&2.0));
+        for &arg in ["x", "=1", "x=", "x=y"].iter() {
+            match parse(&["-param", arg, "in.lisp"]) {
+                Err(UsageError::Custom { text }) => assert!(text.contains("-param"), "{}", text),
+                r => panic!("{}: {:?}", arg, r),
+            }
+        }
+    }
+
+    #[test]
+    fn antialias() {
+        for &(args, expect) in [
+            (&["in.lisp"][..], true),
+            (&["-antialias", "off", "in.lisp"][..], false),
+            (&["--antialias=on", "in.lisp"][..], true),
+        ]
+        .iter()
+        {
+            match parse(args) {
+                Ok(Action::Run(cmd)) => assert_eq!(cmd.antialias, expect, "{:?}", args),
+                r => panic!("{:?}: {:?}", args, r),
+            }
+        }
+        assert!(parse(&["-antialias", "maybe", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn oversample() {
+        match parse(&["-oversample", "4", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.oversample, 4),
+            r => panic!("{:?}", r),
+        }
+        match parse(&["in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.oversample, 1),
+            r => panic!("{:?}", r),
+        }
+        assert!(parse(&["-oversample", "3", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn control_rate() {
+        match parse(&["-control-rate", "64", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.control_rate, 64),
+            r => panic!("{:?}", r),
+        }
+        match parse(&["in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.control_rate, 1),
+            r => panic!("{:?}", r),
+        }
+        assert!(parse(&["-control-rate", "48", "in.lisp"]).is_err());
+        assert!(parse(&["-control-rate", "512", "in.lisp"]).is_err());
+        assert!(parse(&["-control-rate", "0", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn note_velocity() {
+        match parse(&["-notes", "C4,E4@100,m67@0", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(
+                cmd.notes,
+                Some(vec![
+                    vec![(Note(60), 64)],
+                    vec![(Note(64), 100)],
+                    vec![(Note(67), 0)]
+                ])
+            ),
+            r => panic!("{:?}", r),
+        }
+        for &notes in ["C4@128", "C4@", "C4@-1", "@100"].iter() {
+            assert!(parse(&["-notes", notes, "in.lisp"]).is_err(), "{}", notes);
+        }
+    }
+
+    #[test]
+    fn chords() {
+        match parse(&["-notes", "C4+E4@100+G4,A3", "-voices", "2", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => {
+                assert_eq!(
+                    cmd.notes,
+                    Some(vec![
+                        vec![(Note(60), 64), (Note(64), 100), (Note(67), 64)],
+                        vec![(Note(57), 64)]
+                    ])
+                );
+                assert_eq!(cmd.max_voices, 2);
+            }
+            r => panic!("{:?}", r),
+        }
+        match parse(&["in.lisp"]) {
+            Ok(Action::Run(cmd)) => assert_eq!(cmd.max_voices, DEFAULT_MAX_VOICES),
+            r => panic!("{:?}", r),
+        }
+        assert!(parse(&["-notes", "C4+", "in.lisp"]).is_err());
+        assert!(parse(&["-voices", "0", "in.lisp"]).is_err());
+        assert!(parse(&["-voices", "65", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn normalize_options() {
+        match parse(&["-normalize", "rms", "-target-db", "-18", "in.lisp"]) {
+            Ok(Action::Run(cmd)) => {
+                assert_eq!(cmd.normalize, Some(Normalize::Rms));
+                assert_eq!(cmd.target_db, -18.0);
+            }
+            r => panic!("{:?}", r),
+        }
+        assert!(parse(&["-normalize", "loud", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn limit() {
+        use crate::signal::envelope::{Envelope, Segment};
+        use crate::signal::ops::{Multiply, Noise};
+        let path = env::temp_dir().join(format!("ultrafxr-limit-{}.wav", std::process::id()));
+        let mut graph = Graph::new();
+        let noise = graph.add(Box::new(Noise));
+        let gain = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 3.0 },
+                    Segment::Delay { time: 0.25 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let root = graph.add(Box::new(Multiply {
+            inputs: [noise, gain],
+        }));
+        let clipped = |limit: bool| -> usize {
+            let mut args = vec!["in.lisp", "-f", "-write-wav", "-o", path.to_str().unwrap()];
+            if limit {
+                args.push("-limit");
+            }
+            let cmd = match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
             };
-            pos += output.len();
-            unwrap_write(&filename, writer.write(output))?;
+            cmd.write_wave(
+                path.as_ref(),
+                b"",
+                &graph,
+                root,
+                &cmd.render_params().unwrap(),
+            )
+            .unwrap();
+            let data = fs::read(&path).unwrap();
+            let samples = wave::Reader::from_stream(&mut std::io::Cursor::new(data))
+                .unwrap()
+                .read_all()
+                .unwrap();
+            assert!(samples.len() >= 12000);
+            samples.iter().filter(|&&x| x.abs() > 0.999).count()
+        };
+        let plain = clipped(false);
+        let limited = clipped(true);
+        fs::remove_file(&path).unwrap();
+        assert!(limited * 100 < plain, "{} {}", limited, plain);
+    }
+
+    #[test]
+    fn fade() {
+        use crate::signal::envelope::{Envelope, Segment};
+        let path = env::temp_dir().join(format!("ultrafxr-fade-{}.wav", std::process::id()));
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 0.5 },
+                    Segment::Delay { time: 0.5 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let cmd = match parse(&[
+            "in.lisp",
+            "-fade-in",
+            "10",
+            "-fade-out",
+            "20",
+            "-write-wav",
+            "-o",
+            path.to_str().unwrap(),
+        ]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        cmd.write_wave(
+            path.as_ref(),
+            b"",
+            &graph,
+            root,
+            &cmd.render_params().unwrap(),
+        )
+        .unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let samples = wave::Reader::from_stream(&mut std::io::Cursor::new(data))
+            .unwrap()
+            .read_all()
+            .unwrap();
+        let n = samples.len();
+        assert!(n >= 24000);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[n - 1], 0.0);
+        // At 48 kHz, the fade-in is 480 samples and the fade-out is 960.
+        let full = |x: f32| (x - 0.5).abs() < 0.001;
+        assert!(!full(samples[470]));
+        assert!(full(samples[480]));
+        assert!(full(samples[n - 961]));
+        assert!(!full(samples[n - 950]));
+        assert!(parse(&["-fade-in", "-1", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn embed_source() {
+        let path = env::temp_dir().join(format!("ultrafxr-embed-{}.wav", std::process::id()));
+        let script = "(* 1V (envelope (set 0.5) (delay 100ms) (stop)))";
+        let cmd = match parse(&[
+            "-embed-source",
+            "-f",
+            "-sample-rate",
+            "44100",
+            "-write-wav",
+            "-o",
+            path.to_str().unwrap(),
+            "-script",
+            script,
+        ]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        assert!(cmd.embed_source);
+        cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap())
+            .unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let find = |pat: &[u8]| data.windows(pat.len()).any(|w| w == pat);
+        assert!(find(b"LIST"));
+        assert!(find(super::version().as_bytes()));
+        let comment = format!("; sample rate: 44100\n{}\0", script);
+        assert!(find(comment.as_bytes()));
+        let mut stream = std::io::Cursor::new(data);
+        let mut reader = wave::Reader::from_stream(&mut stream).unwrap();
+        assert_eq!(reader.parameters().sample_rate, 44100);
+        assert_eq!(reader.read_all().unwrap().len(), 4410);
+    }
+
+    #[test]
+    fn werror() {
+        let run = |werror: bool| {
+            let mut args = vec!["-check", "-script", "(define x 1)\n(noise)"];
+            if werror {
+                args.push("-werror");
+            }
+            let cmd = match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
+            };
+            assert_eq!(cmd.werror, werror);
+            cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap())
+        };
+        // The unused variable is only a warning.
+        assert!(run(false).is_ok());
+        assert!(run(true).is_err());
+    }
+
+    #[test]
+    fn warn_inexact() {
+        let run = |script: &str, warn_inexact: bool| {
+            let mut args = vec!["-check", "-werror", "-script", script];
+            if warn_inexact {
+                args.push("-warn-inexact");
+            }
+            let cmd = match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
+            };
+            assert_eq!(cmd.warn_inexact, warn_inexact);
+            cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap())
+        };
+        assert!(run("(* 0.5 (noise))", true).is_ok());
+        assert!(run("(* 0.1 (noise))", false).is_ok());
+        assert!(run("(* 0.1 (noise))", true).is_err());
+    }
+
+    #[test]
+    fn raw_output() {
+        let path = env::temp_dir().join(format!("ultrafxr-raw-{}.pcm", std::process::id()));
+        let cmd = match parse(&[
+            "-endian",
+            "big",
+            "-f",
+            "-write-wav",
+            "-o",
+            path.to_str().unwrap(),
+            "-script",
+            "(* 1V (envelope (set 0.5) (delay 10ms) (stop)))",
+        ]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(cmd.format, None);
+        assert_eq!(cmd.endian, Endian::Big);
+        cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap())
+            .unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // At 48 kHz, 10ms is 480 samples of 0.5, with no header.
+        assert!(data.len() >= 960, "length: {}", data.len());
+        for (i, x) in data[..960].chunks(2).enumerate() {
+            assert_eq!(x, [0x40, 0x00], "sample {}", i);
         }
-        unwrap_write(&filename, writer.finish())?;
-        unwrap_write(&filename, file.sync_all())
+    }
+
+    /// Parse a command which writes a script's output to the given path.
+    fn write_command(path: &Path, script: &str) -> Command {
+        match parse(&[
+            "-f",
+            "-write-wav",
+            "-o",
+            path.to_str().unwrap(),
+            "-script",
+            script,
+        ]) {
+            Ok(Action::Run(cmd)) => *cmd,
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[cfg(feature = "flacenc")]
+    #[test]
+    fn flac_output() {
+        let path = env::temp_dir().join(format!("ultrafxr-flac-{}.flac", std::process::id()));
+        let cmd = write_command(&path, "(* 1V (envelope (set 0.5) (delay 10ms) (stop)))");
+        cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap())
+            .unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(reader.streaminfo().sample_rate, 48000);
+        let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        // At 48 kHz, 10ms is 480 samples of 0.5.
+        assert!(samples.len() >= 480, "length: {}", samples.len());
+        for (i, &x) in samples[..480].iter().enumerate() {
+            assert_eq!(x, 0x4000, "sample {}", i);
+        }
+    }
+
+    #[cfg(not(feature = "flacenc"))]
+    #[test]
+    fn flac_unsupported() {
+        let path = env::temp_dir().join(format!("ultrafxr-flac-{}.flac", std::process::id()));
+        let cmd = write_command(&path, "(noise)");
+        assert!(cmd
+            .run_file(&cmd.files[0], &cmd.render_params().unwrap())
+            .is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn output_format() {
+        let output_paths = |args: &[&str]| -> Vec<Option<OsString>> {
+            match parse(args) {
+                Ok(Action::Run(cmd)) => cmd.files.into_iter().map(|f| f.output_wave).collect(),
+                r => panic!("{:?}", r),
+            }
+        };
+        assert_eq!(
+            output_paths(&["-format", "raw", "-write-wav", "a.lisp"]),
+            [Some(OsString::from("a.raw"))]
+        );
+        assert_eq!(
+            output_paths(&["-format", "raw", "-write-wav", "-script", "(noise)"]),
+            [Some(OsString::from("ultrafxr.raw"))]
+        );
+        assert_eq!(
+            OutputFormat::from_path(OsStr::new("x.pcm")),
+            OutputFormat::Raw
+        );
+        assert_eq!(
+            OutputFormat::from_path(OsStr::new("x.flac")),
+            OutputFormat::Flac
+        );
+        assert_eq!(
+            OutputFormat::from_path(OsStr::new("x.wav")),
+            OutputFormat::Wave
+        );
+        assert!(parse(&["-format", "mp3", "a.lisp"]).is_err());
+        assert!(parse(&["-endian", "middle", "a.lisp"]).is_err());
+    }
+
+    #[test]
+    fn timer() {
+        let report = |enabled: bool| {
+            let mut timer = Timer::new(enabled);
+            timer.stage("evaluate");
+            timer.stage("render");
+            timer.audio_length = Some(1.5);
+            let mut out = Vec::new();
+            timer.report(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let text = report(true);
+        assert!(text.starts_with("evaluate: "), "{:?}", text);
+        assert!(text.contains("\nrender: "), "{:?}", text);
+        assert!(
+            text.contains("rendered 1.50 seconds of audio in "),
+            "{:?}",
+            text
+        );
+        assert_eq!(report(false), "");
+    }
+
+    #[test]
+    fn empty_script() {
+        let path = env::temp_dir().join(format!("ultrafxr-empty-{}.wav", std::process::id()));
+        for &script in ["", " \n "].iter() {
+            let cmd = match parse(&[
+                "-write-wav",
+                "-o",
+                path.to_str().unwrap(),
+                "-script",
+                script,
+            ]) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
+            };
+            assert!(cmd
+                .run_file(&cmd.files[0], &cmd.render_params().unwrap())
+                .is_err());
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn check() {
+        use crate::signal::ops::{Add, Constant};
+        let path = env::temp_dir().join(format!("ultrafxr-check-{}.wav", std::process::id()));
+        let cmd = match parse(&[
+            "-check",
+            "-write-wav",
+            "-o",
+            path.to_str().unwrap(),
+            "-script",
+            "(* 1V (envelope (set 0.5) (delay 100ms) (stop)))",
+        ]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        assert!(cmd
+            .run_file(&cmd.files[0], &cmd.render_params().unwrap())
+            .is_ok());
+        assert!(!path.exists());
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(Constant { value: 1.0 }));
+        let sum = graph.add(Box::new(Add { inputs: [x, x] }));
+        assert!(cmd.check(&graph, sum, &cmd.parameters().unwrap()).is_ok());
+        graph.nodes_mut()[sum.0 as usize].inputs_mut()[1] = sum;
+        assert!(cmd.check(&graph, sum, &cmd.parameters().unwrap()).is_err());
+    }
+
+    #[test]
+    fn repl() {
+        let cmd = match parse(&["-repl"]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        let input = "(define gain 0.5)
+(* (noise) gian)
+(define)
+(* (noise)
+   gain)
+(defun f (x) (* x gain))
+(f (noise))
+";
+        let mut output = Vec::new();
+        cmd.repl(
+            &mut input.as_bytes(),
+            &mut output,
+            &cmd.render_params().unwrap(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // The misspelled name and the empty definition are errors, and the
+        // session continues after them.
+        assert_eq!(
+            output.matches("3 nodes, output #2 (Multiply)\n").count(),
+            2,
+            "{:?}",
+            output
+        );
+        assert!(output.contains("... "), "{:?}", output);
+        assert!(parse(&["-repl", "in.lisp"]).is_err());
+    }
+
+    #[test]
+    fn repl_werror() {
+        let run = |werror: bool| {
+            let mut args = vec!["-repl"];
+            if werror {
+                args.push("-werror");
+            }
+            let cmd = match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
+            };
+            // The unused variable is only a warning.
+            let input = "(let ((x 1)) (noise))\n(noise)\n";
+            let mut output = Vec::new();
+            let result = cmd.repl(
+                &mut input.as_bytes(),
+                &mut output,
+                &cmd.render_params().unwrap(),
+            );
+            (result, String::from_utf8(output).unwrap())
+        };
+        let (result, output) = run(false);
+        assert!(result.is_ok());
+        assert_eq!(output.matches("1 nodes").count(), 2, "{:?}", output);
+        // The expression with the warning fails, and the session continues.
+        let (result, output) = run(true);
+        assert!(result.is_err());
+        assert_eq!(output.matches("1 nodes").count(), 1, "{:?}", output);
+    }
+
+    #[test]
+    fn force() {
+        use crate::signal::envelope::{Envelope, Segment};
+        let path = env::temp_dir().join(format!("ultrafxr-test-{}.wav", std::process::id()));
+        fs::write(&path, b"previous").unwrap();
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(Envelope {
+            segments: Box::from(&[Segment::Set { value: 0.5 }, Segment::Stop][..]),
+        }));
+        let command = |force: bool| {
+            let mut args = vec!["in.lisp", "-write-wav", "-o", path.to_str().unwrap()];
+            if force {
+                args.push("-f");
+            }
+            match parse(&args) {
+                Ok(Action::Run(cmd)) => cmd,
+                r => panic!("{:?}", r),
+            }
+        };
+        let cmd = command(false);
+        let result = cmd.write_wave(
+            path.as_ref(),
+            b"",
+            &graph,
+            root,
+            &cmd.render_params().unwrap(),
+        );
+        let data = fs::read(&path).unwrap();
+        assert!(result.is_err());
+        assert_eq!(&data[..], b"previous");
+        let cmd = command(true);
+        let result = cmd.write_wave(
+            path.as_ref(),
+            b"",
+            &graph,
+            root,
+            &cmd.render_params().unwrap(),
+        );
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(&data[..4], b"RIFF");
+    }
+
+    #[test]
+    fn format_source_file() {
+        let path = env::temp_dir().join(format!("ultrafxr-format-{}.lisp", std::process::id()));
+        let text = "; Tone.\n(sine  (oscillator ; pitch\n  440Hz))\n";
+        let expect = "; Tone.\n(sine\n (oscillator ; pitch\n  440Hz))\n";
+        fs::write(&path, text).unwrap();
+        let cmd = match parse(&["-format-source", path.to_str().unwrap()]) {
+            Ok(Action::Run(cmd)) => cmd,
+            r => panic!("{:?}", r),
+        };
+        let result = cmd.run_file(&cmd.files[0], &cmd.render_params().unwrap());
+        let data = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(data, expect);
+        // Scripts which are not files are written to the output.
+        let file = File {
+            input: Input::String(text.to_string()),
+            output_wave: None,
+        };
+        let mut output = Vec::new();
+        write_formatted(
+            &file,
+            "<arg>",
+            text.as_bytes(),
+            expect.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, expect.as_bytes());
+        assert!(parse(&["-format-source", "-play", "in.lisp"]).is_err());
+        assert!(parse(&["-format-source", "-write-wav", "in.lisp"]).is_err());
     }
 }
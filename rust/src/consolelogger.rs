@@ -1,11 +1,13 @@
 use crate::color::{Style, StyleFlag};
 use crate::error::{ErrorHandler, Severity};
+use crate::include::IncludeHost;
 use crate::sourcepos::Span;
 use crate::sourceprint::write_source;
-use crate::sourcetext::SourceText;
+use crate::sourcetext::SourceMap;
 use std::fmt::Arguments;
 use std::io;
 use std::io::{stderr, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 const MESSAGE: Style<'static> = Style(&[StyleFlag::FgBrightWhite]);
 const RESET: Style<'static> = Style(&[StyleFlag::Reset]);
@@ -34,22 +36,62 @@ pub fn write_diagnostic(w: &mut impl Write, severity: Severity, msg: &str) -> io
     )
 }
 
+/// Minimum severity of messages printed by the error! and warning! macros.
+static MIN_SEVERITY: AtomicU8 = AtomicU8::new(Severity::Warning as u8);
+
+/// Set the minimum severity of messages printed by the error! and warning!
+/// macros. Less severe messages are dropped.
+pub fn set_min_severity(severity: Severity) {
+    MIN_SEVERITY.store(severity as u8, Ordering::Relaxed);
+}
+
+/// True if the warning! macro reports warnings as errors.
+static WARNINGS_AS_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// True if the warning! macro has reported a warning as an error.
+static PROMOTED: AtomicBool = AtomicBool::new(false);
+
+/// Set whether the warning! macro reports warnings as errors.
+pub fn set_warnings_as_errors(value: bool) {
+    WARNINGS_AS_ERRORS.store(value, Ordering::Relaxed);
+}
+
+/// Return true if the warning! macro has reported a warning as an error.
+pub fn promoted_warnings() -> bool {
+    PROMOTED.load(Ordering::Relaxed)
+}
+
+/// Suffix for warnings which are reported as errors.
+const PROMOTED_SUFFIX: &str = " (warning treated as error)";
+
 /// Print a diagnostic message to stderr.
 pub fn _print_diagnostic(severity: Severity, args: Arguments) {
+    let (severity, suffix) =
+        if severity == Severity::Warning && WARNINGS_AS_ERRORS.load(Ordering::Relaxed) {
+            PROMOTED.store(true, Ordering::Relaxed);
+            (Severity::Error, PROMOTED_SUFFIX)
+        } else {
+            (severity, "")
+        };
+    if (severity as u8) < MIN_SEVERITY.load(Ordering::Relaxed) {
+        return;
+    }
     let stderr = stderr();
     let mut handle = stderr.lock();
     writeln!(
         &mut handle,
-        "{}{}{}: {}{}",
+        "{}{}{}: {}{}{}",
         severity_color(severity),
         severity,
         MESSAGE,
         args,
+        suffix,
         RESET,
     )
     .unwrap();
 }
 
+#[macro_export]
 macro_rules! error {
     ($($arg:tt)*) => ({
         $crate::consolelogger::_print_diagnostic(
@@ -59,6 +101,7 @@ macro_rules! error {
     });
 }
 
+#[macro_export]
 macro_rules! warning {
     ($($arg:tt)*) => ({
         $crate::consolelogger::_print_diagnostic(
@@ -68,33 +111,176 @@ macro_rules! warning {
     });
 }
 
-pub struct ConsoleLogger<'a> {
-    text: Result<SourceText<'a>, (&'a str, &'a [u8])>,
+pub struct ConsoleLogger {
+    sources: SourceMap,
+    // Messages less severe than this are dropped.
+    min_severity: Severity,
+    // Report warnings as errors.
+    warnings_as_errors: bool,
+    // True if a warning has been reported as an error.
+    promoted: bool,
 }
 
-impl<'a> ConsoleLogger<'a> {
-    pub fn from_text(filename: &'a str, text: &'a [u8]) -> Self {
-        return ConsoleLogger {
-            text: Err((filename, text)),
-        };
+impl ConsoleLogger {
+    /// Create a logger for diagnostics in a single file, which is tokenized
+    /// starting at the default position.
+    pub fn from_text(filename: &str, text: &[u8]) -> Self {
+        let mut sources = SourceMap::new();
+        // Text which is too large cannot be tokenized either, so there will be
+        // no diagnostics in it.
+        let _ = sources.add(filename, text);
+        ConsoleLogger::from_sources(sources)
     }
-    fn init(&mut self) {
-        match self.text {
-            Ok(_) => (),
-            Err((filename, text)) => self.text = Ok(SourceText::new(filename, text)),
+
+    /// Create a logger for diagnostics in the given source files.
+    pub fn from_sources(sources: SourceMap) -> Self {
+        ConsoleLogger {
+            sources,
+            min_severity: Severity::Warning,
+            warnings_as_errors: false,
+            promoted: false,
         }
     }
+
+    /// Set the minimum severity of messages to print. Less severe messages
+    /// are dropped.
+    pub fn set_min_severity(&mut self, severity: Severity) {
+        self.min_severity = severity;
+    }
+
+    /// Set whether warnings are reported as errors.
+    pub fn set_warnings_as_errors(&mut self, value: bool) {
+        self.warnings_as_errors = value;
+    }
+
+    /// Return true if any warning has been reported as an error.
+    pub fn promoted_warnings(&self) -> bool {
+        self.promoted
+    }
+
+    /// Return true if any warning has been reported as an error since the
+    /// last call, and reset the flag.
+    pub fn take_promoted_warnings(&mut self) -> bool {
+        std::mem::replace(&mut self.promoted, false)
+    }
+
+    /// Write a diagnostic message with the source text it refers to.
+    fn write(
+        &self,
+        w: &mut impl Write,
+        severity: Severity,
+        pos: Span,
+        message: &str,
+    ) -> io::Result<()> {
+        write_diagnostic(w, severity, message)?;
+        if let Some(source_text) = self.sources.file(pos.start) {
+            if let Some(text_pos) = source_text.span(pos) {
+                write_source(w, &source_text, &text_pos)?;
+            }
+        }
+        writeln!(w)
+    }
+
+    /// Write a diagnostic message, unless it is below the minimum severity.
+    fn report(
+        &self,
+        w: &mut impl Write,
+        severity: Severity,
+        pos: Span,
+        message: &str,
+    ) -> io::Result<()> {
+        if severity < self.min_severity {
+            return Ok(());
+        }
+        self.write(w, severity, pos, message)
+    }
+
+    /// Print a diagnostic message with the source text it refers to.
+    fn print(&mut self, severity: Severity, pos: Span, message: &str) {
+        self.report(&mut stderr(), severity, pos, message).unwrap();
+    }
 }
 
-impl<'a> ErrorHandler for ConsoleLogger<'a> {
+impl ErrorHandler for ConsoleLogger {
     fn handle(&mut self, pos: Span, message: &str) {
-        self.init();
-        let source_text = self.text.as_ref().unwrap();
-        let mut stderr = stderr();
-        write_diagnostic(&mut stderr, Severity::Error, message).unwrap();
-        if let Some(text_pos) = source_text.span(pos) {
-            write_source(&mut stderr, &source_text, &text_pos).unwrap();
+        self.print(Severity::Error, pos, message);
+    }
+
+    fn warning(&mut self, pos: Span, message: &str) {
+        if self.warnings_as_errors {
+            self.promoted = true;
+            self.print(
+                Severity::Error,
+                pos,
+                &format!("{}{}", message, PROMOTED_SUFFIX),
+            );
+        } else {
+            self.print(Severity::Warning, pos, message);
         }
-        writeln!(stderr).unwrap();
+    }
+}
+
+impl IncludeHost for ConsoleLogger {
+    /// Get the source files diagnostics can refer to.
+    fn sources_mut(&mut self) -> &mut SourceMap {
+        &mut self.sources
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sourcepos::Pos;
+    use std::str;
+
+    #[test]
+    fn filenames() {
+        let mut sources = SourceMap::new();
+        sources.add("first.lisp", b"(noise)\n").unwrap();
+        let start = sources.add("second.lisp", b"(noise)\n(oops)\n").unwrap();
+        let logger = ConsoleLogger::from_sources(sources);
+        let mut out = Vec::new();
+        let pos = Span {
+            start: start + 9,
+            end: start + 13,
+        };
+        logger
+            .write(&mut out, Severity::Error, pos, "bad oops")
+            .unwrap();
+        let out = str::from_utf8(&out).unwrap();
+        assert!(out.contains("bad oops"), "{}", out);
+        assert!(out.contains("second.lisp:2:1"), "{}", out);
+        assert!(!out.contains("first.lisp"), "{}", out);
+        let mut out = Vec::new();
+        let pos = Span {
+            start: Pos(2),
+            end: Pos(7),
+        };
+        logger
+            .write(&mut out, Severity::Warning, pos, "first")
+            .unwrap();
+        let out = str::from_utf8(&out).unwrap();
+        assert!(out.contains("first.lisp:1:1"), "{}", out);
+    }
+
+    #[test]
+    fn min_severity() {
+        let mut logger = ConsoleLogger::from_text("a.lisp", b"(noise)\n");
+        logger.set_min_severity(Severity::Error);
+        let pos = Span {
+            start: Pos(2),
+            end: Pos(7),
+        };
+        let mut out = Vec::new();
+        logger
+            .report(&mut out, Severity::Warning, pos, "ignored")
+            .unwrap();
+        assert!(out.is_empty(), "{:?}", str::from_utf8(&out));
+        logger
+            .report(&mut out, Severity::Error, pos, "failed")
+            .unwrap();
+        let out = str::from_utf8(&out).unwrap();
+        assert!(out.contains("failed"), "{}", out);
+        assert!(out.contains("a.lisp:1:1"), "{}", out);
     }
 }
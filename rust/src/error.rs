@@ -4,6 +4,12 @@ use std::fmt;
 // An object that handles errors during parsing or evaluation.
 pub trait ErrorHandler {
     fn handle(&mut self, pos: Span, message: &str);
+
+    /// Handle a warning, which does not stop processing. Warnings are ignored
+    /// unless this is implemented.
+    fn warning(&mut self, pos: Span, message: &str) {
+        let _ = (pos, message);
+    }
 }
 
 /// Serevrity level for diagnostic messages.
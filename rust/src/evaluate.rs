@@ -9,42 +9,833 @@ mod environment;
 
 mod builtins;
 mod envelope;
+mod macros;
 
 use environment::*;
 
 /// Evaluate an audio synthesis program.
+///
+/// Nodes are numbered in the order they are added to the graph, which only
+/// depends on the program text. Top-level forms are evaluated in order, the
+/// arguments of each call are evaluated from left to right, and each node is
+/// added after its inputs. Numbers used as signals are added when the call
+/// which uses them is evaluated, after its other arguments. Changing a
+/// program only renumbers nodes added after the change, so adding an
+/// expression at the end keeps the numbers of the nodes before it.
 pub fn evaluate_program(
     err_handler: &mut dyn ErrorHandler,
     program: &[SExpr],
 ) -> Result<(Graph, SignalRef), Failed> {
+    let program = macros::expand_program(err_handler, program)?;
     // Break program into the leading forms and the last form. The last form is
     // considered to be the output, and must produce a value.
     let (last, first) = match program.split_last() {
         None => {
-            err_handler.handle(Span::none(), "empty program");
+            err_handler.handle(
+                Span::none(),
+                "program produced no signal, the program is empty",
+            );
             return Err(Failed);
         }
         Some(x) => x,
     };
     let mut env = Env::new(err_handler, builtins::operators());
-    for form in first.iter() {
+    evaluate_statements(&mut env, first);
+    let value = env.evaluate(last);
+    let signal = match value.into_signal(&mut env, Units::volt(1)) {
+        EvalResult(_, Ok(sig)) => sig,
+        EvalResult(label, Err(e)) => {
+            if e != ValueError::Failed {
+                log_error!(env, label.pos, "invalid program body: {}", e);
+            }
+            return Err(Failed);
+        }
+    };
+    env.warn_unused(signal);
+    env.into_graph().map(|g| (g, signal))
+}
+
+/// Evaluate top-level statements, like definitions, which do not produce a
+/// value. This is used to check statements before a program's output is known.
+#[cfg(feature = "cli")]
+pub fn evaluate_statements_only(
+    err_handler: &mut dyn ErrorHandler,
+    program: &[SExpr],
+) -> Result<(), Failed> {
+    let program = macros::expand_program(err_handler, program)?;
+    let mut env = Env::new(err_handler, builtins::operators());
+    evaluate_statements(&mut env, &program);
+    env.into_graph().map(|_| ())
+}
+
+/// Evaluate top-level statements. Only the last form in a program is its
+/// output, so statements before it which produce a value are errors, rather
+/// than a choice between multiple outputs.
+fn evaluate_statements<'a>(env: &mut Env<'a>, forms: &'a [SExpr]) {
+    for form in forms.iter() {
         match env.evaluate(form).into_void() {
             EvalResult(_, Ok(())) => (),
             EvalResult(label, Err(e)) => match e {
                 ValueError::Failed => (),
-                _ => log_error!(env, label.pos, "invalid top-level statement: {}", e),
+                ValueError::BadType { got, .. } if got.0 != DataType::Void => {
+                    log_error!(
+                        env,
+                        label.pos,
+                        "unused value of type {}, only the last top-level expression is the program output",
+                        got
+                    );
+                }
+                _ => {
+                    log_error!(env, label.pos, "invalid top-level statement: {}", e);
+                }
             },
         }
     }
-    let signal = match env.evaluate(last).into_signal(Units::volt(1)) {
-        EvalResult(_, Ok(sig)) => sig,
-        EvalResult(label, Err(e)) => {
-            match e {
-                ValueError::Failed => (),
-                _ => log_error!(env, label.pos, "invalid program body: {}", e),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{ParseResult, Parser};
+    use crate::signal::graph::Param;
+    use crate::sourcepos::Pos;
+    use crate::token::Tokenizer;
+
+    /// Error handler which records the messages it receives.
+    struct Errors(Vec<(Span, String)>);
+
+    impl ErrorHandler for Errors {
+        fn handle(&mut self, pos: Span, message: &str) {
+            self.0.push((pos, message.to_owned()));
+        }
+    }
+
+    fn evaluate_text(text: &str, errors: &mut Errors) -> Result<(Graph, SignalRef), Failed> {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut parser = Parser::new();
+        let mut exprs = Vec::new();
+        loop {
+            match parser.parse(errors, &mut toks) {
+                ParseResult::None => break,
+                ParseResult::Value(expr) => exprs.push(expr),
+                _ => panic!("could not parse: {:?}", text),
             }
-            return Err(Failed);
         }
-    };
-    env.into_graph().map(|g| (g, signal))
+        evaluate_program(errors, &exprs)
+    }
+
+    /// Error handler which records warnings, and fails on errors.
+    struct Warnings(Vec<(Span, String)>);
+
+    impl ErrorHandler for Warnings {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            panic!("error: {}", message);
+        }
+
+        fn warning(&mut self, pos: Span, message: &str) {
+            self.0.push((pos, message.to_owned()));
+        }
+    }
+
+    fn evaluate_warnings(text: &str) -> Vec<(Span, String)> {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut parser = Parser::new();
+        let mut warnings = Warnings(Vec::new());
+        let mut exprs = Vec::new();
+        while let ParseResult::Value(expr) = parser.parse(&mut warnings, &mut toks) {
+            exprs.push(expr);
+        }
+        evaluate_program(&mut warnings, &exprs).unwrap();
+        warnings.0
+    }
+
+    /// Get the span of the nth occurrence of a substring, counting from zero.
+    fn find_span(text: &str, pattern: &str, n: usize) -> Span {
+        let start = text.match_indices(pattern).nth(n).unwrap().0 as u32 + 1;
+        Span {
+            start: Pos(start),
+            end: Pos(start + pattern.len() as u32),
+        }
+    }
+
+    fn evaluate_ok(text: &str) -> (Graph, SignalRef) {
+        let mut errors = Errors(Vec::new());
+        match evaluate_text(text, &mut errors) {
+            Ok(result) => result,
+            Err(_) => panic!("evaluation failed: {:?}", errors.0),
+        }
+    }
+
+    fn dump(graph: &Graph) -> Vec<String> {
+        let mut out = Vec::new();
+        graph.dump(&mut out);
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn stable_ids() {
+        let base = "(define osc (oscillator (note 0)))
+                    (define env (envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0) (stop)))
+                    (* (sine osc) env)";
+        let (graph, _) = evaluate_ok(base);
+        let expect = dump(&graph);
+        // Evaluating again gives the same numbering.
+        assert_eq!(dump(&evaluate_ok(base).0), expect);
+        // Expressions added at the end do not renumber earlier nodes.
+        let extended = [
+            "(define osc (oscillator (note 0)))
+             (define env (envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0) (stop)))
+             (* (* (sine osc) env) (envelope (set 0.5)))",
+            "(define osc (oscillator (note 0)))
+             (define env (envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0) (stop)))
+             (define out (* (sine osc) env))
+             (define osc2 (oscillator (note 12)))
+             (+ out (* (sawtooth osc2) env))",
+        ];
+        for text in extended.iter() {
+            let (graph, _) = evaluate_ok(text);
+            let nodes = dump(&graph);
+            assert!(nodes.len() > expect.len());
+            assert_eq!(&nodes[..expect.len()], &expect[..], "{}", text);
+        }
+    }
+
+    #[test]
+    fn single_root() {
+        let (graph, root) = evaluate_ok("(define x (noise)) (* x 0.5)");
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Multiply");
+        let text = "(noise) (define x 1) (sine (oscillator 440Hz))";
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        let (pos, msg) = &errors.0[0];
+        assert_eq!(*pos, find_span(text, "(noise)", 0));
+        assert!(msg.contains("only the last"), "{:?}", msg);
+    }
+
+    #[test]
+    fn let_shared() {
+        let (graph, root) = evaluate_ok(
+            "(let ((env (envelope (set 1.0) (stop)))) (* (* (sine (oscillator (note 0))) env) env))",
+        );
+        let nodes = graph.nodes();
+        let outer = nodes[root.0 as usize].inputs();
+        let inner = nodes[outer[0].0 as usize].inputs();
+        assert_eq!(outer[1], inner[1]);
+        let envelopes = nodes.iter().filter(|n| n.op_name() == "Envelope").count();
+        assert_eq!(envelopes, 1);
+    }
+
+    #[test]
+    fn let_shadow() {
+        let (graph, root) = evaluate_ok(
+            "(define x (envelope (set 1.0) (stop)))
+             (let ((x (noise))) x)",
+        );
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Noise");
+        let (graph, root) = evaluate_ok(
+            "(define x (noise))
+             (let ((y (let ((x (envelope (set 1.0) (stop)))) x))) x)",
+        );
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Noise");
+    }
+
+    #[test]
+    fn let_unbound() {
+        let text = "(define x (let ((y (noise))) y)) (* y x)";
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(define y (let ((x (noise))) x)) x", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1);
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text(text, &mut errors).is_err());
+        // Source positions start at 1.
+        let start = text.rfind('y').unwrap() as u32 + 1;
+        let span = Span {
+            start: Pos(start),
+            end: Pos(start + 1),
+        };
+        assert!(
+            errors.0.iter().any(|(pos, _)| *pos == span),
+            "{:?}",
+            errors.0
+        );
+    }
+
+    #[test]
+    fn defun_expand() {
+        let (graph, root) = evaluate_ok(
+            "(defun gain (s k) (* s k))
+             (gain (sine (oscillator (note 0))) 0.5)",
+        );
+        let nodes = graph.nodes();
+        let names: Vec<&str> = nodes.iter().map(|n| n.op_name()).collect();
+        assert_eq!(
+            names,
+            [
+                "Note",
+                "Oscillator",
+                "ApplyFunction",
+                "Constant",
+                "Multiply"
+            ]
+        );
+        assert_eq!(root, SignalRef(4));
+        assert_eq!(nodes[4].inputs(), [SignalRef(2), SignalRef(3)]);
+    }
+
+    #[test]
+    fn defun_errors() {
+        let text = "(defun gain (s k) (* s k)) (gain (noise))";
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        let (pos, msg) = &errors.0[0];
+        assert_eq!(pos.start, Pos(text.rfind("gain").unwrap() as u32 + 1));
+        assert!(msg.contains("gain"), "{:?}", msg);
+
+        let mut errors = Errors(Vec::new());
+        let text = "(defun f (x) (g x)) (defun g (x) (f x)) (f (noise))";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert!(
+            errors.0.iter().any(|(_, msg)| msg.contains("recursive")),
+            "{:?}",
+            errors.0
+        );
+    }
+
+    #[test]
+    fn defun_shadow() {
+        // Calling a different function with the same name is not recursion.
+        // Here, the f inside g is not in scope where the outer f is defined,
+        // and the outer f is not in scope inside g.
+        let (graph, _) = evaluate_ok(
+            "(defun g (x) (defun f (y) (* y 0.5)) (f x))
+             (let ((s (noise)))
+               (defun f () (g s))
+               (f))",
+        );
+        assert_eq!(op_names(&graph), ["Noise", "Constant", "Multiply"]);
+        // A function which calls itself through an inner definition is still
+        // recursive.
+        let mut errors = Errors(Vec::new());
+        let text = "(defun f (x) (defun g (y) (f y)) (g x)) (f (noise))";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert!(
+            errors.0.iter().any(|(_, msg)| msg.contains("recursive")),
+            "{:?}",
+            errors.0
+        );
+    }
+
+    #[test]
+    fn scope_capture() {
+        // A function sees the variables where it is defined.
+        let (graph, root) = evaluate_ok(
+            "(let ((s (noise)))
+               (defun f (k) (* s k))
+               (f 0.5))",
+        );
+        assert_eq!(op_names(&graph), ["Noise", "Constant", "Multiply"]);
+        assert_eq!(graph.nodes()[root.0 as usize].inputs()[0], SignalRef(0));
+        // Inner definitions shadow the variables it captures.
+        let (graph, root) = evaluate_ok(
+            "(define s (envelope (set 1.0) (stop)))
+             (let ((s (noise)))
+               (defun f () s)
+               (let ((s (sine (oscillator (note 0)))))
+                 (f)))",
+        );
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Noise");
+        // A function defined in a scope is not visible outside it.
+        let mut errors = Errors(Vec::new());
+        let text = "(define x (let ((s (noise))) (defun f () s) (f))) (f)";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        let (pos, msg) = &errors.0[0];
+        assert_eq!(pos.start, Pos(text.rfind('f').unwrap() as u32 + 1));
+        assert!(msg.contains("undefined function"), "{:?}", msg);
+    }
+
+    #[test]
+    fn scope_call_site() {
+        // A function does not see the variables where it is called.
+        let mut errors = Errors(Vec::new());
+        let text = "(defun f () s) (let ((s (noise))) (f))";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        let (pos, msg) = &errors.0[0];
+        assert_eq!(*pos, find_span(text, "s", 0));
+        assert!(msg.contains("undefined symbol"), "{:?}", msg);
+    }
+
+    #[test]
+    fn scope_order() {
+        // A variable cannot be used before it is defined.
+        let mut errors = Errors(Vec::new());
+        let text = "(define y x) (define x (noise)) x";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        assert_eq!(errors.0[0].0, find_span(text, "x", 0));
+        // A function body is evaluated when it is called, so it sees global
+        // variables defined after the function.
+        let (graph, root) = evaluate_ok("(defun f () x) (define x (noise)) (f)");
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Noise");
+    }
+
+    fn op_names(graph: &Graph) -> Vec<&str> {
+        graph.nodes().iter().map(|n| n.op_name()).collect()
+    }
+
+    #[test]
+    fn defmacro_expand() {
+        let (graph, root) = evaluate_ok(
+            "(defmacro (twice x) (+ x x))
+             (twice (sine (oscillator (note 0))))",
+        );
+        let (expect, expect_root) =
+            evaluate_ok("(+ (sine (oscillator (note 0))) (sine (oscillator (note 0))))");
+        assert_eq!(op_names(&graph), op_names(&expect));
+        assert_eq!(root, expect_root);
+        // Names bound in the macro body do not capture names in the arguments.
+        let (graph, root) = evaluate_ok(
+            "(defmacro (with-noise x) (let ((n (noise))) (+ n x)))
+             (let ((n (sine (oscillator (note 0))))) (with-noise n))",
+        );
+        let nodes = graph.nodes();
+        let inputs = nodes[root.0 as usize].inputs();
+        let mut names: Vec<&str> = inputs
+            .iter()
+            .map(|i| nodes[i.0 as usize].op_name())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["ApplyFunction", "Noise"]);
+    }
+
+    #[test]
+    fn defmacro_recursive() {
+        let text = "(defmacro (f x) (+ (f x) x)) (f (noise))";
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        assert!(errors.0[0].1.contains("recursive"), "{:?}", errors.0);
+    }
+
+    /// Get the constant value of a node in the graph, if it is constant.
+    fn constant_value(graph: &Graph, sig: SignalRef) -> Option<f32> {
+        graph.nodes()[sig.0 as usize].fold(&[])
+    }
+
+    #[test]
+    fn int_parameter() {
+        let (graph, root) = evaluate_ok("(sine (overtone 2 (oscillator 440Hz)))");
+        let scale = graph.nodes()[root.0 as usize].inputs()[0];
+        let node = &graph.nodes()[scale.0 as usize];
+        assert_eq!(node.op_name(), "ScaleInt");
+        assert_eq!(node.parameters(), [("scale", Param::Int(2))]);
+        for (text, arg, msg) in [
+            (
+                "(sine (overtone 2.5 (oscillator 440Hz)))",
+                "2.5",
+                "expected int",
+            ),
+            (
+                "(sine (overtone 0x100000000 (oscillator 440Hz)))",
+                "0x100000000",
+                "out of range",
+            ),
+            (
+                "(sine (oscillator (note 0x100000000)))",
+                "0x100000000",
+                "out of range",
+            ),
+            (
+                "(sine (oscillator (note -3000000000st)))",
+                "-3000000000st",
+                "out of range",
+            ),
+        ]
+        .iter()
+        {
+            let mut errors = Errors(Vec::new());
+            assert!(evaluate_text(text, &mut errors).is_err(), "{}", text);
+            assert_eq!(errors.0.len(), 1, "{}: {:?}", text, errors.0);
+            let (pos, message) = &errors.0[0];
+            assert_eq!(*pos, find_span(text, arg, 0), "{}", text);
+            assert!(message.contains(msg), "{}: {}", text, message);
+        }
+    }
+
+    #[test]
+    fn unit_literals() {
+        let (graph, root) = evaluate_ok("(sine (oscillator 2kHz))");
+        let osc = graph.nodes()[root.0 as usize].inputs()[0];
+        let freq = graph.nodes()[osc.0 as usize].inputs()[0];
+        assert_eq!(constant_value(&graph, freq), Some(2000.0));
+        let (mut graph, root) = evaluate_ok("(sine (oscillator (/ 1 250ms)))");
+        graph.fold_constants();
+        let osc = graph.nodes()[root.0 as usize].inputs()[0];
+        let freq = graph.nodes()[osc.0 as usize].inputs()[0];
+        assert_eq!(constant_value(&graph, freq), Some(4.0));
+        let (graph, root) = evaluate_ok("(sine (oscillator (note 3st)))");
+        let osc = graph.nodes()[root.0 as usize].inputs()[0];
+        let note = graph.nodes()[osc.0 as usize].inputs()[0];
+        assert_eq!(graph.nodes()[note.0 as usize].op_name(), "Note");
+        let (graph, root) = evaluate_ok("(* (noise) (semitone 12st))");
+        let ratio = graph.nodes()[root.0 as usize].inputs()[1];
+        assert_eq!(constant_value(&graph, ratio), Some(2.0));
+        for text in [
+            "(sine (oscillator 250ms))",
+            "(sine (oscillator (note 3dB)))",
+            "(* (noise) (semitone 3Hz))",
+        ]
+        .iter()
+        {
+            let mut errors = Errors(Vec::new());
+            assert!(evaluate_text(text, &mut errors).is_err(), "{}", text);
+            assert_eq!(errors.0.len(), 1, "{}: {:?}", text, errors.0);
+        }
+    }
+
+    #[test]
+    fn literal_signals() {
+        // Numbers are converted to constant signals, keeping their units.
+        let cases: &[(&str, usize, f32)] = &[
+            ("(+ (noise) 0.25V)", 1, 0.25),
+            ("(+ (noise) 1V)", 1, 1.0),
+            ("(* 0.5 (noise))", 0, 0.5),
+            ("(* (noise) 2)", 1, 2.0),
+        ];
+        for &(text, input, value) in cases.iter() {
+            let (graph, root) = evaluate_ok(text);
+            let sig = graph.nodes()[root.0 as usize].inputs()[input];
+            assert_eq!(constant_value(&graph, sig), Some(value), "{}", text);
+        }
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(+ (noise) 1)", &mut errors).is_err());
+    }
+
+    #[test]
+    fn note_name_frequency() {
+        let (graph, root) = evaluate_ok("(sine A4)");
+        let nodes = graph.nodes();
+        let osc = nodes[root.0 as usize].inputs()[0];
+        let pitch = &nodes[nodes[osc.0 as usize].inputs()[0].0 as usize];
+        assert_eq!(pitch.op_name(), "Pitch");
+        assert_eq!(pitch.parameters(), [("note", Param::Int(69))]);
+        // A binding with the same name wins.
+        let (graph, _) = evaluate_ok("(let ((A4 (note 12))) (sine A4))");
+        assert_eq!(op_names(&graph), ["Note", "Oscillator", "ApplyFunction"]);
+        // Names without an octave are not notes.
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(sine A)", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+    }
+
+    #[test]
+    fn variadic_identity() {
+        for &(op, identity) in [("+", 0.0), ("*", 1.0)].iter() {
+            let (graph, root) = evaluate_ok(&format!("(* (noise) ({}))", op));
+            let inputs = graph.nodes()[root.0 as usize].inputs();
+            assert_eq!(
+                constant_value(&graph, inputs[1]),
+                Some(identity),
+                "({})",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn variadic_single() {
+        for &op in ["+", "*"].iter() {
+            let (graph, root) = evaluate_ok(&format!("({} (noise))", op));
+            assert_eq!(graph.nodes().len(), 1, "({} a)", op);
+            assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Noise");
+        }
+    }
+
+    #[test]
+    fn variadic_chain() {
+        let cases = [
+            ("(+ (noise) (noise) (noise))", "Add", "Noise"),
+            ("(* (noise) 0.5 0.25)", "Multiply", "Constant"),
+        ];
+        for &(text, name, last) in cases.iter() {
+            let (graph, root) = evaluate_ok(text);
+            let nodes = graph.nodes();
+            let outer = &nodes[root.0 as usize];
+            assert_eq!(outer.op_name(), name, "{}", text);
+            let inner = &nodes[outer.inputs()[0].0 as usize];
+            assert_eq!(inner.op_name(), name, "{}", text);
+            assert_eq!(nodes[inner.inputs()[0].0 as usize].op_name(), "Noise");
+            assert_eq!(nodes[inner.inputs()[1].0 as usize].op_name(), last);
+            assert_eq!(nodes[outer.inputs()[1].0 as usize].op_name(), last);
+        }
+    }
+
+    /// Evaluate an expression which produces a constant scalar.
+    fn evaluate_constant(text: &str) -> Option<f32> {
+        let (graph, root) = evaluate_ok(&format!("(* (noise) {})", text));
+        let inputs = graph.nodes()[root.0 as usize].inputs();
+        constant_value(&graph, inputs[1])
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(evaluate_constant("(db 0)"), Some(1.0));
+        let x = evaluate_constant("(db -6)").unwrap();
+        assert!((x - 0.501).abs() < 0.001, "(db -6) = {}", x);
+        assert_eq!(evaluate_constant("(semitone 12)"), Some(2.0));
+        assert_eq!(evaluate_constant("(semitone -12)"), Some(0.5));
+        let (graph, root) = evaluate_ok("(* (noise) (db (/ (noise) (noise))))");
+        let inputs = graph.nodes()[root.0 as usize].inputs();
+        let node = &graph.nodes()[inputs[1].0 as usize];
+        assert_eq!(node.op_name(), "ApplyFunction");
+    }
+
+    #[test]
+    fn pow_clamp() {
+        for &(text, name) in [
+            ("(pow 2 (/ (noise) (noise)))", "Power"),
+            ("(clamp (/ (noise) (noise)) -0.5 0.5)", "Clamp"),
+        ]
+        .iter()
+        {
+            let (graph, root) = evaluate_ok(&format!("(* (noise) {})", text));
+            let inputs = graph.nodes()[root.0 as usize].inputs();
+            assert_eq!(graph.nodes()[inputs[1].0 as usize].op_name(), name);
+        }
+        let mut errors = Errors(Vec::new());
+        let text = "(* (noise) (clamp (/ (noise) (noise)) 0.5 -0.5))";
+        assert!(evaluate_text(text, &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        assert!(errors.0[0].1.contains("clamp"), "{:?}", errors.0);
+    }
+
+    #[test]
+    fn rectify_mode() {
+        for &(text, mode) in [
+            ("(rectify (noise))", "Full"),
+            ("(rectify full (noise))", "Full"),
+            ("(rectify half (noise))", "Half"),
+        ]
+        .iter()
+        {
+            let (graph, root) = evaluate_ok(text);
+            let node = &graph.nodes()[root.0 as usize];
+            assert_eq!(node.op_name(), "Rectify", "{}", text);
+            assert_eq!(node.parameters(), [("mode", Param::Mode(mode))], "{}", text);
+        }
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(rectify quarter (noise))", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+        assert!(errors.0[0].1.contains("quarter"), "{:?}", errors.0);
+    }
+
+    #[test]
+    fn saturate_mode() {
+        for &(text, mode) in [
+            ("(saturate (noise))", "Tanh"),
+            ("(saturate atan (noise))", "Atan"),
+            ("(saturate cubic (noise))", "Cubic"),
+        ]
+        .iter()
+        {
+            let (graph, root) = evaluate_ok(text);
+            let node = &graph.nodes()[root.0 as usize];
+            assert_eq!(node.op_name(), "Saturate", "{}", text);
+            assert_eq!(node.parameters(), [("mode", Param::Mode(mode))], "{}", text);
+        }
+    }
+
+    #[test]
+    fn crush() {
+        let (graph, root) = evaluate_ok("(crush (noise) 30 8kHz)");
+        let node = &graph.nodes()[root.0 as usize];
+        assert_eq!(node.op_name(), "Crush");
+        assert_eq!(
+            node.parameters(),
+            [("bits", Param::Int(24)), ("rate", Param::Float(8000.0))]
+        );
+        // Bit depths too large for 32 bits are clamped too.
+        let (graph, root) = evaluate_ok("(crush (noise) 0x7fffffffff 8kHz)");
+        assert_eq!(
+            graph.nodes()[root.0 as usize].parameters()[0],
+            ("bits", Param::Int(24))
+        );
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(crush (noise) 8 0Hz)", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+    }
+
+    #[test]
+    fn mix() {
+        let (graph, root) = evaluate_ok("(mix 0.5 (noise) -6dB (noise) 2 (noise))");
+        let node = &graph.nodes()[root.0 as usize];
+        assert_eq!(node.op_name(), "MixN");
+        assert_eq!(node.inputs().len(), 3);
+        let gains: Vec<f64> = node
+            .parameters()
+            .iter()
+            .map(|&(_, value)| match value {
+                Param::Float(x) => x,
+                _ => panic!("unexpected parameter: {:?}", value),
+            })
+            .collect();
+        assert_eq!(gains.len(), 3);
+        assert_eq!(gains[0], 0.5);
+        assert!((gains[1] - 0.501).abs() < 0.001, "gain = {}", gains[1]);
+        assert_eq!(gains[2], 2.0);
+    }
+
+    #[test]
+    fn param() {
+        let (graph, root) = evaluate_ok("(* (noise) (param velocity))");
+        let inputs = graph.nodes()[root.0 as usize].inputs();
+        let node = &graph.nodes()[inputs[1].0 as usize];
+        assert_eq!(node.op_name(), "Parameter");
+        assert_eq!(node.parameters(), [("name", Param::Mode("velocity"))]);
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(* (noise) (param 1))", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+    }
+
+    #[test]
+    fn glide() {
+        let (graph, root) = evaluate_ok("(sine (glide (note 0) 50ms))");
+        let phase = graph.nodes()[root.0 as usize].inputs()[0];
+        let freq = graph.nodes()[phase.0 as usize].inputs()[0];
+        let node = &graph.nodes()[freq.0 as usize];
+        assert_eq!(node.op_name(), "Glide");
+        assert_eq!(node.parameters(), [("time", Param::Float(0.05))]);
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(sine (glide (note 0) -1s))", &mut errors).is_err());
+        assert_eq!(errors.0.len(), 1, "{:?}", errors.0);
+    }
+
+    #[test]
+    fn sample_hold_slew() {
+        let (graph, root) = evaluate_ok("(slew (sample-hold (noise) (noise)) 10 20)");
+        let node = &graph.nodes()[root.0 as usize];
+        assert_eq!(node.op_name(), "Slew");
+        assert_eq!(
+            node.parameters(),
+            [("rise", Param::Float(10.0)), ("fall", Param::Float(20.0))]
+        );
+        let input = node.inputs()[0];
+        assert_eq!(graph.nodes()[input.0 as usize].op_name(), "SampleHold");
+    }
+
+    #[test]
+    fn sync() {
+        let (graph, root) = evaluate_ok("(sawtooth (sync (note 7) (sine (oscillator (note 0)))))");
+        let phase = graph.nodes()[root.0 as usize].inputs()[0];
+        assert_eq!(graph.nodes()[phase.0 as usize].op_name(), "SyncOscillator");
+    }
+
+    #[test]
+    fn pulse() {
+        let (graph, root) = evaluate_ok("(square (oscillator (note 0)))");
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Pulse");
+        let (graph, root) = evaluate_ok("(pulse (oscillator (note 0)) 0.25)");
+        assert_eq!(graph.nodes()[root.0 as usize].op_name(), "Pulse");
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text("(pulse (oscillator (note 0)) 1s)", &mut errors).is_err());
+    }
+
+    #[test]
+    fn unused_variable() {
+        let text = "(let ((x (noise)) (y (noise))) y)";
+        let warnings = evaluate_warnings(text);
+        assert_eq!(
+            warnings,
+            vec![(find_span(text, "x", 0), "unused variable: \"x\"".to_owned())]
+        );
+        let text = "(define gain 0.5) (define unused 2) (* (noise) gain)";
+        let warnings = evaluate_warnings(text);
+        assert_eq!(
+            warnings,
+            vec![(
+                find_span(text, "unused", 0),
+                "unused variable: \"unused\"".to_owned()
+            )]
+        );
+        assert!(evaluate_warnings("(let ((x (noise))) x)").is_empty());
+    }
+
+    #[test]
+    fn unused_signal() {
+        let text = "(defun first (a b) a) (first (noise) (sine (oscillator (note 0))))";
+        let warnings = evaluate_warnings(text);
+        let start = text.find("(sine").unwrap();
+        let end = start + "(sine (oscillator (note 0)))".len();
+        let span = Span {
+            start: Pos(start as u32 + 1),
+            end: Pos(end as u32 + 1),
+        };
+        assert_eq!(warnings, vec![(span, "signal is not used".to_owned())]);
+    }
+
+    /// Evaluate text which fails, and return the error messages.
+    fn evaluate_errors(text: &str) -> Vec<String> {
+        let mut errors = Errors(Vec::new());
+        assert!(evaluate_text(text, &mut errors).is_err());
+        errors.0.drain(..).map(|(_, message)| message).collect()
+    }
+
+    #[test]
+    fn did_you_mean() {
+        assert_eq!(
+            evaluate_errors("(sawtoth (oscillator (note 0)))"),
+            vec!["undefined function or macro: \"sawtoth\"; did you mean \"sawtooth\"?"]
+        );
+        assert_eq!(
+            evaluate_errors("(defun gain (s) s) (gian (noise))"),
+            vec!["undefined function or macro: \"gian\"; did you mean \"gain\"?"]
+        );
+        assert_eq!(
+            evaluate_errors("(let ((level (noise))) levle)"),
+            vec!["undefined symbol: \"levle\"; did you mean \"level\"?"]
+        );
+        assert_eq!(
+            evaluate_errors("(zzzzzzzz (noise))"),
+            vec!["undefined function or macro: \"zzzzzzzz\""]
+        );
+    }
+
+    #[test]
+    fn bad_args() {
+        for &(text, message) in [
+            ("(sine)", "invalid call to sine: got 0 args, expected 1"),
+            ("(noise 1)", "invalid call to noise: got 1 args, expected 0"),
+            (
+                "(- (noise) (noise) (noise))",
+                "invalid call to -: got 3 args, expected 2",
+            ),
+            (
+                "(mix)",
+                "invalid call to mix: got 0 args, expected at least 2",
+            ),
+            (
+                "(lowPass2 (noise) 1 0.7)",
+                "invalid value for frequency: type is int(scalar), expected signal(Hz)",
+            ),
+            (
+                "(db (noise))",
+                "invalid value for input: type is signal(V), expected signal(scalar)",
+            ),
+            (
+                "(envelope (lin (noise) 1.0))",
+                "invalid value for time: type is signal(V), expected float(s)",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(evaluate_errors(text), vec![message], "{}", text);
+        }
+    }
 }
@@ -1,22 +1,18 @@
 use super::envelope::envelope;
 use super::environment::*;
-use crate::sexpr::SExpr;
+use crate::sexpr::{Content, SExpr, Type as EType};
 use crate::signal::filter;
 use crate::signal::graph::{Node, SignalRef};
 use crate::signal::ops;
 use crate::sourcepos::{HasPos, Span};
+use crate::symbol::Symbol;
 use crate::units::Units;
 use std::collections::hash_map::{HashMap, RandomState};
-use std::convert::TryFrom;
 
-pub fn operators() -> HashMap<&'static str, Operator, RandomState> {
+pub fn operators() -> HashMap<Symbol, Operator, RandomState> {
     let mut map = HashMap::new();
-    fn add(
-        map: &mut HashMap<&'static str, Operator, RandomState>,
-        name: &'static str,
-        value: Operator,
-    ) {
-        if map.insert(name, value).is_some() {
+    fn add(map: &mut HashMap<Symbol, Operator, RandomState>, name: &'static str, value: Operator) {
+        if map.insert(Symbol::new(name), value).is_some() {
             panic!("duplicate operator name: {:?}", name);
         }
     }
@@ -36,14 +32,26 @@ pub fn operators() -> HashMap<&'static str, Operator, RandomState> {
     operators!(
         Macro,
         "define" => define,
+        "defun" => defun,
         "envelope" => envelope,
+        "let" => let_,
+        "param" => param,
+        "rectify" => rectify,
+        "saturate" => saturate,
     );
     operators!(
         Function,
         "*" => multiply,
+        "+" => add_,
+        "-" => subtract,
+        "/" => divide,
         "note" => note,
+        "velocity" => velocity,
         "oscillator" => oscillator,
+        "sync" => sync,
         "sawtooth" => sawtooth,
+        "pulse" => pulse,
+        "square" => square,
         "sine" => sine,
         "noise" => noise,
         "highPass" => high_pass,
@@ -51,12 +59,19 @@ pub fn operators() -> HashMap<&'static str, Operator, RandomState> {
         "highPass2" => high_pass_2,
         "bandPass2" => band_pass_2,
         "lowPass4" => low_pass_4,
-        "saturate" => saturate,
-        "rectify" => rectify,
+        "glide" => glide,
+        "slew" => slew,
+        "sample-hold" => sample_hold,
         "frequency" => frequency,
+        "db" => db,
+        "semitone" => semitone,
+        "pow" => pow,
+        "clamp" => clamp,
+        "crush" => crush,
         "mix" => mix,
         "phase-mod" => phase_mod,
         "overtone" => overtone,
+        "scale-int" => scale_int,
     );
     map
 }
@@ -88,26 +103,259 @@ fn define<'a>(env: &mut Env<'a>, _pos: Span, args: &'a [SExpr]) -> OpResult {
     };
     let mut name = name.and_then(get_symbol);
     match name.value() {
-        Some(nameval) => {
-            if env.variables.contains_key(nameval) {
-                name.1 = error!(
-                    env,
-                    name.source_pos(),
-                    "a variable named {:?} is already defined",
-                    nameval
-                );
-            }
+        Some(nameval) if env.is_defined_in_scope(nameval) => {
+            name.1 = error!(
+                env,
+                name.source_pos(),
+                "a variable named {:?} is already defined",
+                nameval
+            );
         }
         _ => (),
     };
+    let name_pos = name.source_pos();
     let name = name.unwrap(env);
     let value = value.evaluate(env).into_nonvoid().unwrap(env);
     let name = name?;
-    env.variables.insert(name, value);
+    env.bind(name, name_pos, value);
     value?;
     Ok(Value::void())
 }
 
+fn defun<'a>(env: &mut Env<'a>, _pos: Span, args: &'a [SExpr]) -> OpResult {
+    let (name, params, body) = match args {
+        [name, params, body @ ..] if !body.is_empty() => (macro_arg("name", name), params, body),
+        _ => {
+            return Err(OpError::BadNArgs {
+                got: args.len(),
+                min: 3,
+                max: None,
+            });
+        }
+    };
+    let mut name = name.and_then(get_symbol);
+    if let Some(nameval) = name.value() {
+        if env.is_function_defined(nameval) {
+            name.1 = error!(
+                env,
+                name.source_pos(),
+                "a function or macro named {:?} is already defined",
+                nameval
+            );
+        }
+    }
+    let name = name.unwrap(env);
+    let params = match &params.content {
+        Content::List(items) => {
+            let mut names: Vec<Symbol> = Vec::with_capacity(items.len());
+            let mut failed = false;
+            for (n, item) in items.iter().enumerate() {
+                let mut param = macro_arg("param", item).and_then(get_symbol);
+                param.0.index = n + 1;
+                if let Some(paramval) = param.value() {
+                    if names.contains(&paramval) {
+                        param.1 = error!(
+                            env,
+                            param.source_pos(),
+                            "parameter {:?} appears more than once",
+                            paramval
+                        );
+                    }
+                }
+                match param.unwrap(env) {
+                    Ok(param) => names.push(param),
+                    Err(Failed) => failed = true,
+                }
+            }
+            if failed {
+                Err(Failed)
+            } else {
+                Ok(names)
+            }
+        }
+        _ => error!(
+            env,
+            params.source_pos(),
+            "invalid function parameters: {}",
+            ValueError::BadEType {
+                got: params.get_type(),
+                expect: EType::List,
+            }
+        ),
+    };
+    let (name, params) = (name?, params?);
+    env.define_function(name, UserFunction { params, body });
+    Ok(Value::void())
+}
+
+fn let_<'a>(env: &mut Env<'a>, _pos: Span, args: &'a [SExpr]) -> OpResult {
+    let (bindings, body) = match args.split_first() {
+        Some((bindings, body)) if !body.is_empty() => (bindings, body),
+        _ => {
+            return Err(OpError::BadNArgs {
+                got: args.len(),
+                min: 2,
+                max: None,
+            });
+        }
+    };
+    let bindings = match &bindings.content {
+        Content::List(items) => items,
+        _ => {
+            return error!(
+                env,
+                bindings.source_pos(),
+                "invalid let bindings: {}",
+                ValueError::BadEType {
+                    got: bindings.get_type(),
+                    expect: EType::List,
+                }
+            )
+        }
+    };
+    // Evaluate all values in the outer scope, so bindings cannot refer to each
+    // other.
+    let mut values: Vec<(Symbol, Span, Result<Value, Failed>)> = Vec::with_capacity(bindings.len());
+    let mut failed = false;
+    for binding in bindings.iter() {
+        let (name, value) = match &binding.content {
+            Content::List(items) if items.len() == 2 => {
+                (macro_arg("name", &items[0]), macro_arg("value", &items[1]))
+            }
+            _ => {
+                log_error!(
+                    env,
+                    binding.source_pos(),
+                    "invalid let binding, expected (name value)"
+                );
+                failed = true;
+                continue;
+            }
+        };
+        let mut name = name.and_then(get_symbol);
+        if let Some(nameval) = name.value() {
+            if values.iter().any(|&(other, _, _)| other == nameval) {
+                name.1 = error!(
+                    env,
+                    name.source_pos(),
+                    "variable {:?} is bound more than once",
+                    nameval
+                );
+            }
+        }
+        let name_pos = name.source_pos();
+        let name = name.unwrap(env);
+        let value = value.evaluate(env).into_nonvoid().unwrap(env);
+        match name {
+            Ok(name) => values.push((name, name_pos, value)),
+            Err(Failed) => failed = true,
+        }
+    }
+    if failed {
+        return Err(OpError::Failed);
+    }
+    env.push_scope();
+    for (name, pos, value) in values.drain(..) {
+        env.bind(name, pos, value);
+    }
+    let result = env.evaluate_body(body, "let body");
+    env.pop_scope();
+    Ok(result?)
+}
+
+fn param<'a>(env: &mut Env<'a>, pos: Span, args: &'a [SExpr]) -> OpResult {
+    let name = match args {
+        [name] => macro_arg("name", name),
+        _ => {
+            return Err(OpError::BadNArgs {
+                got: args.len(),
+                min: 1,
+                max: Some(1),
+            });
+        }
+    };
+    let name = name.and_then(get_symbol).unwrap(env)?;
+    new_node(
+        env,
+        pos,
+        Units::scalar(),
+        ops::Parameter {
+            name: Box::from(name.as_str()),
+        },
+    )
+}
+
+/// Parse the arguments to a macro which takes an optional mode name followed by
+/// an input, like (rectify half x). The mode defaults to the given value.
+fn mode_args<'a, T: Copy>(
+    env: &mut Env<'a>,
+    args: &'a [SExpr],
+    modes: &[(&str, T)],
+    default: T,
+) -> Result<(T, EvalResult<Value>), OpError> {
+    let (mode, input) = match args {
+        [input] => return Ok((default, macro_arg("input", input).evaluate(env))),
+        [mode, input] => (macro_arg("mode", mode), macro_arg("input", input)),
+        _ => {
+            return Err(OpError::BadNArgs {
+                got: args.len(),
+                min: 1,
+                max: Some(2),
+            });
+        }
+    };
+    let mut mode = mode.and_then(get_symbol);
+    let mut value = default;
+    if let Some(name) = mode.value() {
+        match modes.iter().find(|&&(mname, _)| mname == name.as_str()) {
+            Some(&(_, mvalue)) => value = mvalue,
+            None => {
+                let names: Vec<&str> = modes.iter().map(|&(mname, _)| mname).collect();
+                mode.1 = error!(
+                    env,
+                    mode.source_pos(),
+                    "unknown mode {:?}, expected one of: {}",
+                    name,
+                    names.join(", ")
+                );
+            }
+        }
+    }
+    let mode = mode.unwrap(env);
+    let input = input.evaluate(env);
+    mode?;
+    Ok((value, input))
+}
+
+fn rectify<'a>(env: &mut Env<'a>, pos: Span, args: &'a [SExpr]) -> OpResult {
+    let (mode, input) = mode_args(
+        env,
+        args,
+        &[
+            ("half", ops::RectifyMode::Half),
+            ("full", ops::RectifyMode::Full),
+        ],
+        ops::RectifyMode::Full,
+    )?;
+    let input = input.into_signal(env, Units::volt(1)).unwrap(env)?;
+    new_node(env, pos, Units::volt(1), ops::Rectify { input, mode })
+}
+
+fn saturate<'a>(env: &mut Env<'a>, pos: Span, args: &'a [SExpr]) -> OpResult {
+    let (mode, input) = mode_args(
+        env,
+        args,
+        &[
+            ("tanh", ops::SaturateMode::Tanh),
+            ("atan", ops::SaturateMode::Atan),
+            ("cubic", ops::SaturateMode::Cubic),
+        ],
+        ops::SaturateMode::Tanh,
+    )?;
+    let input = input.into_signal(env, Units::volt(1)).unwrap(env)?;
+    new_node(env, pos, Units::volt(1), ops::Saturate { input, mode })
+}
+
 // =================================================================================================
 // Functions
 // =================================================================================================
@@ -122,20 +370,22 @@ fn new_node(env: &mut Env, pos: Span, units: Units, node: impl Node + 'static) -
 
 fn note(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     parse_args!(args, offset);
-    let offset = offset
-        .into_int()
-        .and_then(|i| i32::try_from(i).map_err(|_| unimplemented!()))
-        .unwrap(env);
+    let offset = offset.into_semitones().unwrap(env);
     new_node(env, pos, Units::hertz(1), ops::Note { offset: offset? })
 }
 
+fn velocity(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args);
+    new_node(env, pos, Units::scalar(), ops::Velocity)
+}
+
 // =================================================================================================
 // Oscillators and generators
 // =================================================================================================
 
 fn oscillator(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     parse_args!(args, frequency);
-    let frequency = frequency.into_signal(Units::hertz(1)).unwrap(env);
+    let frequency = frequency.into_signal(env, Units::hertz(1)).unwrap(env);
     new_node(
         env,
         pos,
@@ -146,6 +396,21 @@ fn oscillator(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult
     )
 }
 
+fn sync(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, frequency, master);
+    let frequency = frequency.into_signal(env, Units::hertz(1)).unwrap(env);
+    let master = master.into_any_signal(env).unwrap(env);
+    let (frequency, (master, _)) = (frequency?, master?);
+    new_node(
+        env,
+        pos,
+        Units::radian(1),
+        ops::SyncOscillator {
+            inputs: [frequency, master],
+        },
+    )
+}
+
 fn apply_function(
     env: &mut Env,
     pos: Span,
@@ -158,7 +423,7 @@ fn apply_function(
     let input = if in_units == Units::radian(1) {
         input.into_phase(env)
     } else {
-        input.into_signal(in_units)
+        input.into_signal(env, in_units)
     };
     let input = input.unwrap(env)?;
     new_node(env, pos, out_units, ops::ApplyFunction { input, function })
@@ -186,25 +451,35 @@ fn sawtooth(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     )
 }
 
-fn saturate(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
-    apply_function(
+fn pulse(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, phase, width);
+    let phase = phase.into_signal(env, Units::radian(1)).unwrap(env);
+    let width = width.into_any_signal(env).unwrap(env);
+    let (phase, (width, units)) = (phase?, width?);
+    if !units.is_scalar() {
+        return error!(env, pos, "pulse width must be a scalar, got {}", units);
+    }
+    new_node(
         env,
         pos,
-        args,
-        ops::PointFunction::Saturate,
-        Units::volt(1),
         Units::volt(1),
+        ops::Pulse {
+            inputs: [phase, width],
+        },
     )
 }
 
-fn rectify(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
-    apply_function(
+fn square(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, phase);
+    let phase = phase.into_signal(env, Units::radian(1)).unwrap(env)?;
+    let width = env.new_node(pos, ops::Constant { value: 0.5 });
+    new_node(
         env,
         pos,
-        args,
-        ops::PointFunction::Rectify,
-        Units::volt(1),
         Units::volt(1),
+        ops::Pulse {
+            inputs: [phase, width],
+        },
     )
 }
 
@@ -220,7 +495,7 @@ fn noise(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
 fn high_pass(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     parse_args!(args, frequency, input);
     let frequency = frequency.into_float(Units::hertz(1)).unwrap(env);
-    let input = input.into_signal(Units::volt(1)).unwrap(env);
+    let input = input.into_signal(env, Units::volt(1)).unwrap(env);
     new_node(
         env,
         pos,
@@ -239,8 +514,8 @@ fn state_variable(
     mode: filter::Mode,
 ) -> OpResult {
     parse_args!(args, input, frequency, q);
-    let frequency = frequency.into_signal(Units::hertz(1)).unwrap(env);
-    let input = input.into_signal(Units::volt(1)).unwrap(env);
+    let frequency = frequency.into_signal(env, Units::hertz(1)).unwrap(env);
+    let input = input.into_signal(env, Units::volt(1)).unwrap(env);
     let q = q.into_float(Units::scalar()).unwrap(env);
     // FIXME: check q >= 0.7
     new_node(
@@ -271,30 +546,118 @@ fn low_pass_4(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult
     state_variable(env, pos, args, filter::Mode::LowPass4)
 }
 
+fn glide(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, input, time);
+    let input = input.into_any_signal(env).unwrap(env);
+    let time = time.into_float(Units::second(1)).unwrap(env);
+    let ((input, units), time) = (input?, time?);
+    if time < 0.0 {
+        return error!(
+            env,
+            pos, "invalid glide time: {}, must not be negative", time
+        );
+    }
+    new_node(env, pos, units, filter::Glide { input, time })
+}
+
+fn slew(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, input, rise, fall);
+    let input = input.into_any_signal(env).unwrap(env);
+    let rise = rise.into_float(Units::scalar()).unwrap(env);
+    let fall = fall.into_float(Units::scalar()).unwrap(env);
+    let ((input, units), rise, fall) = (input?, rise?, fall?);
+    new_node(env, pos, units, filter::Slew { input, rise, fall })
+}
+
+fn sample_hold(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, input, trigger);
+    let input = input.into_any_signal(env).unwrap(env);
+    let trigger = trigger.into_any_signal(env).unwrap(env);
+    let ((input, units), (trigger, _)) = (input?, trigger?);
+    new_node(
+        env,
+        pos,
+        units,
+        ops::SampleHold {
+            inputs: [input, trigger],
+        },
+    )
+}
+
 // =================================================================================================
 // Utilities
 // =================================================================================================
 
 fn frequency(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     parse_args!(args, input);
-    let input = input.into_signal(Units::scalar()).unwrap(env);
+    let input = input.into_signal(env, Units::scalar()).unwrap(env);
     new_node(env, pos, Units::hertz(1), ops::Frequency { input: input? })
 }
 
+/// Create a constant scalar signal.
+fn constant(env: &mut Env, pos: Span, value: f32) -> OpResult {
+    new_node(env, pos, Units::scalar(), ops::Constant { value })
+}
+
+/// Apply a conversion function to a scalar. Constants are converted
+/// immediately, signals are converted by a node in the graph.
+/// Apply a conversion function to a scalar, or to a value with the units the
+/// function converts from.
+fn convert(
+    env: &mut Env,
+    pos: Span,
+    args: &[EvalResult<Value>],
+    units: Units,
+    function: ops::PointFunction,
+) -> OpResult {
+    parse_args!(args, input);
+    let units = match &input {
+        EvalResult(_, Ok(Value(_, vunits))) if *vunits == units => units,
+        _ => Units::scalar(),
+    };
+    if let EvalResult(_, Ok(Value(Data::Signal(_), _))) = input {
+        let input = input.into_signal(env, units).unwrap(env)?;
+        return new_node(
+            env,
+            pos,
+            Units::scalar(),
+            ops::ApplyFunction { input, function },
+        );
+    }
+    let value = input.into_float(units).unwrap(env)?;
+    constant(env, pos, function.apply(value as f32))
+}
+
+fn db(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    convert(
+        env,
+        pos,
+        args,
+        Units::decibel(1),
+        ops::PointFunction::DecibelToRatio,
+    )
+}
+
+fn semitone(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    convert(
+        env,
+        pos,
+        args,
+        Units::semitone(1),
+        ops::PointFunction::SemitoneToRatio,
+    )
+}
+
 fn multiply(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     let (first, rest) = match args.split_first() {
         Some(x) => x,
-        None => {
-            return Err(OpError::BadNArgs {
-                got: args.len(),
-                min: 1,
-                max: None,
-            });
-        }
+        None => return constant(env, pos, 1.0),
     };
-    let mut product = func_argn("arg", 1, first).into_any_signal().unwrap(env);
+    let mut product = func_argn("arg", 1, first).into_any_signal(env).unwrap(env);
     for (n, arg) in rest.iter().enumerate() {
-        let arg = func_argn("arg", n + 2, arg).into_any_signal().unwrap(env);
+        let arg = func_argn("arg", n + 2, arg)
+            .into_any_signal(env)
+            .unwrap(env);
         product = match (product, arg) {
             (Ok((xsig, xunits)), Ok((ysig, yunits))) => match xunits.multiply(&yunits) {
                 Err(e) => error!(
@@ -318,7 +681,138 @@ fn multiply(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     Ok(Value(Data::Signal(sig), units))
 }
 
+/// Apply a binary operator to two signals with units.
+fn binary_op<T: Node + 'static>(
+    env: &mut Env,
+    pos: Span,
+    args: &[EvalResult<Value>],
+    units: fn(&Units, &Units) -> Option<Units>,
+    node: fn([SignalRef; 2]) -> T,
+) -> OpResult {
+    parse_args!(args, x, y);
+    let x = x.into_any_signal(env).unwrap(env);
+    let y = y.into_any_signal(env).unwrap(env);
+    let ((xsig, xunits), (ysig, yunits)) = (x?, y?);
+    match units(&xunits, &yunits) {
+        Some(units) => new_node(env, pos, units, node([xsig, ysig])),
+        None => error!(env, pos, "incompatible units: {} and {}", xunits, yunits),
+    }
+}
+
+/// Return the units for adding or subtracting values with the given units.
+fn sum_units(x: &Units, y: &Units) -> Option<Units> {
+    if x == y {
+        Some(*x)
+    } else {
+        None
+    }
+}
+
+fn add_(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    let (first, rest) = match args.split_first() {
+        Some(x) => x,
+        None => return constant(env, pos, 0.0),
+    };
+    let mut sum = func_argn("arg", 1, first).into_any_signal(env).unwrap(env);
+    for (n, arg) in rest.iter().enumerate() {
+        let arg = func_argn("arg", n + 2, arg)
+            .into_any_signal(env)
+            .unwrap(env);
+        sum = match (sum, arg) {
+            (Ok((xsig, xunits)), Ok((ysig, yunits))) => match sum_units(&xunits, &yunits) {
+                None => error!(env, pos, "could not add {} to {}", yunits, xunits),
+                Some(units) => {
+                    let sig = env.new_node(
+                        pos,
+                        ops::Add {
+                            inputs: [xsig, ysig],
+                        },
+                    );
+                    Ok((sig, units))
+                }
+            },
+            _ => Err(Failed),
+        };
+    }
+    let (sig, units) = sum?;
+    Ok(Value(Data::Signal(sig), units))
+}
+
+fn subtract(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    if args.len() == 1 {
+        let (input, units) = func_arg("input", &args[0])
+            .into_any_signal(env)
+            .unwrap(env)?;
+        return new_node(env, pos, units, ops::Negate { input });
+    }
+    binary_op(env, pos, args, sum_units, |inputs| ops::Subtract { inputs })
+}
+
+fn divide(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    binary_op(
+        env,
+        pos,
+        args,
+        |x, y| x.divide(y).ok(),
+        |inputs| ops::Divide { inputs },
+    )
+}
+
+fn pow(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    binary_op(
+        env,
+        pos,
+        args,
+        |x, y| {
+            if x.is_scalar() && y.is_scalar() {
+                Some(Units::scalar())
+            } else {
+                None
+            }
+        },
+        |inputs| ops::Power { inputs },
+    )
+}
+
+fn clamp(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, input, lo, hi);
+    let (input, units) = input.into_any_signal(env).unwrap(env)?;
+    let lo = lo.into_float(units).unwrap(env);
+    let hi = hi.into_float(units).unwrap(env);
+    let (lo, hi) = (lo?, hi?);
+    if lo > hi {
+        return error!(
+            env,
+            pos, "invalid clamp range: lower bound {} is above upper bound {}", lo, hi
+        );
+    }
+    new_node(env, pos, units, ops::Clamp { input, lo, hi })
+}
+
+fn crush(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, input, bits, rate);
+    let input = input.into_signal(env, Units::volt(1)).unwrap(env);
+    // Bit depths outside 1..=24 are clamped.
+    let bits = bits
+        .into_int()
+        .and_then(|i| Ok(i.clamp(1, 24) as i32))
+        .unwrap(env);
+    let rate = rate.into_float(Units::hertz(1)).unwrap(env);
+    let (input, bits, rate) = (input?, bits?, rate?);
+    if rate <= 0.0 {
+        return error!(env, pos, "invalid crush rate: {}, must be positive", rate);
+    }
+    new_node(env, pos, Units::volt(1), ops::Crush { input, bits, rate })
+}
+
 fn mix(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    if args.is_empty() {
+        return Err(OpError::BadNArgs {
+            got: 0,
+            min: 2,
+            max: None,
+        });
+    }
     if args.len() & 1 != 0 {
         return error!(
             env,
@@ -327,24 +821,34 @@ fn mix(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
             args.len()
         );
     }
-    let mut output: Result<SignalRef, Failed> = Ok(env.new_node(pos, ops::Zero));
+    let mut inputs = Vec::with_capacity(args.len() / 2);
+    let mut gains = Vec::with_capacity(args.len() / 2);
+    let mut failed = false;
     for (n, chunk) in args.chunks_exact(2).enumerate() {
         let gain = func_argn("gain", n + 1, &chunk[0]).into_gain().unwrap(env);
         let signal = func_argn("signal", n + 1, &chunk[1])
-            .into_signal(Units::volt(1))
+            .into_signal(env, Units::volt(1))
             .unwrap(env);
-        output = match (output, gain, signal) {
-            (Ok(xsig), Ok(gain), Ok(ysig)) => Ok(env.new_node(
-                pos,
-                ops::Mix {
-                    inputs: [xsig, ysig],
-                    gain,
-                },
-            )),
-            _ => Err(Failed),
-        };
+        match (gain, signal) {
+            (Ok(gain), Ok(signal)) => {
+                gains.push(gain);
+                inputs.push(signal);
+            }
+            _ => failed = true,
+        }
     }
-    Ok(Value(Data::Signal(output?), Units::volt(1)))
+    if failed {
+        return Err(OpError::Failed);
+    }
+    new_node(
+        env,
+        pos,
+        Units::volt(1),
+        ops::MixN {
+            inputs: Box::from(inputs),
+            gains: Box::from(gains),
+        },
+    )
 }
 
 fn phase_mod(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
@@ -360,7 +864,7 @@ fn phase_mod(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     for (n, chunk) in args[1..].chunks_exact(2).enumerate() {
         let gain = func_argn("gain", n + 1, &chunk[0]).into_gain().unwrap(env);
         let modulator = func_argn("modulator", n + 1, &chunk[1])
-            .into_signal(Units::volt(1))
+            .into_signal(env, Units::volt(1))
             .unwrap(env);
         output = match (output, gain, modulator) {
             (Ok(xsig), Ok(gain), Ok(ysig)) => Ok(env.new_node(
@@ -376,12 +880,17 @@ fn phase_mod(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     Ok(Value(Data::Signal(output?), Units::radian(1)))
 }
 
+fn scale_int(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
+    parse_args!(args, scale, input);
+    let scale = scale.into_i32().unwrap(env);
+    let input = input.into_any_signal(env).unwrap(env);
+    let (scale, (input, units)) = (scale?, input?);
+    new_node(env, pos, units, ops::ScaleInt { input, scale })
+}
+
 fn overtone(env: &mut Env, pos: Span, args: &[EvalResult<Value>]) -> OpResult {
     parse_args!(args, overtone, phase);
-    let overtone = overtone
-        .into_int()
-        .and_then(|i| i32::try_from(i).map_err(|_| unimplemented!()))
-        .unwrap(env);
+    let overtone = overtone.into_i32().unwrap(env);
     let phase = phase.into_phase(env).unwrap(env);
     new_node(
         env,
@@ -13,13 +13,14 @@ fn evaluate<'a>(env: &mut Env<'a>, expr: &'a SExpr) -> Result<Segment, Failed> {
         &Content::Symbol(_) => error!(env, pos, "unexpected symbol in envelope"),
         &Content::Integer(_, _) => error!(env, pos, "unexpected number in envelope"),
         &Content::Float(_, _) => error!(env, pos, "unexpected number in envelope"),
+        Content::String(_) => error!(env, pos, "unexpected string in envelope"),
         &Content::List(ref items) => {
             let (op, args) = match items.split_first() {
                 Some(x) => x,
                 None => return error!(env, pos, "cannot evaluate empty list"),
             };
             let name: &str = match &op.content {
-                &Content::Symbol(ref name) => name.as_ref(),
+                &Content::Symbol(name) => name.as_str(),
                 _ => return error!(env, pos, "envelope segment name must be a symbol"),
             };
             let mut values: Vec<EvalResult<Value>> = Vec::with_capacity(args.len());
@@ -1,11 +1,13 @@
 use crate::error::ErrorHandler;
+use crate::note::Note;
 use crate::sexpr::{Content, SExpr, Type as EType};
 use crate::signal::graph::{Graph, Node, SignalRef};
-use crate::signal::ops::{Constant, Oscillator};
+use crate::signal::ops::{Constant, Oscillator, Pitch};
 use crate::sourcepos::{HasPos, Span};
+use crate::symbol::Symbol;
 use crate::units::Units;
 use std::collections::hash_map::{HashMap, RandomState};
-use std::convert::From;
+use std::convert::{From, TryFrom};
 use std::fmt::{Display, Formatter, Result as FResult};
 
 pub use crate::error::Failed;
@@ -36,6 +38,7 @@ pub enum ValueError {
     BadEType { got: EType, expect: EType },
     BadGain { got: Type },
     BadPhase { got: Type },
+    OutOfRange { got: i64, min: i64, max: i64 },
 }
 
 impl Display for ValueError {
@@ -53,6 +56,13 @@ impl Display for ValueError {
                 Type(DataType::Signal, Some(Units::radian(1))),
                 Units::hertz(1),
             ),
+            OutOfRange { got, min, max } => {
+                write!(
+                    f,
+                    "value {} is out of range, expected {} to {}",
+                    got, min, max
+                )
+            }
         }
     }
 }
@@ -165,6 +175,15 @@ impl Value {
         }
     }
 
+    fn into_i32(self) -> Result<i32, ValueError> {
+        let num = self.into_int()?;
+        i32::try_from(num).map_err(|_| ValueError::OutOfRange {
+            got: num,
+            min: i32::MIN as i64,
+            max: i32::MAX as i64,
+        })
+    }
+
     fn into_float(self, units: Units) -> Result<f64, ValueError> {
         match self {
             Value(Data::Float(num), vunits) if units == vunits => Ok(num),
@@ -191,16 +210,47 @@ impl Value {
         }
     }
 
-    fn into_any_signal(self) -> Result<(SignalRef, Units), ValueError> {
+    fn into_semitones(self) -> Result<i32, ValueError> {
+        let num = match self {
+            Value(Data::Int(num), units)
+                if units == Units::semitone(1) || units == Units::scalar() =>
+            {
+                num
+            }
+            val => return Err(val.bad_type(Type(DataType::Int, Some(Units::semitone(1))))),
+        };
+        i32::try_from(num).map_err(|_| ValueError::OutOfRange {
+            got: num,
+            min: i32::MIN as i64,
+            max: i32::MAX as i64,
+        })
+    }
+
+    fn into_any_signal(self, graph: &mut Graph) -> Result<(SignalRef, Units), ValueError> {
         match self {
             Value(Data::Signal(sig), units) => Ok((sig, units)),
+            Value(Data::Int(value), units) => Ok((
+                graph.add(Box::new(Constant {
+                    value: value as f32,
+                })),
+                units,
+            )),
+            Value(Data::Float(value), units) => Ok((
+                graph.add(Box::new(Constant {
+                    value: value as f32,
+                })),
+                units,
+            )),
             val => Err(val.bad_type(Type(DataType::Signal, None))),
         }
     }
 
-    fn into_signal(self, units: Units) -> Result<SignalRef, ValueError> {
+    fn into_signal(self, graph: &mut Graph, units: Units) -> Result<SignalRef, ValueError> {
         match self {
             Value(Data::Signal(sig), vunits) if vunits == units => Ok(sig),
+            Value(Data::Int(_), vunits) | Value(Data::Float(_), vunits) if vunits == units => {
+                self.into_any_signal(graph).map(|(sig, _)| sig)
+            }
             val => Err(val.bad_type(Type(DataType::Signal, Some(units)))),
         }
     }
@@ -349,6 +399,10 @@ impl EvalResult<Value> {
         self.and_then(Value::into_int)
     }
 
+    pub fn into_i32(self) -> EvalResult<i32> {
+        self.and_then(Value::into_i32)
+    }
+
     pub fn into_float(self, units: Units) -> EvalResult<f64> {
         self.and_then(|v| v.into_float(units))
     }
@@ -357,12 +411,16 @@ impl EvalResult<Value> {
         self.and_then(Value::into_gain)
     }
 
-    pub fn into_any_signal(self) -> EvalResult<(SignalRef, Units)> {
-        self.and_then(Value::into_any_signal)
+    pub fn into_semitones(self) -> EvalResult<i32> {
+        self.and_then(Value::into_semitones)
     }
 
-    pub fn into_signal(self, units: Units) -> EvalResult<SignalRef> {
-        self.and_then(|v| v.into_signal(units))
+    pub fn into_any_signal(self, env: &mut Env) -> EvalResult<(SignalRef, Units)> {
+        self.and_then(|v| v.into_any_signal(&mut env.graph))
+    }
+
+    pub fn into_signal(self, env: &mut Env, units: Units) -> EvalResult<SignalRef> {
+        self.and_then(|v| v.into_signal(&mut env.graph, units))
     }
 
     pub fn into_phase(self, env: &mut Env) -> EvalResult<SignalRef> {
@@ -390,13 +448,60 @@ pub enum Operator {
     Macro(Option<for<'a> fn(&mut Env<'a>, Span, &'a [SExpr]) -> OpResult>),
 }
 
+/// A function defined by the program being evaluated. Calls are expanded by
+/// evaluating the body with the parameters bound to the arguments.
+#[derive(Clone)]
+pub struct UserFunction<'a> {
+    pub params: Vec<Symbol>,
+    pub body: &'a [SExpr],
+}
+
+/// A variable in a scope.
+#[derive(Clone, Copy)]
+struct Binding {
+    value: Result<Value, Failed>,
+    /// Location of the variable name, if a warning should be given when the
+    /// variable is not used.
+    pos: Option<Span>,
+    used: bool,
+}
+
+/// The variables and functions defined in one scope.
+#[derive(Default)]
+struct Scope<'a> {
+    variables: HashMap<Symbol, Binding, RandomState>,
+    functions: HashMap<Symbol, UserFunction<'a>, RandomState>,
+}
+
 /// An environment for evaluating s-expressions.
+///
+/// Names are scoped lexically. The environment is a stack of scopes, with the
+/// global scope at the bottom, and let bodies and function calls each push a
+/// new scope. A name refers to its definition in the innermost scope which
+/// defines it, so inner definitions shadow outer ones. Definitions are visible
+/// to the forms evaluated after them, so a name cannot be used before it is
+/// defined.
+///
+/// A function body sees the scopes where the function was defined, plus its
+/// parameters, and not the scopes at the call site. Functions are defined in
+/// the innermost scope, so they can only be called while the scopes they
+/// refer to still exist. Because the body is evaluated at each call, it sees
+/// names defined in those scopes after the function itself, like later
+/// global definitions.
 pub struct Env<'a> {
     has_error: bool,
     err_handler: &'a mut dyn ErrorHandler,
-    pub variables: HashMap<&'a str, Result<Value, Failed>, RandomState>,
-    operators: HashMap<&'a str, Operator, RandomState>,
+    scopes: Vec<Scope<'a>>,
+    operators: HashMap<Symbol, Operator, RandomState>,
+    /// Bodies of the user-defined functions being called. Functions are
+    /// identified by their definitions rather than by name, since an inner
+    /// definition can shadow an outer one with the same name.
+    call_stack: Vec<&'a [SExpr]>,
     graph: Graph,
+    /// Location of the expression which created each node, indexed by node.
+    node_pos: Vec<Span>,
+    /// Signals bound to variables which were reported as unused.
+    unused_signals: Vec<SignalRef>,
     #[allow(dead_code)]
     tail_length: Option<f64>,
 }
@@ -405,14 +510,17 @@ impl<'a> Env<'a> {
     /// Create a new environment with the given functions defined.
     pub fn new(
         err_handler: &'a mut dyn ErrorHandler,
-        operators: HashMap<&'a str, Operator, RandomState>,
+        operators: HashMap<Symbol, Operator, RandomState>,
     ) -> Self {
         Env {
             has_error: false,
             err_handler,
-            variables: HashMap::new(),
+            scopes: vec![Scope::default()],
             operators,
+            call_stack: Vec::new(),
             graph: Graph::new(),
+            node_pos: Vec::new(),
+            unused_signals: Vec::new(),
             tail_length: None,
         }
     }
@@ -434,25 +542,49 @@ impl<'a> Env<'a> {
     fn evaluate_impl(&mut self, expr: &'a SExpr) -> Result<Value, Failed> {
         let pos = expr.source_pos();
         match &expr.content {
-            &Content::Symbol(ref name) => match self.variables.get(name.as_ref()) {
-                Some(&value) => value,
-                None => error!(self, pos, "undefined symbol: {:?}", name),
+            &Content::Symbol(name) => match self.lookup(name) {
+                Some(value) => value,
+                None if is_note_name(name) => {
+                    let note: Note = name.as_str().parse().unwrap();
+                    let sig = self.new_node(pos, Pitch { note: note.0 });
+                    Ok(Value(Data::Signal(sig), Units::hertz(1)))
+                }
+                None => {
+                    let names = self.scopes.iter().flat_map(|scope| scope.variables.keys());
+                    let hint = did_you_mean(name.as_str(), names.map(|name| name.as_str()));
+                    error!(self, pos, "undefined symbol: {:?}{}", name, hint)
+                }
             },
             &Content::Integer(units, num) => Ok(Value(Data::Int(num), units)),
             &Content::Float(units, num) => Ok(Value(Data::Float(num), units)),
+            Content::String(_) => error!(self, pos, "unexpected string"),
             &Content::List(ref items) => {
                 let (op, args) = match items.split_first() {
                     Some(x) => x,
                     None => return error!(self, pos, "cannot evaluate empty list"),
                 };
-                let name: &str = match &op.content {
-                    &Content::Symbol(ref name) => name.as_ref(),
+                let name = match &op.content {
+                    &Content::Symbol(name) => name,
                     _ => return error!(self, pos, "function or macro name must be a symbol"),
                 };
                 let oppos = op.source_pos();
-                let op = match self.operators.get(name) {
+                let op = match self.operators.get(&name) {
                     Some(x) => *x,
-                    None => return error!(self, oppos, "undefined function or macro: {:?}", name),
+                    None => match self.lookup_function(name) {
+                        Some((depth, func)) => {
+                            return self.call_function(oppos, name, depth, &func, args);
+                        }
+                        None => {
+                            let functions =
+                                self.scopes.iter().flat_map(|scope| scope.functions.keys());
+                            let names = self.operators.keys().chain(functions);
+                            let hint = did_you_mean(name.as_str(), names.map(|name| name.as_str()));
+                            return error!(
+                                self,
+                                oppos, "undefined function or macro: {:?}{}", name, hint
+                            );
+                        }
+                    },
                 };
                 let r = match op {
                     Operator::Function(f) => {
@@ -482,16 +614,219 @@ impl<'a> Env<'a> {
         }
     }
 
+    /// Expand a call to a user-defined function, which is defined in the scope
+    /// at the given depth.
+    fn call_function(
+        &mut self,
+        oppos: Span,
+        name: Symbol,
+        depth: usize,
+        func: &UserFunction<'a>,
+        args: &'a [SExpr],
+    ) -> Result<Value, Failed> {
+        if args.len() != func.params.len() {
+            let e = OpError::BadNArgs {
+                got: args.len(),
+                min: func.params.len(),
+                max: Some(func.params.len()),
+            };
+            return error!(self, oppos, "invalid call to {}: {}", name, e);
+        }
+        // Every call is expanded into the graph, so recursion would never
+        // terminate.
+        if self
+            .call_stack
+            .iter()
+            .any(|&body| std::ptr::eq(body, func.body))
+        {
+            return error!(self, oppos, "recursive call to function {:?}", name);
+        }
+        let mut values: Vec<Result<Value, Failed>> = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            values.push(self.evaluate(arg).into_nonvoid().unwrap(self));
+        }
+        // The body sees the scopes where the function was defined and its own
+        // parameters, not the variables at the call site.
+        let locals = self.scopes.split_off(depth + 1);
+        self.push_scope();
+        for (&param, value) in func.params.iter().zip(values.drain(..)) {
+            self.define(param, value);
+        }
+        self.call_stack.push(func.body);
+        let result = self.evaluate_body(func.body, "function body");
+        self.call_stack.pop();
+        self.scopes.truncate(depth + 1);
+        self.scopes.extend(locals);
+        result
+    }
+
+    /// Evaluate a sequence of forms in the current scope. The leading forms
+    /// must be void, and the last form is the result.
+    pub fn evaluate_body(&mut self, body: &'a [SExpr], what: &str) -> Result<Value, Failed> {
+        let (last, first) = match body.split_last() {
+            Some(x) => x,
+            None => panic!("empty body"),
+        };
+        for form in first.iter() {
+            if let EvalResult(label, Err(e)) = self.evaluate(form).into_void() {
+                if e != ValueError::Failed {
+                    log_error!(self, label.pos, "invalid statement in {}: {}", what, e);
+                }
+            }
+        }
+        self.evaluate(last).into_nonvoid().unwrap(self)
+    }
+
+    /// True if a function or macro with the given name exists.
+    pub fn is_function_defined(&self, name: Symbol) -> bool {
+        self.operators.contains_key(&name)
+            || self
+                .scopes
+                .iter()
+                .any(|scope| scope.functions.contains_key(&name))
+    }
+
+    /// Define a new function in the innermost scope.
+    pub fn define_function(&mut self, name: Symbol, func: UserFunction<'a>) {
+        self.scopes.last_mut().unwrap().functions.insert(name, func);
+    }
+
+    /// Look up a user-defined function, searching from the innermost scope
+    /// outwards. Returns the depth of the scope which defines it.
+    fn lookup_function(&self, name: Symbol) -> Option<(usize, UserFunction<'a>)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.functions.get(&name).map(|f| (depth, f.clone())))
+    }
+
+    /// Look up the value of a variable, searching from the innermost scope
+    /// outwards, and mark the variable as used.
+    pub fn lookup(&mut self, name: Symbol) -> Option<Result<Value, Failed>> {
+        self.scopes.iter_mut().rev().find_map(|scope| {
+            scope.variables.get_mut(&name).map(|binding| {
+                binding.used = true;
+                binding.value
+            })
+        })
+    }
+
+    /// True if a variable with the given name is defined in the innermost
+    /// scope. Variables in outer scopes may be shadowed.
+    pub fn is_defined_in_scope(&self, name: Symbol) -> bool {
+        self.scopes.last().unwrap().variables.contains_key(&name)
+    }
+
+    /// Define a variable in the innermost scope.
+    pub fn define(&mut self, name: Symbol, value: Result<Value, Failed>) {
+        self.insert(name, value, None);
+    }
+
+    /// Define a variable in the innermost scope, which is named by the program
+    /// at the given location. A warning is given if it is not used.
+    pub fn bind(&mut self, name: Symbol, pos: Span, value: Result<Value, Failed>) {
+        self.insert(name, value, Some(pos));
+    }
+
+    fn insert(&mut self, name: Symbol, value: Result<Value, Failed>, pos: Option<Span>) {
+        let binding = Binding {
+            value,
+            pos,
+            used: false,
+        };
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .variables
+            .insert(name, binding);
+    }
+
+    /// Create a new innermost scope for variable definitions.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    /// Discard the innermost scope and all variables defined in it.
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() <= 1 {
+            panic!("cannot pop global scope");
+        }
+        let scope = self.scopes.pop().unwrap();
+        self.warn_unused_variables(scope.variables);
+    }
+
+    /// Warn about variables in a scope which were never used.
+    fn warn_unused_variables(&mut self, variables: HashMap<Symbol, Binding, RandomState>) {
+        if self.has_error {
+            return;
+        }
+        let mut unused: Vec<(Span, Symbol, Binding)> = variables
+            .into_iter()
+            .filter_map(|(name, binding)| match binding.pos {
+                Some(pos) if !binding.used => Some((pos, name, binding)),
+                _ => None,
+            })
+            .collect();
+        unused.sort_by_key(|&(pos, _, _)| pos.start);
+        for (pos, name, binding) in unused.drain(..) {
+            self.warning(pos, format!("unused variable: {:?}", name).as_ref());
+            if let Ok(Value(Data::Signal(sig), _)) = binding.value {
+                self.unused_signals.push(sig);
+            }
+        }
+    }
+
+    /// Warn about global variables which were never used, and nodes in the
+    /// graph which do not contribute to the output.
+    pub fn warn_unused(&mut self, output: SignalRef) {
+        let variables = std::mem::take(&mut self.scopes[0].variables);
+        self.warn_unused_variables(variables);
+        if self.has_error {
+            return;
+        }
+        let nodes = self.graph.nodes();
+        let mut used = vec![false; nodes.len()];
+        used[output.0 as usize] = true;
+        for node in nodes.iter() {
+            for input in node.inputs().iter() {
+                used[input.0 as usize] = true;
+            }
+        }
+        for sig in self.unused_signals.iter() {
+            used[sig.0 as usize] = true;
+        }
+        let unused: Vec<Span> = used
+            .iter()
+            .enumerate()
+            .filter(|&(_, &used)| !used)
+            .map(|(n, _)| self.node_pos.get(n).copied().unwrap_or(Span::none()))
+            .collect();
+        for pos in unused.iter() {
+            self.warning(*pos, "signal is not used");
+        }
+    }
+
     /// Log an error message.
     pub fn error(&mut self, pos: Span, msg: &str) {
         self.has_error = true;
         self.err_handler.handle(pos, msg);
     }
 
+    /// Log a warning message.
+    pub fn warning(&mut self, pos: Span, msg: &str) {
+        self.err_handler.warning(pos, msg);
+    }
+
     /// Add a new audio processing node to the graph.
     pub fn new_node(&mut self, pos: Span, node: impl Node + 'static) -> SignalRef {
-        let _ = pos;
-        self.graph.add(Box::new(node))
+        let sig = self.graph.add(Box::new(node));
+        let index = sig.0 as usize;
+        if self.node_pos.len() <= index {
+            self.node_pos.resize(index + 1, Span::none());
+        }
+        self.node_pos[index] = pos;
+        sig
     }
 
     /// Discard the environment and return the created graph.
@@ -504,10 +839,51 @@ impl<'a> Env<'a> {
     }
 }
 
+/// Get the number of single-character insertions, deletions, and
+/// substitutions needed to change one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// True if an unbound symbol should evaluate to the frequency of a note. Only
+/// names with an octave or MIDI number, like A4 or m69, are notes, so a
+/// misspelled one-letter variable is still an error.
+fn is_note_name(name: Symbol) -> bool {
+    let name = name.as_str();
+    name.ends_with(|c: char| c.is_ascii_digit()) && name.parse::<Note>().is_ok()
+}
+
+/// Get a hint suggesting the closest name to a misspelled name, or an empty
+/// string if no name is close.
+fn did_you_mean<'b>(name: &str, candidates: impl Iterator<Item = &'b str>) -> String {
+    const MAX_DISTANCE: usize = 2;
+    let best = candidates
+        .filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        // Very short names are close to almost everything.
+        .filter(|&(distance, _)| distance <= MAX_DISTANCE && distance < name.chars().count())
+        .min();
+    match best {
+        Some((_, candidate)) => format!("; did you mean {:?}?", candidate),
+        None => String::new(),
+    }
+}
+
 /// Get the name of a symbol.
-pub fn get_symbol(expr: &SExpr) -> Result<&str, ValueError> {
+pub fn get_symbol(expr: &SExpr) -> Result<Symbol, ValueError> {
     match &expr.content {
-        &Content::Symbol(ref name) => Ok(name),
+        &Content::Symbol(name) => Ok(name),
         _ => Err(ValueError::BadEType {
             got: expr.get_type(),
             expect: EType::Symbol,
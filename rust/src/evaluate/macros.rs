@@ -0,0 +1,274 @@
+use super::builtins;
+use crate::error::{ErrorHandler, Failed};
+use crate::sexpr::{Content, SExpr};
+use crate::sourcepos::Span;
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+/// Maximum depth of nested macro expansions. Deeper expansions are assumed to
+/// be runaway recursion.
+const MAX_DEPTH: usize = 64;
+
+/// A syntactic macro, defined with defmacro.
+struct Macro {
+    params: Vec<Symbol>,
+    body: SExpr,
+}
+
+/// Expands macros in a program.
+struct Expander<'a> {
+    err_handler: &'a mut dyn ErrorHandler,
+    macros: HashMap<Symbol, Macro>,
+    // Number of names generated so far, used to make fresh names.
+    fresh: usize,
+}
+
+/// Expand all macros in a program, before it is evaluated. Macro definitions
+/// are removed from the program, and each macro can be used in the forms
+/// following its definition.
+pub fn expand_program(
+    err_handler: &mut dyn ErrorHandler,
+    program: &[SExpr],
+) -> Result<Vec<SExpr>, Failed> {
+    let mut expander = Expander {
+        err_handler,
+        macros: HashMap::new(),
+        fresh: 0,
+    };
+    let mut result = Vec::with_capacity(program.len());
+    let mut failed = false;
+    for form in program.iter() {
+        let status = match defmacro_args(form) {
+            Some(args) => expander.define(form.pos, args),
+            None => expander.expand(form, 0).map(|expr| result.push(expr)),
+        };
+        if status.is_err() {
+            failed = true;
+        }
+    }
+    if failed {
+        Err(Failed)
+    } else {
+        Ok(result)
+    }
+}
+
+/// If the expression is a macro definition, get the arguments to defmacro.
+fn defmacro_args(expr: &SExpr) -> Option<&[SExpr]> {
+    match &expr.content {
+        Content::List(items) => match items.split_first() {
+            Some((
+                SExpr {
+                    content: Content::Symbol(name),
+                    ..
+                },
+                args,
+            )) if name.as_str() == "defmacro" => Some(args),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Get the names bound by let, define, and defun forms inside an expression.
+fn bound_names(expr: &SExpr) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    expr.walk(&mut |e| {
+        let items = match &e.content {
+            Content::List(items) => items,
+            _ => return,
+        };
+        let head = match items.first().map(|e| &e.content) {
+            Some(Content::Symbol(head)) => head.as_str(),
+            _ => return,
+        };
+        let mut add = |e: &SExpr| {
+            if let Content::Symbol(name) = &e.content {
+                names.push(*name);
+            }
+        };
+        match (head, &items[1..]) {
+            ("define", [name, ..]) => add(name),
+            ("defun", [name, params, ..]) => {
+                add(name);
+                if let Content::List(params) = &params.content {
+                    params.iter().for_each(add);
+                }
+            }
+            ("let", [bindings, ..]) => {
+                if let Content::List(bindings) = &bindings.content {
+                    for binding in bindings.iter() {
+                        if let Content::List(binding) = &binding.content {
+                            if let Some(name) = binding.first() {
+                                add(name);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    });
+    names
+}
+
+/// Copy a macro body, replacing parameters with arguments and renaming
+/// symbols.
+fn substitute(
+    expr: &SExpr,
+    args: &HashMap<Symbol, &SExpr>,
+    renames: &HashMap<Symbol, Symbol>,
+) -> SExpr {
+    match &expr.content {
+        Content::Symbol(name) => match (args.get(name), renames.get(name)) {
+            (Some(&arg), _) => arg.clone(),
+            (None, Some(&name)) => SExpr {
+                pos: expr.pos,
+                content: Content::Symbol(name),
+            },
+            (None, None) => expr.clone(),
+        },
+        Content::List(items) => SExpr {
+            pos: expr.pos,
+            content: Content::List(
+                items
+                    .iter()
+                    .map(|item| substitute(item, args, renames))
+                    .collect(),
+            ),
+        },
+        _ => expr.clone(),
+    }
+}
+
+impl<'a> Expander<'a> {
+    fn error(&mut self, pos: Span, msg: &str) -> Result<(), Failed> {
+        self.err_handler.handle(pos, msg);
+        Err(Failed)
+    }
+
+    /// Define a macro, given the arguments to defmacro.
+    fn define(&mut self, pos: Span, args: &[SExpr]) -> Result<(), Failed> {
+        let (signature, body) = match args {
+            [signature, body] => (signature, body),
+            _ => {
+                return self.error(
+                    pos,
+                    &format!(
+                        "defmacro got {} arguments, expected (defmacro (name param...) body)",
+                        args.len()
+                    ),
+                )
+            }
+        };
+        let mut names = Vec::new();
+        if let Content::List(items) = &signature.content {
+            for item in items.iter() {
+                match &item.content {
+                    Content::Symbol(name) => {
+                        if names.contains(name) {
+                            self.error(
+                                item.pos,
+                                &format!("parameter {:?} appears more than once", name),
+                            )?;
+                        }
+                        names.push(*name);
+                    }
+                    _ => {
+                        return self.error(
+                            item.pos,
+                            &format!(
+                                "invalid macro signature, expected symbol, got {}",
+                                item.get_type()
+                            ),
+                        )
+                    }
+                }
+            }
+        }
+        let (name, params) = match names.split_first() {
+            Some((&name, params)) => (name, params.to_vec()),
+            None => {
+                return self.error(
+                    signature.pos,
+                    "invalid macro signature, expected (name param...)",
+                )
+            }
+        };
+        if self.macros.contains_key(&name) || builtins::operators().contains_key(&name) {
+            return self.error(
+                signature.pos,
+                &format!("a function or macro named {:?} is already defined", name),
+            );
+        }
+        self.macros.insert(
+            name,
+            Macro {
+                params,
+                body: body.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Expand all macros in an expression.
+    fn expand(&mut self, expr: &SExpr, depth: usize) -> Result<SExpr, Failed> {
+        let items = match &expr.content {
+            Content::List(items) => items,
+            _ => return Ok(expr.clone()),
+        };
+        if defmacro_args(expr).is_some() {
+            self.error(expr.pos, "defmacro is only allowed at the top level")?;
+        }
+        if let Some(Content::Symbol(name)) = items.first().map(|e| &e.content) {
+            if self.macros.contains_key(name) {
+                if depth >= MAX_DEPTH {
+                    self.error(
+                        expr.pos,
+                        &format!(
+                            "expansion of macro {:?} is nested too deeply, it may be recursive",
+                            name
+                        ),
+                    )?;
+                }
+                let expansion = self.instantiate(*name, expr.pos, &items[1..])?;
+                return self.expand(&expansion, depth + 1);
+            }
+        }
+        let items = items
+            .iter()
+            .map(|item| self.expand(item, depth))
+            .collect::<Result<Box<[SExpr]>, Failed>>()?;
+        Ok(SExpr {
+            pos: expr.pos,
+            content: Content::List(items),
+        })
+    }
+
+    /// Substitute arguments into a macro body. Names bound inside the body are
+    /// renamed to fresh names, so they do not capture names in the arguments.
+    fn instantiate(&mut self, name: Symbol, pos: Span, args: &[SExpr]) -> Result<SExpr, Failed> {
+        let nparams = self.macros[&name].params.len();
+        if args.len() != nparams {
+            let msg = format!(
+                "macro {:?} got {} arguments, expected {}",
+                name,
+                args.len(),
+                nparams
+            );
+            self.error(pos, &msg)?;
+        }
+        let mac = &self.macros[&name];
+        let mut renames: HashMap<Symbol, Symbol> = HashMap::new();
+        for bound in bound_names(&mac.body) {
+            if !mac.params.contains(&bound) && !renames.contains_key(&bound) {
+                self.fresh += 1;
+                renames.insert(bound, Symbol::new(&format!("{}#{}", bound, self.fresh)));
+            }
+        }
+        let args: HashMap<Symbol, &SExpr> = mac.params.iter().copied().zip(args.iter()).collect();
+        let mut expansion = substitute(&mac.body, &args, &renames);
+        expansion.pos = pos;
+        Ok(expansion)
+    }
+}
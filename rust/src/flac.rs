@@ -0,0 +1,106 @@
+use crate::rand::Rand;
+use crate::wave::{dither_i16, Parameters};
+use flacenc::bitsink::ByteSink;
+use flacenc::component::BitRepr;
+use flacenc::error::{EncodeError, Verify};
+use flacenc::source::MemSource;
+use std::fmt;
+use std::io::{Error, Result as IOResult, Write};
+
+/// FLAC file writer. The samples are encoded as 16-bit when the file is
+/// finished.
+pub struct Writer<'a> {
+    stream: &'a mut dyn Write,
+    parameters: Parameters,
+    samples: Vec<i32>,
+    rand: Rand,
+}
+
+/// Convert an encoder error to an IO error.
+fn encode_error(e: impl fmt::Display) -> Error {
+    Error::other(format!("FLAC encoder: {}", e))
+}
+
+impl<'a> Writer<'a> {
+    /// Create a FLAC writer from the given stream.
+    pub fn from_stream(stream: &'a mut dyn Write, parameters: &Parameters) -> Self {
+        Writer {
+            stream,
+            parameters: *parameters,
+            samples: Vec::new(),
+            rand: Rand::with_default_seed(),
+        }
+    }
+
+    /// Write floating-point samples to the file. These samples will be
+    /// converted to 16-bit, with the same dither as WAVE output.
+    pub fn write(&mut self, data: &[f32]) -> IOResult<()> {
+        let rand = &mut self.rand;
+        self.samples
+            .extend(data.iter().map(|&x| dither_i16(rand, x) as i32));
+        Ok(())
+    }
+
+    /// Encode the samples and finish writing the file.
+    pub fn finish(self) -> IOResult<()> {
+        let config = match flacenc::config::Encoder::default().into_verified() {
+            Ok(config) => config,
+            Err((_, e)) => return Err(encode_error(e)),
+        };
+        let source = MemSource::from_samples(
+            &self.samples,
+            self.parameters.channel_count as usize,
+            16,
+            self.parameters.sample_rate as usize,
+        );
+        let stream = match flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        {
+            Ok(stream) => stream,
+            Err(EncodeError::Source(e)) => return Err(encode_error(e)),
+            Err(EncodeError::Config(e)) => return Err(encode_error(e)),
+            Err(_) => return Err(encode_error("encoding failed")),
+        };
+        let mut sink = ByteSink::new();
+        stream.write(&mut sink).map_err(encode_error)?;
+        self.stream.write_all(sink.as_slice())?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let input: Vec<f32> = (0..10000).map(|n| (n as f32 * 0.01).sin() * 0.9).collect();
+        let mut data = Vec::new();
+        let mut writer = Writer::from_stream(
+            &mut data,
+            &Parameters {
+                channel_count: 1,
+                sample_rate: 44100,
+            },
+        );
+        writer.write(&input).unwrap();
+        writer.finish().unwrap();
+        assert!(data.starts_with(b"fLaC"));
+        let mut reader = claxon::FlacReader::new(Cursor::new(data)).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.bits_per_sample, 16);
+        let output: Vec<f32> = reader
+            .samples()
+            .map(|x| x.unwrap() as f32 * (1.0 / 32768.0))
+            .collect();
+        assert_eq!(input.len(), output.len());
+        for (n, (&x, &y)) in input.iter().zip(output.iter()).enumerate() {
+            // Allow for rounding and dither, each less than one LSB.
+            if (x - y).abs() > 2.0 / 32768.0 {
+                panic!("sample {}: wrote {}, read {}", n, x, y);
+            }
+        }
+    }
+}
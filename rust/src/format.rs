@@ -0,0 +1,379 @@
+//! Source formatting which keeps comments.
+//!
+//! Scripts are laid out the same way as [`SExpr::pretty`], but atoms keep
+//! their source text and comments stay with the expressions they were written
+//! next to. A comment on its own line stays before the expression which
+//! follows it, and a comment after code on the same line stays after that
+//! code.
+//!
+//! [`SExpr::pretty`]: crate::sexpr::SExpr::pretty
+
+use crate::error::{ErrorHandler, Failed};
+use crate::parser::{ParseResult, Parser};
+use crate::sourcepos::Span;
+use crate::token::{Tokenizer, Type};
+
+/// Width which formatted source is wrapped to, where possible.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// A piece of a script, with the source text of atoms and comments.
+enum Item<'a> {
+    /// A symbol, number, or string.
+    Atom(&'a [u8]),
+    /// A parenthesized list.
+    List(Vec<Item<'a>>),
+    /// A comment. Trailing comments follow code on the same line.
+    Comment { text: &'a [u8], trailing: bool },
+    /// One or more blank lines between top-level expressions.
+    Blank,
+}
+
+/// Get the width of text in columns, counting each character as one column.
+fn text_width(text: &[u8]) -> usize {
+    text.iter().filter(|&&c| c & 0xc0 != 0x80).count()
+}
+
+impl<'a> Item<'a> {
+    /// Get the item on a single line, or None if it contains comments.
+    fn flat(&self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        if self.flat_impl(&mut out) {
+            Some(out)
+        } else {
+            None
+        }
+    }
+
+    fn flat_impl(&self, out: &mut Vec<u8>) -> bool {
+        match self {
+            Item::Atom(text) => out.extend_from_slice(text),
+            Item::List(items) => {
+                out.push(b'(');
+                for (n, item) in items.iter().enumerate() {
+                    if n > 0 {
+                        out.push(b' ');
+                    }
+                    if !item.flat_impl(out) {
+                        return false;
+                    }
+                }
+                out.push(b')');
+            }
+            Item::Comment { .. } | Item::Blank => return false,
+        }
+        true
+    }
+
+    /// Write an expression starting at the given column.
+    fn write(&self, out: &mut Vec<u8>, column: usize, width: usize) {
+        if let Some(flat) = self.flat() {
+            if column + text_width(&flat) <= width || !matches!(self, Item::List(_)) {
+                out.extend_from_slice(&flat);
+                return;
+            }
+        }
+        let items = match self {
+            Item::List(items) => items,
+            // Atoms are always flat, and comments are written by the list
+            // containing them.
+            _ => unreachable!(),
+        };
+        out.push(b'(');
+        // As in SExpr::pretty, arguments are aligned with the first argument
+        // if the operator is an atom and the first argument fits after it,
+        // and with the operator otherwise.
+        let mut indent = column + 1;
+        let mut same_line = 0;
+        if let [Item::Atom(op), arg, ..] = &items[..] {
+            if let Some(flat) = arg.flat() {
+                let arg_indent = column + 2 + text_width(op);
+                if arg_indent + text_width(&flat) <= width {
+                    indent = arg_indent;
+                    same_line = 2;
+                }
+            }
+        }
+        // True if the next expression must start on a new line.
+        let mut break_line = false;
+        for (n, item) in items.iter().enumerate() {
+            match item {
+                Item::Comment { text, trailing } => {
+                    if *trailing && n > 0 {
+                        out.push(b' ');
+                    } else if n > 0 {
+                        newline(out, indent);
+                    }
+                    write_comment(out, text);
+                    break_line = true;
+                }
+                Item::Blank => (),
+                _ => {
+                    if break_line || n >= same_line.max(1) {
+                        newline(out, indent);
+                    } else if n > 0 {
+                        out.push(b' ');
+                    }
+                    break_line = false;
+                    item.write(out, if n == 0 { column + 1 } else { indent }, width);
+                }
+            }
+        }
+        // A comment runs to the end of the line, so the list must be closed
+        // on the next line.
+        if break_line {
+            newline(out, indent);
+        }
+        out.push(b')');
+    }
+}
+
+/// Start a new line, indented to the given column.
+fn newline(out: &mut Vec<u8>, indent: usize) {
+    out.push(b'\n');
+    out.extend(std::iter::repeat_n(b' ', indent));
+}
+
+/// Write a comment without its trailing whitespace.
+fn write_comment(out: &mut Vec<u8>, text: &[u8]) {
+    let len = text
+        .iter()
+        .rposition(|&c| !c.is_ascii_whitespace())
+        .map_or(0, |n| n + 1);
+    out.extend_from_slice(&text[..len]);
+}
+
+/// Read the items in a script. The script must already have been parsed
+/// without errors.
+fn read_items(text: &[u8]) -> Vec<Item<'_>> {
+    let mut toks = Tokenizer::new(text).unwrap().with_trivia(true);
+    let mut stack: Vec<Vec<Item>> = Vec::new();
+    let mut items = Vec::new();
+    // Number of line breaks since the last token which was not whitespace.
+    let mut line_breaks = 0;
+    // True if the previous token can have a trailing comment after it.
+    let mut after_code = false;
+    loop {
+        let tok = toks.next();
+        if tok.ty == Type::Whitespace {
+            // Count CR LF as one line break.
+            let text = tok.text;
+            line_breaks += (0..text.len())
+                .filter(|&n| {
+                    text[n] == b'\n' || (text[n] == b'\r' && text.get(n + 1) != Some(&b'\n'))
+                })
+                .count();
+            continue;
+        }
+        // Blank lines are only kept between top-level expressions.
+        let blank = stack.is_empty() && line_breaks >= 2;
+        let list = stack.last_mut().unwrap_or(&mut items);
+        let item = match tok.ty {
+            Type::End => break,
+            Type::Comment => {
+                let trailing = after_code && line_breaks == 0;
+                after_code = false;
+                Some(Item::Comment {
+                    text: tok.text,
+                    trailing,
+                })
+            }
+            Type::Open => {
+                after_code = false;
+                None
+            }
+            Type::Close => {
+                after_code = true;
+                let list = Item::List(stack.pop().unwrap());
+                stack.last_mut().unwrap_or(&mut items).push(list);
+                line_breaks = 0;
+                continue;
+            }
+            _ => {
+                after_code = true;
+                Some(Item::Atom(tok.text))
+            }
+        };
+        if blank && !list.is_empty() {
+            list.push(Item::Blank);
+        }
+        match item {
+            Some(item) => list.push(item),
+            None => stack.push(Vec::new()),
+        }
+        line_breaks = 0;
+    }
+    items
+}
+
+/// Format a script, wrapping lines to the given width where possible.
+/// Syntax errors are reported to the error handler, and the script is not
+/// formatted if there are any.
+pub fn format_source(
+    err_handler: &mut dyn ErrorHandler,
+    text: &[u8],
+    width: usize,
+) -> Result<Vec<u8>, Failed> {
+    let mut toks = match Tokenizer::new(text) {
+        Ok(toks) => toks,
+        Err(e) => {
+            err_handler.handle(Span::none(), &e.to_string());
+            return Err(Failed);
+        }
+    };
+    let mut parser = Parser::new();
+    loop {
+        match parser.parse(err_handler, &mut toks) {
+            ParseResult::None => break,
+            ParseResult::Incomplete => {
+                parser.finish(err_handler);
+                return Err(Failed);
+            }
+            ParseResult::Error => return Err(Failed),
+            ParseResult::Value(_) => (),
+        }
+    }
+    let mut out = Vec::new();
+    let mut start = true;
+    for item in read_items(text).iter() {
+        match item {
+            Item::Comment {
+                text,
+                trailing: true,
+            } => {
+                out.push(b' ');
+                write_comment(&mut out, text);
+            }
+            Item::Blank => out.push(b'\n'),
+            _ => {
+                if !start {
+                    out.push(b'\n');
+                }
+                match item {
+                    Item::Comment { text, .. } => write_comment(&mut out, text),
+                    _ => item.write(&mut out, 0, width),
+                }
+            }
+        }
+        start = false;
+    }
+    if !start {
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sexpr::SExpr;
+
+    struct Panic;
+
+    impl ErrorHandler for Panic {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            panic!("error: {}", message);
+        }
+    }
+
+    fn format(text: &str, width: usize) -> String {
+        String::from_utf8(format_source(&mut Panic, text.as_bytes(), width).unwrap()).unwrap()
+    }
+
+    fn parse_all(text: &str) -> Vec<SExpr> {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut parser = Parser::new();
+        let mut exprs = Vec::new();
+        while let ParseResult::Value(expr) = parser.parse(&mut Panic, &mut toks) {
+            exprs.push(expr);
+        }
+        exprs
+    }
+
+    #[test]
+    fn layout() {
+        let cases = [
+            ("  (a   b\n c)", 80, "(a b c)\n"),
+            (
+                "(define x 0x7f) (a 50ms)",
+                80,
+                "(define x 0x7f)\n(a 50ms)\n",
+            ),
+            ("(a b)\n\n\n(c d)", 80, "(a b)\n\n(c d)\n"),
+            (
+                "(envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0))",
+                30,
+                "(envelope (lin 10ms 1.0)\n          (gate)\n          (exp 50ms 0.0))\n",
+            ),
+            ("", 80, ""),
+        ];
+        for &(input, width, expect) in cases.iter() {
+            assert_eq!(format(input, width), expect, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let input = "\
+; Header comment.
+
+(define freq ; trailing after freq
+  ; leading before the value
+  440Hz)
+(sine (oscillator freq)) ; after the call
+; at the end
+";
+        let expect = "\
+; Header comment.
+
+(define freq ; trailing after freq
+        ; leading before the value
+        440Hz)
+(sine (oscillator freq)) ; after the call
+; at the end
+";
+        assert_eq!(format(input, 80), expect);
+        // A comment before a closing parenthesis keeps the list open until
+        // the next line.
+        assert_eq!(format("(a b ; c\n)", 80), "(a b ; c\n   )\n");
+        assert_eq!(format("(a\n; c\nb)", 80), "(a\n ; c\n b)\n");
+    }
+
+    #[test]
+    fn round_trip() {
+        let corpus = [
+            include_str!("../tests/golden/sine.lisp"),
+            include_str!("../tests/golden/filtered_saw.lisp"),
+            include_str!("../tests/golden/note.lisp"),
+            "(a ; 1\n (b ; 2\n c) ; 3\n ; 4\n d)",
+            "(; first\n a b)",
+        ];
+        for text in corpus.iter() {
+            for &width in [0, 20, 80].iter() {
+                let formatted = format(text, width);
+                let (x, y) = (parse_all(text), parse_all(&formatted));
+                assert_eq!(x.len(), y.len(), "{:?}", formatted);
+                for (x, y) in x.iter().zip(y.iter()) {
+                    assert!(x.structurally_eq(y), "{:?}", formatted);
+                }
+                // Every comment is kept, and formatting again changes nothing.
+                assert_eq!(formatted.matches(';').count(), text.matches(';').count());
+                assert_eq!(format(&formatted, width), formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn syntax_error() {
+        struct Count(usize);
+        impl ErrorHandler for Count {
+            fn handle(&mut self, _pos: Span, _message: &str) {
+                self.0 += 1;
+            }
+        }
+        for &text in ["(a b", "(a))", "(a 'b)"].iter() {
+            let mut errors = Count(0);
+            assert_eq!(format_source(&mut errors, text.as_bytes(), 80), Err(Failed));
+            assert!(errors.0 > 0, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::{ErrorHandler, Failed};
+use crate::parser::{ParseResult, Parser, ParserConfig};
+use crate::sexpr::{Content, SExpr};
+use crate::shell::quote_os;
+use crate::sourcepos::Span;
+use crate::sourcetext::SourceMap;
+use crate::token::Tokenizer;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// An error handler which can also record the source files that are read, so
+/// diagnostics in included files can be shown.
+pub trait IncludeHost: ErrorHandler {
+    fn sources_mut(&mut self) -> &mut SourceMap;
+}
+
+/// Splices included files into a program.
+struct Includer<'a, H> {
+    host: &'a mut H,
+    // Directory that the top-level program's includes are relative to.
+    base_dir: PathBuf,
+    // Canonical paths of the files currently being included, outermost first.
+    stack: Vec<PathBuf>,
+    // Options for parsing included files.
+    config: ParserConfig,
+}
+
+/// Replace each top-level (include "path") form in a program with the forms
+/// in the named file. Paths are relative to the directory of the file
+/// containing the include, or to the current directory if the program was
+/// not read from a file. Included files are parsed with the given options.
+pub fn expand_includes<H: IncludeHost>(
+    host: &mut H,
+    path: Option<&Path>,
+    program: Vec<SExpr>,
+    config: ParserConfig,
+) -> Result<Vec<SExpr>, Failed> {
+    let (base_dir, stack) = match path {
+        None => (PathBuf::new(), Vec::new()),
+        Some(path) => {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            (dir, vec![path])
+        }
+    };
+    let mut includer = Includer {
+        host,
+        base_dir,
+        stack,
+        config,
+    };
+    let mut result = Vec::with_capacity(program.len());
+    includer.splice(&mut result, program)?;
+    Ok(result)
+}
+
+/// If the expression is an include form, get the arguments to include.
+fn include_args(expr: &SExpr) -> Option<&[SExpr]> {
+    match &expr.content {
+        Content::List(items) => match items.split_first() {
+            Some((
+                SExpr {
+                    content: Content::Symbol(name),
+                    ..
+                },
+                args,
+            )) if name.as_str() == "include" => Some(args),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<'a, H: IncludeHost> Includer<'a, H> {
+    fn error<T>(&mut self, pos: Span, msg: &str) -> Result<T, Failed> {
+        self.host.handle(pos, msg);
+        Err(Failed)
+    }
+
+    /// Append the forms of a program to the result, expanding includes.
+    fn splice(&mut self, result: &mut Vec<SExpr>, program: Vec<SExpr>) -> Result<(), Failed> {
+        let mut failed = false;
+        for form in program.into_iter() {
+            let status = match include_args(&form) {
+                Some(args) => self.include(result, form.pos, args),
+                None => {
+                    let status = self.check_nested(&form);
+                    result.push(form);
+                    status
+                }
+            };
+            if status.is_err() {
+                failed = true;
+            }
+        }
+        if failed {
+            Err(Failed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Report an error for any include form which is not at the top level.
+    fn check_nested(&mut self, form: &SExpr) -> Result<(), Failed> {
+        let mut nested = None;
+        form.walk(&mut |e| {
+            if nested.is_none() && include_args(e).is_some() {
+                nested = Some(e.pos);
+            }
+        });
+        match nested {
+            None => Ok(()),
+            Some(pos) => self.error(pos, "include is only allowed at the top level"),
+        }
+    }
+
+    /// Read, parse, and splice in a file, given the arguments to include.
+    fn include(
+        &mut self,
+        result: &mut Vec<SExpr>,
+        pos: Span,
+        args: &[SExpr],
+    ) -> Result<(), Failed> {
+        let (arg, name) = match args {
+            [arg @ SExpr {
+                content: Content::String(name),
+                ..
+            }] => (arg, name),
+            _ => return self.error(pos, "invalid include, expected (include \"path\")"),
+        };
+        let dir = match self.stack.last().and_then(|path| path.parent()) {
+            Some(dir) => dir,
+            None => &self.base_dir,
+        };
+        let path = dir.join(name.as_ref());
+        let filename = quote_os(&path);
+        let text = match fs::read(&path) {
+            Ok(text) => text,
+            Err(e) => return self.error(arg.pos, &format!("could not read {}: {}", filename, e)),
+        };
+        let canonical = path.canonicalize().unwrap_or(path);
+        if self.stack.contains(&canonical) {
+            return self.error(
+                arg.pos,
+                &format!("{} includes itself, directly or indirectly", filename),
+            );
+        }
+        let program = self.parse(arg.pos, &filename, &text)?;
+        self.stack.push(canonical);
+        let status = self.splice(result, program);
+        self.stack.pop();
+        status
+    }
+
+    /// Parse all of the forms in a file.
+    fn parse(&mut self, pos: Span, filename: &str, text: &[u8]) -> Result<Vec<SExpr>, Failed> {
+        let start = match self.host.sources_mut().add(filename, text) {
+            Ok(start) => start,
+            Err(e) => return self.error(pos, &format!("could not parse {}: {}", filename, e)),
+        };
+        let mut toks = match Tokenizer::new_at(text, start) {
+            Ok(toks) => toks,
+            Err(e) => return self.error(pos, &format!("could not parse {}: {}", filename, e)),
+        };
+        let mut parser = Parser::new().with_config(self.config);
+        let mut program = Vec::new();
+        loop {
+            match parser.parse(self.host, &mut toks) {
+                ParseResult::None => return Ok(program),
+                ParseResult::Incomplete => {
+                    parser.finish(self.host);
+                    return Err(Failed);
+                }
+                ParseResult::Error => return Err(Failed),
+                ParseResult::Value(expr) => program.push(expr),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Records errors, along with the files that were read.
+    struct Host {
+        errors: Vec<(Span, String)>,
+        sources: SourceMap,
+    }
+
+    impl ErrorHandler for Host {
+        fn handle(&mut self, pos: Span, message: &str) {
+            self.errors.push((pos, message.to_owned()));
+        }
+    }
+
+    impl IncludeHost for Host {
+        fn sources_mut(&mut self) -> &mut SourceMap {
+            &mut self.sources
+        }
+    }
+
+    /// Create an empty temporary directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ultrafxr-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Expand includes in the file at the given path.
+    fn expand_file(path: &Path) -> (Result<Vec<SExpr>, Failed>, Host) {
+        let text = fs::read(path).unwrap();
+        let mut host = Host {
+            errors: Vec::new(),
+            sources: SourceMap::new(),
+        };
+        let start = host.sources.add("<main>", &text).unwrap();
+        let mut toks = Tokenizer::new_at(&text, start).unwrap();
+        let mut parser = Parser::new();
+        let mut program = Vec::new();
+        while let ParseResult::Value(expr) = parser.parse(&mut host, &mut toks) {
+            program.push(expr);
+        }
+        let result = expand_includes(&mut host, Some(path), program, Default::default());
+        (result, host)
+    }
+
+    #[test]
+    fn include() {
+        let dir = temp_dir("include");
+        fs::create_dir(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.lisp"), "(include \"lib/a.lisp\") (f 3)").unwrap();
+        fs::write(dir.join("lib/a.lisp"), "(define x 1)\n(include \"b.lisp\")").unwrap();
+        fs::write(dir.join("lib/b.lisp"), "(defun (f y) (+ x y))").unwrap();
+        let (result, host) = expand_file(&dir.join("main.lisp"));
+        let program = match result {
+            Ok(program) => program,
+            Err(_) => panic!("errors: {:?}", host.errors),
+        };
+        let program: Vec<String> = program.iter().map(|e| e.print()).collect();
+        assert_eq!(program, ["(define x 1)", "(defun (f y) (+ x y))", "(f 3)"]);
+        assert!(host.errors.is_empty(), "{:?}", host.errors);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_missing() {
+        let dir = temp_dir("include-missing");
+        let text = "(define x 1)\n(include \"missing.lisp\")";
+        fs::write(dir.join("main.lisp"), text).unwrap();
+        let (result, host) = expand_file(&dir.join("main.lisp"));
+        assert!(result.is_err());
+        assert_eq!(host.errors.len(), 1, "{:?}", host.errors);
+        let (pos, msg) = &host.errors[0];
+        let start = text.find('"').unwrap() as u32 + 1;
+        assert_eq!(pos.start.0, start);
+        assert_eq!(pos.end.0, text.len() as u32);
+        assert!(msg.contains("could not read"), "{}", msg);
+        assert!(msg.contains("missing.lisp"), "{}", msg);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = temp_dir("include-cycle");
+        fs::write(dir.join("main.lisp"), "(include \"a.lisp\")").unwrap();
+        fs::write(dir.join("a.lisp"), "(include \"b.lisp\")").unwrap();
+        fs::write(dir.join("b.lisp"), "(include \"a.lisp\")").unwrap();
+        let (result, host) = expand_file(&dir.join("main.lisp"));
+        assert!(result.is_err());
+        assert_eq!(host.errors.len(), 1, "{:?}", host.errors);
+        let (pos, msg) = &host.errors[0];
+        assert!(msg.contains("includes itself"), "{}", msg);
+        // The error is reported in b.lisp, where the cycle is closed.
+        let (filename, _) = host.sources.pos(pos.start).unwrap();
+        assert!(filename.contains("b.lisp"), "{}", filename);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_nested() {
+        let dir = temp_dir("include-nested");
+        fs::write(dir.join("main.lisp"), "(define x (include \"a.lisp\"))").unwrap();
+        let (result, host) = expand_file(&dir.join("main.lisp"));
+        assert!(result.is_err());
+        assert_eq!(host.errors.len(), 1, "{:?}", host.errors);
+        assert!(host.errors[0].1.contains("top level"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! UltraFXR renders sound effects from synthesizer scripts.
+//!
+//! Programs can be rendered to a buffer of samples in memory with
+//! [`render::render_program`], or to a WAVE file in memory with
+//! [`render::render_wave`]. Neither touches the filesystem or audio devices,
+//! so they work on targets like WebAssembly. Reading scripts from files with
+//! [`render::render_file`] needs the `fs` feature, which is on by default.
+//!
+//! The command-line tool and the modules only it uses, like the argument
+//! parser and console logger, are behind the `cli` feature, which is also on
+//! by default. Library users can turn it off with `default-features = false`.
+
+#[cfg(feature = "cli")]
+#[macro_use]
+pub mod consolelogger;
+
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(feature = "cli")]
+pub mod cmd_sfx;
+#[cfg(feature = "cli")]
+mod color;
+pub mod error;
+mod evaluate;
+#[cfg(feature = "flacenc")]
+mod flac;
+pub mod format;
+#[cfg(feature = "cli")]
+mod include;
+pub mod note;
+pub mod number;
+pub mod parser;
+#[cfg(feature = "cli")]
+mod raw;
+pub mod render;
+pub mod sexpr;
+pub mod signal;
+pub mod sourcepos;
+#[cfg(feature = "cli")]
+mod sourceprint;
+pub mod sourcetext;
+pub mod symbol;
+pub mod token;
+mod utf8;
+pub mod wave;
+
+#[cfg(feature = "cli")]
+#[allow(dead_code)]
+mod parseargs;
+
+#[allow(dead_code)]
+pub mod units;
+
+#[allow(dead_code)]
+mod rand;
+
+#[cfg(feature = "cli")]
+#[allow(dead_code)]
+mod shell;
+
+#[cfg(test)]
+mod test;
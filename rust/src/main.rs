@@ -1,47 +1,23 @@
-#[macro_use]
-mod consolelogger;
-
-mod cmd_sfx;
-mod color;
-mod error;
-mod evaluate;
-mod note;
-mod number;
-mod parser;
-mod sexpr;
-mod signal;
-mod sourcepos;
-mod sourceprint;
-mod sourcetext;
-mod token;
-mod utf8;
-mod wave;
-
-#[allow(dead_code)]
-mod parseargs;
-
-#[allow(dead_code)]
-mod units;
-
-#[allow(dead_code)]
-mod rand;
-
-#[allow(dead_code)]
-mod shell;
-
-#[cfg(test)]
-mod test;
-
-use crate::error::Failed;
 use std::env;
 use std::process;
+use ultrafxr::cmd_sfx;
+use ultrafxr::error;
+use ultrafxr::error::Failed;
 
 fn main() {
     let mut args = env::args_os();
     // Discard program name.
     args.next();
     let cmd = match cmd_sfx::Command::from_args(args) {
-        Ok(c) => c,
+        Ok(cmd_sfx::Action::Run(c)) => c,
+        Ok(cmd_sfx::Action::Help) => {
+            print!("{}", cmd_sfx::HELP);
+            return;
+        }
+        Ok(cmd_sfx::Action::Version) => {
+            println!("{}", cmd_sfx::version());
+            return;
+        }
         Err(e) => {
             error!("{}", e);
             process::exit(64);
@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::error;
 use std::fmt;
 use std::str::FromStr;
 
@@ -6,6 +7,138 @@ const NOTE_NAMES: [&'static str; 12] = [
     "c", "c#", "d", "d#", "e", "f", "f#", "g", "g#", "a", "a#", "b",
 ];
 
+/// Default frequency of A4, in Hz.
+pub const DEFAULT_TUNING: f64 = 440.0;
+
+/// Get the frequency of a note in equal temperament, in Hz. The note is a MIDI
+/// note value, which may be fractional. The tuning is the frequency of A4 (MIDI
+/// note 69).
+pub fn frequency(note: f32, tuning: f32) -> f32 {
+    tuning * ((note - 69.0) * (1.0 / 12.0)).exp2()
+}
+
+/// Frequency ratios for each interval above the tonic in 5-limit just
+/// intonation.
+const JUST_RATIOS: [f64; 12] = [
+    1.0,
+    16.0 / 15.0,
+    9.0 / 8.0,
+    6.0 / 5.0,
+    5.0 / 4.0,
+    4.0 / 3.0,
+    45.0 / 32.0,
+    3.0 / 2.0,
+    8.0 / 5.0,
+    5.0 / 3.0,
+    9.0 / 5.0,
+    15.0 / 8.0,
+];
+
+/// Frequency ratios for each interval above the tonic in Pythagorean tuning.
+const PYTHAGOREAN_RATIOS: [f64; 12] = [
+    1.0,
+    256.0 / 243.0,
+    9.0 / 8.0,
+    32.0 / 27.0,
+    81.0 / 64.0,
+    4.0 / 3.0,
+    729.0 / 512.0,
+    3.0 / 2.0,
+    128.0 / 81.0,
+    27.0 / 16.0,
+    16.0 / 9.0,
+    243.0 / 128.0,
+];
+
+/// A system for assigning frequencies to the notes in an octave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Temperament {
+    Equal,
+    Just,
+    Pythagorean,
+}
+
+impl Temperament {
+    /// Get the frequency ratio for an interval above the tonic, in semitones.
+    pub fn ratio(self, interval: i32) -> f64 {
+        let octave = interval.div_euclid(12);
+        let step = interval.rem_euclid(12) as usize;
+        let ratio = match self {
+            Temperament::Equal => (step as f64 * (1.0 / 12.0)).exp2(),
+            Temperament::Just => JUST_RATIOS[step],
+            Temperament::Pythagorean => PYTHAGOREAN_RATIOS[step],
+        };
+        ratio * (octave as f64).exp2()
+    }
+}
+
+impl fmt::Display for Temperament {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Temperament::Equal => "equal",
+            Temperament::Just => "just",
+            Temperament::Pythagorean => "pythagorean",
+        })
+    }
+}
+
+impl FromStr for Temperament {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "equal" => Temperament::Equal,
+            "just" => Temperament::Just,
+            "pythagorean" => Temperament::Pythagorean,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Parameters for converting notes to frequencies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    /// Frequency of A4, in Hz.
+    pub reference: f64,
+    pub temperament: Temperament,
+    /// Tonic pitch class, 0-11, with 0 being C. Intervals are tuned relative
+    /// to the tonic, which is tuned to equal temperament.
+    pub tonic: i32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            reference: DEFAULT_TUNING,
+            temperament: Temperament::Equal,
+            tonic: 0,
+        }
+    }
+}
+
+impl Tuning {
+    /// Get the frequency of a note, in Hz. The note is a MIDI note value,
+    /// which may be fractional. Fractional notes are interpolated
+    /// exponentially between the neighboring notes.
+    pub fn frequency(&self, note: f32) -> f32 {
+        if self.temperament == Temperament::Equal {
+            return frequency(note, self.reference as f32);
+        }
+        let tonic = 60 + self.tonic;
+        let base = (tonic - 69) as f64 * (1.0 / 12.0);
+        let base = self.reference * base.exp2();
+        let note = note as f64;
+        let lower = note.floor();
+        let frac = note - lower;
+        let interval = lower as i32 - tonic;
+        let mut ratio = self.temperament.ratio(interval);
+        if frac != 0.0 {
+            let upper = self.temperament.ratio(interval + 1);
+            ratio *= (upper / ratio).powf(frac);
+        }
+        (base * ratio) as f32
+    }
+}
+
 /// A MIDI note value.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Note(pub u8);
@@ -30,6 +163,9 @@ impl fmt::Display for Note {
     }
 }
 
+/// Octave used for notes written without an octave number.
+const DEFAULT_OCTAVE: i32 = 4;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ParseNoteError {
     CannotParse,
@@ -38,11 +174,43 @@ pub enum ParseNoteError {
     OutOfRange,
 }
 
+impl fmt::Display for ParseNoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseNoteError::*;
+        f.write_str(match self {
+            CannotParse => {
+                "invalid note, expected a name like C4, A#3, Db5, or a MIDI number like m60"
+            }
+            UnknownNote => "unknown note name, expected A through G",
+            InvalidAccidentals => "invalid accidentals, expected up to three # or b",
+            OutOfRange => "note is out of range",
+        })
+    }
+}
+
+impl error::Error for ParseNoteError {}
+
 impl FromStr for Note {
     type Err = ParseNoteError;
+    /// Parse a note in scientific pitch notation, like "C4", "A#3", or "Db5".
+    /// The octave defaults to 4 if omitted. MIDI note numbers are written with
+    /// a leading "m", like "m60".
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut chars = s.chars();
         let mut value: i32 = match chars.next().ok_or(ParseNoteError::CannotParse)? {
+            'm' | 'M' => {
+                let rest = chars.as_str();
+                if !rest.bytes().all(|c| c.is_ascii_digit()) {
+                    return Err(ParseNoteError::CannotParse);
+                }
+                return match rest.parse::<u32>() {
+                    Ok(n) => u8::try_from(n)
+                        .map(Note)
+                        .map_err(|_| ParseNoteError::OutOfRange),
+                    Err(_) if rest.is_empty() => Err(ParseNoteError::CannotParse),
+                    Err(_) => Err(ParseNoteError::OutOfRange),
+                };
+            }
             'c' | 'C' => 0,
             'd' | 'D' => 2,
             'e' | 'E' => 4,
@@ -80,9 +248,13 @@ impl FromStr for Note {
             }
             _ => (),
         }
-        let octave = match rest.parse::<i32>() {
-            Ok(n) => n,
-            Err(_) => return Err(ParseNoteError::CannotParse),
+        let octave = if rest.is_empty() {
+            DEFAULT_OCTAVE
+        } else {
+            match rest.parse::<i32>() {
+                Ok(n) => n,
+                Err(_) => return Err(ParseNoteError::CannotParse),
+            }
         };
         if octave < -2 || octave > 20 {
             return Err(ParseNoteError::OutOfRange);
@@ -97,7 +269,7 @@ impl FromStr for Note {
 
 #[cfg(test)]
 mod test {
-    use super::Note;
+    use super::{frequency, Note, ParseNoteError, Temperament, Tuning};
 
     #[test]
     fn octave() {
@@ -136,4 +308,81 @@ mod test {
             assert_eq!(Ok(n), n.to_string().parse::<Note>());
         }
     }
+
+    #[test]
+    fn parse_names() {
+        let cases: &[(&str, u8)] = &[
+            ("A4", 69),
+            ("m69", 69),
+            ("C4", 60),
+            ("C", 60),
+            ("A#3", 58),
+            ("Db5", 73),
+            ("Bb", 70),
+            ("c-1", 0),
+            ("m0", 0),
+            ("M127", 127),
+        ];
+        for &(text, value) in cases.iter() {
+            assert_eq!(text.parse::<Note>(), Ok(Note(value)), "parse {:?}", text);
+        }
+        assert_eq!("A4".parse::<Note>(), "m69".parse::<Note>());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases: &[(&str, ParseNoteError)] = &[
+            ("", ParseNoteError::CannotParse),
+            ("H4", ParseNoteError::UnknownNote),
+            ("C#b4", ParseNoteError::InvalidAccidentals),
+            ("C####", ParseNoteError::InvalidAccidentals),
+            ("C4x", ParseNoteError::CannotParse),
+            ("m", ParseNoteError::CannotParse),
+            ("m-1", ParseNoteError::CannotParse),
+            ("m256", ParseNoteError::OutOfRange),
+            ("C99", ParseNoteError::OutOfRange),
+        ];
+        for &(text, err) in cases.iter() {
+            assert_eq!(text.parse::<Note>(), Err(err), "parse {:?}", text);
+        }
+    }
+
+    #[test]
+    fn note_frequency() {
+        assert_eq!(frequency(69.0, 440.0), 440.0);
+        assert_eq!(frequency(81.0, 440.0), 880.0);
+        assert_eq!(frequency(69.0, 415.0), 415.0);
+        let ratio = frequency(60.0, 415.0) / frequency(60.0, 440.0);
+        assert!((ratio - 415.0 / 440.0).abs() < 1e-6);
+    }
+
+    fn tuning(temperament: Temperament, tonic: i32) -> Tuning {
+        Tuning {
+            temperament,
+            tonic,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn temperament() {
+        let equal = Tuning::default();
+        assert_eq!(equal.frequency(69.0), 440.0);
+        for &temperament in [Temperament::Just, Temperament::Pythagorean].iter() {
+            // Octaves and the tonic are the same in all temperaments.
+            let t = tuning(temperament, 0);
+            for &note in [48.0, 60.0, 72.0].iter() {
+                assert!((t.frequency(note) - equal.frequency(note)).abs() < 1e-3);
+            }
+            // Fifths are pure.
+            let t = tuning(temperament, 2);
+            let ratio = t.frequency(69.0) / t.frequency(62.0);
+            assert!((ratio - 1.5).abs() < 1e-6, "{}: {}", temperament, ratio);
+        }
+        let t = tuning(Temperament::Just, 0);
+        let ratio = t.frequency(64.0) / t.frequency(60.0);
+        assert!((ratio - 1.25).abs() < 1e-6);
+        let f = t.frequency(64.5);
+        assert!(t.frequency(64.0) < f && f < t.frequency(65.0));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::sourcepos::{Pos, Span};
+use crate::sourcepos::Span;
 use std::f64;
 use std::fmt;
 
@@ -11,7 +11,9 @@ pub enum ParseError {
     UnexpectedChar(char),
     NoDigits,
     NoExponentValue,
+    ExponentOutOfRange,
     IntegerTooLarge,
+    NotInteger,
 }
 
 impl fmt::Display for ParseError {
@@ -26,7 +28,13 @@ impl fmt::Display for ParseError {
             UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
             NoDigits => write!(f, "number has no digits"),
             NoExponentValue => write!(f, "missing exponent value"),
+            ExponentOutOfRange => write!(
+                f,
+                "exponent is out of range, must be at most {} in magnitude",
+                MAX_EXPONENT
+            ),
             IntegerTooLarge => write!(f, "integer is too large for 64 bits"),
+            NotInteger => write!(f, "number is not an integer"),
         }
     }
 }
@@ -90,10 +98,14 @@ fn starts_with_hex_digit(s: &str) -> bool {
     }
 }
 
+/// Largest magnitude of an exponent written in a number. Anything larger is
+/// certainly a mistake, since the value would be zero or infinite.
+const MAX_EXPONENT: u32 = 999999;
+
 /// Parse an exponent from a string.
 ///
-/// Return the exponent's value, clamped to the range of i32, and the remainder
-/// of the string after the exponent.
+/// Return the exponent's value and the remainder of the string after the
+/// exponent. Fails if the exponent's magnitude is larger than MAX_EXPONENT.
 fn parse_exponent(text: &str, pos: Span) -> Result<(Option<i32>, &str), (ParseError, Span)> {
     let mut chars = text.chars();
     let mut value: u32 = 0;
@@ -131,21 +143,15 @@ fn parse_exponent(text: &str, pos: Span) -> Result<(Option<i32>, &str), (ParseEr
             pos.sub_span(..text.len() - rest.len()),
         ));
     }
+    if value > MAX_EXPONENT {
+        return Err((
+            ParseError::ExponentOutOfRange,
+            pos.sub_span(..text.len() - rest.len()),
+        ));
+    }
     let value = match sign {
-        Sign::Positive => {
-            if value > i32::max_value() as u32 {
-                i32::max_value()
-            } else {
-                value as i32
-            }
-        }
-        Sign::Negative => {
-            if value > i32::max_value() as u32 {
-                i32::min_value()
-            } else {
-                -(value as i32)
-            }
-        }
+        Sign::Positive => value as i32,
+        Sign::Negative => -(value as i32),
     };
     Ok((Some(value), rest))
 }
@@ -182,33 +188,6 @@ fn make_integer(sign: Sign, radix: Radix, digits: &[u8]) -> Option<i64> {
     Some(r)
 }
 
-/// Powers of 10 which are exact.
-const POWERS_OF_10: [f64; 23] = [
-    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
-    1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
-];
-
-/// Compute a power of 10.
-fn pow10(n: i32) -> f64 {
-    const MAX: i32 = 22;
-    if n <= 0 {
-        1.0
-    } else if n <= MAX {
-        POWERS_OF_10[n as usize]
-    } else {
-        let mut x = POWERS_OF_10[MAX as usize];
-        let mut n = n;
-        while n >= MAX {
-            x *= POWERS_OF_10[MAX as usize];
-            n -= MAX;
-        }
-        if n > 0 {
-            x *= POWERS_OF_10[n as usize];
-        }
-        x
-    }
-}
-
 impl ParsedNumber {
     /// Create an empty parsed number.
     pub fn new() -> Self {
@@ -301,9 +280,15 @@ impl ParsedNumber {
     fn parse_dec<'a>(&mut self, text: &'a str, pos: Span) -> Result<&'a str, (ParseError, Span)> {
         let toklen = text.len();
         self.radix = Radix::Decimal;
-        let (frac_digits, text) = self.parse_mantissa(toklen, text)?;
+        let (frac_digits, text) = self.parse_mantissa(text, pos)?;
         if self.digits.is_empty() {
-            return Err((ParseError::NoDigits, pos));
+            // A point without digits, like "." or ".e3", is not a number. The
+            // error covers the point, or the whole text if there is no point.
+            let len = toklen - text.len();
+            return Err((
+                ParseError::NoDigits,
+                if len > 0 { pos.sub_span(0..len) } else { pos },
+            ));
         }
         let pos = pos.sub_span(toklen - text.len()..);
         self.digits.reverse();
@@ -316,13 +301,7 @@ impl ParsedNumber {
                     -(count as i32)
                 };
                 match exponent {
-                    Some(value) => {
-                        if value == i32::min_value() || value == i32::max_value() {
-                            value
-                        } else {
-                            value.saturating_add(bias)
-                        }
-                    }
+                    Some(value) => value.saturating_add(bias),
                     None => bias,
                 }
             }),
@@ -332,14 +311,16 @@ impl ParsedNumber {
     }
 
     /// Parse the mantissa of a decimal number. Return the number of digits past
-    /// the decimal point and the remainder of the string.
+    /// the decimal point and the remainder of the string. The mantissa may have
+    /// no digits, which the caller must check.
     ///
     /// Pushes the most significant digit first.
     fn parse_mantissa<'a>(
         &mut self,
-        toklen: usize,
         text: &'a str,
+        pos: Span,
     ) -> Result<(Option<usize>, &'a str), (ParseError, Span)> {
+        let toklen = text.len();
         let mut chars = text.chars();
         let point_pos = loop {
             let rest = chars.as_str();
@@ -360,10 +341,7 @@ impl ParsedNumber {
                     '.' => {
                         return Err((
                             ParseError::ExtraPoint,
-                            Span {
-                                start: Pos((toklen - rest.len()) as u32),
-                                end: Pos((toklen - chars.as_str().len()) as u32),
-                            },
+                            pos.sub_span(toklen - rest.len()..toklen - chars.as_str().len()),
                         ));
                     }
                     _ => break rest,
@@ -395,11 +373,11 @@ impl ParsedNumber {
         }
     }
 
-    /// Convert the contained number to an integer. Panics if the contained
+    /// Convert the contained number to an integer. Fails if the contained
     /// number has an exponent or radix point.
     pub fn integer(&self) -> Result<i64, ParseError> {
         if self.exponent.is_some() {
-            panic!("not an integer");
+            return Err(ParseError::NotInteger);
         }
         match make_integer(self.sign, self.radix, self.digits.as_ref()) {
             Some(x) => Ok(x),
@@ -407,57 +385,64 @@ impl ParsedNumber {
         }
     }
 
-    /// Convert the contained number to a floating-point value.
-    ///
-    /// Note: This is a hack for now to avoid pulling in a proper radix
-    /// conversion library. This is the cheap way of doing things, which is only
-    /// correct if the exponent and the precision of the mantissa are within a
-    /// certain (generous) range.
+    /// Convert the contained number to a floating-point value, rounding to
+    /// the nearest representable value.
     pub fn float(&self) -> f64 {
+        use std::fmt::Write;
         if self.radix != Radix::Decimal {
             panic!("cannot convert non-decimal float");
         }
-        if self.digits.len() == 0 {
+        if self.digits.is_empty() {
             return 0.0;
         }
-        // Largest number of digits which will never overflow an i64.
-        // binary -> 63
-        // octal -> 21
-        // hexadecimal -> 15
-        const MAX_LEN: usize = 19;
-        // Number of least significant digits to ignore.
-        let bias = if self.digits.len() <= MAX_LEN {
-            0
-        } else {
-            self.digits.len() - MAX_LEN
-        };
-        let mantissa = match make_integer(Sign::Positive, Radix::Decimal, &self.digits[bias..]) {
-            Some(x) => x,
-            None => panic!("conversion overflow"), // Overflow should not happen (see above).
-        };
-        let mantissa = mantissa as f64;
-        let exponent = self.exponent.unwrap_or(0);
-        let magnitude = if exponent > 0 {
-            // 1e308 rounds to infinity.
-            if exponent >= 308 {
-                f64::INFINITY
-            } else {
-                mantissa * pow10(exponent)
-            }
-        } else if exponent < 0 {
-            // (2*63-1) * 1e-343 rounds to 0.
-            if exponent <= -343 {
-                0.0
-            } else {
-                mantissa / pow10(-exponent)
-            }
-        } else {
-            mantissa
-        };
-        match self.sign {
-            Sign::Positive => magnitude,
-            Sign::Negative => -magnitude,
+        let mut text = String::with_capacity(self.digits.len() + 16);
+        if self.sign == Sign::Negative {
+            text.push('-');
         }
+        text.extend(self.digits.iter().rev().map(|&d| (b'0' + d) as char));
+        write!(text, "e{}", self.exponent.unwrap_or(0)).unwrap();
+        text.parse().unwrap()
+    }
+
+    /// Return true if the given floating-point value is exactly equal to the
+    /// contained number, ignoring sign. Used to check whether float() lost
+    /// precision.
+    pub fn is_exactly(&self, value: f64) -> bool {
+        if !value.is_finite() || self.radix != Radix::Decimal {
+            return false;
+        }
+        // Significant digits of this number, most significant first.
+        let mut digits: &[u8] = &self.digits;
+        let mut exponent = self.exponent.unwrap_or(0) as i64;
+        while let Some((&0, rest)) = digits.split_first() {
+            digits = rest;
+            exponent += 1;
+        }
+        while let Some((&0, rest)) = digits.split_last() {
+            digits = rest;
+        }
+        // Every finite f64 has an exact decimal representation with at most
+        // 767 significant digits, so this formats it exactly.
+        let text = format!("{:.800e}", value.abs());
+        let (mantissa, value_exponent) = text.split_at(text.find('e').unwrap());
+        let mantissa = mantissa.replace('.', "");
+        let value_digits = mantissa.trim_end_matches('0').trim_start_matches('0');
+        if value_digits.is_empty() || digits.is_empty() {
+            return value_digits.is_empty() && digits.is_empty();
+        }
+        let value_exponent: i64 = value_exponent[1..].parse().unwrap();
+        value_digits.len() == digits.len()
+            && value_digits
+                .bytes()
+                .zip(digits.iter().rev())
+                .all(|(c, &d)| c - b'0' == d)
+            && value_exponent - (value_digits.len() as i64 - 1) == exponent
+    }
+}
+
+impl Default for ParsedNumber {
+    fn default() -> Self {
+        ParsedNumber::new()
     }
 }
 
@@ -508,6 +493,7 @@ impl ToString for ParsedNumber {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::sourcepos::Pos;
 
     #[test]
     fn parse_success() {
@@ -579,6 +565,8 @@ mod test {
             ("0b", "b", Positive, Decimal, &[0], None),
             ("0bbb", "bbb", Positive, Decimal, &[0], None),
             ("1.2e3ms", "ms", Positive, Decimal, &[2, 1], Some(2)),
+            (".5e3", "", Positive, Decimal, &[5], Some(2)),
+            ("-.5e-3", "", Negative, Decimal, &[5], Some(-4)),
         ];
         let mut num = ParsedNumber::new();
         for (n, &(input, output, sign, radix, digits, exponent)) in CASES.iter().enumerate() {
@@ -629,6 +617,46 @@ mod test {
         }
     }
 
+    #[test]
+    fn parse_fail() {
+        // Each case is the input, the error, and the range of the input which
+        // the error covers.
+        const CASES: &'static [(&'static str, ParseError, (usize, usize))] = &[
+            (".", ParseError::NoDigits, (0, 1)),
+            ("-.", ParseError::NoDigits, (1, 2)),
+            (".e3", ParseError::NoDigits, (0, 1)),
+            (".ms", ParseError::NoDigits, (0, 1)),
+            ("+x", ParseError::NoDigits, (1, 2)),
+            ("1.2.3", ParseError::ExtraPoint, (3, 4)),
+            ("-.5.", ParseError::ExtraPoint, (3, 4)),
+        ];
+        let mut success = true;
+        let mut num = ParsedNumber::new();
+        for (n, &(input, error, (start, end))) in CASES.iter().enumerate() {
+            let offset: u32 = (1 + n as u32) * 100;
+            let in_span = Span {
+                start: Pos(offset),
+                end: Pos(offset + input.len() as u32),
+            };
+            let expected = Span {
+                start: Pos(offset + start as u32),
+                end: Pos(offset + end as u32),
+            };
+            let output = num.parse(input, in_span);
+            if output != Err((error, expected)) {
+                success = false;
+                eprintln!("Test case {} failed:", n);
+                eprintln!("    Input: {:?}", input);
+                eprintln!("    Output:   {:?}", output);
+                eprintln!("    Expected: {:?}", Err::<&str, _>((error, expected)));
+            }
+        }
+        if !success {
+            eprintln!();
+            panic!("failed");
+        }
+    }
+
     #[test]
     fn integer() {
         const CASES: &'static [(&'static str, i64)] = &[
@@ -705,9 +733,10 @@ mod test {
             "0x8000000000000000",
             "-0x8000000000000001",
         ];
+        const NOT_INTEGER: &'static [&'static str] = &["2.5", "1.0", "1e3", "-0.5"];
         let mut success = true;
         let mut num = ParsedNumber::new();
-        for (n, &input) in CASES.iter().enumerate() {
+        for (n, &input) in CASES.iter().chain(NOT_INTEGER.iter()).enumerate() {
             let in_span = Span {
                 start: Pos(1),
                 end: Pos(1 + input.len() as u32),
@@ -721,7 +750,11 @@ mod test {
                 }
                 Ok(_) => {
                     let output = num.integer();
-                    let expected: Result<i64, ParseError> = Err(ParseError::IntegerTooLarge);
+                    let expected: Result<i64, ParseError> = Err(if NOT_INTEGER.contains(&input) {
+                        ParseError::NotInteger
+                    } else {
+                        ParseError::IntegerTooLarge
+                    });
                     if output != expected {
                         success = false;
                         eprintln!("Test case {} failed:", n);
@@ -747,6 +780,11 @@ mod test {
             ("99999e22", 99999e22),
             ("-0.00001", -0.00001),
             ("1234e-20", 1234e-20),
+            ("1.5e300", 1.5e300),
+            ("2.2250738585072014e-308", 2.2250738585072014e-308),
+            ("0.30000000000000004", 0.30000000000000004),
+            ("1e400", f64::INFINITY),
+            ("1e-400", 0.0),
         ];
         let mut success = true;
         let mut num = ParsedNumber::new();
@@ -779,4 +817,88 @@ mod test {
             panic!("failed");
         }
     }
+
+    #[test]
+    fn exponent_range() {
+        const CASES: &'static [(&'static str, Option<f64>)] = &[
+            ("1e999999", Some(f64::INFINITY)),
+            ("1e-999999", Some(0.0)),
+            ("1.5E+999999", Some(f64::INFINITY)),
+            ("1e1000000", None),
+            ("1e-1000000", None),
+            ("2.5e100000000000", None),
+            ("1e99999999999999999999", None),
+        ];
+        let mut success = true;
+        let mut num = ParsedNumber::new();
+        for (n, &(input, expected)) in CASES.iter().enumerate() {
+            let in_span = Span {
+                start: Pos(100),
+                end: Pos(100 + input.len() as u32),
+            };
+            let output = num.parse(input, in_span).map(|_| num.float());
+            let ok = match (output, expected) {
+                (Ok(x), Some(y)) => x == y,
+                (Err((ParseError::ExponentOutOfRange, span)), None) => {
+                    // The span covers the exponent, starting at the 'e'.
+                    span.start.0 == 100 + input.find('e').unwrap() as u32 && span.end == in_span.end
+                }
+                _ => false,
+            };
+            if !ok {
+                success = false;
+                eprintln!("Test case {} failed:", n);
+                eprintln!("    Input: {:?}", input);
+                eprintln!("    Output:   {:?}", output);
+                eprintln!("    Expected: {:?}", expected);
+            }
+        }
+        if !success {
+            eprintln!();
+            panic!("failed");
+        }
+    }
+
+    #[test]
+    fn is_exactly() {
+        const CASES: &'static [(&'static str, bool)] = &[
+            ("0.0", true),
+            ("-0.000", true),
+            ("0.5", true),
+            ("-2.25", true),
+            ("1e10", true),
+            ("1024.0", true),
+            ("0.1", false),
+            ("44100.0001", false),
+            (
+                "0.1000000000000000055511151231257827021181583404541015625",
+                true,
+            ),
+            ("5e-324", false),
+            ("4.940656458412465441765687928682213723651e-324", false),
+            ("1e400", false),
+            ("1e-400", false),
+        ];
+        let mut success = true;
+        let mut num = ParsedNumber::new();
+        for (n, &(input, expected)) in CASES.iter().enumerate() {
+            let in_span = Span {
+                start: Pos(1),
+                end: Pos(1 + input.len() as u32),
+            };
+            num.parse(input, in_span).unwrap();
+            let output = num.is_exactly(num.float());
+            if output != expected {
+                success = false;
+                eprintln!("Test case {} failed:", n);
+                eprintln!("    Input: {:?}", input);
+                eprintln!("    Output:   {:?}", output);
+                eprintln!("    Expected: {:?}", expected);
+            }
+        }
+        if !success {
+            eprintln!();
+            panic!("failed");
+        }
+    }
 }
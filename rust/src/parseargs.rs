@@ -1,6 +1,6 @@
-use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
+use std::vec;
 
 #[derive(Debug, Clone)]
 pub enum UsageError {
@@ -23,7 +23,9 @@ impl fmt::Display for UsageError {
             MissingArgument { name } => write!(f, "missing argument <{}>", name),
             UnknownOption { option } => write!(f, "unknown option -{}", option),
             OptionMissingParameter { option } => write!(f, "option -{} requires parameter", option),
-            OptionUnexpectedParameter { option } => write!(f, "unknown option {:?}", option),
+            OptionUnexpectedParameter { option } => {
+                write!(f, "option -{} does not take a parameter", option)
+            }
             OptionInvalidValue { option, value } => {
                 write!(f, "invalid value for -{}: {:?}", option, value)
             }
@@ -45,12 +47,12 @@ fn parse_arg(arg: OsString) -> Result<ParsedArg, UsageError> {
     if bytes.len() < 2 || bytes[0] != b'-' {
         return Ok(ParsedArg::Positional(arg));
     }
-    let body = if bytes[1] != b'-' {
-        &bytes[1..]
+    let (body, short) = if bytes[1] != b'-' {
+        (&bytes[1..], true)
     } else if bytes.len() == 2 {
         return Ok(ParsedArg::EndOfFlags);
     } else {
-        &bytes[2..]
+        (&bytes[2..], false)
     };
     let (name, value) = match body.iter().position(|&c| c == b'=') {
         None => (body, None),
@@ -66,64 +68,106 @@ fn parse_arg(arg: OsString) -> Result<ParsedArg, UsageError> {
     let name = Vec::from(name);
     let name = unsafe { String::from_utf8_unchecked(name) };
     let value = value.map(|v| OsString::from_vec(Vec::from(v)));
-    Ok(ParsedArg::Named(name, value))
+    Ok(ParsedArg::Named(name, value, short))
 }
 
 enum ParsedArg {
-    Positional(OsString),            // A positional argument.
-    EndOfFlags,                      // The "--" argument.
-    Named(String, Option<OsString>), // A named option -opt or -opt=value.
+    Positional(OsString), // A positional argument.
+    EndOfFlags,           // The "--" argument.
+    // A named option -opt or -opt=value, and true if it has a single dash.
+    Named(String, Option<OsString>, bool),
 }
 
+/// A table mapping short option names to the equivalent long option names.
+pub type ShortOptions = &'static [(char, &'static str)];
+
 pub struct Args {
-    args: env::ArgsOs,
+    args: vec::IntoIter<OsString>,
     allow_options: bool,
+    shorts: ShortOptions,
+    // Remaining short options from a bundle like -vp.
+    bundle: Vec<char>,
 }
 
 impl Args {
-    pub fn from_args(args: env::ArgsOs) -> Self {
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> Self {
         Args {
-            args,
+            args: args.into_iter().collect::<Vec<_>>().into_iter(),
             allow_options: true,
+            shorts: &[],
+            bundle: Vec::new(),
         }
     }
-    pub fn next(self) -> Result<Arg, UsageError> {
-        let Args {
-            mut args,
-            allow_options,
-        } = self;
-        let arg = match args.next() {
+    /// Recognize short options. A single-dash argument made entirely of short
+    /// option names, like -vp, is treated as a bundle of those options. A short
+    /// option that takes a value consumes the rest of the bundle or, at the
+    /// end of the bundle, the following argument.
+    pub fn with_shorts(self, shorts: ShortOptions) -> Self {
+        Args { shorts, ..self }
+    }
+    fn long_name(&self, c: char) -> Option<&'static str> {
+        self.shorts
+            .iter()
+            .find(|&&(short, _)| short == c)
+            .map(|&(_, long)| long)
+    }
+    fn next_short(mut self) -> Arg {
+        let c = self.bundle.remove(0);
+        let option = self.long_name(c).unwrap().to_string();
+        Arg::Named(NamedArgument {
+            option,
+            option_value: None,
+            args: self,
+        })
+    }
+    pub fn next(mut self) -> Result<Arg, UsageError> {
+        if !self.bundle.is_empty() {
+            return Ok(self.next_short());
+        }
+        let arg = match self.args.next() {
             None => return Ok(Arg::End),
             Some(arg) => arg,
         };
-        let arg = if allow_options {
+        let arg = if self.allow_options {
             parse_arg(arg)?
         } else {
             ParsedArg::Positional(arg)
         };
         Ok(match arg {
-            ParsedArg::Positional(arg) => Arg::Positional(
-                arg,
-                Args {
-                    args,
-                    allow_options,
-                },
-            ),
-            ParsedArg::EndOfFlags => match args.next() {
+            ParsedArg::Positional(arg) => Arg::Positional(arg, self),
+            ParsedArg::EndOfFlags => match self.args.next() {
                 None => Arg::End,
                 Some(arg) => Arg::Positional(
                     arg,
                     Args {
-                        args,
                         allow_options: false,
+                        ..self
                     },
                 ),
             },
-            ParsedArg::Named(name, value) => Arg::Named(NamedArgument {
-                option: name,
-                option_value: value,
-                args,
-            }),
+            ParsedArg::Named(name, value, short) => {
+                if short && name.chars().all(|c| self.long_name(c).is_some()) {
+                    if value.is_some() && name.len() > 1 {
+                        return Err(UsageError::InvalidArgument {
+                            arg: OsString::from(format!("-{}=", name)),
+                        });
+                    }
+                    self.bundle = name.chars().collect();
+                    match self.next_short() {
+                        Arg::Named(option) => Arg::Named(NamedArgument {
+                            option_value: value,
+                            ..option
+                        }),
+                        _ => unreachable!(),
+                    }
+                } else {
+                    Arg::Named(NamedArgument {
+                        option: name,
+                        option_value: value,
+                        args: self,
+                    })
+                }
+            }
         })
     }
 }
@@ -131,7 +175,7 @@ impl Args {
 pub struct NamedArgument {
     option: String,
     option_value: Option<OsString>,
-    args: env::ArgsOs,
+    args: Args,
 }
 
 impl NamedArgument {
@@ -145,20 +189,17 @@ impl NamedArgument {
             mut args,
         } = self;
         let value = match option_value {
-            None => match args.next() {
+            None if !args.bundle.is_empty() => {
+                let value: String = args.bundle.drain(..).collect();
+                OsString::from(value)
+            }
+            None => match args.args.next() {
                 None => return Err(UsageError::OptionMissingParameter { option }),
                 Some(value) => value,
             },
             Some(value) => value,
         };
-        Ok((
-            option,
-            value,
-            Args {
-                args,
-                allow_options: true,
-            },
-        ))
+        Ok((option, value, args))
     }
     pub fn parse_osstr<T, F: FnOnce(&OsStr) -> Option<T>>(
         self,
@@ -179,6 +220,18 @@ impl NamedArgument {
     ) -> Result<(String, T, Args), UsageError> {
         self.parse_osstr(|s| s.to_str().and_then(|s| f(String::from(s).as_str())))
     }
+    pub fn try_parse_str<T, E: fmt::Display, F: FnOnce(&str) -> Result<T, E>>(
+        self,
+        f: F,
+    ) -> Result<(String, T, Args), UsageError> {
+        let (option, value, rest) = self.value_str()?;
+        match f(value.as_str()) {
+            Err(e) => Err(UsageError::Custom {
+                text: format!("invalid value for -{}: {}", option, e),
+            }),
+            Ok(x) => Ok((option, x, rest)),
+        }
+    }
     pub fn no_value(self) -> Result<(String, Args), UsageError> {
         let NamedArgument {
             option,
@@ -188,13 +241,7 @@ impl NamedArgument {
         if option_value.is_some() {
             return Err(UsageError::OptionUnexpectedParameter { option });
         }
-        Ok((
-            option,
-            Args {
-                args,
-                allow_options: true,
-            },
-        ))
+        Ok((option, args))
     }
     pub fn unknown(self) -> UsageError {
         UsageError::UnknownOption {
@@ -208,3 +255,55 @@ pub enum Arg {
     Named(NamedArgument),
     End,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Args {
+        Args::from_args(args.iter().map(OsString::from))
+    }
+
+    fn named(args: Args) -> NamedArgument {
+        match args.next() {
+            Ok(Arg::Named(option)) => option,
+            _ => panic!("expected named argument"),
+        }
+    }
+
+    #[test]
+    fn option_value() {
+        for &arg in [&["-sample-rate=44100"][..], &["--sample-rate", "44100"][..]].iter() {
+            let option = named(args(arg));
+            assert_eq!(option.name(), "sample-rate");
+            let (name, value, rest) = option.value_str().unwrap();
+            assert_eq!(name, "sample-rate");
+            assert_eq!(value, "44100");
+            assert!(matches!(rest.next(), Ok(Arg::End)));
+        }
+        // Only the first "=" separates the name from the value.
+        let (_, value, _) = named(args(&["--x=a=b"])).value_str().unwrap();
+        assert_eq!(value, "a=b");
+        let (_, value, _) = named(args(&["--wav-out="])).value_str().unwrap();
+        assert_eq!(value, "");
+    }
+
+    #[test]
+    fn option_no_value() {
+        let (name, rest) = named(args(&["--play", "x"])).no_value().unwrap();
+        assert_eq!(name, "play");
+        assert!(matches!(rest.next(), Ok(Arg::Positional(..))));
+        match named(args(&["--play=x"])).no_value() {
+            Err(UsageError::OptionUnexpectedParameter { option }) => assert_eq!(option, "play"),
+            _ => panic!("expected error"),
+        }
+    }
+
+    #[test]
+    fn option_missing_value() {
+        match named(args(&["--wav-out"])).value_str() {
+            Err(UsageError::OptionMissingParameter { option }) => assert_eq!(option, "wav-out"),
+            _ => panic!("expected error"),
+        }
+    }
+}
@@ -2,16 +2,34 @@ use crate::error::ErrorHandler;
 use crate::number::ParsedNumber;
 use crate::sexpr::{Content, SExpr};
 use crate::sourcepos::{HasPos, Span};
+use crate::symbol::Symbol;
 use crate::token::{Token, Tokenizer, Type};
 use crate::units::Units;
 use std::fmt::Write;
 use std::str;
 
+/// Options for parsing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParserConfig {
+    warn_inexact: bool,
+}
+
+impl ParserConfig {
+    /// Warn about float literals which cannot be stored exactly, like 0.1,
+    /// showing the value which is stored instead. Off by default.
+    pub fn warn_inexact(self, enabled: bool) -> Self {
+        ParserConfig {
+            warn_inexact: enabled,
+        }
+    }
+}
+
 /// An incremental s-expression parser.
 pub struct Parser {
     exprs: Vec<SExpr>,
     groups: Vec<(Span, usize)>,
     number: ParsedNumber,
+    config: ParserConfig,
 }
 
 /// Get the contents of a token as a string.
@@ -25,14 +43,16 @@ fn tok_str<'a>(tok: &Token<'a>) -> &'a str {
     }
 }
 
-// Convert a token to an owned string.
-fn tok_boxstr(tok: &Token) -> Box<str> {
-    match str::from_utf8(tok.text) {
-        Ok(s) => Box::from(s),
-        // The tokenizer is supposed to check that the non-error tokens are
-        // valid UTF-8, but this is not guaranteed by the type system.
-        Err(_) => panic!("invalid token from tokenizer"),
+/// Get the contents of an escaped symbol or string token, without the
+/// delimiters or escapes.
+fn unescape(tok: &Token) -> String {
+    let text = &tok.text[1..tok.text.len() - 1];
+    let mut name = Vec::with_capacity(text.len());
+    let mut iter = text.iter();
+    while let Some(&c) = iter.next() {
+        name.push(if c == b'\\' { *iter.next().unwrap() } else { c });
     }
+    String::from_utf8_lossy(&name).into_owned()
 }
 
 /// A result from running the parser.
@@ -48,14 +68,14 @@ fn handle_error_token(err_handler: &mut dyn ErrorHandler, pos: Span, text: &[u8]
     let msg: String = match str::from_utf8(text) {
         Ok(s) => match s.chars().next() {
             Some(c) => {
-                if c <= '\x1f' || ('\u{7f}' <= c && c <= '\u{9f}') {
+                if c == '|' {
+                    "unterminated symbol, missing closing '|'".to_owned()
+                } else if c == '"' {
+                    "unterminated string, missing closing '\"'".to_owned()
+                } else if c <= '\x1f' || ('\u{7f}'..='\u{9f}').contains(&c) {
                     format!("unexpected control character U+{:04X}", c as u32)
                 } else if c <= '\u{7f}' {
-                    if c == '\'' {
-                        "unexpected character <'>".to_owned()
-                    } else {
-                        format!("unexpected character '{}'", c)
-                    }
+                    format!("unexpected character '{}'", c)
                 } else {
                     format!("unexpected Unicode character U+{:04X}", c as u32)
                 }
@@ -84,9 +104,15 @@ impl Parser {
             exprs: Vec::new(),
             groups: Vec::new(),
             number: ParsedNumber::new(),
+            config: Default::default(),
         };
     }
 
+    /// Use the given parsing options.
+    pub fn with_config(self, config: ParserConfig) -> Self {
+        Parser { config, ..self }
+    }
+
     /// Parse the next s-expression from the token stream.
     ///
     /// If the stream ends without producing a complete s-expression, parse()
@@ -111,11 +137,16 @@ impl Parser {
                     handle_error_token(err_handler, pos, tok.text);
                     return ParseResult::Error;
                 }
-                Type::Comment => {}
+                Type::Comment | Type::Whitespace => {}
                 Type::Symbol => {
+                    let name = if tok.text.starts_with(b"|") {
+                        Symbol::new(&unescape(&tok))
+                    } else {
+                        Symbol::new(tok_str(&tok))
+                    };
                     let expr = SExpr {
                         pos,
-                        content: Content::Symbol(tok_boxstr(&tok)),
+                        content: Content::Symbol(name),
                     };
                     if self.groups.is_empty() {
                         return ParseResult::Value(expr);
@@ -133,17 +164,28 @@ impl Parser {
                     }
                     self.exprs.push(expr);
                 }
-                Type::ParenOpen => {
+                Type::Quote => {
+                    err_handler.handle(pos, "quoted expressions are not supported");
+                    return ParseResult::Error;
+                }
+                Type::String => {
+                    let expr = SExpr {
+                        pos,
+                        content: Content::String(unescape(&tok).into()),
+                    };
+                    if self.groups.is_empty() {
+                        return ParseResult::Value(expr);
+                    }
+                    self.exprs.push(expr);
+                }
+                Type::Open => {
                     self.groups.push((pos, self.exprs.len()));
                 }
-                Type::ParenClose => match self.groups.pop() {
+                Type::Close => match self.groups.pop() {
                     Some((start_pos, offset)) => {
                         let items: Box<[SExpr]> = self.exprs.drain(offset..).collect();
                         let expr = SExpr {
-                            pos: Span {
-                                start: start_pos.start,
-                                end: pos.source_pos().end,
-                            },
+                            pos: start_pos.merge(pos),
                             content: Content::List(items),
                         };
                         if self.groups.is_empty() {
@@ -192,7 +234,14 @@ impl Parser {
         }
         self.number.trim();
         Some(if self.number.exponent.is_some() {
-            Content::Float(units, self.number.float())
+            let value = self.number.float();
+            if self.config.warn_inexact && !self.number.is_exactly(value) {
+                err_handler.warning(
+                    tokpos,
+                    &format!("number cannot be stored exactly, stored as {:.19e}", value),
+                );
+            }
+            Content::Float(units, value)
         } else {
             let x = match self.number.integer() {
                 Ok(x) => x,
@@ -205,3 +254,95 @@ impl Parser {
         })
     }
 }
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sourcepos::Pos;
+
+    struct Panic;
+
+    impl ErrorHandler for Panic {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            panic!("unexpected error: {}", message);
+        }
+    }
+
+    #[test]
+    fn shape() {
+        let span = |start, end| Span {
+            start: Pos(start),
+            end: Pos(end),
+        };
+        let mut toks = Tokenizer::new(b"(a 1)").unwrap();
+        let expr = match Parser::new().parse(&mut Panic, &mut toks) {
+            ParseResult::Value(expr) => expr,
+            _ => panic!("expected value"),
+        };
+        assert_eq!(expr.pos, span(1, 6));
+        let items = match &expr.content {
+            Content::List(items) => items,
+            c => panic!("expected list, got {:?}", c),
+        };
+        match &items[..] {
+            [SExpr {
+                pos: name_pos,
+                content: Content::Symbol(name),
+            }, SExpr {
+                pos: arg_pos,
+                content: Content::Integer(units, 1),
+            }] => {
+                assert_eq!(name.as_str(), "a");
+                assert_eq!(*name_pos, span(2, 3));
+                assert!(units.is_scalar());
+                assert_eq!(*arg_pos, span(4, 5));
+            }
+            _ => panic!("unexpected items: {:?}", items),
+        }
+    }
+
+    /// Error handler which records warnings, and fails on errors.
+    struct Warnings(Vec<(Span, String)>);
+
+    impl ErrorHandler for Warnings {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            panic!("unexpected error: {}", message);
+        }
+
+        fn warning(&mut self, pos: Span, message: &str) {
+            self.0.push((pos, message.to_owned()));
+        }
+    }
+
+    fn parse_warnings(text: &str, config: ParserConfig) -> Vec<(Span, String)> {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut parser = Parser::new().with_config(config);
+        let mut warnings = Warnings(Vec::new());
+        while let ParseResult::Value(_) = parser.parse(&mut warnings, &mut toks) {}
+        warnings.0
+    }
+
+    #[test]
+    fn warn_inexact() {
+        let config = ParserConfig::default().warn_inexact(true);
+        assert!(parse_warnings("(a 0.5 -2.25 1e3 440Hz)", config).is_empty());
+        assert!(parse_warnings("(a 0.1)", ParserConfig::default()).is_empty());
+        let warnings = parse_warnings("(a 0.1)", config);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        let (pos, message) = &warnings[0];
+        assert_eq!(
+            *pos,
+            Span {
+                start: Pos(4),
+                end: Pos(7)
+            }
+        );
+        assert!(message.contains("1.0000000000000000555e-1"), "{}", message);
+    }
+}
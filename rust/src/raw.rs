@@ -0,0 +1,96 @@
+use crate::rand::Rand;
+use crate::wave::dither_i16;
+use std::io::{Result as IOResult, Write};
+use std::str::FromStr;
+
+/// Byte order of samples in a raw file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl FromStr for Endian {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "little" => Endian::Little,
+            "big" => Endian::Big,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Writer for raw, headerless 16-bit PCM. Channels are interleaved.
+pub struct Writer<'a> {
+    stream: &'a mut dyn Write,
+    endian: Endian,
+    buf: Vec<u8>,
+    rand: Rand,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a raw writer from the given stream.
+    pub fn from_stream(stream: &'a mut dyn Write, endian: Endian) -> Self {
+        Writer {
+            stream,
+            endian,
+            buf: Vec::new(),
+            rand: Rand::with_default_seed(),
+        }
+    }
+
+    /// Write floating-point samples to the file. These samples will be
+    /// converted to 16-bit, with the same dither as WAVE output.
+    pub fn write(&mut self, data: &[f32]) -> IOResult<()> {
+        self.buf.clear();
+        for &x in data.iter() {
+            let x = dither_i16(&mut self.rand, x);
+            let bytes = match self.endian {
+                Endian::Little => x.to_le_bytes(),
+                Endian::Big => x.to_be_bytes(),
+            };
+            self.buf.extend_from_slice(&bytes);
+        }
+        self.stream.write_all(&self.buf)
+    }
+
+    /// Finish writing the file.
+    pub fn finish(self) -> IOResult<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_raw(data: &[f32], endian: Endian) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut writer = Writer::from_stream(&mut out, endian);
+        writer.write(data).unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn samples() {
+        // Multiples of the LSB are not changed by dither.
+        let input = [0.5, -0.25, 2.0, -1.0, 0.0];
+        assert_eq!(
+            write_raw(&input, Endian::Little),
+            [0x00, 0x40, 0x00, 0xe0, 0xff, 0x7f, 0x00, 0x80, 0x00, 0x00]
+        );
+        assert_eq!(
+            write_raw(&input, Endian::Big),
+            [0x40, 0x00, 0xe0, 0x00, 0x7f, 0xff, 0x80, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn parse_endian() {
+        assert_eq!("little".parse(), Ok(Endian::Little));
+        assert_eq!("big".parse(), Ok(Endian::Big));
+        assert_eq!("middle".parse::<Endian>(), Err(()));
+    }
+}
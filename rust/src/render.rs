@@ -0,0 +1,842 @@
+use crate::error::ErrorHandler;
+use crate::evaluate::evaluate_program;
+use crate::parser::{ParseResult, Parser};
+use crate::signal::graph::{Graph, SignalRef};
+use crate::signal::program::{Error as ProgramError, Input, Parameters, Program};
+use crate::sourcepos::Span;
+use crate::token::Tokenizer;
+use crate::wave;
+use std::error;
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+/// A diagnostic message reported to the error handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub pos: Span,
+    pub message: String,
+}
+
+/// Error returned when rendering fails. Diagnostics are also reported to the
+/// error handler as they occur.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The source text could not be tokenized.
+    Tokenize(String),
+    /// The source text contains syntax errors.
+    Parse(Vec<Diagnostic>),
+    /// The program could not be evaluated.
+    Evaluate(Vec<Diagnostic>),
+    /// The signal graph contains a cycle through the given signals, in order.
+    GraphCycle(Vec<SignalRef>),
+    /// The program could not be instantiated from the signal graph.
+    Program(String),
+    /// The source file could not be read.
+    Io(io::Error),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RenderError::*;
+        match self {
+            Tokenize(msg) => write!(f, "could not tokenize program: {}", msg),
+            Parse(diagnostics) => write!(f, "syntax error ({} errors)", diagnostics.len()),
+            Evaluate(diagnostics) => write!(
+                f,
+                "could not evaluate program ({} errors)",
+                diagnostics.len()
+            ),
+            GraphCycle(signals) => {
+                f.write_str("audio graph contains cycle:")?;
+                for sig in signals.iter() {
+                    write!(f, " #{}", sig.0)?;
+                }
+                Ok(())
+            }
+            Program(msg) => write!(f, "could not create program: {}", msg),
+            Io(e) => write!(f, "could not read program: {}", e),
+        }
+    }
+}
+
+impl error::Error for RenderError {}
+
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self {
+        RenderError::Io(e)
+    }
+}
+
+/// Error handler which forwards messages to another handler and records them.
+struct Recorder<'a> {
+    inner: &'a mut dyn ErrorHandler,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> ErrorHandler for Recorder<'a> {
+    fn handle(&mut self, pos: Span, message: &str) {
+        self.inner.handle(pos, message);
+        self.diagnostics.push(Diagnostic {
+            pos,
+            message: message.to_owned(),
+        });
+    }
+
+    fn warning(&mut self, pos: Span, message: &str) {
+        self.inner.warning(pos, message);
+    }
+}
+
+/// MIDI velocity of notes which do not specify one.
+pub const DEFAULT_VELOCITY: u8 = 64;
+
+/// Default maximum number of notes which sound at once.
+pub const DEFAULT_MAX_VOICES: usize = 8;
+
+/// A note to play.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteEvent {
+    /// MIDI note value.
+    pub note: f32,
+    /// Note velocity, from 0 to 1.
+    pub velocity: f32,
+}
+
+impl NoteEvent {
+    /// Create a note with the default velocity.
+    pub fn new(note: f32) -> Self {
+        NoteEvent::with_velocity(note, DEFAULT_VELOCITY)
+    }
+
+    /// Create a note with the given MIDI velocity, from 0 to 127.
+    pub fn with_velocity(note: f32, velocity: u8) -> Self {
+        NoteEvent {
+            note,
+            velocity: f32::from(velocity.min(127)) / 127.0,
+        }
+    }
+}
+
+/// Parameters for rendering a program to a buffer.
+#[derive(Debug)]
+pub struct RenderParams {
+    /// Parameters for instantiating the program.
+    pub parameters: Parameters,
+    /// Chords to play, one after another. Each note in a chord is a separate
+    /// voice, with its own instance of the program, and the voices are
+    /// summed.
+    pub notes: Vec<Vec<NoteEvent>>,
+    /// Length of the gate for each note, in samples.
+    pub gate: usize,
+    /// Maximum length of the output for each chord, in samples. Output
+    /// stops here if the program has not stopped already.
+    pub max_length: usize,
+    /// Maximum number of voices in a chord. Notes past the limit are
+    /// dropped, starting from the end of the chord.
+    pub max_voices: usize,
+}
+
+impl Default for RenderParams {
+    fn default() -> Self {
+        RenderParams {
+            parameters: Default::default(),
+            notes: vec![vec![NoteEvent::new(69.0)]],
+            gate: 24000,
+            max_length: 480000,
+            max_voices: DEFAULT_MAX_VOICES,
+        }
+    }
+}
+
+/// Tokenize, parse, and evaluate a program, returning the optimized graph and
+/// its output signal.
+pub fn compile_program(
+    err_handler: &mut dyn ErrorHandler,
+    source: &str,
+) -> Result<(Graph, SignalRef), RenderError> {
+    let mut toks = match Tokenizer::new(source.as_bytes()) {
+        Ok(toks) => toks,
+        Err(e) => {
+            let message = e.to_string();
+            err_handler.handle(Span::none(), &message);
+            return Err(RenderError::Tokenize(message));
+        }
+    };
+    let mut recorder = Recorder {
+        inner: err_handler,
+        diagnostics: Vec::new(),
+    };
+    let mut parser = Parser::new();
+    let mut exprs = Vec::new();
+    loop {
+        match parser.parse(&mut recorder, &mut toks) {
+            ParseResult::None => break,
+            ParseResult::Incomplete => {
+                parser.finish(&mut recorder);
+                return Err(RenderError::Parse(recorder.diagnostics));
+            }
+            ParseResult::Error => return Err(RenderError::Parse(recorder.diagnostics)),
+            ParseResult::Value(expr) => exprs.push(expr),
+        }
+    }
+    let (mut graph, root) = match evaluate_program(&mut recorder, exprs.as_ref()) {
+        Ok(result) => result,
+        Err(_) => return Err(RenderError::Evaluate(recorder.diagnostics)),
+    };
+    graph.fold_constants();
+    graph.dedup();
+    Ok((graph, root))
+}
+
+/// Renders a program one block at a time. Each block is at most the buffer
+/// size of the program parameters, so memory use does not depend on the
+/// length of the output.
+pub struct BlockRenderer {
+    /// Voices for the chords which have not finished, in reverse order.
+    chords: Vec<Vec<(NoteEvent, Program)>>,
+    /// Sum of the voices in the current block.
+    mix: Vec<f32>,
+    buffer_size: usize,
+    gate: usize,
+    max_length: usize,
+    length: Option<usize>,
+    /// Position in the current chord.
+    pos: usize,
+    /// Number of samples rendered for previous chords.
+    done: usize,
+    progress: Option<Box<dyn FnMut(usize, usize)>>,
+}
+
+impl BlockRenderer {
+    /// Create a renderer for the given signal in a graph.
+    pub fn new(graph: &Graph, root: SignalRef, params: &RenderParams) -> Result<Self, RenderError> {
+        let mut chords = Vec::with_capacity(params.notes.len());
+        for chord in params.notes.iter().rev() {
+            let mut voices = Vec::new();
+            for &note in chord.iter().take(params.max_voices) {
+                let program = match Program::new(graph, root, &params.parameters) {
+                    Ok(program) => program,
+                    Err(e) => {
+                        return Err(match e.downcast_ref::<ProgramError>() {
+                            Some(ProgramError::ContainsLoop(signals)) => {
+                                RenderError::GraphCycle(signals.clone())
+                            }
+                            _ => RenderError::Program(e.to_string()),
+                        })
+                    }
+                };
+                voices.push((note, program));
+            }
+            chords.push(voices);
+        }
+        let length = match graph.length(root, &params.parameters, params.gate) {
+            Some(length) => length.min(params.max_length),
+            None => params.max_length,
+        };
+        let mut renderer = BlockRenderer {
+            chords,
+            mix: vec![0.0; params.parameters.buffer_size],
+            buffer_size: params.parameters.buffer_size,
+            gate: params.gate,
+            max_length: params.max_length,
+            length: if length == usize::MAX {
+                None
+            } else {
+                length.checked_mul(params.notes.len())
+            },
+            pos: 0,
+            done: 0,
+            progress: None,
+        };
+        renderer.skip_done();
+        Ok(renderer)
+    }
+
+    /// Get the total length of the output in samples, if it is known in
+    /// advance.
+    pub fn length(&self) -> Option<usize> {
+        self.length
+    }
+
+    /// Set a function to call after each block is rendered, with the number
+    /// of samples rendered so far and the total length. The function is only
+    /// called if the length is known.
+    pub fn set_progress(&mut self, callback: impl FnMut(usize, usize) + 'static) {
+        self.progress = Some(Box::new(callback));
+    }
+
+    /// Return true if the output is complete.
+    pub fn is_done(&self) -> bool {
+        self.chords.is_empty()
+    }
+
+    /// Remove chords from the front of the queue once all of their voices
+    /// are done.
+    fn skip_done(&mut self) {
+        while let Some(voices) = self.chords.last() {
+            if self.pos < self.max_length && voices.iter().any(|(_, p)| !p.is_done()) {
+                break;
+            }
+            self.chords.pop();
+            self.done += self.pos.min(self.max_length);
+            self.pos = 0;
+        }
+    }
+
+    /// Render the next block, or return None if the output is complete.
+    pub fn next_block(&mut self) -> Option<&[f32]> {
+        let voices = self.chords.last_mut()?;
+        let pos = self.pos;
+        let end = self.gate;
+        let gate = if pos < end && end - pos < self.buffer_size {
+            Some(end - pos)
+        } else {
+            None
+        };
+        // Voices may stop at different times, so the block is as long as the
+        // longest voice.
+        let mut len = 0;
+        for (note, program) in voices.iter_mut() {
+            let output = match program.render(&Input {
+                gate,
+                note: note.note,
+                velocity: note.velocity,
+            }) {
+                Some(output) => output,
+                None => continue,
+            };
+            let mixed = len.min(output.len());
+            for (y, &x) in self.mix[..mixed].iter_mut().zip(output.iter()) {
+                *y += x;
+            }
+            if output.len() > len {
+                self.mix[len..output.len()].copy_from_slice(&output[len..]);
+                len = output.len();
+            }
+        }
+        self.pos += len;
+        let len = len.min(self.max_length - pos);
+        self.skip_done();
+        if let (Some(callback), Some(length)) = (&mut self.progress, self.length) {
+            callback((self.done + self.pos).min(length), length);
+        }
+        Some(&self.mix[..len])
+    }
+}
+
+/// Render a signal graph to a buffer of samples.
+pub fn render_graph(
+    graph: &Graph,
+    root: SignalRef,
+    params: &RenderParams,
+) -> Result<Vec<f32>, RenderError> {
+    let mut renderer = BlockRenderer::new(graph, root, params)?;
+    let mut samples = Vec::new();
+    while let Some(block) = renderer.next_block() {
+        samples.extend_from_slice(block);
+    }
+    Ok(samples)
+}
+
+/// A measure of level for normalizing output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalize {
+    /// Largest absolute sample value.
+    Peak,
+    /// Root mean square of the samples.
+    Rms,
+}
+
+impl FromStr for Normalize {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "peak" => Normalize::Peak,
+            "rms" => Normalize::Rms,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Scale samples so their level is the target, in decibels relative to full
+/// scale. Silent input is left unchanged.
+pub fn normalize(samples: &mut [f32], mode: Normalize, target_db: f64) {
+    let level = match mode {
+        Normalize::Peak => samples.iter().fold(0.0f64, |m, &x| m.max((x as f64).abs())),
+        Normalize::Rms => {
+            if samples.is_empty() {
+                return;
+            }
+            let sum: f64 = samples.iter().map(|&x| x as f64 * x as f64).sum();
+            (sum / samples.len() as f64).sqrt()
+        }
+    };
+    if level == 0.0 {
+        return;
+    }
+    let scale = (10.0f64.powf(target_db / 20.0) / level) as f32;
+    for x in samples.iter_mut() {
+        *x *= scale;
+    }
+}
+
+/// Look-ahead peak limiter, which keeps the output within ±1.
+///
+/// The gain needed for each sample is known a short time before the sample is
+/// output, so the gain can ramp down before a peak instead of clipping it.
+/// The output has the same length and timing as the input.
+#[derive(Debug)]
+pub struct Limiter {
+    /// Delayed input samples, as a circular buffer.
+    delay: Box<[f32]>,
+    /// Gain required by each sample in the delay buffer.
+    required: Box<[f32]>,
+    pos: usize,
+    /// Number of samples to discard from the start of the output, so the
+    /// output is not delayed.
+    skip: usize,
+    gain: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl Limiter {
+    /// Look-ahead time, in seconds.
+    const LOOKAHEAD: f64 = 0.005;
+    /// Release time constant, in seconds.
+    const RELEASE: f64 = 0.05;
+
+    /// Create a limiter for the given sample rate.
+    pub fn new(sample_rate: f64) -> Self {
+        let len = ((Limiter::LOOKAHEAD * sample_rate).round() as usize).max(1);
+        // The attack reaches the target well within the look-ahead time.
+        let attack = -(-4.0 / len as f64).exp_m1() as f32;
+        let release = -(-1.0 / (Limiter::RELEASE * sample_rate)).exp_m1() as f32;
+        Limiter {
+            delay: vec![0.0; len].into_boxed_slice(),
+            required: vec![1.0; len].into_boxed_slice(),
+            pos: 0,
+            skip: len,
+            gain: 1.0,
+            attack,
+            release,
+        }
+    }
+
+    /// Process one sample, returning the delayed output sample.
+    fn next(&mut self, x: f32) -> f32 {
+        let pos = self.pos;
+        let y = self.delay[pos];
+        let y_required = self.required[pos];
+        self.delay[pos] = x;
+        self.required[pos] = if x.abs() > 1.0 { 1.0 / x.abs() } else { 1.0 };
+        self.pos = if pos + 1 == self.delay.len() {
+            0
+        } else {
+            pos + 1
+        };
+        let target = self.required.iter().fold(y_required, |m, &g| m.min(g));
+        let coeff = if target < self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += (target - self.gain) * coeff;
+        y * self.gain.min(y_required)
+    }
+
+    /// Limit samples, appending the output to a buffer. The output lags the
+    /// input, so it is shorter until finish is called.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for &x in input.iter() {
+            let y = self.next(x);
+            if self.skip > 0 {
+                self.skip -= 1;
+            } else {
+                output.push(y);
+            }
+        }
+    }
+
+    /// Append the remaining samples to the output.
+    pub fn finish(&mut self, output: &mut Vec<f32>) {
+        let remaining = self.delay.len() - self.skip;
+        self.skip = 0;
+        for _ in 0..remaining {
+            let y = self.next(0.0);
+            output.push(y);
+        }
+    }
+}
+
+/// Linear gain ramps at the start and end of a stream of samples, which
+/// remove clicks when the sound starts or ends mid-cycle.
+///
+/// The first sample of a fade-in and the last sample of a fade-out are zero.
+/// The length of the stream is not known in advance, so the last samples are
+/// held back until finish is called.
+#[derive(Debug)]
+pub struct Fade {
+    fade_in: usize,
+    fade_out: usize,
+    /// Number of samples which have been output.
+    pos: usize,
+    /// Samples which have not been output yet.
+    pending: Vec<f32>,
+}
+
+impl Fade {
+    /// Create fades with the given lengths, in samples.
+    pub fn new(fade_in: usize, fade_out: usize) -> Self {
+        Fade {
+            fade_in,
+            fade_out,
+            pos: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Apply the fades to pending samples and move them to the output. The
+    /// fades have the given lengths, and the stream has the given total
+    /// length.
+    fn flush(
+        &mut self,
+        count: usize,
+        fade_in: usize,
+        fade_out: usize,
+        total: usize,
+        output: &mut Vec<f32>,
+    ) {
+        for (i, &x) in self.pending[..count].iter().enumerate() {
+            let n = self.pos + i;
+            let mut gain = 1.0;
+            if n < fade_in {
+                gain = n as f32 / fade_in as f32;
+            }
+            let remaining = total - 1 - n;
+            if remaining < fade_out {
+                gain *= remaining as f32 / fade_out as f32;
+            }
+            output.push(x * gain);
+        }
+        self.pending.drain(..count);
+        self.pos += count;
+    }
+
+    /// Fade samples, appending the output to a buffer. The output lags the
+    /// input, so it is shorter until finish is called.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.pending.extend_from_slice(input);
+        let total = self.pos + self.pending.len();
+        // Samples are held until the fade-in is known to fit, and the
+        // fade-out is always held.
+        if total < self.fade_in {
+            return;
+        }
+        let count = self.pending.len().saturating_sub(self.fade_out);
+        self.flush(count, self.fade_in, 0, total, output);
+    }
+
+    /// Append the remaining samples to the output. Returns false if either
+    /// fade was longer than the stream, in which case it is shortened to fit.
+    pub fn finish(&mut self, output: &mut Vec<f32>) -> bool {
+        let total = self.pos + self.pending.len();
+        let fade_in = self.fade_in.min(total);
+        let fade_out = self.fade_out.min(total);
+        self.flush(self.pending.len(), fade_in, fade_out, total, output);
+        self.fade_in <= total && self.fade_out <= total
+    }
+}
+
+/// Render a program to a buffer of samples. Diagnostics are reported to the
+/// error handler.
+pub fn render_program(
+    err_handler: &mut dyn ErrorHandler,
+    source: &str,
+    params: &RenderParams,
+) -> Result<Vec<f32>, RenderError> {
+    let (graph, root) = compile_program(err_handler, source)?;
+    let result = render_graph(&graph, root, params);
+    if let Err(ref e) = result {
+        err_handler.handle(Span::none(), &e.to_string());
+    }
+    result
+}
+
+/// Render a program to a mono, 16-bit WAVE file in memory. Diagnostics are
+/// reported to the error handler.
+pub fn render_wave(
+    err_handler: &mut dyn ErrorHandler,
+    source: &str,
+    params: &RenderParams,
+) -> Result<Vec<u8>, RenderError> {
+    let samples = render_program(err_handler, source, params)?;
+    let mut stream = io::Cursor::new(Vec::new());
+    let mut writer = wave::Writer::from_stream(
+        &mut stream,
+        &wave::Parameters {
+            channel_count: 1,
+            sample_rate: params.parameters.sample_rate.round() as u32,
+        },
+    );
+    // Writing to memory does not fail.
+    writer.write(&samples).unwrap();
+    writer.finish().unwrap();
+    Ok(stream.into_inner())
+}
+
+/// Read a program from a file and render it to a buffer of samples.
+#[cfg(feature = "fs")]
+pub fn render_file(
+    err_handler: &mut dyn ErrorHandler,
+    path: &Path,
+    params: &RenderParams,
+) -> Result<Vec<f32>, RenderError> {
+    let source = fs::read_to_string(path)?;
+    render_program(err_handler, &source, params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::ops;
+
+    /// Error handler which discards the messages it receives.
+    struct Ignore;
+
+    impl ErrorHandler for Ignore {
+        fn handle(&mut self, _pos: Span, _message: &str) {}
+    }
+
+    #[test]
+    fn empty_program() {
+        for &source in ["", "  \n\t", "; only a comment\n"].iter() {
+            match compile_program(&mut Ignore, source) {
+                Err(RenderError::Evaluate(diagnostics)) => {
+                    assert_eq!(diagnostics.len(), 1, "{:?}", source);
+                    let Diagnostic { pos, message } = &diagnostics[0];
+                    assert_eq!(*pos, Span::none());
+                    assert!(message.contains("no signal"), "{:?}", message);
+                }
+                r => panic!("unexpected result for {:?}: {:?}", source, r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn graph_cycle() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Constant { value: 1.0 }));
+        let y = graph.add(Box::new(ops::Constant { value: 2.0 }));
+        let sum = graph.add(Box::new(ops::Add { inputs: [x, y] }));
+        let product = graph.add(Box::new(ops::Multiply { inputs: [sum, y] }));
+        let root = graph.add(Box::new(ops::Negate { input: product }));
+        // Feed the product back into the sum.
+        graph.nodes_mut()[sum.0 as usize].inputs_mut()[1] = product;
+        match render_graph(&graph, root, &Default::default()) {
+            Err(RenderError::GraphCycle(signals)) => assert_eq!(signals, vec![product, sum]),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn block_size() {
+        let source = "(* (lowPass2 (+ (sawtooth (oscillator (note 0))) (noise)) (frequency (envelope (set 0.5))) 0.7) \
+                      (envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0) (stop)))";
+        let (graph, root) = compile_program(&mut Ignore, source).unwrap();
+        let render = |buffer_size| {
+            let mut params = RenderParams {
+                gate: 5000,
+                ..Default::default()
+            };
+            params.parameters.buffer_size = buffer_size;
+            render_graph(&graph, root, &params).unwrap()
+        };
+        let small = render(32);
+        let large = render(1024);
+        assert!(small.len() > 5000);
+        assert_eq!(small, large);
+    }
+
+    #[test]
+    fn control_rate() {
+        // Control-rate signals can be up to one control period early, so the
+        // envelope segments are long compared to the period.
+        let source = "(* (sine (oscillator (note 0))) \
+                      (envelope (lin 320ms 1.0) (gate) (exp 640ms 0.0) (stop)))";
+        let (graph, root) = compile_program(&mut Ignore, source).unwrap();
+        let render = |control_rate| {
+            let mut params = RenderParams {
+                gate: 24000,
+                ..Default::default()
+            };
+            params.parameters.control_rate = control_rate;
+            render_graph(&graph, root, &params).unwrap()
+        };
+        let audio = render(1);
+        let control = render(64);
+        assert!(audio.len() > 24000);
+        assert!(
+            audio.len().max(control.len()) - audio.len().min(control.len()) <= 64,
+            "length: {} vs {}",
+            audio.len(),
+            control.len()
+        );
+        for (n, (&x, &y)) in audio.iter().zip(control.iter()).enumerate() {
+            assert!((x - y).abs() < 0.02, "sample {}: {} vs {}", n, x, y);
+        }
+    }
+
+    #[test]
+    fn progress() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let source = "(* (noise) (envelope (lin 10ms 1.0) (gate) (exp 50ms 0.0) (stop)))";
+        let (graph, root) = compile_program(&mut Ignore, source).unwrap();
+        let params = RenderParams {
+            gate: 5000,
+            ..Default::default()
+        };
+        let mut renderer = BlockRenderer::new(&graph, root, &params).unwrap();
+        let length = renderer.length().unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let calls2 = calls.clone();
+        renderer.set_progress(move |pos, total| calls2.borrow_mut().push((pos, total)));
+        let mut rendered = 0;
+        while let Some(block) = renderer.next_block() {
+            rendered += block.len();
+        }
+        assert_eq!(rendered, length);
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), (length + 1023) / 1024);
+        for (i, &(pos, total)) in calls.iter().enumerate() {
+            assert_eq!(total, length);
+            assert_eq!(pos, ((i + 1) * 1024).min(length));
+        }
+    }
+
+    /// Create a sine wave with the given amplitude.
+    fn sine(amplitude: f32) -> Vec<f32> {
+        (0..4800)
+            .map(|i| amplitude * (i as f32 * (2.0 * std::f32::consts::PI / 48.0)).sin())
+            .collect()
+    }
+
+    #[test]
+    fn normalize_peak() {
+        let mut samples = sine(0.5);
+        normalize(&mut samples, Normalize::Peak, 0.0);
+        let peak = samples.iter().fold(0.0f32, |m, &x| m.max(x.abs()));
+        assert!((peak - 1.0).abs() < 1e-6, "peak {}", peak);
+    }
+
+    #[test]
+    fn normalize_rms() {
+        let mut samples = sine(0.5);
+        normalize(&mut samples, Normalize::Rms, -12.0);
+        let rms = (samples.iter().map(|&x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+        let db = 20.0 * rms.log10();
+        assert!((db + 12.0).abs() < 1e-3, "rms {} dB", db);
+    }
+
+    #[test]
+    fn normalize_silent() {
+        for &mode in [Normalize::Peak, Normalize::Rms].iter() {
+            let mut samples = vec![0.0; 100];
+            normalize(&mut samples, mode, 0.0);
+            assert!(samples.iter().all(|&x| x == 0.0));
+            normalize(&mut [], mode, 0.0);
+        }
+    }
+
+    /// Limit samples, in blocks of the given size.
+    fn limit(input: &[f32], block_size: usize) -> Vec<f32> {
+        let mut limiter = Limiter::new(48000.0);
+        let mut output = Vec::new();
+        for block in input.chunks(block_size) {
+            limiter.process(block, &mut output);
+        }
+        limiter.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn limiter_loud() {
+        let input = sine(2.5);
+        for &block_size in [64, 1000, 10000].iter() {
+            let output = limit(&input, block_size);
+            assert_eq!(output.len(), input.len());
+            for (&x, &y) in input.iter().zip(output.iter()) {
+                assert!(y.abs() <= 1.0, "{} -> {}", x, y);
+                // The sign is preserved.
+                assert!(x * y >= 0.0, "{} -> {}", x, y);
+            }
+            let peak = output.iter().fold(0.0f32, |m, &y| m.max(y.abs()));
+            assert!(peak > 0.9, "peak {}", peak);
+        }
+    }
+
+    #[test]
+    fn limiter_quiet() {
+        let input = sine(0.9);
+        assert_eq!(limit(&input, 100), input);
+    }
+
+    /// Apply fades to input delivered in blocks of the given size.
+    fn fade(input: &[f32], fade_in: usize, fade_out: usize, block_size: usize) -> (Vec<f32>, bool) {
+        let mut fade = Fade::new(fade_in, fade_out);
+        let mut output = Vec::new();
+        for block in input.chunks(block_size) {
+            fade.process(block, &mut output);
+        }
+        let fits = fade.finish(&mut output);
+        (output, fits)
+    }
+
+    #[test]
+    fn fade_ramps() {
+        let input = vec![1.0; 1000];
+        for &block_size in [1, 64, 1000].iter() {
+            let (output, fits) = fade(&input, 100, 200, block_size);
+            assert!(fits);
+            assert_eq!(output.len(), input.len());
+            assert_eq!(output[0], 0.0);
+            assert_eq!(output[999], 0.0);
+            // Each ramp reaches full gain after the requested length.
+            assert_eq!(output[50], 0.5);
+            assert!(output[99] < 1.0);
+            assert!(output[100..800].iter().all(|&x| x == 1.0));
+            assert!(output[800] < 1.0);
+            assert_eq!(output[899], 0.5);
+        }
+    }
+
+    #[test]
+    fn fade_clamp() {
+        let input = vec![1.0; 100];
+        let (output, fits) = fade(&input, 500, 0, 30);
+        assert!(!fits);
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[0], 0.0);
+        assert_eq!(output[50], 0.5);
+        let (output, fits) = fade(&input, 0, 500, 30);
+        assert!(!fits);
+        assert_eq!(output[99], 0.0);
+        assert_eq!(output[49], 0.5);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn io() {
+        let path = Path::new("/nonexistent/file.lisp");
+        match render_file(&mut Ignore, path, &Default::default()) {
+            Err(RenderError::Io(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
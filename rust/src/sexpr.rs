@@ -1,13 +1,17 @@
 use crate::sourcepos::{HasPos, Span};
+use crate::symbol::Symbol;
+use crate::token::{self, Tokenizer};
 use crate::units::Units;
 use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+use std::hash::{Hash, Hasher};
 
 /// The type of an s-expression.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Type {
     Symbol,
     Integer,
     Float,
+    String,
     List,
 }
 
@@ -18,17 +22,25 @@ impl Display for Type {
             Symbol => "symbol",
             Integer => "integer",
             Float => "float",
+            String => "string",
             List => "list",
         })
     }
 }
 
 /// The contents of an s-expression.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Content {
-    Symbol(Box<str>),
+    /// A symbol, like `sine` or `|a b|`.
+    Symbol(Symbol),
+    /// An integer and its units, like `3` or `50ms`. The units are scaled so
+    /// the value is an integer in base units where possible.
     Integer(Units, i64),
+    /// A floating-point number and its units, like `0.5` or `440.0Hz`.
     Float(Units, f64),
+    /// A string, like `"lib.lisp"`, without its quotes or escapes.
+    String(Box<str>),
+    /// A parenthesized list of expressions.
     List(Box<[SExpr]>),
 }
 
@@ -39,14 +51,58 @@ impl Content {
             Content::Symbol(_) => Type::Symbol,
             Content::Integer(_, _) => Type::Integer,
             Content::Float(_, _) => Type::Float,
+            Content::String(_) => Type::String,
             Content::List(_) => Type::List,
         }
     }
 }
 
-/// An s-expression.
-#[derive(Debug)]
+/// An s-expression, as produced by the parser.
+///
+/// Every expression, including each item in a list, records the span of
+/// source text it was parsed from.
+///
+/// ```
+/// use ultrafxr::sexpr::{Content, SExpr};
+/// use ultrafxr::sourcepos::{Pos, Span};
+/// use ultrafxr::symbol::Symbol;
+/// use ultrafxr::units::Units;
+///
+/// // The expression (a 1), at the start of a file.
+/// let span = |start, end| Span {
+///     start: Pos(start),
+///     end: Pos(end),
+/// };
+/// let expr = SExpr {
+///     pos: span(1, 6),
+///     content: Content::List(Box::new([
+///         SExpr {
+///             pos: span(2, 3),
+///             content: Content::Symbol(Symbol::new("a")),
+///         },
+///         SExpr {
+///             pos: span(4, 5),
+///             content: Content::Integer(Units::scalar(), 1),
+///         },
+///     ])),
+/// };
+/// assert_eq!(expr.print(), "(a 1)");
+///
+/// // Find the name and argument of a function call.
+/// if let Content::List(items) = &expr.content {
+///     if let [SExpr {
+///         content: Content::Symbol(name),
+///         ..
+///     }, arg] = &items[..]
+///     {
+///         assert_eq!(name.as_str(), "a");
+///         assert_eq!(arg.pos, span(4, 5));
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
 pub struct SExpr {
+    /// The source text the expression was parsed from.
     pub pos: Span,
     pub content: Content,
 }
@@ -57,6 +113,35 @@ impl HasPos for SExpr {
     }
 }
 
+/// Write a symbol name, between bars if it would not otherwise be read back as
+/// the same symbol.
+fn write_symbol(out: &mut String, name: &str) {
+    let plain = match Tokenizer::new(name.as_bytes()) {
+        Ok(mut tokens) => {
+            let tok = tokens.next();
+            tok.ty == token::Type::Symbol && tok.text.len() == name.len() && !name.starts_with('|')
+        }
+        Err(_) => false,
+    };
+    if plain {
+        out.push_str(name);
+    } else {
+        write_delimited(out, name, '|');
+    }
+}
+
+/// Write text between delimiters, escaping the delimiter and backslashes.
+fn write_delimited(out: &mut String, text: &str, delimiter: char) {
+    out.push(delimiter);
+    for c in text.chars() {
+        if c == delimiter || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push(delimiter);
+}
+
 impl SExpr {
     /// Get the expression's type.
     pub fn get_type(&self) -> Type {
@@ -73,21 +158,30 @@ impl SExpr {
     fn print_impl(&self, out: &mut String) {
         use Content::*;
         match &self.content {
-            Symbol(sym) => out.push_str(sym),
-            Integer(units, num) => {
-                if units.is_scalar() {
-                    write!(out, "{}", num).unwrap();
-                } else {
-                    write!(out, "[{} {}]", units, num).unwrap();
-                }
-            }
+            Symbol(sym) => write_symbol(out, sym.as_str()),
+            Integer(units, num) => match units.suffix() {
+                Some(suffix) => write!(out, "{}{}", num, suffix).unwrap(),
+                None => write!(out, "[{} {}]", units, num).unwrap(),
+            },
             Float(units, num) => {
-                if units.is_scalar() {
-                    write!(out, "{}", num).unwrap();
+                // Floats are written so they are not read back as integers,
+                // and with enough digits to read back the same value.
+                // Infinity is written as an exponent too large to represent,
+                // which is how it is read from source.
+                let mut text = if num.is_infinite() {
+                    (if *num > 0.0 { "1e999" } else { "-1e999" }).to_owned()
                 } else {
-                    write!(out, "[{} {}]", units, num).unwrap();
+                    format!("{:?}", num)
+                };
+                if !text.contains(['.', 'e', 'N']) {
+                    text.push_str(".0");
+                }
+                match units.suffix() {
+                    Some(suffix) => write!(out, "{}{}", text, suffix).unwrap(),
+                    None => write!(out, "[{} {}]", units, text).unwrap(),
                 }
             }
+            String(text) => write_delimited(out, text, '"'),
             List(list) => {
                 out.push('(');
                 let mut iter = list.iter();
@@ -105,4 +199,313 @@ impl SExpr {
             }
         }
     }
+
+    /// True if two expressions have the same contents, ignoring their
+    /// locations in the source code.
+    pub fn structurally_eq(&self, other: &SExpr) -> bool {
+        use Content::*;
+        match (&self.content, &other.content) {
+            (Symbol(x), Symbol(y)) => x == y,
+            (Integer(xu, x), Integer(yu, y)) => xu == yu && x == y,
+            (Float(xu, x), Float(yu, y)) => xu == yu && x.to_bits() == y.to_bits(),
+            (String(x), String(y)) => x == y,
+            (List(x), List(y)) => {
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| x.structurally_eq(y))
+            }
+            _ => false,
+        }
+    }
+
+    /// Get a reference which compares and hashes the expression by its
+    /// contents, ignoring its location in the source code.
+    pub fn by_value(&self) -> ByValue<'_> {
+        ByValue(self)
+    }
+
+    /// Call a function on this expression and every expression inside it, in
+    /// preorder: each list is visited before its elements.
+    pub fn walk(&self, f: &mut dyn FnMut(&SExpr)) {
+        f(self);
+        if let Content::List(items) = &self.content {
+            for item in items.iter() {
+                item.walk(f);
+            }
+        }
+    }
+
+    /// Print the s-expression to a string, breaking lists across lines so
+    /// they fit within the given width where possible. Atoms are never split,
+    /// so lines with long atoms may be wider.
+    pub fn pretty(&self, width: usize) -> String {
+        let mut out = String::new();
+        self.pretty_impl(&mut out, 0, width);
+        out
+    }
+
+    /// Pretty-print an expression starting at the given column.
+    fn pretty_impl(&self, out: &mut String, column: usize, width: usize) {
+        let flat = self.print();
+        let items = match &self.content {
+            Content::List(items) if column + flat.chars().count() > width => items,
+            _ => {
+                out.push_str(&flat);
+                return;
+            }
+        };
+        let (first, rest) = match items.split_first() {
+            Some(x) => x,
+            None => {
+                out.push_str(&flat);
+                return;
+            }
+        };
+        out.push('(');
+        first.pretty_impl(out, column + 1, width);
+        // Arguments are aligned with the first argument if the operator is an
+        // atom and the first argument fits after it, and with the operator
+        // otherwise.
+        let (rest, indent) = match (&first.content, rest.split_first()) {
+            (Content::List(_), _) | (_, None) => (rest, column + 1),
+            (_, Some((arg, args))) => {
+                let indent = column + 2 + first.print().chars().count();
+                if indent + arg.print().chars().count() <= width {
+                    out.push(' ');
+                    arg.pretty_impl(out, indent, width);
+                    (args, indent)
+                } else {
+                    (rest, column + 1)
+                }
+            }
+        };
+        for item in rest.iter() {
+            out.push('\n');
+            out.extend(std::iter::repeat_n(' ', indent));
+            item.pretty_impl(out, indent, width);
+        }
+        out.push(')');
+    }
+}
+
+/// A reference to an s-expression which compares and hashes by contents, like
+/// structurally_eq. Numbers compare by value, so 01 and 1 are equal, but
+/// integers are never equal to floats.
+#[derive(Debug, Clone, Copy)]
+pub struct ByValue<'a>(pub &'a SExpr);
+
+impl<'a> PartialEq for ByValue<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.structurally_eq(other.0)
+    }
+}
+
+impl<'a> Eq for ByValue<'a> {}
+
+impl<'a> Hash for ByValue<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Content::*;
+        let content = &self.0.content;
+        content.get_type().hash(state);
+        match content {
+            Symbol(name) => name.hash(state),
+            Integer(units, num) => {
+                units.hash(state);
+                num.hash(state);
+            }
+            Float(units, num) => {
+                units.hash(state);
+                num.to_bits().hash(state);
+            }
+            String(text) => text.hash(state),
+            List(items) => {
+                items.len().hash(state);
+                for item in items.iter() {
+                    item.by_value().hash(state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorHandler;
+    use crate::parser::{ParseResult, Parser};
+    use crate::sourcepos::Span;
+    use crate::token::Tokenizer;
+
+    struct Panic;
+
+    impl ErrorHandler for Panic {
+        fn handle(&mut self, _pos: Span, message: &str) {
+            panic!("error: {}", message);
+        }
+    }
+
+    fn parse(text: &str) -> SExpr {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        match Parser::new().parse(&mut Panic, &mut toks) {
+            ParseResult::Value(expr) => expr,
+            _ => panic!("could not parse: {:?}", text),
+        }
+    }
+
+    /// Parse all expressions in the text.
+    fn parse_all(text: &str) -> Vec<SExpr> {
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut parser = Parser::new();
+        let mut exprs = Vec::new();
+        loop {
+            match parser.parse(&mut Panic, &mut toks) {
+                ParseResult::None => break,
+                ParseResult::Value(expr) => exprs.push(expr),
+                _ => panic!("could not parse: {:?}", text),
+            }
+        }
+        exprs
+    }
+
+    #[test]
+    fn round_trip() {
+        let corpus = [
+            "(define x 1) (define y -25) (define z 0x7f)",
+            "(a 0.1 1.0 -2.5 1e-7 1e20 1.5e300 123456789.125)",
+            "(a 1e400 -1e400 1e400V 5e999ms)",
+            "(b 1V 0.5V 50ms 2s 440Hz 2kHz 3.14159rad -6dB 0dB)",
+            "(c (d (e) () f) ((g)))",
+            r#"(include "lib.lisp" "a \"b\" \\ |c|" "")"#,
+            "(param |a b| |x\\|y| |back\\\\slash| || |12| |(|)",
+            include_str!("../tests/golden/sine.lisp"),
+            include_str!("../tests/golden/filtered_saw.lisp"),
+            include_str!("../tests/golden/note.lisp"),
+        ];
+        for text in corpus.iter() {
+            for expr in parse_all(text).iter() {
+                for &width in [0, 10, 20, 80].iter() {
+                    let printed = expr.pretty(width);
+                    let reparsed = parse_all(&printed);
+                    assert_eq!(reparsed.len(), 1, "{:?}", printed);
+                    assert!(
+                        expr.structurally_eq(&reparsed[0]),
+                        "{:?} != {:?}",
+                        expr.print(),
+                        printed
+                    );
+                }
+            }
+        }
+        let x = parse("(a 1 2)");
+        assert!(!x.structurally_eq(&parse("(a 1 2.0)")));
+        assert!(!x.structurally_eq(&parse("(a 1 2V)")));
+        assert!(!x.structurally_eq(&parse("(a 1)")));
+        assert!(x.structurally_eq(&parse("( a\n1  2 )")));
+    }
+
+    #[test]
+    fn by_value() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        fn hash(expr: &SExpr) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            expr.by_value().hash(&mut hasher);
+            hasher.finish()
+        }
+        let x = parse("(a (b 1 2.5V) |c| \"d\")");
+        let y = parse("  (a\n  (b 01 2.5V)\n  c \"d\")");
+        assert_ne!(x.pos, y.pos);
+        assert_eq!(x.by_value(), y.by_value());
+        assert_eq!(hash(&x), hash(&y));
+        // Numbers are normalized by value, but integers and floats differ.
+        assert_eq!(parse("0x10").by_value(), parse("16").by_value());
+        assert_eq!(hash(&parse("0x10")), hash(&parse("16")));
+        assert_ne!(parse("1").by_value(), parse("1.0").by_value());
+        assert_ne!(parse("1.0").by_value(), parse("1.0s").by_value());
+        assert_ne!(parse("a").by_value(), parse("\"a\"").by_value());
+        let exprs = parse_all("(a 1) (a 01) (a 1.0) (a 1) ((a 1))");
+        let set: HashSet<ByValue> = exprs.iter().map(SExpr::by_value).collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn pretty() {
+        let expr = parse(
+            "(lowPass2 (sawtooth (oscillator (note 0))) (frequency (envelope (set 0.5))) 0.7)",
+        );
+        assert_eq!(expr.pretty(80), expr.print());
+        assert_eq!(
+            expr.pretty(20),
+            "\
+(lowPass2
+ (sawtooth
+  (oscillator
+   (note 0)))
+ (frequency
+  (envelope
+   (set 0.5)))
+ 0.7)"
+        );
+        assert_eq!(
+            expr.pretty(45),
+            "\
+(lowPass2 (sawtooth (oscillator (note 0)))
+          (frequency (envelope (set 0.5)))
+          0.7)"
+        );
+        // Atoms are not split, even if they do not fit.
+        let expr = parse("(a-long-function-name another-long-argument)");
+        assert_eq!(
+            expr.pretty(10),
+            "(a-long-function-name\n another-long-argument)"
+        );
+        let expr = parse("((f x) y)");
+        assert_eq!(expr.pretty(5), "((f x)\n y)");
+    }
+
+    #[test]
+    fn walk() {
+        let expr = parse("(a (b c) 4V)");
+        let mut visited = Vec::new();
+        expr.walk(&mut |e| visited.push(e.print()));
+        assert_eq!(visited, ["(a (b c) 4V)", "a", "(b c)", "b", "c", "4V"]);
+    }
+
+    #[test]
+    fn escaped_symbol() {
+        let expr = parse("(param |a b|)");
+        match &expr.content {
+            Content::List(items) => match &items[1].content {
+                Content::Symbol(sym) => assert_eq!(sym.as_str(), "a b"),
+                _ => panic!("not a symbol"),
+            },
+            _ => panic!("not a list"),
+        }
+        assert_eq!(expr.pretty(80), "(param |a b|)");
+        assert_eq!(parse(r"|x\|y\\|").print(), r"|x\|y\\|");
+        assert_eq!(parse("|plain|").print(), "plain");
+        assert_eq!(parse(r"|\|x|").print(), r"|\|x|");
+    }
+
+    struct Errors(Vec<(Span, String)>);
+
+    impl ErrorHandler for Errors {
+        fn handle(&mut self, pos: Span, message: &str) {
+            self.0.push((pos, message.to_owned()));
+        }
+    }
+
+    #[test]
+    fn escaped_symbol_unterminated() {
+        let text = "(param |a b)";
+        let mut toks = Tokenizer::new(text.as_bytes()).unwrap();
+        let mut errors = Errors(Vec::new());
+        match Parser::new().parse(&mut errors, &mut toks) {
+            ParseResult::Error => (),
+            _ => panic!("expected error"),
+        }
+        assert_eq!(errors.0.len(), 1);
+        let (pos, message) = &errors.0[0];
+        // Positions start at 1, so this is the opening bar at byte offset 7.
+        assert_eq!((pos.start.0, pos.end.0), (8, 9));
+        assert!(message.contains("unterminated"), "{:?}", message);
+    }
 }
@@ -126,12 +126,49 @@ fn quote_u8(s: &[u8]) -> String {
     }
 }
 
+/// Quote a string as it would appear on a Windows command line. Strings with
+/// spaces or special characters are put in double quotes. Backslashes are
+/// path separators on Windows, so they are not escaped.
+fn quote_windows(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "\\/:.-_+~".contains(c) || c > '\x7f');
+    if plain {
+        return s.to_string();
+    }
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        if c == '"' {
+            r.push('"');
+        }
+        r.push(c);
+    }
+    r.push('"');
+    r
+}
+
 /// Quote an OsStr as it would appear in a shell script.
+#[cfg(unix)]
 fn quote_osstr(s: &OsStr) -> String {
     use std::os::unix::ffi::OsStrExt;
     quote_u8(s.as_bytes())
 }
 
+/// Quote an OsStr as it would appear on the command line. Text which is not
+/// valid Unicode is replaced with U+FFFD.
+#[cfg(windows)]
+fn quote_osstr(s: &OsStr) -> String {
+    quote_windows(&s.to_string_lossy())
+}
+
+/// Quote an OsStr as it would appear in a shell script. Text which is not
+/// valid Unicode is replaced with U+FFFD.
+#[cfg(not(any(unix, windows)))]
+fn quote_osstr(s: &OsStr) -> String {
+    quote_str(&s.to_string_lossy())
+}
+
 /// Quote an OsStr as it would appear in a shell script.
 pub fn quote_os<T>(s: &T) -> String
 where
@@ -142,7 +179,7 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::{quote, quote_os};
+    use super::{quote, quote_os, quote_windows};
 
     const CASES: &'static [(&'static str, &'static str)] = &[
         ("a", "a"),
@@ -187,6 +224,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_quote_windows() {
+        const CASES: &'static [(&'static str, &'static str)] = &[
+            ("a.ufx", "a.ufx"),
+            ("C:\\Patches\\a.ufx", "C:\\Patches\\a.ufx"),
+            ("C:\\My Patches\\a.ufx", "\"C:\\My Patches\\a.ufx\""),
+            ("", "\"\""),
+            ("a&b", "\"a&b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("caf\u{e9}", "caf\u{e9}"),
+        ];
+        for &(input, expect) in CASES.iter() {
+            assert_eq!(quote_windows(input), expect, "{:?}", input);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_quote_os_windows() {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::OsStringExt;
+        use std::path::Path;
+        assert_eq!(
+            quote_os(Path::new("C:\\My Patches\\a.ufx")),
+            "\"C:\\My Patches\\a.ufx\""
+        );
+        assert_eq!(quote_os(Path::new("C:\\a.ufx")), "C:\\a.ufx");
+        // An unpaired surrogate is not valid Unicode.
+        let path = OsString::from_wide(&[0x61, 0xd800, 0x62]);
+        assert_eq!(quote_os(&path), "a\u{fffd}b");
+    }
+
+    #[cfg(unix)]
     #[test]
     fn test_quote_os() {
         use std::ffi::OsStr;
@@ -1,5 +1,11 @@
+pub mod builder;
+pub mod controlrate;
 pub mod envelope;
 pub mod filter;
 pub mod graph;
 pub mod ops;
+pub mod oversample;
 pub mod program;
+
+#[allow(dead_code)]
+pub mod serialize;
@@ -0,0 +1,160 @@
+use super::envelope::{Envelope, Segment};
+use super::graph::{Graph, Node, SignalRef};
+use super::ops::{
+    Add, ApplyFunction, Constant, Multiply, Negate, Noise, Note, Oscillator, PointFunction,
+    Subtract,
+};
+
+/// Builds a signal graph programmatically.
+///
+/// Each method adds a node and returns a reference to its output, which can
+/// be passed as an input to later nodes. Nodes without a dedicated method can
+/// be added with `node`.
+pub struct GraphBuilder {
+    graph: Graph,
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        GraphBuilder {
+            graph: Graph::new(),
+        }
+    }
+
+    /// Add any node to the graph.
+    pub fn node(&mut self, node: impl Node + 'static) -> SignalRef {
+        self.graph.add(Box::new(node))
+    }
+
+    /// Generate a constant value.
+    pub fn constant(&mut self, value: f64) -> SignalRef {
+        self.node(Constant {
+            value: value as f32,
+        })
+    }
+
+    /// Get the frequency of the note being played, in Hz, offset by the given
+    /// number of semitones.
+    pub fn note(&mut self, offset: i32) -> SignalRef {
+        self.node(Note { offset })
+    }
+
+    /// Generate a phase ramp from a frequency in Hz.
+    pub fn oscillator(&mut self, frequency: SignalRef) -> SignalRef {
+        self.node(Oscillator {
+            inputs: [frequency],
+        })
+    }
+
+    /// Generate a sine wave from a phase.
+    pub fn sine(&mut self, phase: SignalRef) -> SignalRef {
+        self.apply(phase, PointFunction::Sine)
+    }
+
+    /// Generate a sawtooth wave from a phase.
+    pub fn sawtooth(&mut self, phase: SignalRef) -> SignalRef {
+        self.apply(phase, PointFunction::Sawtooth)
+    }
+
+    /// Apply a function to each sample of a signal.
+    pub fn apply(&mut self, input: SignalRef, function: PointFunction) -> SignalRef {
+        self.node(ApplyFunction { input, function })
+    }
+
+    /// Generate white noise.
+    pub fn noise(&mut self) -> SignalRef {
+        self.node(Noise)
+    }
+
+    /// Generate an envelope from a sequence of segments.
+    pub fn envelope(&mut self, segments: &[Segment]) -> SignalRef {
+        self.node(Envelope {
+            segments: Box::from(segments),
+        })
+    }
+
+    pub fn multiply(&mut self, x: SignalRef, y: SignalRef) -> SignalRef {
+        self.node(Multiply { inputs: [x, y] })
+    }
+
+    pub fn add(&mut self, x: SignalRef, y: SignalRef) -> SignalRef {
+        self.node(Add { inputs: [x, y] })
+    }
+
+    pub fn subtract(&mut self, x: SignalRef, y: SignalRef) -> SignalRef {
+        self.node(Subtract { inputs: [x, y] })
+    }
+
+    pub fn negate(&mut self, input: SignalRef) -> SignalRef {
+        self.node(Negate { input })
+    }
+
+    /// Finish building the graph, whose output is the given root signal.
+    /// Panics if the root is not a signal in the graph.
+    pub fn build(self, root: SignalRef) -> Graph {
+        if root.0 as usize >= self.graph.nodes().len() {
+            panic!("root signal out of range");
+        }
+        self.graph
+    }
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        GraphBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::render_graph;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sine_patch() {
+        let mut b = GraphBuilder::new();
+        let freq = b.constant(1000.0);
+        let phase = b.oscillator(freq);
+        let wave = b.sine(phase);
+        let gain = b.envelope(&[
+            Segment::Set { value: 0.5 },
+            Segment::Delay { time: 0.1 },
+            Segment::Stop,
+        ]);
+        let root = b.multiply(wave, gain);
+        assert_eq!(root, SignalRef(4));
+        let graph = b.build(root);
+        let names: Vec<&str> = graph.nodes().iter().map(|n| n.op_name()).collect();
+        assert_eq!(
+            names,
+            [
+                "Constant",
+                "Oscillator",
+                "ApplyFunction",
+                "Envelope",
+                "Multiply"
+            ]
+        );
+        let samples = render_graph(&graph, root, &Default::default()).unwrap();
+        assert!(samples.len() >= 4800, "length: {}", samples.len());
+        for (i, &x) in samples[..4800].iter().enumerate() {
+            let expect = 0.5 * (i as f32 * (2.0 * PI / 48.0)).sin();
+            assert!(
+                (x - expect).abs() < 1e-3,
+                "sample {}: {} != {}",
+                i,
+                x,
+                expect
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn build_out_of_range() {
+        let mut b = GraphBuilder::new();
+        b.noise();
+        b.build(SignalRef(1));
+    }
+}
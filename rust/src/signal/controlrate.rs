@@ -0,0 +1,43 @@
+use super::program::{Function, State};
+
+/// Converts a control-rate signal to the audio rate by linear interpolation.
+///
+/// Each control-rate sample is the value at the end of a control period, so
+/// the output ramps from the previous sample to the current one over the
+/// period. The first input buffer has one extra sample at the start, which is
+/// the value at time zero.
+#[derive(Debug)]
+pub struct Interpolate {
+    factor: usize,
+    previous: Option<f32>,
+}
+
+impl Interpolate {
+    /// Create an interpolator for a control rate which is the audio rate
+    /// divided by the given factor.
+    pub fn new(factor: usize) -> Self {
+        Interpolate {
+            factor,
+            previous: None,
+        }
+    }
+}
+
+impl Function for Interpolate {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let input = inputs[0];
+        let (mut previous, input) = match self.previous {
+            Some(previous) => (previous, input),
+            None => (input[0], &input[1..]),
+        };
+        let scale = 1.0 / self.factor as f32;
+        for (output, &value) in output.chunks_mut(self.factor).zip(input.iter()) {
+            let step = (value - previous) * scale;
+            for (n, y) in output.iter_mut().enumerate() {
+                *y = previous + step * n as f32;
+            }
+            previous = value;
+        }
+        self.previous = Some(previous);
+    }
+}
@@ -1,10 +1,10 @@
-use super::graph::{Node, NodeResult, SignalRef};
+use super::graph::{Node, NodeResult, Param, SignalRef};
 use super::program::{Function, Parameters, State};
 use std::cmp::min;
 use std::f32;
 
 /// Segment of an envelope.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Segment {
     /// Move to target value instantly, then hold.
     Set { value: f64 },
@@ -22,11 +22,14 @@ pub enum Segment {
 }
 
 /// Envelope generator.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Envelope {
     pub segments: Box<[Segment]>,
 }
 
+/// Exponential segments end when they are this close to their target.
+const EXPONENTIAL_THRESHOLD: f32 = 0.05;
+
 fn time_from(time: f32) -> usize {
     if time >= 0.0 {
         if time < usize::max_value() as f32 {
@@ -40,9 +43,82 @@ fn time_from(time: f32) -> usize {
 }
 
 impl Node for Envelope {
+    fn op_name(&self) -> &'static str {
+        "Envelope"
+    }
     fn inputs(&self) -> &[SignalRef] {
         &[]
     }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        let mut params = Vec::new();
+        for &seg in self.segments.iter() {
+            match seg {
+                Segment::Set { value } => {
+                    params.push(("segment", Param::Mode("Set")));
+                    params.push(("value", Param::Float(value)));
+                }
+                Segment::Linear { time, value } => {
+                    params.push(("segment", Param::Mode("Linear")));
+                    params.push(("time", Param::Float(time)));
+                    params.push(("value", Param::Float(value)));
+                }
+                Segment::Exponential {
+                    time_constant,
+                    value,
+                } => {
+                    params.push(("segment", Param::Mode("Exponential")));
+                    params.push(("time_constant", Param::Float(time_constant)));
+                    params.push(("value", Param::Float(value)));
+                }
+                Segment::Delay { time } => {
+                    params.push(("segment", Param::Mode("Delay")));
+                    params.push(("time", Param::Float(time)));
+                }
+                Segment::Gate => params.push(("segment", Param::Mode("Gate"))),
+                Segment::Stop => params.push(("segment", Param::Mode("Stop"))),
+            }
+        }
+        params
+    }
+    fn length(&self, parameters: &Parameters, gate: usize) -> Option<usize> {
+        let samples = |time: f64| time_from((time * parameters.sample_rate) as f32);
+        let mut pos: usize = 0;
+        let mut current: f32 = 0.0;
+        for &seg in self.segments.iter() {
+            match seg {
+                Segment::Set { value } => current = value as f32,
+                Segment::Linear { time, value } => {
+                    pos = pos.saturating_add(samples(time));
+                    current = value as f32;
+                }
+                Segment::Exponential {
+                    time_constant,
+                    value,
+                } => {
+                    // Same as the end time computed when rendering.
+                    let target = value as f32;
+                    let time_constant = (time_constant * parameters.sample_rate) as f32;
+                    let offset = current - target;
+                    pos = pos.saturating_add(time_from(
+                        time_constant * (offset.abs() / EXPONENTIAL_THRESHOLD).ln(),
+                    ));
+                    current = target;
+                }
+                Segment::Delay { time } => pos = pos.saturating_add(samples(time)),
+                // Segments after the gate start when the gate releases, and a
+                // stop before the gate has already returned.
+                Segment::Gate => pos = gate,
+                Segment::Stop => return Some(pos),
+            }
+        }
+        None
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
     fn instantiate(&self, parameters: &Parameters) -> NodeResult {
         let mut states = Vec::<Section>::new();
         let mut segments = Vec::<FSegment>::new();
@@ -80,7 +156,7 @@ impl Node for Envelope {
                     segments.push(FSegment::Exponential {
                         value,
                         time_constant,
-                        threshold: 0.05,
+                        threshold: EXPONENTIAL_THRESHOLD,
                     });
                 }
                 Segment::Delay { time } => {
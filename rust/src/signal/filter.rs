@@ -1,7 +1,7 @@
-use super::graph::{Node, NodeResult, SignalRef};
+use super::graph::{Node, NodeResult, Param, SignalRef};
 use super::program::{Function, Parameters, State};
 use std::f64;
-use std::slice::from_ref;
+use std::slice::{from_mut, from_ref};
 
 // =================================================================================================
 
@@ -14,8 +14,34 @@ pub enum Mode {
     LowPass4,
 }
 
+impl Mode {
+    /// Get the name of the filter mode.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::LowPass2 => "LowPass2",
+            Mode::HighPass2 => "HighPass2",
+            Mode::BandPass2 => "BandPass2",
+            Mode::LowPass4 => "LowPass4",
+        }
+    }
+
+    /// Get the filter mode with the given name.
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Mode::LowPass2,
+            Mode::HighPass2,
+            Mode::BandPass2,
+            Mode::LowPass4,
+        ]
+        .iter()
+        .copied()
+        .find(|m| m.name() == name)
+    }
+}
+
 /// A state-variable filter with a control input for frequency.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct StateVariable {
     /// (input, frequency)
     pub inputs: [SignalRef; 2],
@@ -24,9 +50,21 @@ pub struct StateVariable {
 }
 
 impl Node for StateVariable {
+    fn op_name(&self) -> &'static str {
+        "StateVariable"
+    }
     fn inputs(&self) -> &[SignalRef] {
         &self.inputs[..]
     }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![
+            ("mode", Param::Mode(self.mode.name())),
+            ("q", Param::Float(self.q)),
+        ]
+    }
     fn instantiate(&self, parameters: &Parameters) -> NodeResult {
         let q = match self.mode {
             Mode::LowPass4 => (self.q * 0.5f64.sqrt()).sqrt(),
@@ -79,16 +117,25 @@ impl Function for StateVariableF {
 // =================================================================================================
 
 /// A two-pole high pass filter with Q=0.707 and fixed frequency.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct HighPass {
     pub input: SignalRef,
     pub frequency: f64,
 }
 
 impl Node for HighPass {
+    fn op_name(&self) -> &'static str {
+        "HighPass"
+    }
     fn inputs(&self) -> &[SignalRef] {
         from_ref(&self.input)
     }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("frequency", Param::Float(self.frequency))]
+    }
     fn instantiate(&self, parameters: &Parameters) -> NodeResult {
         Ok(Box::new(HighPassF {
             svf: SVF([0.0, 0.0]),
@@ -120,6 +167,150 @@ impl Function for HighPassF {
 
 // =================================================================================================
 
+/// Smooth changes in the input with a one-pole low-pass filter, for
+/// portamento. The output approaches the input exponentially, reaching 1-1/e
+/// (about 63%) of a step after the given time. The output starts at the first
+/// input value, and a time of zero passes the input through unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glide {
+    pub input: SignalRef,
+    /// Time constant, in seconds.
+    pub time: f64,
+}
+
+impl Node for Glide {
+    fn op_name(&self) -> &'static str {
+        "Glide"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("time", Param::Float(self.time))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0])
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        let samples = self.time * parameters.sample_rate;
+        Ok(Box::new(GlideF {
+            coefficient: if samples > 0.0 {
+                -(-samples.recip()).exp_m1() as f32
+            } else {
+                1.0
+            },
+            value: None,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct GlideF {
+    /// Fraction of the remaining distance to move each sample.
+    coefficient: f32,
+    value: Option<f32>,
+}
+
+impl Function for GlideF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let input = inputs[0];
+        let mut value = match self.value {
+            Some(value) => value,
+            None => match input.first() {
+                Some(&x) => x,
+                None => return,
+            },
+        };
+        let coefficient = self.coefficient;
+        for (y, &x) in output.iter_mut().zip(input.iter()) {
+            value += (x - value) * coefficient;
+            *y = value;
+        }
+        self.value = Some(value);
+    }
+}
+
+// =================================================================================================
+
+/// Limit the rate of change of the input. The output starts at the first input
+/// value and moves towards the input no faster than the given rates, which are
+/// in input units per second. A rate which is not positive is unlimited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slew {
+    pub input: SignalRef,
+    /// Maximum rate of increase.
+    pub rise: f64,
+    /// Maximum rate of decrease.
+    pub fall: f64,
+}
+
+impl Node for Slew {
+    fn op_name(&self) -> &'static str {
+        "Slew"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![
+            ("rise", Param::Float(self.rise)),
+            ("fall", Param::Float(self.fall)),
+        ]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0])
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        let step = |rate: f64| {
+            if rate > 0.0 {
+                (rate / parameters.sample_rate) as f32
+            } else {
+                f32::INFINITY
+            }
+        };
+        Ok(Box::new(SlewF {
+            rise: step(self.rise),
+            fall: step(self.fall),
+            value: None,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct SlewF {
+    /// Maximum increase per sample.
+    rise: f32,
+    /// Maximum decrease per sample.
+    fall: f32,
+    value: Option<f32>,
+}
+
+impl Function for SlewF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let input = inputs[0];
+        let mut value = match self.value {
+            Some(value) => value,
+            None => match input.first() {
+                Some(&x) => x,
+                None => return,
+            },
+        };
+        for (y, &x) in output.iter_mut().zip(input.iter()) {
+            value += (x - value).max(-self.fall).min(self.rise);
+            *y = value;
+        }
+        self.value = Some(value);
+    }
+}
+
+// =================================================================================================
+
 /// Mode for a state variable filter.
 #[derive(Debug)]
 enum SVFMode {
@@ -175,3 +366,138 @@ impl SVF {
         self.render(output, input, frequency, invq, SVFMode::BandPass);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::envelope::{Envelope, Segment};
+    use crate::signal::graph::Graph;
+    use crate::signal::program::{Input, Program};
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// Render a graph until it stops.
+    fn render(graph: &Graph, root: SignalRef) -> Vec<f32> {
+        let parameters = Parameters {
+            sample_rate: SAMPLE_RATE,
+            buffer_size: 256,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let mut output = Vec::new();
+        while let Some(data) = program.render(&Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        }) {
+            output.extend_from_slice(data);
+        }
+        output
+    }
+
+    /// Create a glide following a step from 0 to 1 after the given delay.
+    fn glide_step(graph: &mut Graph, delay: f64, time: f64) -> SignalRef {
+        let input = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 0.0 },
+                    Segment::Delay { time: delay },
+                    Segment::Set { value: 1.0 },
+                    Segment::Delay { time: 0.1 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        graph.add(Box::new(Glide { input, time }))
+    }
+
+    #[test]
+    fn glide() {
+        const DELAY: f64 = 0.01;
+        const TIME: f64 = 0.02;
+        let mut graph = Graph::new();
+        let root = glide_step(&mut graph, DELAY, TIME);
+        let output = render(&graph, root);
+        let step = (DELAY * SAMPLE_RATE) as usize;
+        assert_eq!(output[step - 1], 0.0);
+        let y = output[step - 1 + (TIME * SAMPLE_RATE) as usize];
+        let expect = 1.0 - (-1.0f32).exp();
+        assert!(
+            (y - expect).abs() < 0.01,
+            "glide = {}, expect {}",
+            y,
+            expect
+        );
+        assert!(output.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn glide_zero() {
+        let mut graph = Graph::new();
+        let root = glide_step(&mut graph, 0.01, 0.0);
+        let input = graph.nodes()[root.0 as usize].inputs()[0];
+        assert_eq!(render(&graph, root), render(&graph, input));
+    }
+
+    #[test]
+    fn state_variable_parameters() {
+        let node = StateVariable {
+            inputs: [SignalRef(0), SignalRef(1)],
+            mode: Mode::BandPass2,
+            q: 0.7,
+        };
+        assert_eq!(node.op_name(), "StateVariable");
+        assert_eq!(
+            node.parameters(),
+            [("mode", Param::Mode("BandPass2")), ("q", Param::Float(0.7))]
+        );
+        assert_eq!(
+            node.parameters()
+                .iter()
+                .map(|(_, p)| p.to_string())
+                .collect::<Vec<_>>(),
+            ["BandPass2", "0.7"]
+        );
+    }
+
+    #[test]
+    fn slew() {
+        const RISE: f64 = 20.0;
+        const FALL: f64 = 40.0;
+        let mut graph = Graph::new();
+        let input = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 0.0 },
+                    Segment::Delay { time: 0.01 },
+                    Segment::Set { value: 1.0 },
+                    Segment::Delay { time: 0.1 },
+                    Segment::Set { value: 0.0 },
+                    Segment::Delay { time: 0.1 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let root = graph.add(Box::new(Slew {
+            input,
+            rise: RISE,
+            fall: FALL,
+        }));
+        let output = render(&graph, root);
+        let rise = (RISE / SAMPLE_RATE) as f32;
+        let fall = (FALL / SAMPLE_RATE) as f32;
+        let step = (0.01 * SAMPLE_RATE) as usize;
+        assert_eq!(output[step - 1], 0.0);
+        let y = output[step + 99];
+        assert!((y - 100.0 * rise).abs() < 1e-4, "rise = {}", y);
+        let y = output[(0.06 * SAMPLE_RATE) as usize];
+        assert_eq!(y, 1.0);
+        let step = (0.11 * SAMPLE_RATE) as usize;
+        let y = output[step + 99];
+        assert!((y - (1.0 - 100.0 * fall)).abs() < 1e-4, "fall = {}", y);
+        for w in output.windows(2) {
+            let delta = w[1] - w[0];
+            assert!(delta <= rise * 1.001 && delta >= -fall * 1.001);
+        }
+    }
+}
@@ -1,17 +1,131 @@
+use super::ops::Constant;
 use super::program::{Function, Parameters};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::error::Error;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter, Result as FResult};
 use std::io;
 
 /// Result of instantiating a node.
 pub type NodeResult = Result<Box<dyn Function>, Box<dyn Error>>;
 
+/// A parameter value for a node, for introspection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param<'a> {
+    Float(f64),
+    Int(i64),
+    Mode(&'a str),
+}
+
+impl<'a> Display for Param<'a> {
+    fn fmt(&self, f: &mut Formatter) -> FResult {
+        match self {
+            Param::Float(x) => write!(f, "{:?}", x),
+            Param::Int(x) => write!(f, "{}", x),
+            Param::Mode(x) => f.write_str(x),
+        }
+    }
+}
+
+/// A hashable version of a parameter value.
+#[derive(PartialEq, Eq, Hash)]
+enum ParamKey {
+    Float(u64),
+    Int(i64),
+    Mode(String),
+}
+
+impl<'a> From<Param<'a>> for ParamKey {
+    fn from(param: Param<'a>) -> Self {
+        match param {
+            Param::Float(x) => ParamKey::Float(x.to_bits()),
+            Param::Int(x) => ParamKey::Int(x),
+            Param::Mode(x) => ParamKey::Mode(x.to_string()),
+        }
+    }
+}
+
+/// A key which identifies structurally identical nodes.
+type NodeKey = (&'static str, Vec<SignalRef>, Vec<(&'static str, ParamKey)>);
+
+/// Escape text for a quoted string in a Graphviz file. Line breaks become
+/// centered line breaks.
+fn dot_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => (),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
 /// A node in the audio processing graph description.
 pub trait Node: Debug {
+    /// Get the name of the node's operation.
+    fn op_name(&self) -> &'static str;
+
     /// Get a list of node inputs.
     fn inputs(&self) -> &[SignalRef];
 
+    /// Get a mutable list of node inputs, so they can be rewritten.
+    fn inputs_mut(&mut self) -> &mut [SignalRef];
+
+    /// Get the node's parameters, other than its inputs.
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        Vec::new()
+    }
+
+    /// Compute the node's output if its inputs are constant. Returns None if
+    /// the node has state or depends on time or program input, in which case
+    /// the output is not constant even if the inputs are.
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        let _ = inputs;
+        None
+    }
+
+    /// If the node's output is always identical to one of its inputs, return
+    /// that input. Such nodes are eliminated by dedup and are not part of
+    /// programs created from the graph.
+    fn forward(&self) -> Option<SignalRef> {
+        None
+    }
+
+    /// True if the node can be merged with another node which has the same
+    /// operation, parameters, and inputs. This is true even for most nodes
+    /// with state, since their state evolves identically. It is false for
+    /// nodes like noise, where each node produces a different output.
+    fn can_merge(&self) -> bool {
+        true
+    }
+
+    /// True if the node's function is nonlinear, so it creates harmonics
+    /// which can alias. These nodes are oversampled when oversampling is
+    /// enabled.
+    fn nonlinear(&self) -> bool {
+        false
+    }
+
+    /// True if the node can run at the control rate when all of its inputs
+    /// do, computing one value per control period which is interpolated up to
+    /// the audio rate. This is true for envelopes and other sources which
+    /// change slowly, and for arithmetic without state. It is false for
+    /// oscillators, filters, and other nodes with audio-rate output.
+    fn control_rate(&self) -> bool {
+        false
+    }
+
+    /// Get the number of samples after which the node stops the program, if
+    /// it is known in advance. The gate is the length of the gate, in samples.
+    fn length(&self, params: &Parameters, gate: usize) -> Option<usize> {
+        let _ = (params, gate);
+        None
+    }
+
     /// Create an instance of the node's audio function.
     fn instantiate(&self, params: &Parameters) -> NodeResult;
 }
@@ -39,19 +153,416 @@ impl Graph {
         SignalRef(idx)
     }
 
-    /// Dump the graph to a stream in text format.
+    /// Replace nodes which have constant output with Constant nodes.
+    ///
+    /// Since every node's inputs come before it in the graph, a single pass in
+    /// order reaches a fixpoint. Nodes which are no longer referenced are left
+    /// in the graph, but will not be part of any program created from it.
+    pub fn fold_constants(&mut self) {
+        let mut values: Vec<Option<f32>> = Vec::with_capacity(self.nodes.len());
+        let mut inputs = Vec::new();
+        for node in self.nodes.iter_mut() {
+            inputs.clear();
+            let mut is_constant = true;
+            for &SignalRef(idx) in node.inputs().iter() {
+                match values[idx as usize] {
+                    Some(x) => inputs.push(x),
+                    None => {
+                        is_constant = false;
+                        break;
+                    }
+                }
+            }
+            let value = if is_constant {
+                node.fold(&inputs)
+            } else {
+                None
+            };
+            if let Some(value) = value {
+                if !node.inputs().is_empty() {
+                    *node = Box::new(Constant { value });
+                }
+            }
+            values.push(value);
+        }
+    }
+
+    /// Merge structurally identical nodes, which have the same operation,
+    /// parameters, and inputs, and remove nodes which forward their input.
+    ///
+    /// References to merged nodes are rewritten to refer to the first
+    /// identical node, and references to forwarding nodes are rewritten to
+    /// refer to the node they forward. The merged nodes are left in the graph
+    /// with their inputs rewritten, so existing references to them remain
+    /// valid.
+    pub fn dedup(&mut self) {
+        let mut remap: Vec<SignalRef> = Vec::with_capacity(self.nodes.len());
+        let mut canonical: HashMap<NodeKey, SignalRef> = HashMap::new();
+        for (n, node) in self.nodes.iter_mut().enumerate() {
+            for input in node.inputs_mut().iter_mut() {
+                *input = remap[input.0 as usize];
+            }
+            if let Some(input) = node.forward() {
+                remap.push(input);
+                continue;
+            }
+            let sig = SignalRef(n as u32);
+            if !node.can_merge() {
+                remap.push(sig);
+                continue;
+            }
+            let key: NodeKey = (
+                node.op_name(),
+                node.inputs().to_vec(),
+                node.parameters()
+                    .into_iter()
+                    .map(|(name, value)| (name, ParamKey::from(value)))
+                    .collect(),
+            );
+            remap.push(*canonical.entry(key).or_insert(sig));
+        }
+    }
+
+    /// Dump the graph to a stream in text format, one node per line.
+    ///
+    /// Nodes are written in order, which is a topological order, and each line
+    /// has the form "#n = OpName #input... name=value...".
     pub fn dump(&self, f: &mut dyn io::Write) {
+        for (sig, node) in self.iter() {
+            write!(f, "#{} = {}", sig.0, node.op_name()).unwrap();
+            for input in node.inputs().iter() {
+                write!(f, " #{}", input.0).unwrap();
+            }
+            for (name, value) in node.parameters().iter() {
+                write!(f, " {}={}", name, value).unwrap();
+            }
+            writeln!(f).unwrap();
+        }
+    }
+
+    /// Dump the graph to a stream in Graphviz DOT format. The root node is
+    /// drawn with a double border.
+    pub fn dump_dot(&self, f: &mut dyn io::Write, root: SignalRef) {
+        writeln!(f, "digraph ultrafxr {{").unwrap();
         for (n, node) in self.nodes.iter().enumerate() {
-            writeln!(f, "{}: {:?}", n, node).unwrap();
+            let mut label = format!("#{} {}", n, node.op_name());
+            for (name, value) in node.parameters().iter() {
+                label.push_str(&format!("\n{}={}", name, value));
+            }
+            write!(f, "  n{} [label=\"{}\"", n, dot_escape(&label)).unwrap();
+            if n == root.0 as usize {
+                write!(f, ", peripheries=2").unwrap();
+            }
+            writeln!(f, "];").unwrap();
+        }
+        for (n, node) in self.nodes.iter().enumerate() {
+            for input in node.inputs().iter() {
+                writeln!(f, "  n{} -> n{};", input.0, n).unwrap();
+            }
+        }
+        writeln!(f, "}}").unwrap();
+    }
+
+    /// Get the length of the program which renders a signal, in samples, if
+    /// it is known in advance. This is when the first node that the signal
+    /// depends on stops the program.
+    pub fn length(&self, root: SignalRef, params: &Parameters, gate: usize) -> Option<usize> {
+        let root = root.0 as usize;
+        let mut reachable = vec![false; self.nodes.len()];
+        reachable[root] = true;
+        let mut length = None;
+        for (n, node) in self.nodes[..=root].iter().enumerate().rev() {
+            if !reachable[n] {
+                continue;
+            }
+            for input in node.inputs().iter() {
+                reachable[input.0 as usize] = true;
+            }
+            if let Some(node_length) = node.length(params, gate) {
+                length = Some(length.map_or(node_length, |x: usize| x.min(node_length)));
+            }
         }
+        length
     }
 
     /// Return all nodes in the graph.
     pub fn nodes(&self) -> &[Box<dyn Node>] {
         &self.nodes
     }
+
+    /// Get the node which produces a signal, or None if the signal is not in
+    /// the graph.
+    pub fn node(&self, sig: SignalRef) -> Option<&dyn Node> {
+        self.nodes.get(sig.0 as usize).map(|node| node.as_ref())
+    }
+
+    /// Iterate over the nodes in the graph and the signals they produce, in
+    /// the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (SignalRef, &dyn Node)> {
+        (0..)
+            .map(SignalRef)
+            .zip(self.nodes.iter().map(|node| node.as_ref()))
+    }
+
+    /// Get the nodes in the graph, for modification in tests. Unlike add,
+    /// this permits creating cycles.
+    #[cfg(test)]
+    pub(crate) fn nodes_mut(&mut self) -> &mut [Box<dyn Node>] {
+        &mut self.nodes
+    }
 }
 
 /// A reference to a signal in the audio processing graph.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct SignalRef(pub u32);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::envelope::{Envelope, Segment};
+    use crate::signal::ops;
+    use crate::signal::program::{Input, Program};
+
+    fn constant_value(graph: &Graph, sig: SignalRef) -> Option<f64> {
+        let node = &graph.nodes()[sig.0 as usize];
+        if node.op_name() != "Constant" {
+            return None;
+        }
+        match node.parameters()[..] {
+            [("value", Param::Float(x))] => Some(x),
+            _ => panic!("unexpected parameters"),
+        }
+    }
+
+    #[test]
+    fn iter() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Constant { value: 2.0 }));
+        let y = graph.add(Box::new(ops::Noise));
+        let xy = graph.add(Box::new(ops::Multiply { inputs: [x, y] }));
+        let nodes: Vec<(SignalRef, &str)> = graph
+            .iter()
+            .map(|(sig, node)| (sig, node.op_name()))
+            .collect();
+        assert_eq!(nodes, [(x, "Constant"), (y, "Noise"), (xy, "Multiply")]);
+        assert_eq!(graph.node(xy).unwrap().inputs(), [x, y]);
+        assert!(graph.node(SignalRef(3)).is_none());
+    }
+
+    #[test]
+    fn fold_constants() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Constant { value: 2.0 }));
+        let y = graph.add(Box::new(ops::Constant { value: 3.0 }));
+        let xy = graph.add(Box::new(ops::Multiply { inputs: [x, y] }));
+        let z = graph.add(Box::new(ops::Zero));
+        let sum = graph.add(Box::new(ops::Mix {
+            inputs: [z, xy],
+            gain: 0.5,
+        }));
+        let out = graph.add(Box::new(ops::ScaleInt {
+            input: sum,
+            scale: -2,
+        }));
+        graph.fold_constants();
+        assert_eq!(constant_value(&graph, xy), Some(6.0));
+        assert_eq!(constant_value(&graph, sum), Some(3.0));
+        assert_eq!(constant_value(&graph, out), Some(-6.0));
+    }
+
+    #[test]
+    fn fold_stateful() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Constant { value: 2.0 }));
+        let freq = graph.add(Box::new(ops::Constant { value: 440.0 }));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let wave = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sine,
+        }));
+        let out = graph.add(Box::new(ops::Multiply { inputs: [x, wave] }));
+        let noise = graph.add(Box::new(ops::Noise));
+        let note = graph.add(Box::new(ops::Note { offset: 0 }));
+        graph.fold_constants();
+        for &sig in [phase, wave, out, noise, note].iter() {
+            assert_eq!(constant_value(&graph, sig), None);
+        }
+    }
+
+    fn render(graph: &Graph, root: SignalRef) -> Vec<f32> {
+        let mut program = Program::new(
+            graph,
+            root,
+            &Parameters {
+                sample_rate: 48000.0,
+                buffer_size: 256,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        while let Some(data) = program.render(&Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        }) {
+            output.extend_from_slice(data);
+        }
+        output
+    }
+
+    #[test]
+    fn dedup() {
+        let mut graph = Graph::new();
+        let mut sines = Vec::new();
+        for _ in 0..2 {
+            let freq = graph.add(Box::new(ops::Note { offset: 0 }));
+            let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+            sines.push(graph.add(Box::new(ops::ApplyFunction {
+                input: phase,
+                function: ops::PointFunction::Sine,
+            })));
+        }
+        let product = graph.add(Box::new(ops::Multiply {
+            inputs: [sines[0], sines[1]],
+        }));
+        let env = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 1.0 },
+                    Segment::Delay { time: 0.01 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let root = graph.add(Box::new(ops::Multiply {
+            inputs: [product, env],
+        }));
+        let expect = render(&graph, root);
+        graph.dedup();
+        assert_eq!(
+            graph.nodes()[product.0 as usize].inputs(),
+            &[sines[0], sines[0]]
+        );
+        assert_eq!(render(&graph, root), expect);
+    }
+
+    #[test]
+    fn deref() {
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(ops::Note { offset: 0 }));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let sine = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sine,
+        }));
+        let x = graph.add(Box::new(ops::Deref { input: sine }));
+        let y = graph.add(Box::new(ops::Deref { input: x }));
+        let env = graph.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 1.0 },
+                    Segment::Delay { time: 0.01 },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let root = graph.add(Box::new(ops::Multiply { inputs: [y, env] }));
+        let alias = graph.add(Box::new(ops::Deref { input: root }));
+        let expect = render(&graph, root);
+        assert_eq!(render(&graph, alias), expect);
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 256,
+            ..Default::default()
+        };
+        let mut text = Vec::new();
+        Program::new(&graph, alias, &parameters)
+            .unwrap()
+            .disassemble(&graph, &mut text)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(!text.contains("Deref"), "{}", text);
+        graph.dedup();
+        assert_eq!(graph.nodes()[root.0 as usize].inputs(), &[sine, env]);
+        assert_eq!(render(&graph, root), expect);
+    }
+
+    #[test]
+    fn dedup_noise() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Noise));
+        let y = graph.add(Box::new(ops::Noise));
+        let root = graph.add(Box::new(ops::Multiply { inputs: [x, y] }));
+        graph.dedup();
+        assert_eq!(graph.nodes()[root.0 as usize].inputs(), &[x, y]);
+    }
+
+    fn dump_graph() -> (Graph, SignalRef) {
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(ops::Note { offset: 12 }));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let wave = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sine,
+        }));
+        let env = graph.add(Box::new(Envelope {
+            segments: Box::from(&[Segment::Set { value: 1.0 }, Segment::Stop][..]),
+        }));
+        let out = graph.add(Box::new(ops::Multiply {
+            inputs: [wave, env],
+        }));
+        (graph, out)
+    }
+
+    #[test]
+    fn dump() {
+        let (graph, _) = dump_graph();
+        let mut out1 = Vec::new();
+        graph.dump(&mut out1);
+        let mut out2 = Vec::new();
+        graph.dump(&mut out2);
+        assert_eq!(out1, out2);
+        let text = String::from_utf8(out1).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "#0 = Note offset=12");
+        assert_eq!(lines[2], "#2 = ApplyFunction #1 function=Sine");
+        assert_eq!(lines[4], "#4 = Multiply #2 #3");
+    }
+
+    #[test]
+    fn dump_dot() {
+        let (graph, root) = dump_graph();
+        let mut out1 = Vec::new();
+        graph.dump_dot(&mut out1, root);
+        let mut out2 = Vec::new();
+        graph.dump_dot(&mut out2, root);
+        assert_eq!(out1, out2);
+        let text = String::from_utf8(out1).unwrap();
+        assert!(text.starts_with("digraph ultrafxr {\n"));
+        assert!(text.ends_with("}\n"));
+        assert!(text.contains("  n0 [label=\"#0 Note\\noffset=12\"];\n"));
+        assert!(text.contains("  n4 [label=\"#4 Multiply\", peripheries=2];\n"));
+        assert!(text.contains("  n2 -> n4;\n"));
+        assert!(text.contains("  n3 -> n4;\n"));
+    }
+
+    #[test]
+    fn dump_dot_escape() {
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(ops::Parameter {
+            name: Box::from("a \"b\" \\c\nd"),
+        }));
+        let mut out = Vec::new();
+        graph.dump_dot(&mut out, root);
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text.contains(
+                "  n0 [label=\"#0 Parameter\\nname=a \\\"b\\\" \\\\c\\nd\", peripheries=2];\n"
+            ),
+            "{}",
+            text
+        );
+    }
+}
@@ -1,9 +1,10 @@
-use super::graph::{Node, NodeResult, SignalRef};
+use super::graph::{Node, NodeResult, Param, SignalRef};
 use super::program::{Function, Parameters, State};
+use crate::note::Tuning;
 use std::error;
 use std::f32;
 use std::fmt::{Display, Formatter, Result as FResult};
-use std::slice::from_ref;
+use std::slice::{from_mut, from_ref};
 
 /// Unimplemented operator error.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,18 +28,28 @@ fn unimplemented(name: &'static str) -> NodeResult {
 // =================================================================================================
 
 /// Generate phase from frequency.
-#[derive(Debug)]
+///
+/// The oscillator is a phase accumulator. It integrates its input frequency,
+/// in Hz, over time to produce a phase ramp in the range 0..1, which can drive
+/// waveform functions like Sine and Sawtooth. The first output sample is zero.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Oscillator {
     pub inputs: [SignalRef; 1],
 }
 
 impl Node for Oscillator {
+    fn op_name(&self) -> &'static str {
+        "Oscillator"
+    }
     fn inputs(&self) -> &[SignalRef] {
         &self.inputs[..]
     }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
         Ok(Box::new(OscillatorF {
-            scale: 1.0 / 48000.0,
+            scale: 1.0 / parameters.sample_rate,
             phase: 0.0,
         }))
     }
@@ -46,8 +57,10 @@ impl Node for Oscillator {
 
 #[derive(Debug)]
 struct OscillatorF {
-    scale: f32,
-    phase: f32,
+    scale: f64,
+    // Phase is accumulated in double precision so it does not drift over long
+    // renders.
+    phase: f64,
 }
 
 impl Function for OscillatorF {
@@ -56,13 +69,82 @@ impl Function for OscillatorF {
         let scale = self.scale;
         let mut phase = self.phase;
         for (output, &frequency) in output.iter_mut().zip(frequency.iter()) {
-            *output = phase;
-            phase += frequency * scale;
-            if phase > 1.0 {
-                phase -= 1.0;
+            *output = phase as f32;
+            phase += frequency as f64 * scale;
+            if !(0.0..1.0).contains(&phase) {
+                phase -= phase.floor();
+            }
+        }
+        self.phase = phase;
+    }
+}
+
+// =================================================================================================
+
+/// Generate phase from frequency, resetting the phase on each rising edge of a
+/// master signal, for oscillator hard sync.
+///
+/// A rising edge is where the master changes from zero or less to positive.
+/// The time of the edge is interpolated linearly between samples, and the
+/// phase after the reset accounts for the fraction of the sample after the
+/// edge. The waveform discontinuity at the reset is not band-limited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncOscillator {
+    /// (frequency, master)
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for SyncOscillator {
+    fn op_name(&self) -> &'static str {
+        "SyncOscillator"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(SyncOscillatorF {
+            scale: 1.0 / parameters.sample_rate,
+            phase: 0.0,
+            master: 0.0,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct SyncOscillatorF {
+    scale: f64,
+    phase: f64,
+    /// Previous master sample.
+    master: f32,
+}
+
+impl Function for SyncOscillatorF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let scale = self.scale;
+        let mut phase = self.phase;
+        let mut last = self.master;
+        for (output, (&frequency, &master)) in output
+            .iter_mut()
+            .zip(inputs[0].iter().zip(inputs[1].iter()))
+        {
+            let increment = frequency as f64 * scale;
+            if last <= 0.0 && master > 0.0 {
+                // Fraction of the previous sample interval after the edge.
+                let fraction = master as f64 / (master as f64 - last as f64);
+                phase = increment * fraction;
+            }
+            last = master;
+            if !(0.0..1.0).contains(&phase) {
+                phase -= phase.floor();
             }
+            *output = phase as f32;
+            phase += increment;
         }
         self.phase = phase;
+        self.master = last;
     }
 }
 
@@ -73,23 +155,74 @@ impl Function for OscillatorF {
 pub enum PointFunction {
     Sine,
     Sawtooth,
-    Saturate,
-    Rectify,
+    DecibelToRatio,
+    SemitoneToRatio,
+}
+
+impl PointFunction {
+    /// Apply the function to a single value.
+    pub fn apply(self, x: f32) -> f32 {
+        use PointFunction::*;
+        match self {
+            Sine => (x * (2.0 * f32::consts::PI)).sin(),
+            Sawtooth => wrap_phase(x) * 2.0 - 1.0,
+            DecibelToRatio => 10.0f32.powf(x * (1.0 / 20.0)),
+            SemitoneToRatio => (x * (1.0 / 12.0)).exp2(),
+        }
+    }
+
+    /// Get the name of the function.
+    pub fn name(self) -> &'static str {
+        use PointFunction::*;
+        match self {
+            Sine => "Sine",
+            Sawtooth => "Sawtooth",
+            DecibelToRatio => "DecibelToRatio",
+            SemitoneToRatio => "SemitoneToRatio",
+        }
+    }
+
+    /// Get the function with the given name.
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        use PointFunction::*;
+        [Sine, Sawtooth, DecibelToRatio, SemitoneToRatio]
+            .iter()
+            .copied()
+            .find(|f| f.name() == name)
+    }
 }
 
 /// Apply a function to the waveform.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ApplyFunction {
     pub input: SignalRef,
     pub function: PointFunction,
 }
 
 impl Node for ApplyFunction {
+    fn op_name(&self) -> &'static str {
+        "ApplyFunction"
+    }
     fn inputs(&self) -> &[SignalRef] {
         from_ref(&self.input)
     }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(ApplyFunctionF(self.function)))
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("function", Param::Mode(self.function.name()))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(self.function.apply(inputs[0]))
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        Ok(match self.function {
+            PointFunction::Sawtooth if parameters.antialias => {
+                Box::new(SawtoothF { previous: 0.0 })
+            }
+            function => Box::new(ApplyFunctionF(function)),
+        })
     }
 }
 
@@ -108,260 +241,1304 @@ impl Function for ApplyFunctionF {
             }
             Sawtooth => {
                 for (output, &phase) in items {
-                    let phase = phase % 1.0;
-                    let phase = if phase < 0.0 { phase + 1.0 } else { phase };
-                    *output = phase * 2.0 - 1.0;
-                }
-            }
-            Saturate => {
-                for (y, &x) in items {
-                    *y = x.tanh();
+                    *output = wrap_phase(phase) * 2.0 - 1.0;
                 }
             }
-            Rectify => {
+            function @ (DecibelToRatio | SemitoneToRatio) => {
                 for (y, &x) in items {
-                    *y = x.abs();
+                    *y = function.apply(x);
                 }
             }
         }
     }
 }
 
-// =================================================================================================
-
-/// Generate uniform noise at the full sample rate.
-#[derive(Debug)]
-pub struct Noise;
-
-impl Node for Noise {
-    fn inputs(&self) -> &[SignalRef] {
-        &[]
-    }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(NoiseF))
+/// Residual of a band-limited step with a two-sample polynomial
+/// approximation, for a step of height 2 at phase zero. The phase is in the
+/// range 0..1, and the increment is the absolute change in phase per sample.
+fn poly_blep(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let x = phase / increment;
+        x + x - x * x - 1.0
+    } else if phase > 1.0 - increment {
+        let x = (phase - 1.0) / increment;
+        x * x + x + x + 1.0
+    } else {
+        0.0
     }
 }
 
-#[derive(Debug)]
-struct NoiseF;
-
-impl Function for NoiseF {
-    fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], state: &mut State) {
-        let rand = state.rand();
-        for output in output.iter_mut() {
-            *output = rand.next_float() * 2.0 - 1.0
-        }
+/// Wrap a phase to the range 0..1.
+fn wrap_phase(phase: f32) -> f32 {
+    let phase = phase % 1.0;
+    if phase < 0.0 {
+        phase + 1.0
+    } else {
+        phase
     }
 }
 
-// =================================================================================================
-
-/// Multiply two inputs.
-#[derive(Debug)]
-pub struct Multiply {
-    pub inputs: [SignalRef; 2],
-}
-
-impl Node for Multiply {
-    fn inputs(&self) -> &[SignalRef] {
-        &self.inputs[..]
-    }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(MultiplyF))
-    }
+/// Get the phase increment between two samples, which is the difference
+/// wrapped to the range -0.5..0.5.
+fn phase_increment(previous: f32, phase: f32) -> f32 {
+    let delta = phase - previous;
+    (delta - delta.round()).abs()
 }
 
+/// Sawtooth wave, band-limited with PolyBLEP. The phase increment is
+/// measured from the input.
 #[derive(Debug)]
-struct MultiplyF;
+struct SawtoothF {
+    previous: f32,
+}
 
-impl Function for MultiplyF {
+impl Function for SawtoothF {
     fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
-        let inputx = inputs[0];
-        let inputy = inputs[1];
-        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
-            *output = x * y;
+        let mut previous = self.previous;
+        for (output, &phase) in output.iter_mut().zip(inputs[0].iter()) {
+            let increment = phase_increment(previous, phase);
+            previous = phase;
+            let phase = wrap_phase(phase);
+            *output = phase * 2.0 - 1.0 - poly_blep(phase, increment);
         }
+        self.previous = previous;
     }
 }
 
 // =================================================================================================
 
-/// Multiply an input by a constant gain and add it to the base signal.
-#[derive(Debug)]
-pub struct Mix {
-    /// (base, input) => base + gain * input
+/// Pulse wave, with a duty cycle given by the width input. The output is +1
+/// for the first part of each cycle and -1 for the rest. When antialiasing is
+/// enabled, both edges are band-limited with PolyBLEP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pulse {
+    /// (phase, width)
     pub inputs: [SignalRef; 2],
-    pub gain: f64,
 }
 
-impl Node for Mix {
+impl Node for Pulse {
+    fn op_name(&self) -> &'static str {
+        "Pulse"
+    }
     fn inputs(&self) -> &[SignalRef] {
         &self.inputs[..]
     }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(MixF {
-            gain: self.gain as f32,
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(pulse(wrap_phase(inputs[0]), inputs[1]))
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(PulseF {
+            antialias: parameters.antialias,
+            previous: 0.0,
         }))
     }
 }
 
+fn pulse(phase: f32, width: f32) -> f32 {
+    if phase < width {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
 #[derive(Debug)]
-struct MixF {
-    gain: f32,
+struct PulseF {
+    antialias: bool,
+    previous: f32,
 }
 
-impl Function for MixF {
+impl Function for PulseF {
     fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
-        for (output, (&base, &input)) in output
+        let items = output
             .iter_mut()
-            .zip(inputs[0].iter().zip(inputs[1].iter()))
-        {
-            *output = base + self.gain * input;
+            .zip(inputs[0].iter().zip(inputs[1].iter()));
+        if !self.antialias {
+            for (output, (&phase, &width)) in items {
+                *output = pulse(wrap_phase(phase), width.clamp(0.0, 1.0));
+            }
+            return;
+        }
+        let mut previous = self.previous;
+        for (output, (&phase, &width)) in items {
+            let increment = phase_increment(previous, phase);
+            previous = phase;
+            let phase = wrap_phase(phase);
+            let width = width.clamp(0.0, 1.0);
+            *output = pulse(phase, width) + poly_blep(phase, increment)
+                - poly_blep(wrap_phase(phase - width), increment);
         }
+        self.previous = previous;
     }
 }
 
 // =================================================================================================
 
-/// Convert numbers from -1..+1 to 20..20000, exponentially.
-#[derive(Debug)]
-pub struct Frequency {
+/// Saturation curves. Each curve has unit slope at zero and approaches ±1 for
+/// large inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaturateMode {
+    /// Hyperbolic tangent.
+    Tanh,
+    /// Arctangent, scaled by 2/pi.
+    Atan,
+    /// Cubic soft clip, x - 4/27 x^3, which reaches ±1 at ±1.5.
+    Cubic,
+}
+
+impl SaturateMode {
+    /// Saturate a single value.
+    pub fn apply(self, x: f32) -> f32 {
+        use SaturateMode::*;
+        match self {
+            Tanh => x.tanh(),
+            Atan => (x * f32::consts::FRAC_PI_2).atan() * f32::consts::FRAC_2_PI,
+            Cubic => {
+                let x = x.clamp(-1.5, 1.5);
+                x - (4.0 / 27.0) * x * x * x
+            }
+        }
+    }
+
+    /// Get the name of the mode.
+    pub fn name(self) -> &'static str {
+        use SaturateMode::*;
+        match self {
+            Tanh => "Tanh",
+            Atan => "Atan",
+            Cubic => "Cubic",
+        }
+    }
+
+    /// Get the mode with the given name.
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        use SaturateMode::*;
+        [Tanh, Atan, Cubic]
+            .iter()
+            .copied()
+            .find(|m| m.name() == name)
+    }
+}
+
+/// Saturate the input, limiting it softly to -1..+1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Saturate {
     pub input: SignalRef,
+    pub mode: SaturateMode,
 }
 
-impl Node for Frequency {
+impl Node for Saturate {
+    fn op_name(&self) -> &'static str {
+        "Saturate"
+    }
     fn inputs(&self) -> &[SignalRef] {
         from_ref(&self.input)
     }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("mode", Param::Mode(self.mode.name()))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(self.mode.apply(inputs[0]))
+    }
+    fn nonlinear(&self) -> bool {
+        true
+    }
     fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(FrequencyF))
+        Ok(Box::new(SaturateF(self.mode)))
     }
 }
 
 #[derive(Debug)]
-struct FrequencyF;
+struct SaturateF(SaturateMode);
 
-impl Function for FrequencyF {
+impl Function for SaturateF {
     fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let mode = self.0;
         for (y, &x) in output.iter_mut().zip(inputs[0].iter()) {
-            *y = 630.0 * 32.0f32.powf(x);
+            *y = mode.apply(x);
         }
     }
 }
 
 // =================================================================================================
 
-/// Create a zero buffer.
-#[derive(Debug)]
-pub struct Zero;
+/// Types of rectification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RectifyMode {
+    /// Half-wave rectification, max(x, 0).
+    Half,
+    /// Full-wave rectification, abs(x).
+    Full,
+}
 
-impl Node for Zero {
+impl RectifyMode {
+    /// Rectify a single value.
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            RectifyMode::Half => x.max(0.0),
+            RectifyMode::Full => x.abs(),
+        }
+    }
+
+    /// Get the name of the mode.
+    pub fn name(self) -> &'static str {
+        match self {
+            RectifyMode::Half => "Half",
+            RectifyMode::Full => "Full",
+        }
+    }
+
+    /// Get the mode with the given name.
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        [RectifyMode::Half, RectifyMode::Full]
+            .iter()
+            .copied()
+            .find(|m| m.name() == name)
+    }
+}
+
+/// Rectify the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rectify {
+    pub input: SignalRef,
+    pub mode: RectifyMode,
+}
+
+impl Node for Rectify {
+    fn op_name(&self) -> &'static str {
+        "Rectify"
+    }
     fn inputs(&self) -> &[SignalRef] {
-        &[]
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("mode", Param::Mode(self.mode.name()))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(self.mode.apply(inputs[0]))
+    }
+    fn nonlinear(&self) -> bool {
+        true
     }
     fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(ZeroF))
+        Ok(Box::new(RectifyF(self.mode)))
     }
 }
 
 #[derive(Debug)]
-struct ZeroF;
+struct RectifyF(RectifyMode);
 
-impl Function for ZeroF {
-    fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], _state: &mut State) {
-        for output in output.iter_mut() {
-            *output = 0.0;
+impl Function for RectifyF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let items = output.iter_mut().zip(inputs[0].iter());
+        match self.0 {
+            RectifyMode::Half => {
+                for (y, &x) in items {
+                    *y = x.max(0.0);
+                }
+            }
+            RectifyMode::Full => {
+                for (y, &x) in items {
+                    *y = x.abs();
+                }
+            }
         }
     }
 }
 
 // =================================================================================================
 
-/// Scale input by an integer.
-#[derive(Debug)]
-pub struct ScaleInt {
+/// Quantize x to the given number of bits over the range -1..+1, like a
+/// signed integer with that many bits. There are 2^bits levels with a step
+/// size of 2^(1-bits), from -1 up to one step below +1. Values are rounded
+/// down. The number of bits is clamped to 1..=24.
+fn quantize(x: f32, bits: i32) -> f32 {
+    let scale = ((bits.clamp(1, 24) - 1) as f32).exp2();
+    (x * scale).floor().clamp(-scale, scale - 1.0) / scale
+}
+
+/// Reduce the bit depth and sample rate of the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crush {
     pub input: SignalRef,
-    pub scale: i32,
+    /// Bit depth of the output, clamped to 1..=24.
+    pub bits: i32,
+    /// Sample rate of the output, in Hz, clamped to the program sample rate.
+    pub rate: f64,
 }
 
-impl Node for ScaleInt {
+impl Node for Crush {
+    fn op_name(&self) -> &'static str {
+        "Crush"
+    }
     fn inputs(&self) -> &[SignalRef] {
         from_ref(&self.input)
     }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(ScaleIntF {
-            scale: self.scale as f32,
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![
+            ("bits", Param::Int(self.bits as i64)),
+            ("rate", Param::Float(self.rate)),
+        ]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(quantize(inputs[0], self.bits))
+    }
+    fn nonlinear(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        let rate = if self.rate > 0.0 {
+            self.rate.min(parameters.sample_rate)
+        } else {
+            parameters.sample_rate
+        };
+        Ok(Box::new(CrushF {
+            bits: self.bits,
+            period: ((parameters.sample_rate / rate).round() as usize).max(1),
+            remaining: 0,
+            value: 0.0,
         }))
     }
 }
 
 #[derive(Debug)]
-struct ScaleIntF {
-    scale: f32,
+struct CrushF {
+    bits: i32,
+    /// Number of samples to hold each output value.
+    period: usize,
+    /// Number of samples remaining before the next value is taken.
+    remaining: usize,
+    value: f32,
 }
 
-impl Function for ScaleIntF {
+impl Function for CrushF {
     fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
-        for (output, &input) in output.iter_mut().zip(inputs[0].iter()) {
-            *output = input * self.scale
+        for (y, &x) in output.iter_mut().zip(inputs[0].iter()) {
+            if self.remaining == 0 {
+                self.value = quantize(x, self.bits);
+                self.remaining = self.period;
+            }
+            self.remaining -= 1;
+            *y = self.value;
         }
     }
 }
 
-/*
-// Parameter references
-op!(Parameter, 0, 0, 1); // -> deref and derefcopy
-op!(Note, 1, 0, 1);
- */
-
 // =================================================================================================
 
-/// Generate input note frequency.
-#[derive(Debug)]
-pub struct Note {
-    /// Offset to apply to input note, in semitones.
-    pub offset: i32,
-}
+/// Generate uniform noise at the full sample rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Noise;
 
-impl Node for Note {
+impl Node for Noise {
+    fn op_name(&self) -> &'static str {
+        "Noise"
+    }
     fn inputs(&self) -> &[SignalRef] {
         &[]
     }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn can_merge(&self) -> bool {
+        false
+    }
     fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
-        Ok(Box::new(NoteF {
-            offset: self.offset,
-        }))
+        Ok(Box::new(NoiseF))
     }
 }
 
 #[derive(Debug)]
-struct NoteF {
-    offset: i32,
-}
+struct NoiseF;
 
-impl Function for NoteF {
+impl Function for NoiseF {
     fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], state: &mut State) {
-        let frequency =
-            440.0 * 2.0f32.powf((state.note() + (self.offset - 69) as f32) * (1.0 / 12.0));
-        for x in output.iter_mut() {
-            *x = frequency;
+        let rand = state.rand();
+        for output in output.iter_mut() {
+            *output = rand.next_float() * 2.0 - 1.0
         }
     }
 }
 
 // =================================================================================================
 
-/// Generate a constant value.
-#[derive(Debug)]
-pub struct Constant {
-    pub value: f32,
+/// Multiply two inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multiply {
+    pub inputs: [SignalRef; 2],
 }
 
-impl Node for Constant {
+impl Node for Multiply {
+    fn op_name(&self) -> &'static str {
+        "Multiply"
+    }
     fn inputs(&self) -> &[SignalRef] {
-        &[]
+        &self.inputs[..]
     }
-    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0] * inputs[1])
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(MultiplyF))
+    }
+}
+
+#[derive(Debug)]
+struct MultiplyF;
+
+impl Function for MultiplyF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let inputx = inputs[0];
+        let inputy = inputs[1];
+        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
+            *output = x * y;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Add two inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Add {
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for Add {
+    fn op_name(&self) -> &'static str {
+        "Add"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0] + inputs[1])
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(AddF))
+    }
+}
+
+#[derive(Debug)]
+struct AddF;
+
+impl Function for AddF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let inputx = inputs[0];
+        let inputy = inputs[1];
+        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
+            *output = x + y;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Subtract the second input from the first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subtract {
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for Subtract {
+    fn op_name(&self) -> &'static str {
+        "Subtract"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0] - inputs[1])
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(SubtractF))
+    }
+}
+
+#[derive(Debug)]
+struct SubtractF;
+
+impl Function for SubtractF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let inputx = inputs[0];
+        let inputy = inputs[1];
+        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
+            *output = x - y;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Smallest magnitude for a divisor. Smaller divisors are clamped to this
+/// magnitude so division never produces infinity or NaN.
+const MIN_DIVISOR: f32 = 1.0e-6;
+
+/// Divide x by y, clamping the magnitude of y.
+fn divide(x: f32, y: f32) -> f32 {
+    if y.abs() >= MIN_DIVISOR {
+        x / y
+    } else if y < 0.0 {
+        x / -MIN_DIVISOR
+    } else {
+        x / MIN_DIVISOR
+    }
+}
+
+/// Divide the first input by the second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divide {
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for Divide {
+    fn op_name(&self) -> &'static str {
+        "Divide"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(divide(inputs[0], inputs[1]))
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(DivideF))
+    }
+}
+
+#[derive(Debug)]
+struct DivideF;
+
+impl Function for DivideF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let inputx = inputs[0];
+        let inputy = inputs[1];
+        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
+            *output = divide(x, y);
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Negate the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negate {
+    pub input: SignalRef,
+}
+
+impl Node for Negate {
+    fn op_name(&self) -> &'static str {
+        "Negate"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(-inputs[0])
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(NegateF))
+    }
+}
+
+#[derive(Debug)]
+struct NegateF;
+
+impl Function for NegateF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let input = inputs[0];
+        for (output, &x) in output.iter_mut().zip(input.iter()) {
+            *output = -x;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Raise x to the power y. The absolute value of x is used, so negative bases
+/// with fractional exponents do not produce NaN.
+fn power(x: f32, y: f32) -> f32 {
+    x.abs().powf(y)
+}
+
+/// Raise the first input to the power of the second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Power {
+    /// (base, exponent) => |base| ^ exponent
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for Power {
+    fn op_name(&self) -> &'static str {
+        "Power"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(power(inputs[0], inputs[1]))
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(PowerF))
+    }
+}
+
+#[derive(Debug)]
+struct PowerF;
+
+impl Function for PowerF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let inputx = inputs[0];
+        let inputy = inputs[1];
+        for (output, (&x, &y)) in output.iter_mut().zip(inputx.iter().zip(inputy.iter())) {
+            *output = power(x, y);
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Clamp the input to the range lo..hi.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clamp {
+    pub input: SignalRef,
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Node for Clamp {
+    fn op_name(&self) -> &'static str {
+        "Clamp"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("lo", Param::Float(self.lo)), ("hi", Param::Float(self.hi))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0].max(self.lo as f32).min(self.hi as f32))
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(ClampF {
+            lo: self.lo as f32,
+            hi: self.hi as f32,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct ClampF {
+    lo: f32,
+    hi: f32,
+}
+
+impl Function for ClampF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        for (y, &x) in output.iter_mut().zip(inputs[0].iter()) {
+            *y = x.max(self.lo).min(self.hi);
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Multiply an input by a constant gain and add it to the base signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mix {
+    /// (base, input) => base + gain * input
+    pub inputs: [SignalRef; 2],
+    pub gain: f64,
+}
+
+impl Node for Mix {
+    fn op_name(&self) -> &'static str {
+        "Mix"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("gain", Param::Float(self.gain))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0] + self.gain as f32 * inputs[1])
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(MixF {
+            gain: self.gain as f32,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct MixF {
+    gain: f32,
+}
+
+impl Function for MixF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        for (output, (&base, &input)) in output
+            .iter_mut()
+            .zip(inputs[0].iter().zip(inputs[1].iter()))
+        {
+            *output = base + self.gain * input;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Mismatched number of inputs and gains in a MixN node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadMix {
+    pub inputs: usize,
+    pub gains: usize,
+}
+
+impl Display for BadMix {
+    fn fmt(&self, f: &mut Formatter) -> FResult {
+        write!(f, "mix has {} inputs but {} gains", self.inputs, self.gains)
+    }
+}
+
+impl error::Error for BadMix {}
+
+/// Sum any number of inputs, each multiplied by a constant gain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixN {
+    pub inputs: Box<[SignalRef]>,
+    /// Gain for each input, the same length as inputs.
+    pub gains: Box<[f64]>,
+}
+
+impl Node for MixN {
+    fn op_name(&self) -> &'static str {
+        "MixN"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        self.gains
+            .iter()
+            .map(|&gain| ("gain", Param::Float(gain)))
+            .collect()
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        if inputs.len() != self.gains.len() {
+            return None;
+        }
+        Some(
+            inputs
+                .iter()
+                .zip(self.gains.iter())
+                .map(|(&x, &gain)| gain as f32 * x)
+                .sum(),
+        )
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        if self.inputs.len() != self.gains.len() {
+            return Err(Box::new(BadMix {
+                inputs: self.inputs.len(),
+                gains: self.gains.len(),
+            }));
+        }
+        Ok(Box::new(MixNF {
+            gains: self.gains.iter().map(|&gain| gain as f32).collect(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct MixNF {
+    gains: Box<[f32]>,
+}
+
+impl Function for MixNF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        for y in output.iter_mut() {
+            *y = 0.0;
+        }
+        for (&input, &gain) in inputs.iter().zip(self.gains.iter()) {
+            for (y, &x) in output.iter_mut().zip(input.iter()) {
+                *y += gain * x;
+            }
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Convert a control value to a frequency in Hz, exponentially. The input range
+/// -1..+1 maps to roughly 20..20000 Hz, with 0 at 630 Hz, and each step of 0.2
+/// is one octave.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frequency {
+    pub input: SignalRef,
+}
+
+impl Node for Frequency {
+    fn op_name(&self) -> &'static str {
+        "Frequency"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(630.0 * 32.0f32.powf(inputs[0]))
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(FrequencyF))
+    }
+}
+
+#[derive(Debug)]
+struct FrequencyF;
+
+impl Function for FrequencyF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        for (y, &x) in output.iter_mut().zip(inputs[0].iter()) {
+            *y = 630.0 * 32.0f32.powf(x);
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Create a zero buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zero;
+
+impl Node for Zero {
+    fn op_name(&self) -> &'static str {
+        "Zero"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn fold(&self, _inputs: &[f32]) -> Option<f32> {
+        Some(0.0)
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(ZeroF))
+    }
+}
+
+#[derive(Debug)]
+struct ZeroF;
+
+impl Function for ZeroF {
+    fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], _state: &mut State) {
+        for output in output.iter_mut() {
+            *output = 0.0;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Multiply the input by an integer. A scale of 0 produces silence, and a
+/// negative scale inverts the input. Applied to a phase, this produces an
+/// overtone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScaleInt {
+    pub input: SignalRef,
+    pub scale: i32,
+}
+
+impl Node for ScaleInt {
+    fn op_name(&self) -> &'static str {
+        "ScaleInt"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("scale", Param::Int(self.scale as i64))]
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0] * self.scale as f32)
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(ScaleIntF {
+            scale: self.scale as f32,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct ScaleIntF {
+    scale: f32,
+}
+
+impl Function for ScaleIntF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        for (output, &input) in output.iter_mut().zip(inputs[0].iter()) {
+            *output = input * self.scale
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Sample the input on each rising edge of the trigger, and hold the value
+/// until the next rising edge. A rising edge is where the trigger changes from
+/// zero or less to positive, and the output is zero before the first edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleHold {
+    /// (input, trigger)
+    pub inputs: [SignalRef; 2],
+}
+
+impl Node for SampleHold {
+    fn op_name(&self) -> &'static str {
+        "SampleHold"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &self.inputs[..]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut self.inputs[..]
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(SampleHoldF {
+            value: 0.0,
+            high: false,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct SampleHoldF {
+    value: f32,
+    /// True if the last trigger sample was positive.
+    high: bool,
+}
+
+impl Function for SampleHoldF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        let mut value = self.value;
+        let mut high = self.high;
+        for (y, (&x, &trigger)) in output
+            .iter_mut()
+            .zip(inputs[0].iter().zip(inputs[1].iter()))
+        {
+            let is_high = trigger > 0.0;
+            if is_high && !high {
+                value = x;
+            }
+            high = is_high;
+            *y = value;
+        }
+        self.value = value;
+        self.high = high;
+    }
+}
+
+// =================================================================================================
+
+/// Forward the input unchanged. This creates a separate handle for an existing
+/// signal, and is removed when programs are created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deref {
+    pub input: SignalRef,
+}
+
+impl Node for Deref {
+    fn op_name(&self) -> &'static str {
+        "Deref"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        from_ref(&self.input)
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        from_mut(&mut self.input)
+    }
+    fn forward(&self) -> Option<SignalRef> {
+        Some(self.input)
+    }
+    fn fold(&self, inputs: &[f32]) -> Option<f32> {
+        Some(inputs[0])
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(DerefF))
+    }
+}
+
+#[derive(Debug)]
+struct DerefF;
+
+impl Function for DerefF {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], _state: &mut State) {
+        output.copy_from_slice(&inputs[0][..output.len()]);
+    }
+}
+
+// =================================================================================================
+
+/// Read the value of a named control. The value is supplied by the program
+/// parameters when the program is created, and is zero if it is not supplied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: Box<str>,
+}
+
+impl Node for Parameter {
+    fn op_name(&self) -> &'static str {
+        "Parameter"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("name", Param::Mode(&self.name))]
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        let value = parameters.controls.get(&*self.name).copied();
+        Ok(Box::new(ConstantF {
+            value: value.unwrap_or(0.0) as f32,
+        }))
+    }
+}
+
+// =================================================================================================
+
+/// Generate input note frequency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    /// Offset to apply to input note, in semitones.
+    pub offset: i32,
+}
+
+impl Node for Note {
+    fn op_name(&self) -> &'static str {
+        "Note"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("offset", Param::Int(self.offset as i64))]
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(NoteF {
+            offset: self.offset,
+            tuning: parameters.tuning,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct NoteF {
+    offset: i32,
+    tuning: Tuning,
+}
+
+impl Function for NoteF {
+    fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], state: &mut State) {
+        let frequency = self.tuning.frequency(state.note() + self.offset as f32);
+        for x in output.iter_mut() {
+            *x = frequency;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Generate input note velocity, from 0 to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Velocity;
+
+impl Node for Velocity {
+    fn op_name(&self) -> &'static str {
+        "Velocity"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(VelocityF))
+    }
+}
+
+#[derive(Debug)]
+struct VelocityF;
+
+impl Function for VelocityF {
+    fn render(&mut self, output: &mut [f32], _inputs: &[&[f32]], state: &mut State) {
+        let velocity = state.velocity();
+        for x in output.iter_mut() {
+            *x = velocity;
+        }
+    }
+}
+
+// =================================================================================================
+
+/// Generate the frequency of a fixed note, using the tuning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pitch {
+    /// MIDI note value.
+    pub note: u8,
+}
+
+impl Node for Pitch {
+    fn op_name(&self) -> &'static str {
+        "Pitch"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("note", Param::Int(self.note as i64))]
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, parameters: &Parameters) -> NodeResult {
+        Ok(Box::new(ConstantF {
+            value: parameters.tuning.frequency(self.note as f32),
+        }))
+    }
+}
+
+// =================================================================================================
+
+/// Generate a constant value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub value: f32,
+}
+
+impl Node for Constant {
+    fn op_name(&self) -> &'static str {
+        "Constant"
+    }
+    fn inputs(&self) -> &[SignalRef] {
+        &[]
+    }
+    fn inputs_mut(&mut self) -> &mut [SignalRef] {
+        &mut []
+    }
+    fn parameters(&self) -> Vec<(&'static str, Param<'_>)> {
+        vec![("value", Param::Float(self.value as f64))]
+    }
+    fn fold(&self, _inputs: &[f32]) -> Option<f32> {
+        Some(self.value)
+    }
+    fn control_rate(&self) -> bool {
+        true
+    }
+    fn instantiate(&self, _parameters: &Parameters) -> NodeResult {
         Ok(Box::new(ConstantF { value: self.value }))
     }
 }
@@ -378,3 +1555,542 @@ impl Function for ConstantF {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::note::Temperament;
+    use crate::signal::graph::Graph;
+    use crate::signal::program::{Input, Program};
+
+    /// Render the first buffer from a binary operator applied to constants.
+    fn render_binary(x: f32, y: f32, node: impl FnOnce([SignalRef; 2]) -> Box<dyn Node>) -> f32 {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(Constant { value: x }));
+        let y = graph.add(Box::new(Constant { value: y }));
+        let root = graph.add(node([x, y]));
+        render_one(&graph, root)
+    }
+
+    fn render_one(graph: &Graph, root: SignalRef) -> f32 {
+        render_note(graph, root, 69.0, Default::default())
+    }
+
+    /// Render the first buffer of a graph which produces a constant value.
+    fn render_note(graph: &Graph, root: SignalRef, note: f32, tuning: Tuning) -> f32 {
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 16,
+            tuning,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let output = program
+            .render(&Input {
+                gate: None,
+                note,
+                velocity: 1.0,
+            })
+            .unwrap();
+        assert_eq!(output.len(), 16);
+        let value = output[0];
+        assert!(output.iter().all(|&x| x == value));
+        value
+    }
+
+    #[test]
+    fn node_eq() {
+        let x = Constant { value: 1.0 };
+        assert_eq!(x, Constant { value: 1.0 });
+        assert_ne!(x, Constant { value: 2.0 });
+        assert_eq!(x.clone(), x);
+        let m = Multiply {
+            inputs: [SignalRef(0), SignalRef(1)],
+        };
+        assert_eq!(m.clone(), m);
+        assert_ne!(
+            m,
+            Multiply {
+                inputs: [SignalRef(1), SignalRef(0)],
+            }
+        );
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(
+            render_binary(2.0, 3.0, |inputs| Box::new(Add { inputs })),
+            5.0
+        );
+    }
+
+    #[test]
+    fn subtract() {
+        assert_eq!(
+            render_binary(2.0, 3.0, |inputs| Box::new(Subtract { inputs })),
+            -1.0
+        );
+    }
+
+    #[test]
+    fn divide() {
+        assert_eq!(
+            render_binary(3.0, 2.0, |inputs| Box::new(Divide { inputs })),
+            1.5
+        );
+        for &y in [0.0, -0.0, 1.0e-30, -1.0e-30].iter() {
+            let value = render_binary(1.0, y, |inputs| Box::new(Divide { inputs }));
+            assert!(value.is_finite(), "1 / {} = {}", y, value);
+        }
+    }
+
+    #[test]
+    fn negate() {
+        let mut graph = Graph::new();
+        let input = graph.add(Box::new(Constant { value: 2.5 }));
+        let root = graph.add(Box::new(Negate { input }));
+        assert_eq!(render_one(&graph, root), -2.5);
+    }
+
+    #[test]
+    fn power() {
+        assert_eq!(
+            render_binary(2.0, 3.0, |inputs| Box::new(Power { inputs })),
+            8.0
+        );
+        assert_eq!(
+            render_binary(4.0, -0.5, |inputs| Box::new(Power { inputs })),
+            0.5
+        );
+        assert_eq!(
+            render_binary(-4.0, 0.5, |inputs| Box::new(Power { inputs })),
+            2.0
+        );
+    }
+
+    #[test]
+    fn clamp() {
+        for &(x, expect) in [(-2.0, -1.0), (0.25, 0.25), (3.0, 0.5)].iter() {
+            let mut graph = Graph::new();
+            let input = graph.add(Box::new(Constant { value: x }));
+            let root = graph.add(Box::new(Clamp {
+                input,
+                lo: -1.0,
+                hi: 0.5,
+            }));
+            assert_eq!(render_one(&graph, root), expect, "clamp({})", x);
+        }
+    }
+
+    /// Render the first buffer of a graph.
+    fn render_buffer(graph: &Graph, root: SignalRef) -> Vec<f32> {
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 256,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let output = program.render(&Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        });
+        output.unwrap().to_vec()
+    }
+
+    #[test]
+    fn rectify() {
+        for &mode in [RectifyMode::Half, RectifyMode::Full].iter() {
+            let mut graph = Graph::new();
+            let freq = graph.add(Box::new(Note { offset: 0 }));
+            let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+            let input = graph.add(Box::new(ApplyFunction {
+                input: phase,
+                function: PointFunction::Sine,
+            }));
+            let root = graph.add(Box::new(Rectify { input, mode }));
+            let sine = render_buffer(&graph, input);
+            let output = render_buffer(&graph, root);
+            assert!(sine.iter().any(|&x| x < -0.5));
+            for (&x, &y) in sine.iter().zip(output.iter()) {
+                let expect = match mode {
+                    RectifyMode::Half => x.max(0.0),
+                    RectifyMode::Full => x.abs(),
+                };
+                assert_eq!(y, expect, "{:?}({})", mode, x);
+            }
+        }
+    }
+
+    const SATURATE_MODES: [SaturateMode; 3] =
+        [SaturateMode::Tanh, SaturateMode::Atan, SaturateMode::Cubic];
+
+    #[test]
+    fn saturate() {
+        for &mode in SATURATE_MODES.iter() {
+            let mut graph = Graph::new();
+            let input = graph.add(Box::new(Constant { value: 2.0 }));
+            let root = graph.add(Box::new(Saturate { input, mode }));
+            let y = render_one(&graph, root);
+            assert!(y > 0.5 && y <= 1.0, "{:?}(2) = {}", mode, y);
+            if mode != SaturateMode::Cubic {
+                assert!(y < 1.0, "{:?}(2) = {}", mode, y);
+            }
+            let y = mode.apply(0.01);
+            assert!((y - 0.01).abs() < 1e-5, "{:?}(0.01) = {}", mode, y);
+        }
+    }
+
+    #[test]
+    fn saturate_monotonic() {
+        for &mode in SATURATE_MODES.iter() {
+            let mut last = mode.apply(-4.0);
+            assert!(last >= -1.0);
+            for i in -399..=400 {
+                let x = i as f32 * 0.01;
+                let y = mode.apply(x);
+                assert!(y >= last, "{:?}({}) = {} < {}", mode, x, y, last);
+                last = y;
+            }
+            assert!(last <= 1.0);
+        }
+    }
+
+    /// Render a crushed ramp from -1 to +1 over the first buffer.
+    fn render_crush(bits: i32, rate: f64) -> Vec<f32> {
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 / 256.0,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let input = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sawtooth,
+        }));
+        let root = graph.add(Box::new(Crush { input, bits, rate }));
+        render_buffer(&graph, root)
+    }
+
+    #[test]
+    fn crush_bits() {
+        for &bits in [1, 2, 4].iter() {
+            let step = 2.0f32.powi(1 - bits);
+            let output = render_crush(bits, 48000.0);
+            for &y in output.iter() {
+                assert_eq!((y / step).fract(), 0.0, "bits = {}, y = {}", bits, y);
+            }
+            // The ramp goes through every level once.
+            let mut levels: Vec<f32> = output.clone();
+            levels.dedup();
+            assert_eq!(levels.len(), 1 << bits, "bits = {}", bits);
+            assert_eq!(levels[0], -1.0, "bits = {}", bits);
+            assert_eq!(levels[levels.len() - 1], 1.0 - step, "bits = {}", bits);
+        }
+        assert_eq!(render_crush(0, 48000.0), render_crush(1, 48000.0));
+    }
+
+    #[test]
+    fn crush_rate() {
+        let input = render_crush(24, 48000.0);
+        let output = render_crush(24, 12000.0);
+        for (n, &y) in output.iter().enumerate() {
+            assert_eq!(y, input[n - n % 4], "sample {}", n);
+        }
+        assert_eq!(render_crush(24, 96000.0), input);
+    }
+
+    #[test]
+    fn oscillator_wrap() {
+        const RATE: usize = 44100;
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant { value: 1.0 }));
+        let root = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let parameters = Parameters {
+            sample_rate: RATE as f64,
+            buffer_size: 1000,
+            ..Default::default()
+        };
+        let mut program = Program::new(&graph, root, &parameters).unwrap();
+        let input = Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        };
+        let mut wraps = Vec::new();
+        let mut last = 0.0;
+        for n in 0..(10 * RATE / 1000) {
+            let output = program.render(&input).unwrap();
+            for (i, &x) in output.iter().enumerate() {
+                assert!((0.0..1.0).contains(&x), "phase = {}", x);
+                if x < last {
+                    wraps.push(n * 1000 + i);
+                }
+                last = x;
+            }
+        }
+        assert_eq!(wraps.len(), 9, "wraps at {:?}", wraps);
+        for (n, &pos) in wraps.iter().enumerate() {
+            let expect = (n + 1) * RATE;
+            assert!(
+                pos == expect || pos == expect + 1,
+                "wrap {} at {}, expected {}",
+                n,
+                pos,
+                expect
+            );
+        }
+    }
+
+    #[test]
+    fn mix_n() {
+        let mut graph = Graph::new();
+        let inputs: Vec<SignalRef> = [1.0, 2.0, 3.0]
+            .iter()
+            .map(|&value| graph.add(Box::new(Constant { value })))
+            .collect();
+        let root = graph.add(Box::new(MixN {
+            inputs: Box::from(&inputs[..]),
+            gains: Box::from(&[0.5, -0.25, 2.0][..]),
+        }));
+        assert_eq!(render_one(&graph, root), 6.0);
+        let root = graph.add(Box::new(MixN {
+            inputs: Box::from(&inputs[..]),
+            gains: Box::from(&[0.5][..]),
+        }));
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 16,
+            ..Default::default()
+        };
+        assert!(Program::new(&graph, root, &parameters).is_err());
+    }
+
+    #[test]
+    fn parameter() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(Parameter {
+            name: Box::from("x"),
+        }));
+        let y = graph.add(Box::new(Parameter {
+            name: Box::from("y"),
+        }));
+        let mut parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 16,
+            ..Default::default()
+        };
+        parameters.controls.insert("x".to_string(), 0.75);
+        let input = Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        };
+        for &(root, expect) in [(x, 0.75), (y, 0.0)].iter() {
+            let mut program = Program::new(&graph, root, &parameters).unwrap();
+            let output = program.render(&input).unwrap();
+            assert!(output.iter().all(|&v| v == expect), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn sample_hold() {
+        let mut graph = Graph::new();
+        // Input ramps from -1 to +1 over each 256 samples.
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 / 256.0,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let input = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sawtooth,
+        }));
+        // Trigger is a square wave with a period of 64 samples, starting low.
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 / 64.0,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let trigger = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sawtooth,
+        }));
+        let root = graph.add(Box::new(SampleHold {
+            inputs: [input, trigger],
+        }));
+        let input = render_buffer(&graph, input);
+        let trigger = render_buffer(&graph, trigger);
+        let output = render_buffer(&graph, root);
+        let mut edges = 0;
+        for n in 1..output.len() {
+            if trigger[n] > 0.0 && trigger[n - 1] <= 0.0 {
+                edges += 1;
+                assert_eq!(output[n], input[n], "sample {}", n);
+            } else {
+                assert_eq!(output[n], output[n - 1], "sample {}", n);
+            }
+        }
+        assert_eq!(edges, 4);
+    }
+
+    #[test]
+    fn sync_oscillator() {
+        // Master has a period of 100 samples, the slave is not a multiple.
+        const PERIOD: usize = 100;
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 / PERIOD as f32,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let master = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sawtooth,
+        }));
+        let freq = graph.add(Box::new(Constant { value: 1100.0 }));
+        let root = graph.add(Box::new(SyncOscillator {
+            inputs: [freq, master],
+        }));
+        let master = render_buffer(&graph, master);
+        let output = render_buffer(&graph, root);
+        let increment = 1100.0 / 48000.0;
+        let mut resets = Vec::new();
+        for n in 1..output.len() {
+            if master[n - 1] <= 0.0 && master[n] > 0.0 {
+                resets.push(n);
+                assert!(
+                    output[n] <= increment + 1e-6,
+                    "phase {} at {}",
+                    output[n],
+                    n
+                );
+            } else {
+                let delta = (output[n] - output[n - 1]).rem_euclid(1.0);
+                assert!((delta - increment).abs() < 1e-4, "delta {} at {}", delta, n);
+            }
+        }
+        assert!(resets.len() >= 2);
+        for pair in resets.windows(2) {
+            assert_eq!(pair[1] - pair[0], PERIOD);
+        }
+        for n in resets[0]..resets[1] {
+            assert!(
+                (output[n] - output[n + PERIOD]).abs() < 1e-4,
+                "sample {}",
+                n
+            );
+        }
+    }
+
+    /// Energy in each DFT bin of the signal, up to Nyquist.
+    fn power_spectrum(signal: &[f32]) -> Vec<f64> {
+        let n = signal.len();
+        (0..=n / 2)
+            .map(|k| {
+                let (mut re, mut im) = (0.0f64, 0.0f64);
+                for (i, &x) in signal.iter().enumerate() {
+                    let angle = 2.0 * std::f64::consts::PI * ((k * i) % n) as f64 / n as f64;
+                    re += x as f64 * angle.cos();
+                    im -= x as f64 * angle.sin();
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    /// Energy above half of Nyquist which is not at a harmonic, for a
+    /// waveform with the given number of cycles per buffer.
+    fn aliased_energy(graph: &Graph, root: SignalRef, cycles: usize, antialias: bool) -> f64 {
+        const SIZE: usize = 1024;
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: SIZE,
+            antialias,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let output = program
+            .render(&Input {
+                gate: None,
+                note: 69.0,
+                velocity: 1.0,
+            })
+            .unwrap();
+        power_spectrum(output)
+            .iter()
+            .enumerate()
+            .filter(|&(k, _)| k > SIZE / 4 && k % cycles != 0)
+            .map(|(_, &e)| e)
+            .sum()
+    }
+
+    #[test]
+    fn antialias() {
+        // Exactly 107 cycles in the buffer, about 5 kHz.
+        const CYCLES: usize = 107;
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 * CYCLES as f32 / 1024.0,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let saw = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sawtooth,
+        }));
+        let width = graph.add(Box::new(Constant { value: 0.5 }));
+        let square = graph.add(Box::new(Pulse {
+            inputs: [phase, width],
+        }));
+        for &root in [saw, square].iter() {
+            let naive = aliased_energy(&graph, root, CYCLES, false);
+            let smooth = aliased_energy(&graph, root, CYCLES, true);
+            assert!(smooth < naive * 0.25, "{} {}", smooth, naive);
+        }
+    }
+
+    fn tuning(reference: f64, temperament: Temperament) -> Tuning {
+        Tuning {
+            reference,
+            temperament,
+            tonic: 0,
+        }
+    }
+
+    #[test]
+    fn note_tuning() {
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(Note { offset: 0 }));
+        let t440 = tuning(440.0, Temperament::Equal);
+        let t415 = tuning(415.0, Temperament::Equal);
+        assert_eq!(render_note(&graph, root, 69.0, t440), 440.0);
+        assert_eq!(render_note(&graph, root, 69.0, t415), 415.0);
+        let c4 = render_note(&graph, root, 60.0, t440);
+        assert!((c4 - 261.626).abs() < 0.001, "C4 = {}", c4);
+        let ratio = render_note(&graph, root, 60.0, t415) / c4;
+        assert!((ratio - 415.0 / 440.0).abs() < 1e-6, "ratio = {}", ratio);
+        let root = graph.add(Box::new(Note { offset: 12 }));
+        assert_eq!(render_note(&graph, root, 69.0, t415), 830.0);
+    }
+
+    #[test]
+    fn pitch_tuning() {
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(Pitch { note: 69 }));
+        let t415 = tuning(415.0, Temperament::Equal);
+        // The input note does not affect a fixed pitch.
+        assert_eq!(render_note(&graph, root, 60.0, t415), 415.0);
+        let root = graph.add(Box::new(Pitch { note: 81 }));
+        assert_eq!(render_note(&graph, root, 60.0, t415), 830.0);
+    }
+
+    #[test]
+    fn note_temperament() {
+        let mut graph = Graph::new();
+        let root = graph.add(Box::new(Note { offset: 7 }));
+        let fifth = |temperament| {
+            let t = tuning(440.0, temperament);
+            render_note(&graph, root, 60.0, t) / render_note(&graph, root, 53.0, t)
+        };
+        let equal = fifth(Temperament::Equal);
+        let just = fifth(Temperament::Just);
+        assert!((equal - 1.498307).abs() < 1e-5, "equal fifth = {}", equal);
+        assert!((just - 1.5).abs() < 1e-6, "just fifth = {}", just);
+    }
+}
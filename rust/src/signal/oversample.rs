@@ -0,0 +1,243 @@
+use super::program::{Function, State};
+use std::f64::consts::PI;
+
+/// Number of filter taps for each phase of the polyphase filters.
+const TAPS: usize = 16;
+
+/// Create a windowed sinc lowpass filter for resampling by the given factor.
+/// The cutoff is the Nyquist frequency of the lower rate, and the filter has
+/// unity gain at DC.
+fn kernel(factor: usize) -> Box<[f32]> {
+    let len = factor * TAPS;
+    let center = (len - 1) as f64 / 2.0;
+    let mut kernel: Vec<f64> = (0..len)
+        .map(|i| {
+            let x = (i as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            // Blackman window.
+            let t = 2.0 * PI * i as f64 / (len - 1) as f64;
+            let window = 0.42 - 0.5 * t.cos() + 0.08 * (2.0 * t).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    for x in kernel.iter_mut() {
+        *x /= sum;
+    }
+    kernel.iter().map(|&x| x as f32).collect()
+}
+
+/// Wraps a function so it runs at a multiple of the sample rate. The inputs
+/// are upsampled, the function is applied, and the output is downsampled, with
+/// polyphase FIR filters. This reduces aliasing from nonlinear functions.
+///
+/// The filters delay the output by slightly less than TAPS samples at the
+/// original rate.
+#[derive(Debug)]
+pub struct Oversample {
+    function: Box<dyn Function>,
+    factor: usize,
+    kernel: Box<[f32]>,
+    /// For each input, the previous TAPS - 1 samples followed by the current
+    /// buffer.
+    history: Vec<Vec<f32>>,
+    /// Upsampled input buffers.
+    upsampled: Vec<Vec<f32>>,
+    /// The previous kernel.len() - 1 samples of oversampled output, followed
+    /// by the output for the current buffer.
+    output: Vec<f32>,
+}
+
+impl Oversample {
+    /// Wrap a function, which was instantiated with the sample rate and buffer
+    /// size multiplied by the factor.
+    pub fn new(
+        function: Box<dyn Function>,
+        factor: usize,
+        input_count: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let kernel = kernel(factor);
+        Oversample {
+            function,
+            factor,
+            history: vec![vec![0.0; TAPS - 1 + buffer_size]; input_count],
+            upsampled: vec![vec![0.0; buffer_size * factor]; input_count],
+            output: vec![0.0; kernel.len() - 1 + buffer_size * factor],
+            kernel,
+        }
+    }
+}
+
+impl Function for Oversample {
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], state: &mut State) {
+        let factor = self.factor;
+        let len = output.len();
+        let kernel = &self.kernel[..];
+        for ((history, upsampled), input) in self
+            .history
+            .iter_mut()
+            .zip(self.upsampled.iter_mut())
+            .zip(inputs.iter())
+        {
+            history.copy_within(len..len + TAPS - 1, 0);
+            history[TAPS - 1..TAPS - 1 + len].copy_from_slice(&input[..len]);
+            // Each output sample of the upsampler uses one phase of the
+            // kernel, applied to the most recent input samples.
+            for (n, out) in upsampled[..len * factor]
+                .chunks_exact_mut(factor)
+                .enumerate()
+            {
+                let window = &history[n..n + TAPS];
+                for (phase, y) in out.iter_mut().enumerate() {
+                    let mut sum = 0.0;
+                    for (k, &x) in window.iter().rev().enumerate() {
+                        sum += kernel[phase + k * factor] * x;
+                    }
+                    *y = sum * factor as f32;
+                }
+            }
+        }
+        let inputs: Vec<&[f32]> = self.upsampled.iter().map(|x| &x[..len * factor]).collect();
+        let delay = kernel.len() - 1;
+        let buffer = &mut self.output;
+        buffer.copy_within(len * factor..len * factor + delay, 0);
+        self.function
+            .render(&mut buffer[delay..delay + len * factor], &inputs, state);
+        // The downsampler only computes the samples it keeps.
+        for (n, y) in output.iter_mut().enumerate() {
+            let window = &buffer[n * factor..n * factor + kernel.len()];
+            let mut sum = 0.0;
+            for (&h, &x) in kernel.iter().zip(window.iter().rev()) {
+                sum += h * x;
+            }
+            *y = sum;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::graph::{Graph, SignalRef};
+    use crate::signal::ops::{ApplyFunction, Constant, Multiply, Oscillator, PointFunction};
+    use crate::signal::ops::{Saturate, SaturateMode};
+    use crate::signal::program::{Input, Parameters, Program};
+
+    const SIZE: usize = 1024;
+
+    /// Render the second buffer of output, after the filters have settled.
+    fn render(graph: &Graph, root: SignalRef, oversample: usize) -> Vec<f32> {
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size: SIZE,
+            oversample,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let input = Input {
+            gate: None,
+            note: 69.0,
+            velocity: 1.0,
+        };
+        program.render(&input).unwrap();
+        program.render(&input).unwrap().to_vec()
+    }
+
+    /// Energy in each DFT bin, up to Nyquist.
+    fn power_spectrum(signal: &[f32]) -> Vec<f64> {
+        let n = signal.len();
+        (0..=n / 2)
+            .map(|k| {
+                let (mut re, mut im) = (0.0f64, 0.0f64);
+                for (i, &x) in signal.iter().enumerate() {
+                    let angle = 2.0 * PI * ((k * i) % n) as f64 / n as f64;
+                    re += x as f64 * angle.cos();
+                    im -= x as f64 * angle.sin();
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernel_gain() {
+        for &factor in [2, 4, 8].iter() {
+            let kernel = kernel(factor);
+            // Each phase of the upsampler has unity gain at DC.
+            for phase in 0..factor {
+                let sum: f32 = kernel[phase..].iter().step_by(factor).sum();
+                assert!((sum * factor as f32 - 1.0).abs() < 0.01, "{}", sum);
+            }
+        }
+    }
+
+    #[test]
+    fn linear() {
+        // Oversampling a linear function only delays it, so the level of the
+        // sine is unchanged.
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant { value: 1500.0 }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let sine = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sine,
+        }));
+        // Cubic saturation of a small signal is nearly linear.
+        let small = graph.add(Box::new(Constant { value: 0.01 }));
+        let quiet = graph.add(Box::new(Multiply {
+            inputs: [sine, small],
+        }));
+        let quiet_root = graph.add(Box::new(Saturate {
+            input: quiet,
+            mode: SaturateMode::Cubic,
+        }));
+        let plain = render(&graph, quiet_root, 1);
+        let over = render(&graph, quiet_root, 2);
+        let plain = power_spectrum(&plain);
+        let over = power_spectrum(&over);
+        let bin = 1500 * SIZE / 48000;
+        assert!((over[bin] / plain[bin] - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn aliasing() {
+        // A sine near Nyquist, at exactly 213 cycles per buffer, driven hard
+        // into saturation. All harmonics are above Nyquist, so everything
+        // except the fundamental is aliasing.
+        const CYCLES: usize = 213;
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(Constant {
+            value: 48000.0 * CYCLES as f32 / SIZE as f32,
+        }));
+        let phase = graph.add(Box::new(Oscillator { inputs: [freq] }));
+        let sine = graph.add(Box::new(ApplyFunction {
+            input: phase,
+            function: PointFunction::Sine,
+        }));
+        let gain = graph.add(Box::new(Constant { value: 4.0 }));
+        let loud = graph.add(Box::new(Multiply {
+            inputs: [sine, gain],
+        }));
+        let root = graph.add(Box::new(Saturate {
+            input: loud,
+            mode: SaturateMode::Tanh,
+        }));
+        let aliased = |oversample| -> f64 {
+            let spectrum = power_spectrum(&render(&graph, root, oversample));
+            spectrum
+                .iter()
+                .enumerate()
+                .filter(|&(k, _)| k != CYCLES)
+                .map(|(_, &e)| e)
+                .sum()
+        };
+        let plain = aliased(1);
+        let over = aliased(4);
+        assert!(over < plain * 0.1, "{} {}", over, plain);
+    }
+}
@@ -1,8 +1,13 @@
-use super::graph::{Graph, SignalRef};
+use super::controlrate::Interpolate;
+use super::graph::{Graph, Node as GraphNode, NodeResult, SignalRef};
+use super::oversample::Oversample;
+use crate::note::Tuning;
 use crate::rand::Rand;
 use std::cmp::min;
+use std::collections::HashMap;
 use std::error;
 use std::fmt::{Debug, Display, Formatter, Result as FResult};
+use std::io;
 
 /// Parameters for instantiating a synthesizer program.
 #[derive(Debug)]
@@ -11,6 +16,32 @@ pub struct Parameters {
     pub sample_rate: f64,
     /// Size of audio buffers.
     pub buffer_size: usize,
+    /// Conversion from notes to frequencies.
+    pub tuning: Tuning,
+    /// Values of named controls, read by Parameter nodes. Controls which are
+    /// not present have the value zero.
+    pub controls: HashMap<String, f64>,
+    /// Whether to band-limit discontinuous waveforms.
+    pub antialias: bool,
+    /// Factor to oversample nonlinear nodes by, or 1 for no oversampling.
+    pub oversample: usize,
+    /// Number of samples in each control period, or 1 to render every node
+    /// at the audio rate. Must divide the buffer size.
+    pub control_rate: usize,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            sample_rate: 48000.0,
+            buffer_size: 1024,
+            tuning: Default::default(),
+            controls: Default::default(),
+            antialias: true,
+            oversample: 1,
+            control_rate: 1,
+        }
+    }
 }
 
 /// Input to a synthesizer program.
@@ -18,14 +49,17 @@ pub struct Parameters {
 pub struct Input {
     /// The number of samples before the gate ends.
     pub gate: Option<usize>,
-    /// MIDI note value (69 is A4, which sounds at 440 Hz).
+    /// MIDI note value (69 is A4, which sounds at the tuning frequency).
     pub note: f32,
+    /// Note velocity, from 0 to 1.
+    pub velocity: f32,
 }
 
-/// Audio program execution state.
+/// Audio program execution state for a single node.
 pub struct State {
     gate: Option<usize>,
     note: f32,
+    velocity: f32,
     end: Option<usize>,
     rand: Rand,
 }
@@ -41,6 +75,11 @@ impl State {
         self.note
     }
 
+    /// Get the note velocity, from 0 to 1.
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
     /// Stop the program execution after the given number of samples.
     pub fn stop(&mut self, pos: usize) {
         self.end = Some(match self.end {
@@ -56,33 +95,194 @@ impl State {
 }
 
 /// An audio function, consuming input buffers and filling an output buffer.
-pub trait Function: Debug {
+///
+/// This is the block processing interface for nodes. Each node in the graph
+/// is instantiated as a Function, and [`Program::render`] calls `render` once
+/// per buffer for each node, never once per sample. The [`State`] is the
+/// render context for the call. It is mutable because functions use it to
+/// stop the program and to draw random numbers.
+///
+/// Keep the per-sample loop free of early exits and branches on state that
+/// does not change during the buffer, so the compiler can vectorize it.
+pub trait Function: Debug + Send {
     /// Render the next output buffer.
     fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], state: &mut State);
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    ContainsLoop,
+    /// The graph contains a cycle through the given signals, in order.
+    ContainsLoop(Vec<SignalRef>),
     BadBuffer,
+    BadControlRate,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> FResult {
-        f.write_str(match self {
-            Error::ContainsLoop => "audio graph contains cycle",
-            Error::BadBuffer => "invalid buffer size",
-        })
+        match self {
+            Error::ContainsLoop(signals) => {
+                f.write_str("audio graph contains cycle:")?;
+                for sig in signals.iter() {
+                    write!(f, " #{}", sig.0)?;
+                }
+                Ok(())
+            }
+            Error::BadBuffer => f.write_str("invalid buffer size"),
+            Error::BadControlRate => {
+                f.write_str("invalid control rate, must divide the buffer size")
+            }
+        }
     }
 }
 
 impl error::Error for Error {}
 
+/// The rate at which a node in a program runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rate {
+    Audio,
+    /// One sample per control period. The first buffer has an extra sample at
+    /// the start, so later samples fall at the end of each period.
+    Control {
+        factor: usize,
+        started: bool,
+    },
+    /// Converts its control-rate input to the audio rate.
+    Interpolate,
+}
+
 /// Metadata for a node in an audio program.
 struct Node {
+    signal: SignalRef,
     function: Box<dyn Function>,
-    input_count: usize,
-    inputs: [usize; 4],
+    inputs: Box<[usize]>,
+    // Each node has its own random number generator, so the output does not
+    // depend on the order in which nodes are rendered.
+    rand: Rand,
+    // If the node has no inputs and a constant output, its value. The output
+    // buffer is filled once when the program is created, and the node is not
+    // rendered.
+    constant: Option<f32>,
+    rate: Rate,
+}
+
+impl Node {
+    /// Create a node which interpolates the output of the given control-rate
+    /// node, which has the given index.
+    fn interpolate(node: &Node, index: usize) -> Node {
+        let factor = match node.rate {
+            Rate::Control { factor, .. } => factor,
+            _ => panic!("interpolating audio-rate node"),
+        };
+        Node {
+            signal: node.signal,
+            function: Box::new(Interpolate::new(factor)),
+            inputs: Box::new([index]),
+            rand: node.rand.clone(),
+            constant: None,
+            rate: Rate::Interpolate,
+        }
+    }
+
+    /// Return true if the node runs at the control rate.
+    fn is_control(&self) -> bool {
+        matches!(self.rate, Rate::Control { .. })
+    }
+
+    /// Get the length of the node's next output buffer.
+    fn output_len(&self, buffer_size: usize) -> usize {
+        match self.rate {
+            Rate::Control { factor, started } => buffer_size / factor + if started { 0 } else { 1 },
+            _ => buffer_size,
+        }
+    }
+
+    /// Render the node's next output buffer. Returns the position where the
+    /// node stopped the program, if any, in audio-rate samples.
+    fn render(&mut self, output: &mut [f32], inputs: &[&[f32]], input: &Input) -> Option<usize> {
+        // Time of the first control-rate sample, in audio-rate samples.
+        let (factor, offset) = match &mut self.rate {
+            Rate::Control { factor, started } => {
+                let offset = if *started { *factor } else { 0 };
+                *started = true;
+                (*factor, offset)
+            }
+            _ => (1, 0),
+        };
+        let mut state = State {
+            // The gate is open for control-rate samples before the gate ends.
+            gate: input
+                .gate
+                .map(|gate| gate.saturating_sub(offset).div_ceil(factor)),
+            note: input.note,
+            velocity: input.velocity,
+            end: None,
+            rand: self.rand.clone(),
+        };
+        self.function.render(output, inputs, &mut state);
+        self.rand = state.rand;
+        if factor == 1 {
+            return state.end;
+        }
+        // Output is valid up to the last control-rate sample before the end.
+        state
+            .end
+            .map(|end| (end * factor + offset).saturating_sub(factor))
+    }
+}
+
+/// Instantiate a node, oversampling it if it is nonlinear and oversampling is
+/// enabled. Control-rate nodes run at the control rate, and are never
+/// oversampled.
+fn instantiate(node: &dyn GraphNode, parameters: &Parameters, rate: Rate) -> NodeResult {
+    if let Rate::Control { factor, .. } = rate {
+        return node.instantiate(&Parameters {
+            sample_rate: parameters.sample_rate / factor as f64,
+            buffer_size: parameters.buffer_size / factor + 1,
+            tuning: parameters.tuning,
+            controls: parameters.controls.clone(),
+            antialias: parameters.antialias,
+            oversample: 1,
+            control_rate: 1,
+        });
+    }
+    let factor = parameters.oversample;
+    if factor <= 1 || !node.nonlinear() {
+        return node.instantiate(parameters);
+    }
+    let function = node.instantiate(&Parameters {
+        sample_rate: parameters.sample_rate * factor as f64,
+        buffer_size: parameters.buffer_size * factor,
+        tuning: parameters.tuning,
+        controls: parameters.controls.clone(),
+        antialias: parameters.antialias,
+        oversample: 1,
+        control_rate: 1,
+    })?;
+    Ok(Box::new(Oversample::new(
+        function,
+        factor,
+        node.inputs().len(),
+        parameters.buffer_size,
+    )))
+}
+
+/// A node to render, with its input and output buffers.
+#[cfg(feature = "parallel")]
+struct Job<'a> {
+    index: usize,
+    node: &'a mut Node,
+    output: &'a mut [f32],
+    inputs: Vec<&'a [f32]>,
+}
+
+/// Get the earlier of two optional end positions.
+fn min_end(x: Option<usize>, y: Option<usize>) -> Option<usize> {
+    match (x, y) {
+        (Some(x), Some(y)) => Some(min(x, y)),
+        (x, None) => x,
+        (None, y) => y,
+    }
 }
 
 /// A program which can render audio.
@@ -95,7 +295,12 @@ pub struct Program {
     nodes: Box<[Node]>,
     // If true, the program is done and has no more output.
     done: bool,
-    rand: Rand,
+    // Nodes grouped by depth in the graph. Nodes at the same depth do not
+    // depend on each other and can be rendered concurrently.
+    #[cfg(feature = "parallel")]
+    levels: Box<[Box<[usize]>]>,
+    #[cfg(feature = "parallel")]
+    threads: usize,
 }
 
 impl Program {
@@ -121,11 +326,26 @@ impl Program {
         }
         use Action::*;
         let gnodes = graph.nodes();
+        // Nodes which forward their input are skipped, and references to them
+        // use the forwarded node's output instead.
+        let mut output = output;
+        while let Some(input) = gnodes[output.0 as usize].forward() {
+            output = input;
+        }
         let mut states = Vec::new();
         states.resize(gnodes.len(), NodeState::Unvisited);
         let mut stack = Vec::new();
         stack.push((output, Pre));
+        let control_rate = parameters.control_rate;
+        if control_rate == 0 || !parameters.buffer_size.is_multiple_of(control_rate) {
+            return Err(Box::new(Error::BadControlRate));
+        }
         let mut nodes: Vec<Node> = Vec::new();
+        // Number of nodes from the graph, not counting interpolation.
+        let mut node_count: u64 = 0;
+        // For each control-rate node which feeds an audio-rate node, the
+        // index of the node which interpolates it.
+        let mut interpolated: HashMap<usize, usize> = HashMap::new();
         loop {
             let (sig, action) = match stack.pop() {
                 Some(x) => x,
@@ -143,27 +363,83 @@ impl Program {
                         }
                     }
                     Visiting => {
-                        return Err(Box::new(Error::ContainsLoop));
+                        // The nodes being visited are the path from the
+                        // output to this node, and the cycle is the part of
+                        // the path starting at this node.
+                        let path: Vec<SignalRef> = stack
+                            .iter()
+                            .filter_map(|&(sig, action)| match action {
+                                Post(_) => Some(sig),
+                                Pre => None,
+                            })
+                            .collect();
+                        let start = path.iter().position(|&s| s == sig).unwrap();
+                        return Err(Box::new(Error::ContainsLoop(path[start..].to_vec())));
                     }
                     Visited(_) => {}
                 },
                 Post(inputs) => {
-                    *state = Visited(nodes.len());
-                    let mut input_array = [usize::max_value(); 4];
-                    for (n, &input) in inputs.iter().enumerate() {
-                        input_array[n] = match states[input.0 as usize] {
+                    if let Some(input) = gnodes[sig.0 as usize].forward() {
+                        states[sig.0 as usize] = states[input.0 as usize];
+                        continue;
+                    }
+                    let mut inputs: Box<[usize]> = inputs
+                        .iter()
+                        .map(|&input| match states[input.0 as usize] {
                             Visited(idx) => idx,
                             _ => panic!("node not visited"), // Should not happen.
-                        };
-                    }
+                        })
+                        .collect();
+                    let gnode = gnodes[sig.0 as usize].as_ref();
+                    let rate = if control_rate > 1
+                        && gnode.control_rate()
+                        && inputs.iter().all(|&i| nodes[i].is_control())
+                    {
+                        Rate::Control {
+                            factor: control_rate,
+                            started: false,
+                        }
+                    } else {
+                        // Audio-rate nodes read control-rate inputs through
+                        // an interpolating node.
+                        for input in inputs.iter_mut() {
+                            if nodes[*input].is_control() {
+                                *input = *interpolated.entry(*input).or_insert_with(|| {
+                                    nodes.push(Node::interpolate(&nodes[*input], *input));
+                                    nodes.len() - 1
+                                });
+                            }
+                        }
+                        Rate::Audio
+                    };
+                    states[sig.0 as usize] = Visited(nodes.len());
                     nodes.push(Node {
-                        function: gnodes[sig.0 as usize].instantiate(parameters)?,
-                        input_count: inputs.len(),
-                        inputs: input_array,
+                        signal: sig,
+                        function: instantiate(gnode, parameters, rate)?,
+                        constant: if inputs.is_empty() {
+                            gnode.fold(&[])
+                        } else {
+                            None
+                        },
+                        inputs,
+                        // Hexadecimal digits of pi, with a separate sequence
+                        // for each node.
+                        rand: Rand::with_seed(
+                            0x243f6a8885a308d3,
+                            0x13198a2e03707344u64.wrapping_add(node_count),
+                        ),
+                        rate,
                     });
+                    node_count += 1;
                 }
             }
         }
+        // The output is always at the audio rate.
+        if let Some(root) = nodes.last() {
+            if root.is_control() {
+                nodes.push(Node::interpolate(root, nodes.len() - 1));
+            }
+        }
         let buffer_size = parameters.buffer_size;
         if buffer_size == 0 {
             return Err(Box::new(Error::BadBuffer));
@@ -173,58 +449,357 @@ impl Program {
         let mut buffer = Vec::new();
         let size = buffer_size.checked_mul(nodes.len()).unwrap();
         buffer.resize(size, Default::default());
+        for (node, output) in nodes.iter().zip(buffer.chunks_mut(buffer_size)) {
+            if let Some(value) = node.constant {
+                output.fill(value);
+            }
+        }
         let buffer = Box::<[f32]>::from(buffer);
+        #[cfg(feature = "parallel")]
+        let levels = {
+            let mut depth = Vec::with_capacity(nodes.len());
+            let mut levels: Vec<Vec<usize>> = Vec::new();
+            for (n, node) in nodes.iter().enumerate() {
+                let d = node.inputs.iter().map(|&i| depth[i] + 1).max().unwrap_or(0);
+                depth.push(d);
+                if levels.len() <= d {
+                    levels.resize(d + 1, Vec::new());
+                }
+                levels[d].push(n);
+            }
+            levels.into_iter().map(Box::from).collect()
+        };
         Ok(Program {
             buffer_size,
             buffer,
             nodes,
             done: false,
-            // Hexadecimal digits of pi.
-            rand: Rand::with_seed(0x243f6a8885a308d3, 0x13198a2e03707344),
+            #[cfg(feature = "parallel")]
+            levels,
+            #[cfg(feature = "parallel")]
+            threads: 1,
         })
     }
 
+    /// Set the number of threads used for rendering. Independent nodes are
+    /// rendered concurrently. The output does not depend on the number of
+    /// threads.
+    #[cfg(feature = "parallel")]
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Write the program to a stream as human-readable instructions, one per
+    /// line, in evaluation order. The graph must be the graph the program was
+    /// created from.
+    pub fn disassemble(&self, graph: &Graph, f: &mut dyn io::Write) -> io::Result<()> {
+        let gnodes = graph.nodes();
+        for (n, node) in self.nodes.iter().enumerate() {
+            let gnode = &gnodes[node.signal.0 as usize];
+            if node.rate == Rate::Interpolate {
+                writeln!(f, "${} = Interpolate ${}", n, node.inputs[0])?;
+                continue;
+            }
+            write!(f, "${} = {}", n, gnode.op_name())?;
+            for &input in node.inputs.iter() {
+                write!(f, " ${}", input)?;
+            }
+            for (name, value) in gnode.parameters().iter() {
+                write!(f, " {}={}", name, value)?;
+            }
+            write!(f, " ; {:?}", node.signal)?;
+            if node.is_control() {
+                write!(f, " control")?;
+            }
+            writeln!(f)?;
+        }
+        if !self.nodes.is_empty() {
+            writeln!(f, "root = ${}", self.nodes.len() - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Return true if the program is done and has no more output.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
     /// Render the next output buffer. This will return a series of full
     /// buffers, then optionally a short buffer, and then None.
     pub fn render(&mut self, input: &Input) -> Option<&[f32]> {
         if self.done {
             return None;
         }
+        #[cfg(feature = "parallel")]
+        let end = if self.threads > 1 {
+            self.render_parallel(input)
+        } else {
+            self.render_serial(input)
+        };
+        #[cfg(not(feature = "parallel"))]
+        let end = self.render_serial(input);
+        let output = self
+            .buffer
+            .chunks_exact(self.buffer_size)
+            .next_back()
+            .unwrap();
+        Some(match end {
+            Some(len) => {
+                self.done = true;
+                &output[..len]
+            }
+            None => output,
+        })
+    }
+
+    /// Render every node in order on the current thread.
+    fn render_serial(&mut self, input: &Input) -> Option<usize> {
         // TODO: Change this function so it doesn't allocate memory.
         let buffer_size = self.buffer_size;
         let buffer = &mut self.buffer[..];
         let nodes = &mut self.nodes[..];
         let mut outputs = Vec::new();
         outputs.resize(nodes.len(), Default::default());
-        let mut state = State {
-            note: input.note,
-            gate: input.gate,
-            end: None,
-            rand: self.rand.clone(),
-        };
+        let mut inputs: Vec<&[f32]> = Vec::new();
+        let mut end = None;
         for (n, (node, output)) in nodes
             .iter_mut()
             .zip(buffer.chunks_mut(buffer_size))
             .enumerate()
         {
-            let input_count = node.input_count;
-            let mut inputs: [&[f32]; 4] = [Default::default(); 4];
-            for (i, &index) in node.inputs[0..input_count].iter().enumerate() {
-                debug_assert!(index < n);
-                inputs[i] = outputs[index];
-            }
-            node.function
-                .render(output, &inputs[0..input_count], &mut state);
+            let output = &mut output[..node.output_len(buffer_size)];
+            if node.constant.is_none() {
+                inputs.clear();
+                for &index in node.inputs.iter() {
+                    debug_assert!(index < n);
+                    inputs.push(outputs[index]);
+                }
+                end = min_end(end, node.render(output, &inputs, input));
+            }
             outputs[n] = output;
         }
-        self.rand = state.rand.clone();
-        let output = buffer.chunks_exact(self.buffer_size).next_back().unwrap();
-        Some(match state.end {
-            Some(len) => {
-                self.done = true;
-                &output[..len]
+        end
+    }
+
+    /// Render the nodes one level at a time, dividing the nodes in each level
+    /// among the threads.
+    #[cfg(feature = "parallel")]
+    fn render_parallel(&mut self, input: &Input) -> Option<usize> {
+        use std::thread;
+        let threads = self.threads;
+        let buffer_size = self.buffer_size;
+        let mut nodes: Vec<Option<&mut Node>> = self.nodes.iter_mut().map(Some).collect();
+        let mut outputs: Vec<Option<&mut [f32]>> =
+            self.buffer.chunks_mut(self.buffer_size).map(Some).collect();
+        let mut finished: Vec<&[f32]> = vec![&[]; nodes.len()];
+        let mut end = None;
+        for level in self.levels.iter() {
+            let mut jobs: Vec<Job> = level
+                .iter()
+                .map(|&n| {
+                    let node = nodes[n].take().unwrap();
+                    let inputs = node.inputs.iter().map(|&i| finished[i]).collect();
+                    let len = node.output_len(buffer_size);
+                    Job {
+                        index: n,
+                        node,
+                        output: &mut outputs[n].take().unwrap()[..len],
+                        inputs,
+                    }
+                })
+                .collect();
+            let chunk_size = jobs.len().div_ceil(threads);
+            let run = |chunk: &mut [Job]| {
+                chunk.iter_mut().fold(None, |end, job| {
+                    if job.node.constant.is_some() {
+                        return end;
+                    }
+                    min_end(end, job.node.render(job.output, &job.inputs, input))
+                })
+            };
+            end = thread::scope(|scope| {
+                let mut chunks = jobs.chunks_mut(chunk_size);
+                let first = chunks.next().unwrap();
+                let handles: Vec<_> = chunks
+                    .map(|chunk| scope.spawn(move || run(chunk)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .fold(min_end(end, run(first)), |end, handle| {
+                        min_end(end, handle.join().unwrap())
+                    })
+            });
+            for job in jobs {
+                finished[job.index] = job.output;
             }
-            None => output,
-        })
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signal::ops;
+
+    #[test]
+    fn disassemble() {
+        let mut graph = Graph::new();
+        let freq = graph.add(Box::new(ops::Note { offset: 12 }));
+        // Unused nodes are not part of the program.
+        graph.add(Box::new(ops::Noise));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let wave = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sine,
+        }));
+        let gain = graph.add(Box::new(ops::Constant { value: 0.5 }));
+        let out = graph.add(Box::new(ops::Multiply {
+            inputs: [wave, gain],
+        }));
+        let program = Program::new(
+            &graph,
+            out,
+            &Parameters {
+                sample_rate: 48000.0,
+                buffer_size: 64,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut text = Vec::new();
+        program.disassemble(&graph, &mut text).unwrap();
+        let expect = "\
+$0 = Constant value=0.5 ; SignalRef(4)
+$1 = Note offset=12 ; SignalRef(0)
+$2 = Oscillator $1 ; SignalRef(2)
+$3 = ApplyFunction $2 function=Sine ; SignalRef(3)
+$4 = Multiply $3 $0 ; SignalRef(5)
+root = $4
+";
+        assert_eq!(String::from_utf8(text).unwrap(), expect);
+    }
+
+    /// Render a signal from the graph, in blocks of the given size.
+    fn render_blocks(graph: &Graph, root: SignalRef, buffer_size: usize) -> Vec<f32> {
+        let parameters = Parameters {
+            sample_rate: 48000.0,
+            buffer_size,
+            ..Default::default()
+        };
+        let mut program = Program::new(graph, root, &parameters).unwrap();
+        let mut output = Vec::new();
+        while output.len() < 1024 {
+            match program.render(&Input {
+                gate: None,
+                note: 60.0,
+                velocity: 1.0,
+            }) {
+                Some(block) => output.extend_from_slice(block),
+                None => break,
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn constant_block() {
+        let mut graph = Graph::new();
+        let x = graph.add(Box::new(ops::Constant { value: 0.25 }));
+        let y = graph.add(Box::new(ops::Zero));
+        let noise = graph.add(Box::new(ops::Noise));
+        let sum = graph.add(Box::new(ops::Add { inputs: [x, y] }));
+        let root = graph.add(Box::new(ops::Add {
+            inputs: [sum, noise],
+        }));
+        for &sig in [x, y, sum].iter() {
+            let expect = if sig == y { 0.0 } else { 0.25 };
+            let output = render_blocks(&graph, sig, 64);
+            assert_eq!(output.len(), 1024);
+            assert!(output.iter().all(|&v| v == expect), "{:?}", sig);
+        }
+        // Nodes after the constants see the same value in every block.
+        let output = render_blocks(&graph, root, 64);
+        let noise = render_blocks(&graph, noise, 64);
+        for (&v, &n) in output.iter().zip(noise.iter()) {
+            assert_eq!(v, 0.25 + n);
+        }
+    }
+
+    #[test]
+    fn block_matches_sample() {
+        // Processing a block at once gives the same output as processing one
+        // sample at a time, for every type of node. The per-sample reference
+        // is each node's function called with one-sample buffers, not a
+        // separate implementation.
+        let (graph, _) = crate::signal::serialize::test::all_nodes();
+        for n in 0..graph.nodes().len() {
+            let root = SignalRef(n as u32);
+            let block = render_blocks(&graph, root, 256);
+            let sample = render_blocks(&graph, root, 1);
+            let name = graph.nodes()[n].op_name();
+            assert_eq!(block.len(), sample.len(), "{}", name);
+            for (i, (&x, &y)) in block.iter().zip(sample.iter()).enumerate() {
+                // Compare bits, so NaN matches NaN.
+                assert_eq!(
+                    x.to_bits(),
+                    y.to_bits(),
+                    "{}: sample {}: {} {}",
+                    name,
+                    i,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel() {
+        // Two independent branches, mixed at the end.
+        let mut graph = Graph::new();
+        let noise = graph.add(Box::new(ops::Noise));
+        let cutoff = graph.add(Box::new(ops::Constant { value: 1000.0 }));
+        let filtered = graph.add(Box::new(crate::signal::filter::StateVariable {
+            inputs: [noise, cutoff],
+            mode: crate::signal::filter::Mode::LowPass2,
+            q: 0.7,
+        }));
+        let freq = graph.add(Box::new(ops::Note { offset: 0 }));
+        let phase = graph.add(Box::new(ops::Oscillator { inputs: [freq] }));
+        let saw = graph.add(Box::new(ops::ApplyFunction {
+            input: phase,
+            function: ops::PointFunction::Sawtooth,
+        }));
+        let noise2 = graph.add(Box::new(ops::Noise));
+        let noisy = graph.add(Box::new(ops::Multiply {
+            inputs: [saw, noise2],
+        }));
+        let root = graph.add(Box::new(ops::Add {
+            inputs: [filtered, noisy],
+        }));
+        let render = |threads| {
+            let parameters = Parameters {
+                sample_rate: 48000.0,
+                buffer_size: 64,
+                ..Default::default()
+            };
+            let mut program = Program::new(&graph, root, &parameters).unwrap();
+            program.set_threads(threads);
+            let mut output = Vec::new();
+            for _ in 0..16 {
+                let block = program.render(&Input {
+                    gate: None,
+                    note: 60.0,
+                    velocity: 1.0,
+                });
+                output.extend_from_slice(block.unwrap());
+            }
+            output
+        };
+        let serial = render(1);
+        assert_eq!(render(4), serial);
+        assert_eq!(render(2), serial);
     }
 }
@@ -0,0 +1,587 @@
+use super::envelope::{Envelope, Segment};
+use super::filter;
+use super::graph::{Graph, Node, Param, SignalRef};
+use super::ops;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FResult};
+
+/// Magic bytes at the start of a serialized graph.
+const MAGIC: &[u8; 4] = b"UFXG";
+
+/// Version of the serialized graph format.
+const VERSION: u8 = 2;
+
+const TAG_FLOAT: u8 = 0;
+const TAG_INT: u8 = 1;
+const TAG_MODE: u8 = 2;
+
+/// An error deserializing a graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingData,
+    BadString,
+    BadParamType(u8),
+    UnknownOp(String),
+    BadInput { op: String },
+    BadParameters { op: String },
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut Formatter) -> FResult {
+        use GraphError::*;
+        match self {
+            BadMagic => f.write_str("not a serialized graph"),
+            UnsupportedVersion(v) => write!(f, "unsupported graph format version: {}", v),
+            Truncated => f.write_str("unexpected end of data"),
+            TrailingData => f.write_str("extra data after graph"),
+            BadString => f.write_str("invalid string"),
+            BadParamType(t) => write!(f, "invalid parameter type: {}", t),
+            UnknownOp(op) => write!(f, "unknown node type: {:?}", op),
+            BadInput { op } => write!(f, "invalid inputs for {} node", op),
+            BadParameters { op } => write!(f, "invalid parameters for {} node", op),
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+/// A parameter read from serialized data.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Float(f64),
+    Int(i64),
+    Mode(String),
+}
+
+/// Reader for serialized data.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], GraphError> {
+        if self.data.len() < n {
+            return Err(GraphError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, GraphError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, GraphError> {
+        Ok(u32::from_le_bytes(
+            <[u8; 4]>::try_from(self.bytes(4)?).unwrap(),
+        ))
+    }
+    fn u64(&mut self) -> Result<u64, GraphError> {
+        Ok(u64::from_le_bytes(
+            <[u8; 8]>::try_from(self.bytes(8)?).unwrap(),
+        ))
+    }
+    fn str(&mut self) -> Result<&'a str, GraphError> {
+        let n = self.u8()? as usize;
+        std::str::from_utf8(self.bytes(n)?).map_err(|_| GraphError::BadString)
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    let n = u8::try_from(s.len()).expect("string too long");
+    out.push(n);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Reads the parameters for a node, in order.
+struct Params<'a> {
+    op: &'a str,
+    params: std::vec::IntoIter<(&'a str, Value)>,
+}
+
+impl<'a> Params<'a> {
+    fn error(&self) -> GraphError {
+        GraphError::BadParameters {
+            op: self.op.to_string(),
+        }
+    }
+    fn next(&mut self, name: &str) -> Result<Value, GraphError> {
+        match self.params.next() {
+            Some((pname, value)) if pname == name => Ok(value),
+            _ => Err(self.error()),
+        }
+    }
+    fn float(&mut self, name: &str) -> Result<f64, GraphError> {
+        match self.next(name)? {
+            Value::Float(x) => Ok(x),
+            _ => Err(self.error()),
+        }
+    }
+    fn int<T: TryFrom<i64>>(&mut self, name: &str) -> Result<T, GraphError> {
+        match self.next(name)? {
+            Value::Int(x) => T::try_from(x).map_err(|_| self.error()),
+            _ => Err(self.error()),
+        }
+    }
+    fn mode<T>(&mut self, name: &str, f: impl FnOnce(&str) -> Option<T>) -> Result<T, GraphError> {
+        match self.next(name)? {
+            Value::Mode(x) => f(&x).ok_or_else(|| self.error()),
+            _ => Err(self.error()),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.params.len() == 0
+    }
+    fn finish(&self) -> Result<(), GraphError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+}
+
+/// Create an envelope from its flattened segment parameters.
+fn envelope(params: &mut Params) -> Result<Envelope, GraphError> {
+    let mut segments = Vec::new();
+    while !params.is_empty() {
+        let kind = params.mode("segment", |s| Some(s.to_string()))?;
+        segments.push(match kind.as_str() {
+            "Set" => Segment::Set {
+                value: params.float("value")?,
+            },
+            "Linear" => Segment::Linear {
+                time: params.float("time")?,
+                value: params.float("value")?,
+            },
+            "Exponential" => Segment::Exponential {
+                time_constant: params.float("time_constant")?,
+                value: params.float("value")?,
+            },
+            "Delay" => Segment::Delay {
+                time: params.float("time")?,
+            },
+            "Gate" => Segment::Gate,
+            "Stop" => Segment::Stop,
+            _ => return Err(params.error()),
+        });
+    }
+    Ok(Envelope {
+        segments: Box::from(segments),
+    })
+}
+
+/// Create a node from its operation name, inputs, and parameters.
+fn make_node(
+    op: &str,
+    inputs: &[SignalRef],
+    params: Vec<(&str, Value)>,
+) -> Result<Box<dyn Node>, GraphError> {
+    let mut params = Params {
+        op,
+        params: params.into_iter(),
+    };
+    let bad_input = || GraphError::BadInput { op: op.to_string() };
+    let inputs0 = || -> Result<(), GraphError> {
+        if inputs.is_empty() {
+            Ok(())
+        } else {
+            Err(bad_input())
+        }
+    };
+    let inputs1 = || -> Result<SignalRef, GraphError> {
+        match inputs {
+            &[x] => Ok(x),
+            _ => Err(bad_input()),
+        }
+    };
+    let inputs2 = || -> Result<[SignalRef; 2], GraphError> {
+        <[SignalRef; 2]>::try_from(inputs).map_err(|_| bad_input())
+    };
+    let node: Box<dyn Node> = match op {
+        "Oscillator" => Box::new(ops::Oscillator {
+            inputs: [inputs1()?],
+        }),
+        "Pulse" => Box::new(ops::Pulse { inputs: inputs2()? }),
+        "SyncOscillator" => Box::new(ops::SyncOscillator { inputs: inputs2()? }),
+        "ApplyFunction" => Box::new(ops::ApplyFunction {
+            input: inputs1()?,
+            function: params.mode("function", ops::PointFunction::from_name)?,
+        }),
+        "Rectify" => Box::new(ops::Rectify {
+            input: inputs1()?,
+            mode: params.mode("mode", ops::RectifyMode::from_name)?,
+        }),
+        "Saturate" => Box::new(ops::Saturate {
+            input: inputs1()?,
+            mode: params.mode("mode", ops::SaturateMode::from_name)?,
+        }),
+        "Crush" => Box::new(ops::Crush {
+            input: inputs1()?,
+            bits: params.int("bits")?,
+            rate: params.float("rate")?,
+        }),
+        "Noise" => {
+            inputs0()?;
+            Box::new(ops::Noise)
+        }
+        "Multiply" => Box::new(ops::Multiply { inputs: inputs2()? }),
+        "Add" => Box::new(ops::Add { inputs: inputs2()? }),
+        "Subtract" => Box::new(ops::Subtract { inputs: inputs2()? }),
+        "Divide" => Box::new(ops::Divide { inputs: inputs2()? }),
+        "Negate" => Box::new(ops::Negate { input: inputs1()? }),
+        "Power" => Box::new(ops::Power { inputs: inputs2()? }),
+        "Clamp" => Box::new(ops::Clamp {
+            input: inputs1()?,
+            lo: params.float("lo")?,
+            hi: params.float("hi")?,
+        }),
+        "Mix" => Box::new(ops::Mix {
+            inputs: inputs2()?,
+            gain: params.float("gain")?,
+        }),
+        "MixN" => {
+            let mut gains = Vec::new();
+            while !params.is_empty() {
+                gains.push(params.float("gain")?);
+            }
+            if gains.len() != inputs.len() {
+                return Err(params.error());
+            }
+            Box::new(ops::MixN {
+                inputs: Box::from(inputs),
+                gains: Box::from(gains),
+            })
+        }
+        "Frequency" => Box::new(ops::Frequency { input: inputs1()? }),
+        "Zero" => {
+            inputs0()?;
+            Box::new(ops::Zero)
+        }
+        "ScaleInt" => Box::new(ops::ScaleInt {
+            input: inputs1()?,
+            scale: params.int("scale")?,
+        }),
+        "Note" => {
+            inputs0()?;
+            Box::new(ops::Note {
+                offset: params.int("offset")?,
+            })
+        }
+        "Pitch" => {
+            inputs0()?;
+            Box::new(ops::Pitch {
+                note: params.int("note")?,
+            })
+        }
+        "Velocity" => {
+            inputs0()?;
+            Box::new(ops::Velocity)
+        }
+        "SampleHold" => Box::new(ops::SampleHold { inputs: inputs2()? }),
+        "Deref" => Box::new(ops::Deref { input: inputs1()? }),
+        "Parameter" => {
+            inputs0()?;
+            Box::new(ops::Parameter {
+                name: Box::from(params.mode("name", |s| Some(s.to_string()))?),
+            })
+        }
+        "Constant" => {
+            inputs0()?;
+            Box::new(ops::Constant {
+                value: params.float("value")? as f32,
+            })
+        }
+        "StateVariable" => Box::new(filter::StateVariable {
+            inputs: inputs2()?,
+            mode: params.mode("mode", filter::Mode::from_name)?,
+            q: params.float("q")?,
+        }),
+        "HighPass" => Box::new(filter::HighPass {
+            input: inputs1()?,
+            frequency: params.float("frequency")?,
+        }),
+        "Glide" => Box::new(filter::Glide {
+            input: inputs1()?,
+            time: params.float("time")?,
+        }),
+        "Slew" => Box::new(filter::Slew {
+            input: inputs1()?,
+            rise: params.float("rise")?,
+            fall: params.float("fall")?,
+        }),
+        "Envelope" => {
+            inputs0()?;
+            Box::new(envelope(&mut params)?)
+        }
+        _ => return Err(GraphError::UnknownOp(op.to_string())),
+    };
+    params.finish()?;
+    Ok(node)
+}
+
+impl Graph {
+    /// Serialize the graph to a compact binary format. Nodes keep their
+    /// indexes, so signal references into the graph remain valid after the
+    /// graph is deserialized.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        let nodes = self.nodes();
+        out.extend_from_slice(&u32::try_from(nodes.len()).unwrap().to_le_bytes());
+        for node in nodes.iter() {
+            write_str(&mut out, node.op_name());
+            let inputs = node.inputs();
+            out.extend_from_slice(&u32::try_from(inputs.len()).unwrap().to_le_bytes());
+            for input in inputs.iter() {
+                out.extend_from_slice(&input.0.to_le_bytes());
+            }
+            let params = node.parameters();
+            out.extend_from_slice(&u32::try_from(params.len()).unwrap().to_le_bytes());
+            for &(name, value) in params.iter() {
+                write_str(&mut out, name);
+                match value {
+                    Param::Float(x) => {
+                        out.push(TAG_FLOAT);
+                        out.extend_from_slice(&x.to_bits().to_le_bytes());
+                    }
+                    Param::Int(x) => {
+                        out.push(TAG_INT);
+                        out.extend_from_slice(&x.to_le_bytes());
+                    }
+                    Param::Mode(x) => {
+                        out.push(TAG_MODE);
+                        write_str(&mut out, x);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Deserialize a graph created by serialize.
+    pub fn deserialize(data: &[u8]) -> Result<Graph, GraphError> {
+        let mut r = Reader { data };
+        if r.bytes(MAGIC.len()).map_err(|_| GraphError::BadMagic)? != MAGIC {
+            return Err(GraphError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(GraphError::UnsupportedVersion(version));
+        }
+        let count = r.u32()?;
+        let mut graph = Graph::new();
+        let mut inputs = Vec::new();
+        for n in 0..count {
+            let op = r.str()?;
+            inputs.clear();
+            for _ in 0..r.u32()? {
+                let input = r.u32()?;
+                if input >= n {
+                    return Err(GraphError::BadInput { op: op.to_string() });
+                }
+                inputs.push(SignalRef(input));
+            }
+            let param_count = r.u32()?;
+            let mut params = Vec::new();
+            for _ in 0..param_count {
+                let name = r.str()?;
+                let value = match r.u8()? {
+                    TAG_FLOAT => Value::Float(f64::from_bits(r.u64()?)),
+                    TAG_INT => Value::Int(r.u64()? as i64),
+                    TAG_MODE => Value::Mode(r.str()?.to_string()),
+                    t => return Err(GraphError::BadParamType(t)),
+                };
+                params.push((name, value));
+            }
+            graph.add(make_node(op, &inputs, params)?);
+        }
+        if !r.data.is_empty() {
+            return Err(GraphError::TrailingData);
+        }
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Create a graph containing every type of node.
+    pub(crate) fn all_nodes() -> (Graph, SignalRef) {
+        let mut g = Graph::new();
+        let note = g.add(Box::new(ops::Note { offset: -3 }));
+        let osc = g.add(Box::new(ops::Oscillator { inputs: [note] }));
+        let sync = g.add(Box::new(ops::SyncOscillator {
+            inputs: [note, osc],
+        }));
+        let sine = g.add(Box::new(ops::ApplyFunction {
+            input: sync,
+            function: ops::PointFunction::Sine,
+        }));
+        let pulse = g.add(Box::new(ops::Pulse {
+            inputs: [sync, sine],
+        }));
+        let sat = g.add(Box::new(ops::Saturate {
+            input: pulse,
+            mode: ops::SaturateMode::Cubic,
+        }));
+        let rect = g.add(Box::new(ops::Rectify {
+            input: sat,
+            mode: ops::RectifyMode::Half,
+        }));
+        let crush = g.add(Box::new(ops::Crush {
+            input: rect,
+            bits: 8,
+            rate: 11025.0,
+        }));
+        let noise = g.add(Box::new(ops::Noise));
+        let mul = g.add(Box::new(ops::Multiply {
+            inputs: [crush, noise],
+        }));
+        let add = g.add(Box::new(ops::Add {
+            inputs: [mul, sine],
+        }));
+        let sub = g.add(Box::new(ops::Subtract {
+            inputs: [add, noise],
+        }));
+        let div = g.add(Box::new(ops::Divide {
+            inputs: [sub, sine],
+        }));
+        let neg = g.add(Box::new(ops::Negate { input: div }));
+        let pow = g.add(Box::new(ops::Power {
+            inputs: [neg, sine],
+        }));
+        let clamp = g.add(Box::new(ops::Clamp {
+            input: pow,
+            lo: -0.5,
+            hi: 2.0,
+        }));
+        let zero = g.add(Box::new(ops::Zero));
+        let mix = g.add(Box::new(ops::Mix {
+            inputs: [zero, clamp],
+            gain: 0.25,
+        }));
+        let vel = g.add(Box::new(ops::Velocity));
+        let pitch = g.add(Box::new(ops::Pitch { note: 57 }));
+        let mix_n = g.add(Box::new(ops::MixN {
+            inputs: Box::from(&[mix, zero, neg, vel, pitch][..]),
+            gains: Box::from(&[0.5, 1.5, -2.0, 0.75, 0.001][..]),
+        }));
+        let deref = g.add(Box::new(ops::Deref { input: mix_n }));
+        let freq = g.add(Box::new(ops::Frequency { input: deref }));
+        let scale = g.add(Box::new(ops::ScaleInt {
+            input: freq,
+            scale: 3,
+        }));
+        let c = g.add(Box::new(ops::Parameter {
+            name: Box::from("cutoff"),
+        }));
+        let svf = g.add(Box::new(filter::StateVariable {
+            inputs: [scale, c],
+            mode: filter::Mode::BandPass2,
+            q: 0.9,
+        }));
+        let hp = g.add(Box::new(filter::HighPass {
+            input: svf,
+            frequency: 20.0,
+        }));
+        let glide = g.add(Box::new(filter::Glide {
+            input: hp,
+            time: 0.05,
+        }));
+        let slew = g.add(Box::new(filter::Slew {
+            input: glide,
+            rise: 100.0,
+            fall: 50.0,
+        }));
+        let hold = g.add(Box::new(ops::SampleHold {
+            inputs: [slew, noise],
+        }));
+        let env = g.add(Box::new(Envelope {
+            segments: Box::from(
+                &[
+                    Segment::Set { value: 0.0 },
+                    Segment::Linear {
+                        time: 0.01,
+                        value: 1.0,
+                    },
+                    Segment::Delay { time: 0.5 },
+                    Segment::Gate,
+                    Segment::Exponential {
+                        time_constant: 0.1,
+                        value: 0.0,
+                    },
+                    Segment::Stop,
+                ][..],
+            ),
+        }));
+        let root = g.add(Box::new(ops::Multiply {
+            inputs: [hold, env],
+        }));
+        (g, root)
+    }
+
+    fn dump(graph: &Graph) -> String {
+        let mut out = Vec::new();
+        graph.dump(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (graph, root) = all_nodes();
+        let data = graph.serialize();
+        let graph2 = Graph::deserialize(&data).unwrap();
+        assert_eq!(dump(&graph), dump(&graph2));
+        let node = &graph2.nodes()[root.0 as usize];
+        assert_eq!(node.op_name(), "Multiply");
+        assert_eq!(graph2.serialize(), data);
+    }
+
+    #[test]
+    fn many_inputs() {
+        let mut g = Graph::new();
+        let inputs: Vec<SignalRef> = (0..256)
+            .map(|n| g.add(Box::new(ops::Constant { value: n as f32 })))
+            .collect();
+        let gains = vec![0.5; inputs.len()];
+        g.add(Box::new(ops::MixN {
+            inputs: Box::from(inputs),
+            gains: Box::from(gains),
+        }));
+        let data = g.serialize();
+        let g2 = Graph::deserialize(&data).unwrap();
+        assert_eq!(dump(&g), dump(&g2));
+        assert_eq!(g2.nodes()[256].inputs().len(), 256);
+    }
+
+    #[test]
+    fn errors() {
+        let (graph, _) = all_nodes();
+        let data = graph.serialize();
+        assert_eq!(
+            Graph::deserialize(b"XXXX").err(),
+            Some(GraphError::BadMagic)
+        );
+        let mut bad = data.clone();
+        bad[4] = VERSION + 1;
+        assert_eq!(
+            Graph::deserialize(&bad).err(),
+            Some(GraphError::UnsupportedVersion(VERSION + 1))
+        );
+        for n in 5..data.len() {
+            assert!(Graph::deserialize(&data[..n]).is_err(), "length {}", n);
+        }
+        let mut bad = data.clone();
+        bad.push(0);
+        assert_eq!(
+            Graph::deserialize(&bad).err(),
+            Some(GraphError::TrailingData)
+        );
+    }
+}
@@ -1,10 +1,24 @@
 use std::convert::TryFrom;
-use std::ops::{Range, RangeFrom, RangeFull, RangeTo};
+use std::ops::{Add, Range, RangeFrom, RangeFull, RangeTo, Sub};
 
 // A position within source text. The position represents a byte offset.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Pos(pub u32);
 
+impl Add<u32> for Pos {
+    type Output = Pos;
+    fn add(self, rhs: u32) -> Pos {
+        Pos(self.0 + rhs)
+    }
+}
+
+impl Sub<u32> for Pos {
+    type Output = Pos;
+    fn sub(self, rhs: u32) -> Pos {
+        Pos(self.0 - rhs)
+    }
+}
+
 // A half-open range of source text. The positions represent byte offsets.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Span {
@@ -21,6 +35,45 @@ impl Span {
         }
     }
 
+    /// An empty span at the given position.
+    #[allow(dead_code)]
+    pub fn point(pos: Pos) -> Self {
+        Span {
+            start: pos,
+            end: pos,
+        }
+    }
+
+    /// Get the smallest span which covers both spans.
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// True if the position is inside the span. The end position is not
+    /// inside the span, so empty spans contain no positions.
+    #[allow(dead_code)]
+    pub fn contains(&self, pos: Pos) -> bool {
+        self.start <= pos && pos < self.end
+    }
+
+    /// True if the other span is entirely inside this span. An empty span is
+    /// inside this span if its position is between the start and end,
+    /// inclusive.
+    #[allow(dead_code)]
+    pub fn contains_span(&self, other: Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// True if the spans have any positions in common. Adjacent spans do not
+    /// overlap, and empty spans overlap nothing.
+    #[allow(dead_code)]
+    pub fn overlaps(&self, other: Span) -> bool {
+        !self.is_empty() && !other.is_empty() && self.start < other.end && other.start < self.end
+    }
+
     /// True if the span contains nothing.
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
@@ -109,17 +162,63 @@ impl HasPos for Span {
 mod test {
     use super::*;
 
+    fn span(start: u32, end: u32) -> Span {
+        Span {
+            start: Pos(start),
+            end: Pos(end),
+        }
+    }
+
     #[test]
     fn slice() {
-        fn span(start: u32, end: u32) -> Span {
-            Span {
-                start: Pos(start),
-                end: Pos(end),
-            }
-        }
         assert_eq!(span(5, 10).sub_span(1..2), span(6, 7));
         assert_eq!(span(5, 10).sub_span(1..), span(6, 10));
         assert_eq!(span(5, 10).sub_span(..2), span(5, 7));
         assert_eq!(span(5, 10).sub_span(..), span(5, 10));
     }
+
+    #[test]
+    fn merge() {
+        assert_eq!(span(2, 4).merge(span(6, 9)), span(2, 9));
+        assert_eq!(span(6, 9).merge(span(2, 4)), span(2, 9));
+        assert_eq!(span(2, 9).merge(span(4, 6)), span(2, 9));
+        assert_eq!(span(4, 6).merge(Span::point(Pos(8))), span(4, 8));
+    }
+
+    #[test]
+    fn contains() {
+        let s = span(5, 10);
+        assert!(s.contains(Pos(5)));
+        assert!(s.contains(Pos(9)));
+        assert!(!s.contains(Pos(10)));
+        assert!(!s.contains(Pos(4)));
+        assert!(!span(5, 5).contains(Pos(5)));
+        assert!(s.contains_span(s));
+        assert!(s.contains_span(span(6, 8)));
+        assert!(s.contains_span(span(10, 10)));
+        assert!(!s.contains_span(span(8, 11)));
+        assert!(!s.contains_span(span(10, 12)));
+    }
+
+    #[test]
+    fn overlaps() {
+        let s = span(5, 10);
+        assert!(s.overlaps(s));
+        assert!(s.overlaps(span(9, 12)));
+        assert!(s.overlaps(span(2, 6)));
+        assert!(s.overlaps(span(6, 7)));
+        assert!(!s.overlaps(span(10, 12)));
+        assert!(!s.overlaps(span(2, 5)));
+        assert!(!s.overlaps(span(7, 7)));
+        assert!(!span(7, 7).overlaps(s));
+    }
+
+    #[test]
+    fn empty() {
+        assert!(Span::point(Pos(3)).is_empty());
+        assert!(span(3, 3).is_empty());
+        assert!(!span(3, 4).is_empty());
+        assert_eq!(Pos(3) + 4, Pos(7));
+        assert_eq!(Pos(7) - 4, Pos(3));
+    }
 }
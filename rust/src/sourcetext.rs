@@ -1,4 +1,8 @@
 use crate::sourcepos::{Pos, Span};
+use crate::token::TokenError;
+use crate::utf8::parse_character;
+use std::borrow::Cow;
+use std::cell::RefCell;
 
 // A decoded position within a source file.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -18,48 +22,98 @@ pub struct TextSpan {
     pub end: TextPos,
 }
 
+/// Distance in bytes between checkpoints in a column index.
+const COLUMN_CHECKPOINT: u32 = 4096;
+
+/// Character columns at regular intervals within a single line, so columns can
+/// be found without scanning the line from the start.
+struct ColumnIndex {
+    line: u32,
+    // Byte offset and column of character boundaries, in increasing order.
+    checkpoints: Vec<(u32, u32)>,
+}
+
+// Count the characters in a line which start before the given byte offset,
+// starting from a character boundary with a known column. Returns the first
+// character boundary at or after the end, and its column.
+fn count_columns(line: &[u8], mut byte: u32, mut column: u32, end: u32) -> (u32, u32) {
+    while byte < end {
+        let (_, n) = parse_character(&line[byte as usize..]);
+        byte += n as u32;
+        column += 1;
+    }
+    (byte, column)
+}
+
+// Get the start offset of each line in a file.
+fn line_starts(text: &[u8]) -> Vec<u32> {
+    let mut prev = b'\0';
+    let mut lines = Vec::<u32>::new();
+    lines.push(0);
+    for (n, &c) in text.iter().enumerate() {
+        match c {
+            b'\n' => {
+                if prev == b'\r' {
+                    lines.pop();
+                }
+                lines.push(n as u32 + 1);
+            }
+            b'\r' => {
+                lines.push(n as u32 + 1);
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    lines
+}
+
 // A decoder for source positions within a single source file.
 pub struct SourceText<'a> {
     filename_str: &'a str,
     text: &'a [u8],
-    lines: Vec<u32>, // Start offset of each line.
+    lines: Cow<'a, [u32]>, // Start offset of each line.
     span: Span,
+    // Index for the most recent line passed to column().
+    columns: RefCell<Option<ColumnIndex>>,
 }
 
 impl<'a> SourceText<'a> {
     // Create a new source location decoder for a file with the given contents.
-    pub fn new(filename: &'a str, text: &'a [u8]) -> Self {
-        let mut prev = b'\0';
-        let mut lines = Vec::<u32>::new();
-        lines.push(0);
-        for (n, &c) in text.iter().enumerate() {
-            match c {
-                b'\n' => {
-                    if prev == b'\r' {
-                        lines.pop();
-                    }
-                    lines.push(n as u32 + 1);
-                }
-                b'\r' => {
-                    lines.push(n as u32 + 1);
-                }
-                _ => {}
-            }
-            prev = c;
+    pub fn new(filename: &'a str, text: &'a [u8]) -> Result<Self, TokenError> {
+        SourceText::new_at(filename, text, Pos(1))
+    }
+
+    /// Create a new source location decoder for a file whose first byte is at
+    /// the given position. Fails if the end of the file would not fit in a
+    /// Pos, the same as Tokenizer::new_at.
+    pub fn new_at(filename: &'a str, text: &'a [u8], start: Pos) -> Result<Self, TokenError> {
+        if text.len() > (u32::MAX - start.0) as usize {
+            return Err(TokenError::TooMuchText);
         }
+        Ok(SourceText::with_lines(
+            filename,
+            text,
+            start,
+            Cow::Owned(line_starts(text)),
+        ))
+    }
+
+    fn with_lines(filename: &'a str, text: &'a [u8], start: Pos, lines: Cow<'a, [u32]>) -> Self {
         SourceText {
             filename_str: filename,
             text,
             lines,
             span: Span {
-                start: Pos(1),
-                end: Pos(text.len() as u32 + 1),
+                start,
+                end: start + text.len() as u32,
             },
+            columns: RefCell::new(None),
         }
     }
 
     /// Get the filename.
-    pub fn filename(&self) -> &str {
+    pub fn filename(&self) -> &'a str {
         self.filename_str
     }
 
@@ -115,16 +169,127 @@ impl<'a> SourceText<'a> {
             }
         }
     }
+
+    /// Get the column of a position, counting characters from the start of
+    /// the line. Each invalid UTF-8 sequence counts as one character. A
+    /// position inside a character has the column of the following character.
+    ///
+    /// The first lookup on a line scans the whole line and records the column
+    /// at regular intervals. Later lookups on the same line only scan from the
+    /// nearest recorded column, so very long lines stay fast.
+    pub fn column(&self, pos: TextPos) -> u32 {
+        let line = self.line(pos.line);
+        let byte = if (pos.byte as usize) < line.len() {
+            pos.byte
+        } else {
+            line.len() as u32
+        };
+        let mut columns = self.columns.borrow_mut();
+        let index = match &mut *columns {
+            Some(index) if index.line == pos.line => index,
+            columns => columns.get_or_insert(ColumnIndex::new(pos.line, line)),
+        };
+        let (start, column) = match index.checkpoints.binary_search_by(|&(b, _)| b.cmp(&byte)) {
+            Ok(i) => index.checkpoints[i],
+            Err(i) => index.checkpoints[i - 1],
+        };
+        count_columns(line, start, column, byte).1
+    }
+}
+
+impl ColumnIndex {
+    fn new(line: u32, text: &[u8]) -> Self {
+        let len = text.len() as u32;
+        let mut checkpoints = vec![(0, 0)];
+        let mut pos = (0, 0);
+        while pos.0 < len {
+            let end = len.min(pos.0 + COLUMN_CHECKPOINT);
+            pos = count_columns(text, pos.0, pos.1, end);
+            checkpoints.push(pos);
+        }
+        ColumnIndex { line, checkpoints }
+    }
+}
+
+/// A source file in a source map.
+struct SourceFile {
+    filename: String,
+    text: Box<[u8]>,
+    lines: Vec<u32>,
+    start: Pos,
+}
+
+/// A collection of source files. Each file has its own range of positions, so
+/// a position identifies both the file and the location within it.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a file and return the position of its first byte. The file must
+    /// be tokenized starting at this position for its spans to refer to it.
+    pub fn add(&mut self, filename: &str, text: &[u8]) -> Result<Pos, TokenError> {
+        // The end of each file is a valid position, so the next file starts
+        // after it.
+        let start = match self.files.last() {
+            None => 1,
+            Some(file) => file.start.0 as u64 + file.text.len() as u64 + 1,
+        };
+        if start + text.len() as u64 > u32::MAX as u64 {
+            return Err(TokenError::TooMuchText);
+        }
+        let start = Pos(start as u32);
+        self.files.push(SourceFile {
+            filename: filename.to_string(),
+            text: Box::from(text),
+            lines: line_starts(text),
+            start,
+        });
+        Ok(start)
+    }
+
+    /// Get the file which contains the given position.
+    pub fn file(&self, pos: Pos) -> Option<SourceText<'_>> {
+        let idx = match self.files.binary_search_by(|file| file.start.cmp(&pos)) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let file = &self.files[idx];
+        if pos.0 - file.start.0 > file.text.len() as u32 {
+            return None;
+        }
+        Some(SourceText::with_lines(
+            &file.filename,
+            &file.text,
+            file.start,
+            Cow::Borrowed(&file.lines),
+        ))
+    }
+
+    /// Convert a position to the name of the file containing it and the
+    /// location within that file.
+    pub fn pos(&self, pos: Pos) -> Option<(&str, TextPos)> {
+        let file = self.file(pos)?;
+        Some((file.filename(), file.pos(pos)?))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{SourceText, TextPos};
+    use super::{SourceMap, SourceText, TextPos, COLUMN_CHECKPOINT};
     use crate::sourcepos::Pos;
+    use crate::token::Tokenizer;
+    use crate::utf8::parse_character;
 
     fn test_lookup(input: &[u8], outputs: &[(u32, u32)]) {
         assert_eq!(input.len() + 1, outputs.len());
-        let text = SourceText::new("<test>", input);
+        let text = SourceText::new("<test>", input).unwrap();
         let mut success = true;
         for (n, &expect) in (1..).zip(outputs.iter()) {
             let expect = Some(match expect {
@@ -181,9 +346,58 @@ mod test {
         test_lookup(b"a\rb\r", &[(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)]);
     }
 
+    #[test]
+    fn too_much_text() {
+        // Start near the end of the position space instead of allocating
+        // 4 GiB of text.
+        let end = u32::max_value();
+        let text = SourceText::new_at("<test>", b"abc", Pos(end - 3)).unwrap();
+        assert_eq!(text.pos(Pos(end)), Some(TextPos { line: 0, byte: 3 }));
+        assert!(SourceText::new_at("<test>", b"abcd", Pos(end - 3)).is_err());
+        assert!(Tokenizer::new_at(b"abc", Pos(end - 3)).is_ok());
+        assert!(Tokenizer::new_at(b"abcd", Pos(end - 3)).is_err());
+    }
+
+    #[test]
+    fn source_map_too_much_text() {
+        let mut map = SourceMap::new();
+        let first = map.add("first.lisp", b"abc").unwrap();
+        assert_eq!(first, Pos(1));
+        // The positions are full after a file ending at u32::MAX.
+        map.files[0].start = Pos(u32::max_value() - 3);
+        assert!(map.add("second.lisp", b"").is_err());
+    }
+
+    #[test]
+    fn source_map() {
+        let mut map = SourceMap::new();
+        let first = map.add("first.lisp", b"(a)\n(b)\n").unwrap();
+        let second = map.add("second.lisp", b"(c)\n(d e)").unwrap();
+        assert_eq!(first, Pos(1));
+        assert!(second > first + 8);
+        assert_eq!(
+            map.pos(first + 4),
+            Some(("first.lisp", TextPos { line: 1, byte: 0 }))
+        );
+        // The end of a file is part of that file.
+        assert_eq!(
+            map.pos(first + 8),
+            Some(("first.lisp", TextPos { line: 2, byte: 0 }))
+        );
+        assert_eq!(
+            map.pos(second + 7),
+            Some(("second.lisp", TextPos { line: 1, byte: 3 }))
+        );
+        let file = map.file(second + 7).unwrap();
+        assert_eq!(file.filename(), "second.lisp");
+        assert_eq!(file.line(1), b"(d e)");
+        assert_eq!(map.pos(Pos(0)), None);
+        assert!(map.file(second + 20).is_none());
+    }
+
     #[test]
     fn line() {
-        let text = SourceText::new("<test>", b"abc\ndef\rghi\r\njkl");
+        let text = SourceText::new("<test>", b"abc\ndef\rghi\r\njkl").unwrap();
         let lines: &[&'static [u8]] = &[b"abc", b"def", b"ghi", b"jkl"];
         let mut success = true;
         for (n, &line) in lines.iter().enumerate() {
@@ -195,4 +409,72 @@ mod test {
         }
         assert!(success);
     }
+
+    // Count columns by scanning from the start of the line.
+    fn simple_column(line: &[u8], byte: u32) -> u32 {
+        let (mut pos, mut column) = (0, 0);
+        while pos < byte as usize && pos < line.len() {
+            pos += parse_character(&line[pos..]).1;
+            column += 1;
+        }
+        column
+    }
+
+    #[test]
+    fn column() {
+        let text = SourceText::new("<test>", b"ab\ncd\xc3\xa9f\x80g\n").unwrap();
+        let cases: &[(u32, u32, u32)] = &[
+            (0, 0, 0),
+            (0, 2, 2),
+            (1, 2, 2),
+            (1, 3, 3), // Inside a character.
+            (1, 4, 3),
+            (1, 6, 5),
+            (1, 8, 6),
+            (1, 100, 6),
+            (2, 0, 0),
+        ];
+        let mut success = true;
+        for &(line, byte, expect) in cases.iter() {
+            let got = text.column(TextPos { line, byte });
+            if got != expect {
+                success = false;
+                eprintln!("column({}, {}): got {}, expect {}", line, byte, got, expect);
+            }
+        }
+        assert!(success);
+    }
+
+    #[test]
+    fn column_long_line() {
+        // A line spanning several checkpoints, with a mix of one, two, three,
+        // and four byte characters and invalid sequences.
+        let pattern = "(sine 440Hz) \u{e9}\u{2014}\u{1f50a}".as_bytes();
+        let mut input = Vec::new();
+        while input.len() < 3 * COLUMN_CHECKPOINT as usize + 100 {
+            input.extend_from_slice(pattern);
+            if input.len() % 7 == 0 {
+                input.push(0xff);
+            }
+        }
+        let text = SourceText::new("<test>", &input).unwrap();
+        let len = input.len() as u32;
+        // Forward, backward, and scattered lookups.
+        let offsets = (0..len)
+            .step_by(97)
+            .chain((0..len).rev().step_by(101))
+            .chain((0..200u32).map(|n| n.wrapping_mul(2654435761) % (len + 1)));
+        let mut failures = 0;
+        for byte in offsets {
+            let got = text.column(TextPos { line: 0, byte });
+            let expect = simple_column(&input, byte);
+            if got != expect {
+                failures += 1;
+                if failures <= 10 {
+                    eprintln!("column({}): got {}, expect {}", byte, got, expect);
+                }
+            }
+        }
+        assert_eq!(failures, 0);
+    }
 }
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// An interned string, used for names in programs. Symbols with the same text
+/// have the same value, so they can be compared and hashed without looking at
+/// the text.
+///
+/// The text of every symbol is kept for the rest of the process.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Table of all interned strings.
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    /// Get the symbol with the given text.
+    pub fn new(name: &str) -> Self {
+        let mut interner = interner().lock().unwrap();
+        if let Some(&symbol) = interner.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(interner.names.len() as u32);
+        let name: &'static str = Box::leak(Box::from(name));
+        interner.names.push(name);
+        interner.symbols.insert(name, symbol);
+        symbol
+    }
+
+    /// Get the symbol's text.
+    pub fn as_str(self) -> &'static str {
+        interner().lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intern() {
+        let text = String::from("sine sine");
+        let (a, b) = text.split_at(4);
+        let x = Symbol::new(a);
+        let y = Symbol::new(b.trim());
+        assert_eq!(x, y);
+        assert_ne!(x, Symbol::new("sawtooth"));
+        assert_eq!(x.to_string(), "sine");
+        assert_eq!(format!("{:?}", x), "\"sine\"");
+    }
+}
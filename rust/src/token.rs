@@ -20,16 +20,36 @@ impl fmt::Display for TokenError {
 
 impl Error for TokenError {}
 
-// Token types.
+/// The lexical class of a token.
+///
+/// More kinds of token may be added, so code outside this crate must handle
+/// unknown kinds.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Type {
-    End,   // End of input.
-    Error, // Invalid character.
+    /// End of input. The token text is empty.
+    End,
+    /// Invalid character, or the opening delimiter of an unterminated symbol
+    /// or string.
+    Error,
+    /// Comment, from ";" to the end of the line, not including the line break.
     Comment,
+    /// Whitespace, only produced by tokenizers with trivia enabled.
+    Whitespace,
+    /// Symbol, like "abc" or "+", or an escaped symbol between bars, like
+    /// "|a b|". The text of an escaped symbol includes the bars and escapes.
     Symbol,
+    /// Number, like "12", "-0.5", or "100ms", including any units.
     Number,
-    ParenOpen,
-    ParenClose,
+    /// Opening parenthesis, "(".
+    Open,
+    /// Closing parenthesis, ")".
+    Close,
+    /// Quote, "'".
+    Quote,
+    /// String between double quotes, like "\"abc\"". The text includes the
+    /// quotes and escapes.
+    String,
 }
 
 // A token in an s-expression.
@@ -42,10 +62,66 @@ pub struct Token<'a> {
 
 impl HasPos for Token<'_> {
     fn source_pos(&self) -> Span {
-        let Pos(off) = self.pos;
         Span {
             start: self.pos,
-            end: Pos(off + self.text.len() as u32),
+            end: self.pos + self.text.len() as u32,
+        }
+    }
+}
+
+/// Punctuation which can appear in symbols by default.
+const DEFAULT_SYMBOL_PUNCTUATION: &[u8] = b"-!$%&*+./:<=>?@^_~";
+
+/// Characters which always delimit tokens, and cannot appear in symbols.
+const DELIMITERS: &[u8] = b"()|\"';";
+
+/// Controls which ASCII punctuation characters are part of symbols. Other
+/// punctuation is a delimiter, and is an error where a token is expected.
+/// Letters and digits are always part of symbols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizerConfig {
+    // Bit N is set if ASCII character N is punctuation allowed in symbols.
+    symbol_punctuation: u128,
+}
+
+impl TokenizerConfig {
+    /// Allow or disallow a punctuation character in symbols. Panics if the
+    /// character is not ASCII punctuation, or is one of the delimiters
+    /// ( ) | " ' ;.
+    pub fn symbol_char(self, c: char, allowed: bool) -> Self {
+        if !c.is_ascii_punctuation() || DELIMITERS.contains(&(c as u8)) {
+            panic!("cannot configure symbol character {:?}", c);
+        }
+        let bit = 1u128 << (c as u32);
+        TokenizerConfig {
+            symbol_punctuation: if allowed {
+                self.symbol_punctuation | bit
+            } else {
+                self.symbol_punctuation & !bit
+            },
+        }
+    }
+
+    /// Return true if the character can appear in a normal symbol.
+    fn is_symbol(&self, c: u8) -> bool {
+        c.is_ascii_alphanumeric() || (c < 128 && self.symbol_punctuation & (1 << c) != 0)
+    }
+
+    /// Get the number of symbol characters at the beginning of a string.
+    fn symbol_len(&self, text: &[u8]) -> usize {
+        match text.iter().position(|&c| !self.is_symbol(c)) {
+            Some(idx) => idx,
+            _ => text.len(),
+        }
+    }
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig {
+            symbol_punctuation: DEFAULT_SYMBOL_PUNCTUATION
+                .iter()
+                .fold(0, |bits, &c| bits | 1 << c),
         }
     }
 }
@@ -54,6 +130,8 @@ pub struct Tokenizer<'a> {
     text: &'a [u8],
     pos: u32,
     start_pos: u32,
+    trivia: bool,
+    config: TokenizerConfig,
 }
 
 // Return true if the character is ASCII whitespace.
@@ -62,41 +140,38 @@ fn is_space(c: u8) -> bool {
     c == 32 || (9 <= c && c <= 13)
 }
 
-// Return true if the character can appear in a normal symbol.
-fn is_symbol(c: u8) -> bool {
-    match c as char {
-        // Lower case
-        'a' | 'b' | 'c' | 'd' | 'e' | 'f' | 'g' | 'h' | 'i' | 'j' | 'k' | 'l' | 'm'
-            | 'n' | 'o' | 'p' | 'q' | 'r' | 's' | 't' | 'u' | 'v' | 'w' | 'x' | 'y' | 'z' |
-        // Upper case
-        'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | 'H' | 'I' | 'J' | 'K' | 'L' | 'M'
-            | 'N' | 'O' | 'P' | 'Q' | 'R' | 'S' | 'T' | 'U' | 'V' | 'W' | 'X' | 'Y' | 'Z' |
-        // Digits
-        '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' |
-        // Punctuation
-        '-' | '!' | '$' | '%' | '&' | '*' | '+' | '.' | '/' | ':'
-            | '<' | '=' | '>' | '?' | '@' | '^' | '_' | '~' => true,
-        _ => false,
-    }
-}
-
 // Return true if the character is a line break character, CR or LF.
 fn is_line_break(c: u8) -> bool {
     c == b'\n' || c == b'\r'
 }
 
-/// Get the number of symbol characters at the beginning of a string.
-fn symbol_len(text: &[u8]) -> usize {
-    match text.iter().position(|&c| !is_symbol(c)) {
-        Some(idx) => idx,
-        _ => text.len(),
+/// Get the length of delimited text after the opening delimiter, including
+/// the closing delimiter, or None if there is no closing delimiter. A
+/// backslash escapes the following character.
+fn delimited_len(text: &[u8], delimiter: u8) -> Option<usize> {
+    let mut iter = text.iter().enumerate();
+    while let Some((n, &c)) = iter.next() {
+        match c {
+            _ if c == delimiter => return Some(n + 1),
+            b'\\' => {
+                iter.next();
+            }
+            _ => (),
+        }
     }
+    None
 }
 
 impl<'a> Tokenizer<'a> {
     // Create a new tokenizer that returns a stream of tokens from the given text.
     pub fn new(text: &'a [u8]) -> Result<Self, TokenError> {
-        let start_pos: u32 = 1;
+        Tokenizer::new_at(text, Pos(1))
+    }
+
+    /// Create a new tokenizer for text whose first byte is at the given
+    /// position, like a file added to a SourceMap.
+    pub fn new_at(text: &'a [u8], start: Pos) -> Result<Self, TokenError> {
+        let start_pos = start.0;
         if text.len() > (u32::max_value() - start_pos) as usize {
             return Err(TokenError::TooMuchText);
         }
@@ -104,9 +179,22 @@ impl<'a> Tokenizer<'a> {
             text,
             pos: 0,
             start_pos,
+            trivia: false,
+            config: Default::default(),
         })
     }
 
+    /// Use the given rules for which characters are part of symbols.
+    pub fn with_config(self, config: TokenizerConfig) -> Self {
+        Tokenizer { config, ..self }
+    }
+
+    /// Produce whitespace tokens. With trivia, the texts of all tokens
+    /// concatenated together are the complete input.
+    pub fn with_trivia(self, trivia: bool) -> Self {
+        Tokenizer { trivia, ..self }
+    }
+
     /// Rewind tokenizer to start of stream.
     #[allow(dead_code)]
     pub fn rewind(&mut self) -> () {
@@ -116,44 +204,46 @@ impl<'a> Tokenizer<'a> {
     // Return the next token from the stream.
     pub fn next(&mut self) -> Token<'a> {
         use Type::*;
-        let pos = match self.text[self.pos as usize..]
-            .iter()
-            .position(|&c| !is_space(c))
-        {
-            Some(n) => self.pos as usize + n,
-            None => {
-                let pos = self.text.len() as u32;
-                self.pos = pos;
-                return Token {
-                    ty: End,
-                    pos: Pos(pos + self.start_pos),
-                    text: &[],
-                };
-            }
+        let start = self.pos as usize;
+        let pos = match self.text[start..].iter().position(|&c| !is_space(c)) {
+            Some(n) => start + n,
+            None => self.text.len(),
         };
+        if self.trivia && pos > start {
+            self.pos = pos as u32;
+            return Token {
+                ty: Whitespace,
+                pos: Pos(start as u32 + self.start_pos),
+                text: &self.text[start..pos],
+            };
+        }
+        if pos == self.text.len() {
+            self.pos = pos as u32;
+            return Token {
+                ty: End,
+                pos: Pos(pos as u32 + self.start_pos),
+                text: &[],
+            };
+        }
         let (&first, rest) = self.text[pos..].split_first().unwrap();
+        let config = self.config;
+        let symbol_len = |text| config.symbol_len(text);
         let (ty, len) = match first as char {
-            // Lower case
-            'a' | 'b' | 'c' | 'd' | 'e' | 'f' | 'g' | 'h' | 'i' | 'j' | 'k' | 'l' | 'm'
-                | 'n' | 'o' | 'p' | 'q' | 'r' | 's' | 't' | 'u' | 'v' | 'w' | 'x' | 'y' | 'z' |
-            // Upper case
-            'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | 'H' | 'I' | 'J' | 'K' | 'L' | 'M'
-                | 'N' | 'O' | 'P' | 'Q' | 'R' | 'S' | 'T' | 'U' | 'V' | 'W' | 'X' | 'Y' | 'Z' |
-            // Punctuation
-            '!' | '$' | '%' | '&' | '*' | '/' | ':' | '<'
-                | '=' | '>' | '?' | '@' | '^' | '_' | '~' =>
-                (Symbol, symbol_len(rest)),
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' =>
-                (Number, symbol_len(rest)),
-            ';' =>
-		(Comment, rest.iter().position(|&c| is_line_break(c)).unwrap_or(rest.len())),
+            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => (Number, symbol_len(rest)),
+            ';' => (
+                Comment,
+                rest.iter()
+                    .position(|&c| is_line_break(c))
+                    .unwrap_or(rest.len()),
+            ),
             '.' => {
                 // Either a number or a symbol.
                 let ty = match rest.split_first() {
                     Some((&c, _)) if b'0' <= c && c <= b'9' => Number,
-                    _ => Symbol,
+                    _ if config.is_symbol(first) => Symbol,
+                    _ => Error,
                 };
-                (ty, symbol_len(rest))
+                (ty, if ty == Error { 0 } else { symbol_len(rest) })
             }
             '-' | '+' => {
                 // Either a number or a symbol.
@@ -172,14 +262,31 @@ impl<'a> Tokenizer<'a> {
                     }
                     _ => Symbol,
                 };
-		(ty, symbol_len(rest))
+                let ty = if ty == Symbol && !config.is_symbol(first) {
+                    Error
+                } else {
+                    ty
+                };
+                (ty, if ty == Error { 0 } else { symbol_len(rest) })
             }
-            '(' => (ParenOpen, 0),
-            ')' => (ParenClose, 0),
+            // Unterminated symbols and strings are errors at the opening
+            // delimiter.
+            '|' => match delimited_len(rest, b'|') {
+                Some(n) => (Symbol, n),
+                None => (Error, 0),
+            },
+            '"' => match delimited_len(rest, b'"') {
+                Some(n) => (String, n),
+                None => (Error, 0),
+            },
+            '\'' => (Quote, 0),
+            '(' => (Open, 0),
+            ')' => (Close, 0),
+            _ if config.is_symbol(first) => (Symbol, symbol_len(rest)),
             _ => {
-		let (_, n) = parse_character(&self.text[pos..]);
-		(Error, n-1)
-	    }
+                let (_, n) = parse_character(&self.text[pos..]);
+                (Error, n - 1)
+            }
         };
         let end = pos + 1 + len;
         self.pos = end as u32;
@@ -193,7 +300,7 @@ impl<'a> Tokenizer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Token, Tokenizer, Type};
+    use super::{Token, Tokenizer, TokenizerConfig, Type};
     use crate::sourcepos::Pos;
     use crate::test::*;
     use std::fmt;
@@ -254,8 +361,20 @@ mod tests {
             (b".67 ", Number),
             (b"-.0 ", Number),
             (b"+.9 ", Number),
-            (b"(a", ParenOpen),
-            (b")a", ParenClose),
+            (b"|a b| ", Symbol),
+            (b"|| ", Symbol),
+            (b"|a\\|b| ", Symbol),
+            (b"|(\\\\| ", Symbol),
+            (b"| ", Error),
+            (b"\"a b\" ", String),
+            (b"\"\" ", String),
+            (b"\"a\\\"b\"c", String),
+            (b"\"a|\\\\\"|", String),
+            (b"\" ", Error),
+            (b"'a", Quote),
+            (b"'(", Quote),
+            (b"(a", Open),
+            (b")a", Close),
             (b"\x01 ", Error),
             (b"\x7f ", Error),
             (b"\x80 ", Error),
@@ -291,13 +410,135 @@ mod tests {
         tests.done()
     }
 
+    #[test]
+    fn classify() {
+        use Type::*;
+        let input = b"(sym |a b| -1.5ms 'x \"str\" ;note\n) \x01";
+        let mut toks = Tokenizer::new(input).unwrap();
+        let mut got = Vec::new();
+        loop {
+            let tok = toks.next();
+            got.push((tok.ty, str::from_utf8(tok.text).unwrap()));
+            if tok.ty == End {
+                break;
+            }
+        }
+        assert_eq!(
+            got,
+            [
+                (Open, "("),
+                (Symbol, "sym"),
+                (Symbol, "|a b|"),
+                (Number, "-1.5ms"),
+                (Quote, "'"),
+                (Symbol, "x"),
+                (String, "\"str\""),
+                (Comment, ";note"),
+                (Close, ")"),
+                (Error, "\x01"),
+                (End, ""),
+            ]
+        );
+    }
+
+    /// Get the types and text of all tokens in the input.
+    fn tokens(input: &[u8], config: TokenizerConfig) -> Vec<(Type, &str)> {
+        let mut toks = Tokenizer::new(input).unwrap().with_config(config);
+        let mut result = Vec::new();
+        loop {
+            let tok = toks.next();
+            if tok.ty == Type::End {
+                return result;
+            }
+            result.push((tok.ty, str::from_utf8(tok.text).unwrap()));
+        }
+    }
+
+    #[test]
+    fn config() {
+        use Type::*;
+        let default = TokenizerConfig::default();
+        assert_eq!(
+            tokens(b"~foo a~b", default),
+            [(Symbol, "~foo"), (Symbol, "a~b")]
+        );
+        let strict = default.symbol_char('~', false).symbol_char('-', false);
+        assert_eq!(
+            tokens(b"~foo a~b", strict),
+            [
+                (Error, "~"),
+                (Symbol, "foo"),
+                (Symbol, "a"),
+                (Error, "~"),
+                (Symbol, "b")
+            ]
+        );
+        // Numbers still start with a sign.
+        assert_eq!(
+            tokens(b"-1 a-b -", strict),
+            [
+                (Number, "-1"),
+                (Symbol, "a"),
+                (Error, "-"),
+                (Symbol, "b"),
+                (Error, "-")
+            ]
+        );
+        let extended = default.symbol_char('#', true);
+        assert_eq!(tokens(b"#foo", default), [(Error, "#"), (Symbol, "foo")]);
+        assert_eq!(
+            tokens(b"#foo a#", extended),
+            [(Symbol, "#foo"), (Symbol, "a#")]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn config_delimiter() {
+        TokenizerConfig::default().symbol_char('(', true);
+    }
+
+    #[test]
+    fn trivia() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"  ",
+            b"(a b)",
+            b"  (sym |a b|\t-1.5ms ; note\r\n  'x \"s\")\n\n",
+            b"; only a comment",
+            b"\x01 \xff x",
+        ];
+        for &input in inputs.iter() {
+            let mut toks = Tokenizer::new(input).unwrap().with_trivia(true);
+            let mut text = Vec::new();
+            loop {
+                let tok = toks.next();
+                assert_eq!(tok.pos, Pos(text.len() as u32 + 1), "{}", Str(input));
+                text.extend_from_slice(tok.text);
+                if tok.ty == Type::End {
+                    break;
+                }
+            }
+            assert_eq!(text, input);
+        }
+        let mut toks = Tokenizer::new(b" a ").unwrap().with_trivia(true);
+        assert_eq!(toks.next().ty, Type::Whitespace);
+        assert_eq!(toks.next().ty, Type::Symbol);
+        assert_eq!(toks.next().ty, Type::Whitespace);
+        assert_eq!(toks.next().ty, Type::End);
+        // Without trivia, whitespace is skipped.
+        let mut toks = Tokenizer::new(b" a ").unwrap();
+        assert_eq!(toks.next().ty, Type::Symbol);
+        assert_eq!(toks.next().ty, Type::End);
+    }
+
     #[test]
     fn doc() -> Result<(), TestFailure> {
         use Type::*;
         let input = b"(abc  +1.0\r\n+)";
         let expect: &'static [Token<'static>] = &[
             Token {
-                ty: ParenOpen,
+                ty: Open,
                 pos: Pos(1),
                 text: b"(",
             },
@@ -317,7 +558,7 @@ mod tests {
                 text: b"+",
             },
             Token {
-                ty: ParenClose,
+                ty: Close,
                 pos: Pos(14),
                 text: b")",
             },
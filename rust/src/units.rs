@@ -63,12 +63,13 @@ fn parse_prefix(c: char) -> Option<i32> {
 }
 
 /// Units associated with a quantity.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
 pub struct Units {
     pub volt: i8,
     pub second: i8,
     pub radian: i8,
     pub decibel: i8,
+    pub semitone: i8,
 }
 
 macro_rules! def_units {
@@ -94,7 +95,7 @@ impl Units {
         Default::default()
     }
 
-    def_units!(volt, second, radian, decibel);
+    def_units!(volt, second, radian, decibel, semitone);
 
     pub fn hertz(n: i8) -> Self {
         Units::second(-n)
@@ -111,7 +112,8 @@ impl Units {
         let (second, o2) = self.second.overflowing_add(other.second);
         let (radian, o3) = self.radian.overflowing_add(other.radian);
         let (decibel, o4) = self.decibel.overflowing_add(other.decibel);
-        if o1 || o2 || o3 || o4 {
+        let (semitone, o5) = self.semitone.overflowing_add(other.semitone);
+        if o1 || o2 || o3 || o4 || o5 {
             Err(UnitError::Overflow)
         } else {
             Ok(Units {
@@ -119,6 +121,27 @@ impl Units {
                 second,
                 radian,
                 decibel,
+                semitone,
+            })
+        }
+    }
+
+    /// Divides two units.
+    pub fn divide(&self, other: &Units) -> Result<Self, UnitError> {
+        let (volt, o1) = self.volt.overflowing_sub(other.volt);
+        let (second, o2) = self.second.overflowing_sub(other.second);
+        let (radian, o3) = self.radian.overflowing_sub(other.radian);
+        let (decibel, o4) = self.decibel.overflowing_sub(other.decibel);
+        let (semitone, o5) = self.semitone.overflowing_sub(other.semitone);
+        if o1 || o2 || o3 || o4 || o5 {
+            Err(UnitError::Overflow)
+        } else {
+            Ok(Units {
+                volt,
+                second,
+                radian,
+                decibel,
+                semitone,
             })
         }
     }
@@ -156,6 +179,28 @@ impl Units {
         }
     }
 
+    /// Get the suffix which gives a number these units in source code, or
+    /// None if numbers cannot be written with these units.
+    pub fn suffix(&self) -> Option<&'static str> {
+        Some(if *self == Units::scalar() {
+            ""
+        } else if *self == Units::volt(1) {
+            "V"
+        } else if *self == Units::second(1) {
+            "s"
+        } else if *self == Units::hertz(1) {
+            "Hz"
+        } else if *self == Units::radian(1) {
+            "rad"
+        } else if *self == Units::decibel(1) {
+            "dB"
+        } else if *self == Units::semitone(1) {
+            "st"
+        } else {
+            return None;
+        })
+    }
+
     /// Parse units without metric prefix.
     ///
     /// Returns true if the units are permitted to have a metric prefix.
@@ -166,6 +211,7 @@ impl Units {
             "Hz" => (true, Units::second(-1)),
             "rad" => (true, Units::radian(1)),
             "dB" => (false, Units::decibel(1)),
+            "st" => (false, Units::semitone(1)),
             _ => return None,
         })
     }
@@ -206,6 +252,7 @@ impl fmt::Display for Units {
         write_unit(f, &mut has_text, "s", "Hz", self.second)?;
         write_unit(f, &mut has_text, "rad", "", self.radian)?;
         write_unit(f, &mut has_text, "dB", "", self.decibel)?;
+        write_unit(f, &mut has_text, "st", "", self.semitone)?;
         if !has_text {
             f.write_str("scalar")?;
         }
@@ -226,12 +273,14 @@ mod test {
         assert_eq!(Units::second(-1).to_string(), "Hz");
         assert_eq!(Units::radian(1).to_string(), "rad");
         assert_eq!(Units::decibel(1).to_string(), "dB");
+        assert_eq!(Units::semitone(1).to_string(), "st");
         assert_eq!(
             Units {
                 volt: 2,
                 second: 1,
                 radian: -1,
                 decibel: 0,
+                semitone: 0,
             }
             .to_string(),
             "V^2*s*rad^-1"
@@ -248,6 +297,7 @@ mod test {
             ("Hz", Units::hertz(1), 0, 0, 2),
             ("rad", Units::radian(1), 0, 0, 3),
             ("dB", Units::decibel(1), 0, 0, 2),
+            ("st", Units::semitone(1), 0, 0, 2),
             ("mV", Units::volt(1), -3, 1, 2),
             ("kHz", Units::hertz(1), 3, 1, 3),
             ("\u{03BC}s", Units::second(1), -6, 2, 3),
@@ -285,6 +335,7 @@ mod test {
         const CASES: &'static [(&'static str, ParseError, u32, u32)] = &[
             ("v", UnknownUnits, 0, 1),       // Wrong case, should be V.
             ("mdB", PrefixNotAllowed, 0, 3), // Prefix not permitted, dB already has prefix.
+            ("kst", PrefixNotAllowed, 0, 3), // Prefix not permitted with semitones.
             ("kv", UnknownUnits, 1, 2),      // Wrong case, should be kV.
             ("k", UnknownUnits, 0, 1),       // No units.
             ("qV", UnknownPrefix, 0, 1),     // Invalid prefix.
@@ -323,18 +374,21 @@ mod test {
             second: 2,
             radian: 3,
             decibel: 4,
+            semitone: 5,
         };
         let y = Units {
             volt: 10,
             second: 20,
             radian: 30,
             decibel: 40,
+            semitone: 50,
         };
         let z = Units {
             volt: 11,
             second: 22,
             radian: 33,
             decibel: 44,
+            semitone: 55,
         };
         assert_eq!(x.multiply(&y), Ok(z));
     }
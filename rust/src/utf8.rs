@@ -35,9 +35,11 @@ pub fn parse_character(text: &[u8]) -> (Option<char>, usize) {
 }
 
 /// Iterator over segments of UTF-8 text that may contain errors.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
 pub struct UTF8Segments<'a>(pub &'a [u8]);
 
 /// A segment of valid or invalid UTF-8 text.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
 pub type UTF8Segment<'a> = Result<&'a str, &'a [u8]>;
 
 impl<'a> Iterator for UTF8Segments<'a> {
@@ -1,6 +1,8 @@
 use crate::rand::Rand;
 use std::cmp::min;
-use std::io::{Result as IOResult, Seek, SeekFrom, Write};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Result as IOResult, Seek, SeekFrom, Write};
 
 /// Parameters for a WAVE file.
 #[derive(Debug, Clone, Copy)]
@@ -49,6 +51,8 @@ macro_rules! data {
 struct Header {
     frame_count: u32,
     parameters: Parameters,
+    // Size of the chunks after the data chunk, including padding.
+    trailer_length: u32,
 }
 
 impl Header {
@@ -60,7 +64,7 @@ impl Header {
         data![
             44,
             [u8;4]: *b"RIFF", // Chunk ID
-            u32: data_length_bytes + 36, // ChunkSize
+            u32: data_length_bytes + 36 + self.trailer_length, // ChunkSize
             [u8;4]: *b"WAVE", // Format
             [u8;4]: *b"fmt ", // Subchunk ID
             u32: 16, // Subchunk size
@@ -76,6 +80,20 @@ impl Header {
     }
 }
 
+/// Convert a sample to 16-bit, with dither. Samples out of range are clamped.
+pub fn dither_i16(rand: &mut Rand, x: f32) -> i16 {
+    // Random variable with rectangular distribution for dithering.
+    let r = (rand.next() as f32) * (1.0 / 4294967296.0);
+    let x = (x * 32768.0 + r).floor();
+    if x > i16::MAX as f32 {
+        i16::MAX
+    } else if x < i16::MIN as f32 {
+        i16::MIN
+    } else {
+        x as i16
+    }
+}
+
 /// Trait for streams that can both seek and write.
 pub trait SeekWrite: Seek + Write {}
 
@@ -94,6 +112,7 @@ pub struct Writer<'a> {
     sample_count: usize,
     rand: Rand,
     parameters: Parameters,
+    info: Vec<([u8; 4], Box<[u8]>)>,
 }
 
 impl<'a> Writer<'a> {
@@ -109,9 +128,16 @@ impl<'a> Writer<'a> {
             sample_count: 0,
             rand: Rand::with_default_seed(),
             parameters: *parameters,
+            info: Vec::new(),
         }
     }
 
+    /// Add an entry to the LIST/INFO chunk, which is written after the sample
+    /// data. The ID is a RIFF INFO ID such as ISFT or ICMT.
+    pub fn add_info(&mut self, id: [u8; 4], value: &[u8]) {
+        self.info.push((id, Box::from(value)));
+    }
+
     /// Write floating-point samples to the file. These samples will be
     /// converted to 16-bit.
     pub fn write(&mut self, data: &[f32]) -> IOResult<()> {
@@ -123,16 +149,7 @@ impl<'a> Writer<'a> {
                 let n = min(data.len(), buf.len() / 2);
                 let (first, rest) = data.split_at(n);
                 for (&x, y) in first.iter().zip(buf.chunks_mut(2)) {
-                    // Random variable with rectangular distribution for dithering.
-                    let r = (self.rand.next() as f32) * (1.0 / 4294967296.0);
-                    let x = (x * 32768.0 + r).floor();
-                    let x = if x > i16::max_value() as f32 {
-                        i16::max_value()
-                    } else if x < i16::min_value() as f32 {
-                        i16::min_value()
-                    } else {
-                        x as i16
-                    };
+                    let x = dither_i16(&mut self.rand, x);
                     y.copy_from_slice(&x.to_le_bytes()[..]);
                 }
                 data = rest;
@@ -152,12 +169,503 @@ impl<'a> Writer<'a> {
         if self.buf_pos > 0 {
             self.stream.write_all(&self.buf[..self.buf_pos])?;
         }
+        // The data chunk always has an even length, so no padding is needed
+        // before the trailer.
+        let trailer = info_chunk(&self.info);
+        self.stream.write_all(&trailer)?;
         let header = Header {
             frame_count: (self.sample_count / (self.parameters.channel_count as usize)) as u32,
             parameters: self.parameters,
+            trailer_length: trailer.len() as u32,
         };
         let header = header.to_bytes();
         self.stream.seek(SeekFrom::Start(0))?;
         self.stream.write_all(&header[..])
     }
 }
+
+/// Encode a LIST/INFO chunk. Returns nothing if there are no entries.
+fn info_chunk(info: &[([u8; 4], Box<[u8]>)]) -> Vec<u8> {
+    if info.is_empty() {
+        return Vec::new();
+    }
+    let mut data = Vec::new();
+    data.extend_from_slice(b"LIST\0\0\0\0INFO");
+    for (id, value) in info.iter() {
+        // Values are null-terminated, and chunks are padded to an even
+        // length.
+        data.extend_from_slice(id);
+        data.extend_from_slice(&(value.len() as u32 + 1).to_le_bytes());
+        data.extend_from_slice(value);
+        data.push(0);
+        if data.len() & 1 != 0 {
+            data.push(0);
+        }
+    }
+    let size = data.len() as u32 - 8;
+    data[4..8].copy_from_slice(&size.to_le_bytes());
+    data
+}
+
+/// Format of the samples in a WAVE file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    I16,
+    I24,
+    F32,
+}
+
+impl SampleFormat {
+    /// Get the size of one sample, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::F32 => 4,
+        }
+    }
+}
+
+/// An error from reading a WAVE file.
+#[derive(Debug)]
+pub enum ReadError {
+    IO(io::Error),
+    NotWave,
+    BadChunk,
+    MissingFormat,
+    MissingData,
+    UnsupportedFormat(u16),
+    UnsupportedSampleSize(u16),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ReadError::*;
+        match self {
+            IO(e) => e.fmt(f),
+            NotWave => f.write_str("not a WAVE file"),
+            BadChunk => f.write_str("invalid chunk in WAVE file"),
+            MissingFormat => f.write_str("WAVE file has no format chunk"),
+            MissingData => f.write_str("WAVE file has no data chunk"),
+            UnsupportedFormat(tag) => write!(
+                f,
+                "unsupported WAVE format 0x{:04x}, only PCM and IEEE float are supported",
+                tag
+            ),
+            UnsupportedSampleSize(bits) => write!(f, "unsupported sample size: {} bits", bits),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::IO(e)
+    }
+}
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Size of the largest format chunk, for WAVE_FORMAT_EXTENSIBLE. Anything in
+/// a format chunk past this is skipped.
+const MAX_FORMAT_SIZE: usize = 40;
+
+fn read_u16(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[0], data[1]])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Read a chunk header, returning the chunk ID and size. Returns None at the
+/// end of the stream.
+fn read_chunk_header(stream: &mut dyn Read) -> Result<Option<([u8; 4], u32)>, ReadError> {
+    let mut header = [0u8; 8];
+    let mut pos = 0;
+    while pos < header.len() {
+        match stream.read(&mut header[pos..]) {
+            Ok(0) => {
+                return if pos == 0 {
+                    Ok(None)
+                } else {
+                    Err(ReadError::BadChunk)
+                }
+            }
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(ReadError::IO(e)),
+        }
+    }
+    let mut id = [0u8; 4];
+    id.copy_from_slice(&header[..4]);
+    Ok(Some((id, read_u32(&header[4..]))))
+}
+
+/// Skip over the given number of bytes in a stream.
+fn skip(stream: &mut dyn Read, count: u64) -> Result<(), ReadError> {
+    let n = io::copy(&mut stream.take(count), &mut io::sink())?;
+    if n != count {
+        return Err(ReadError::BadChunk);
+    }
+    Ok(())
+}
+
+/// Parse the contents of a format chunk.
+fn parse_format(data: &[u8]) -> Result<(Parameters, SampleFormat), ReadError> {
+    if data.len() < 16 {
+        return Err(ReadError::BadChunk);
+    }
+    let mut tag = read_u16(&data[0..]);
+    let channel_count = read_u16(&data[2..]) as u32;
+    let sample_rate = read_u32(&data[4..]);
+    let bits_per_sample = read_u16(&data[14..]);
+    if tag == FORMAT_EXTENSIBLE {
+        // The subformat GUID starts with the format tag.
+        if data.len() < 26 {
+            return Err(ReadError::BadChunk);
+        }
+        tag = read_u16(&data[24..]);
+    }
+    let format = match (tag, bits_per_sample) {
+        (FORMAT_PCM, 16) => SampleFormat::I16,
+        (FORMAT_PCM, 24) => SampleFormat::I24,
+        (FORMAT_FLOAT, 32) => SampleFormat::F32,
+        (FORMAT_PCM, _) | (FORMAT_FLOAT, _) => {
+            return Err(ReadError::UnsupportedSampleSize(bits_per_sample))
+        }
+        _ => return Err(ReadError::UnsupportedFormat(tag)),
+    };
+    if channel_count == 0 {
+        return Err(ReadError::BadChunk);
+    }
+    Ok((
+        Parameters {
+            channel_count,
+            sample_rate,
+        },
+        format,
+    ))
+}
+
+/// WAVE file reader.
+pub struct Reader<'a> {
+    stream: &'a mut dyn Read,
+    buf: Box<[u8]>,
+    remaining: usize,
+    parameters: Parameters,
+    format: SampleFormat,
+}
+
+impl<'a> Reader<'a> {
+    /// Create a WAVE reader from the given stream. This reads the header and
+    /// leaves the stream positioned at the start of the sample data. Chunks
+    /// other than the format and data chunks are skipped.
+    pub fn from_stream(stream: &'a mut dyn Read) -> Result<Self, ReadError> {
+        const BUFFER_SIZE: usize = 32 * 1024;
+        let mut riff = [0u8; 12];
+        stream.read_exact(&mut riff).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ReadError::NotWave,
+            _ => ReadError::IO(e),
+        })?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+            return Err(ReadError::NotWave);
+        }
+        let mut format = None;
+        let data_size = loop {
+            let (id, size) = match read_chunk_header(stream)? {
+                Some(x) => x,
+                None => return Err(ReadError::MissingData),
+            };
+            match &id {
+                b"fmt " => {
+                    // The chunk size is not trusted, so only the fields which
+                    // are used are read.
+                    let mut data = [0; MAX_FORMAT_SIZE];
+                    let n = min(size as usize, MAX_FORMAT_SIZE);
+                    stream.read_exact(&mut data[..n])?;
+                    format = Some(parse_format(&data[..n])?);
+                    skip(stream, (size as usize - n) as u64 + (size & 1) as u64)?;
+                }
+                b"data" => break size as usize,
+                // Skip LIST, fact, and any other chunks. Chunks are padded to
+                // an even number of bytes.
+                _ => skip(stream, size as u64 + (size & 1) as u64)?,
+            }
+        };
+        let (parameters, format) = match format {
+            Some(x) => x,
+            None => return Err(ReadError::MissingFormat),
+        };
+        let frame_size = format.size() * parameters.channel_count as usize;
+        // Round the buffer down to a whole number of samples.
+        let buf_size = BUFFER_SIZE - BUFFER_SIZE % format.size();
+        Ok(Reader {
+            stream,
+            buf: Box::from(vec![0; buf_size]),
+            remaining: data_size - data_size % frame_size,
+            parameters,
+            format,
+        })
+    }
+
+    /// Get the parameters of the file.
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// Get the format of the samples in the file.
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// Read floating-point samples from the file, with channels interleaved.
+    /// Returns the number of samples read, which is zero at the end of the
+    /// data.
+    pub fn read(&mut self, data: &mut [f32]) -> Result<usize, ReadError> {
+        let sample_size = self.format.size();
+        let n = min(
+            data.len(),
+            min(self.remaining, self.buf.len()) / sample_size,
+        );
+        if n == 0 {
+            return Ok(0);
+        }
+        let buf = &mut self.buf[..n * sample_size];
+        self.stream.read_exact(buf)?;
+        self.remaining -= buf.len();
+        let data = &mut data[..n];
+        match self.format {
+            SampleFormat::I16 => {
+                for (y, x) in data.iter_mut().zip(buf.chunks_exact(2)) {
+                    *y = i16::from_le_bytes([x[0], x[1]]) as f32 * (1.0 / 32768.0);
+                }
+            }
+            SampleFormat::I24 => {
+                for (y, x) in data.iter_mut().zip(buf.chunks_exact(3)) {
+                    let x = i32::from_le_bytes([0, x[0], x[1], x[2]]) >> 8;
+                    *y = x as f32 * (1.0 / 8388608.0);
+                }
+            }
+            SampleFormat::F32 => {
+                for (y, x) in data.iter_mut().zip(buf.chunks_exact(4)) {
+                    *y = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    /// Read all remaining samples from the file, with channels interleaved.
+    pub fn read_all(&mut self) -> Result<Vec<f32>, ReadError> {
+        // The size of the data chunk is not trusted either, so the result
+        // grows as samples are read instead of being allocated up front.
+        let mut result = Vec::new();
+        let mut buf = vec![0.0; self.buf.len() / self.format.size()];
+        loop {
+            let n = self.read(&mut buf)?;
+            if n == 0 {
+                return Ok(result);
+            }
+            result.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_signal() -> Vec<f32> {
+        (0..10000).map(|n| (n as f32 * 0.01).sin() * 0.9).collect()
+    }
+
+    fn write_wave(data: &[f32]) -> Vec<u8> {
+        let mut stream = Cursor::new(Vec::new());
+        let mut writer = Writer::from_stream(
+            &mut stream,
+            &Parameters {
+                channel_count: 1,
+                sample_rate: 44100,
+            },
+        );
+        writer.write(data).unwrap();
+        writer.finish().unwrap();
+        stream.into_inner()
+    }
+
+    fn make_file(tag: u16, bits: u16, samples: &[u8]) -> Vec<u8> {
+        let block_align = bits / 8;
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"WAVEfmt ");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&48000u32.to_le_bytes());
+        data.extend_from_slice(&(48000 * block_align as u32).to_le_bytes());
+        data.extend_from_slice(&block_align.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        data.extend_from_slice(samples);
+        data
+    }
+
+    fn read_file(data: Vec<u8>) -> Result<Vec<f32>, ReadError> {
+        let mut stream = Cursor::new(data);
+        Reader::from_stream(&mut stream)?.read_all()
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = test_signal();
+        let mut stream = Cursor::new(write_wave(&input));
+        let mut reader = Reader::from_stream(&mut stream).unwrap();
+        assert_eq!(reader.parameters().channel_count, 1);
+        assert_eq!(reader.parameters().sample_rate, 44100);
+        assert_eq!(reader.format(), SampleFormat::I16);
+        let output = reader.read_all().unwrap();
+        assert_eq!(input.len(), output.len());
+        for (n, (&x, &y)) in input.iter().zip(output.iter()).enumerate() {
+            // Allow for rounding and dither, each less than one LSB.
+            if (x - y).abs() > 2.0 / 32768.0 {
+                panic!("sample {}: wrote {}, read {}", n, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn clamp() {
+        // Samples out of range are clamped, not wrapped.
+        let input = [2.0, -2.0, 1.0, -1.0, f32::INFINITY];
+        let output = read_file(write_wave(&input)).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                32767.0 / 32768.0,
+                -1.0,
+                32767.0 / 32768.0,
+                -1.0,
+                32767.0 / 32768.0
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_chunks() {
+        let input = test_signal();
+        let file = write_wave(&input);
+        // Insert a LIST chunk with an odd length, which requires padding.
+        let mut data = Vec::new();
+        data.extend_from_slice(&file[..36]);
+        data.extend_from_slice(b"LIST");
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(b"INFO\0\0");
+        data.extend_from_slice(&file[36..]);
+        assert_eq!(read_file(data).unwrap().len(), input.len());
+    }
+
+    #[test]
+    fn info() {
+        let input = test_signal();
+        let mut stream = Cursor::new(Vec::new());
+        let mut writer = Writer::from_stream(
+            &mut stream,
+            &Parameters {
+                channel_count: 1,
+                sample_rate: 44100,
+            },
+        );
+        writer.add_info(*b"ISFT", b"ultrafxr");
+        writer.add_info(*b"ICMT", b"(noise)");
+        writer.write(&input).unwrap();
+        writer.finish().unwrap();
+        let file = stream.into_inner();
+        assert_eq!(read_u32(&file[4..]) as usize, file.len() - 8);
+        let trailer = &file[44 + input.len() * 2..];
+        let mut expect = Vec::new();
+        expect.extend_from_slice(b"LIST");
+        expect.extend_from_slice(&38u32.to_le_bytes());
+        expect.extend_from_slice(b"INFO");
+        expect.extend_from_slice(b"ISFT\x09\0\0\0ultrafxr\0\0");
+        expect.extend_from_slice(b"ICMT\x08\0\0\0(noise)\0");
+        assert_eq!(trailer, &expect[..]);
+        assert_eq!(read_file(file).unwrap().len(), input.len());
+    }
+
+    #[test]
+    fn sample_formats() {
+        let mut samples = Vec::new();
+        samples.extend_from_slice(&0.5f32.to_le_bytes());
+        samples.extend_from_slice(&(-0.25f32).to_le_bytes());
+        assert_eq!(
+            read_file(make_file(FORMAT_FLOAT, 32, &samples)).unwrap(),
+            vec![0.5, -0.25]
+        );
+        assert_eq!(
+            read_file(make_file(FORMAT_PCM, 24, &[0, 0, 0x40, 0, 0, 0xc0])).unwrap(),
+            vec![0.5, -0.5]
+        );
+        assert_eq!(
+            read_file(make_file(FORMAT_PCM, 16, &[0, 0x40, 0, 0xc0])).unwrap(),
+            vec![0.5, -0.5]
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        match read_file(make_file(2, 4, &[0, 0])) {
+            Err(ReadError::UnsupportedFormat(2)) => {}
+            r => panic!("expected unsupported format, got {:?}", r),
+        }
+        match read_file(make_file(FORMAT_PCM, 12, &[0, 0])) {
+            Err(ReadError::UnsupportedSampleSize(12)) => {}
+            r => panic!("expected unsupported sample size, got {:?}", r),
+        }
+        match read_file(b"RIFF\0\0\0\0AVI ".to_vec()) {
+            Err(ReadError::NotWave) => {}
+            r => panic!("expected not wave, got {:?}", r),
+        }
+    }
+
+    /// Set the size of the format chunk in a file from make_file, and insert
+    /// padding after the fields which are read.
+    fn resize_format(mut data: Vec<u8>, size: u32, padding: usize) -> Vec<u8> {
+        data[16..20].copy_from_slice(&size.to_le_bytes());
+        data.splice(36..36, std::iter::repeat_n(0, padding));
+        data
+    }
+
+    #[test]
+    fn format_size() {
+        // Extra data at the end of the format chunk is skipped, including the
+        // padding byte after an odd-sized chunk.
+        let samples = [0, 0x40, 0, 0xc0];
+        for &size in [16, 17, 50, 1000].iter() {
+            let padding = size as usize - 16 + (size & 1) as usize;
+            let file = resize_format(make_file(FORMAT_PCM, 16, &samples), size, padding);
+            assert_eq!(read_file(file).unwrap(), vec![0.5, -0.5], "size {}", size);
+        }
+        // A huge format chunk is an error when the file ends, without
+        // allocating memory for the whole chunk.
+        let file = resize_format(make_file(FORMAT_PCM, 16, &samples), u32::MAX - 1, 24);
+        match read_file(file) {
+            Err(ReadError::BadChunk) => {}
+            r => panic!("expected bad chunk, got {:?}", r),
+        }
+        // A truncated data chunk is an error, and its size is not trusted for
+        // allocating memory.
+        let mut file = make_file(FORMAT_PCM, 16, &samples);
+        file[40..44].copy_from_slice(&(u32::MAX - 1).to_le_bytes());
+        match read_file(file) {
+            Err(ReadError::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            r => panic!("expected end of file, got {:?}", r),
+        }
+    }
+}
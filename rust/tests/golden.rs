@@ -0,0 +1,81 @@
+//! Golden output tests. Each patch in tests/golden is rendered and a hash of
+//! the output samples is compared with the hash checked in next to it.
+//!
+//! When a change to the output is intentional, regenerate the hashes by
+//! running the tests with BLESS=1 in the environment and check in the updated
+//! .hash files:
+//!
+//!     BLESS=1 cargo test --test golden
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use ultrafxr::error::ErrorHandler;
+use ultrafxr::render::{render_program, RenderParams};
+use ultrafxr::sourcepos::Span;
+
+/// Error handler which records the messages it receives.
+struct Errors(Vec<String>);
+
+impl ErrorHandler for Errors {
+    fn handle(&mut self, _pos: Span, message: &str) {
+        self.0.push(message.to_owned());
+    }
+}
+
+/// Compute the 64-bit FNV-1a hash of the samples' little-endian bytes.
+fn hash_samples(samples: &[f32]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &x in samples.iter() {
+        for &b in x.to_le_bytes().iter() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Render a patch and compare its hash with the golden hash.
+fn check(name: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let source = fs::read_to_string(dir.join(format!("{}.lisp", name))).unwrap();
+    let mut errors = Errors(Vec::new());
+    let params = RenderParams {
+        max_length: 48000 * 3,
+        ..Default::default()
+    };
+    let samples = match render_program(&mut errors, &source, &params) {
+        Ok(samples) => samples,
+        Err(e) => panic!("{}: {}: {:?}", name, e, errors.0),
+    };
+    let hash = format!("{:016x} {}\n", hash_samples(&samples), samples.len());
+    let path = dir.join(format!("{}.hash", name));
+    if env::var_os("BLESS").map_or(false, |v| v == "1") {
+        fs::write(&path, &hash).unwrap();
+        return;
+    }
+    let expect = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => panic!("{}: could not read golden hash: {}", path.display(), e),
+    };
+    assert_eq!(
+        hash, expect,
+        "{}: output does not match, run with BLESS=1 to update",
+        name
+    );
+}
+
+#[test]
+fn sine() {
+    check("sine");
+}
+
+#[test]
+fn filtered_saw() {
+    check("filtered_saw");
+}
+
+#[test]
+fn note() {
+    check("note");
+}
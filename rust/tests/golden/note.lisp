@@ -0,0 +1,6 @@
+;; Enveloped note with a noise attack.
+(+
+ (* (sawtooth (oscillator (note 0)))
+    (envelope (set 1.0) (exp 1.0s 0.0) (lin 100ms 0.0) (stop)))
+ (* (noise)
+    (envelope (set 0.5) (exp 20ms 0.0) (lin 10ms 0.0))))
@@ -0,0 +1,2 @@
+;; Sine wave at A4.
+(sine (oscillator (note 0)))
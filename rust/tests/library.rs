@@ -0,0 +1,67 @@
+//! Uses the library without the command-line tool. This also runs with
+//! `cargo test --no-default-features`, where the CLI modules are not built.
+
+use std::io::Cursor;
+use ultrafxr::error::ErrorHandler;
+use ultrafxr::number::ParsedNumber;
+use ultrafxr::parser::{ParseResult, Parser};
+use ultrafxr::render::{compile_program, render_graph, RenderParams};
+use ultrafxr::sourcepos::{Pos, Span};
+use ultrafxr::token::Tokenizer;
+use ultrafxr::wave;
+
+struct Panic;
+
+impl ErrorHandler for Panic {
+    fn handle(&mut self, _pos: Span, message: &str) {
+        panic!("error: {}", message);
+    }
+}
+
+#[test]
+fn render_in_memory() {
+    let source = "(* 0.5V (envelope (set 0.25)))";
+
+    // The script can be parsed on its own.
+    let mut toks = Tokenizer::new(source.as_bytes()).unwrap();
+    let mut parser = Parser::new();
+    match parser.parse(&mut Panic, &mut toks) {
+        ParseResult::Value(expr) => assert_eq!(expr.print(), source),
+        _ => panic!("could not parse {:?}", source),
+    }
+    let mut number = ParsedNumber::new();
+    let pos = Span {
+        start: Pos(0),
+        end: Pos(4),
+    };
+    assert_eq!(number.parse("0.25", pos).ok(), Some(""));
+    assert_eq!(number.float(), 0.25);
+
+    // Render it and write the samples to a WAVE file in memory.
+    let (graph, root) = compile_program(&mut Panic, source).unwrap();
+    let params = RenderParams {
+        max_length: 500,
+        ..Default::default()
+    };
+    let samples = render_graph(&graph, root, &params).unwrap();
+    assert_eq!(samples.len(), 500);
+    let mut stream = Cursor::new(Vec::new());
+    let mut writer = wave::Writer::from_stream(
+        &mut stream,
+        &wave::Parameters {
+            channel_count: 1,
+            sample_rate: 48000,
+        },
+    );
+    writer.write(&samples).unwrap();
+    writer.finish().unwrap();
+
+    let mut stream = Cursor::new(stream.into_inner());
+    let mut reader = wave::Reader::from_stream(&mut stream).unwrap();
+    assert_eq!(reader.parameters().sample_rate, 48000);
+    let decoded = reader.read_all().unwrap();
+    assert_eq!(decoded.len(), samples.len());
+    for &x in decoded.iter() {
+        assert!((x - 0.125).abs() < 1e-3, "sample {}", x);
+    }
+}
@@ -0,0 +1,222 @@
+use ultrafxr::error::ErrorHandler;
+use ultrafxr::render::{render_program, render_wave, NoteEvent, RenderError, RenderParams};
+use ultrafxr::sourcepos::Span;
+
+/// Error handler which records the messages it receives.
+struct Errors(Vec<String>);
+
+impl ErrorHandler for Errors {
+    fn handle(&mut self, _pos: Span, message: &str) {
+        self.0.push(message.to_owned());
+    }
+}
+
+#[test]
+fn constant() {
+    let mut errors = Errors(Vec::new());
+    let params = RenderParams {
+        max_length: 3000,
+        ..Default::default()
+    };
+    let samples = match render_program(&mut errors, "(* 1V (envelope (set 0.25)))", &params) {
+        Ok(samples) => samples,
+        Err(_) => panic!("render failed: {:?}", errors.0),
+    };
+    assert_eq!(samples.len(), 3000);
+    for &x in samples.iter() {
+        assert!((x - 0.25).abs() < 1e-6, "sample {}", x);
+    }
+}
+
+#[test]
+fn error() {
+    let mut errors = Errors(Vec::new());
+    match render_program(&mut errors, "(sine", &Default::default()) {
+        Err(RenderError::Parse(diagnostics)) => {
+            assert!(!diagnostics.is_empty());
+            let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+            assert_eq!(messages, errors.0);
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    let mut errors = Errors(Vec::new());
+    match render_program(&mut errors, "(sine 1s)", &Default::default()) {
+        Err(RenderError::Evaluate(diagnostics)) => assert_eq!(diagnostics.len(), errors.0.len()),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn scale_int() {
+    let params = RenderParams {
+        max_length: 1000,
+        ..Default::default()
+    };
+    let render = |source: &str| {
+        let mut errors = Errors(Vec::new());
+        match render_program(&mut errors, source, &params) {
+            Ok(samples) => samples,
+            Err(_) => panic!("render failed: {:?}", errors.0),
+        }
+    };
+    let input = "(sawtooth (oscillator 100Hz))";
+    let base = render(input);
+    assert!(base.iter().any(|&x| x != 0.0));
+    for &scale in [2, 0, -1].iter() {
+        let samples = render(&format!("(scale-int {} {})", scale, input));
+        assert_eq!(samples.len(), base.len());
+        for (n, (&x, &y)) in samples.iter().zip(base.iter()).enumerate() {
+            assert_eq!(x, y * scale as f32, "scale {}, sample {}", scale, n);
+        }
+    }
+    // The scale must be an integer.
+    let mut errors = Errors(Vec::new());
+    assert!(render_program(&mut errors, &format!("(scale-int 1.5 {})", input), &params).is_err());
+    assert!(
+        errors.0.iter().any(|m| m.contains("expected int")),
+        "{:?}",
+        errors.0
+    );
+}
+
+/// Count the upward zero crossings in a signal.
+fn count_cycles(samples: &[f32]) -> usize {
+    samples
+        .windows(2)
+        .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+        .count()
+}
+
+#[test]
+fn notes() {
+    let render = |source: &str, notes: &[f32]| {
+        let params = RenderParams {
+            notes: notes
+                .iter()
+                .map(|&note| vec![NoteEvent::new(note)])
+                .collect(),
+            gate: 24000,
+            ..Default::default()
+        };
+        let mut errors = Errors(Vec::new());
+        match render_program(&mut errors, source, &params) {
+            Ok(samples) => samples,
+            Err(_) => panic!("render failed: {:?}", errors.0),
+        }
+    };
+    let source = "(* (sine (oscillator (note 0))) (envelope (set 1.0) (gate) (stop)))";
+    let c4 = render(source, &[60.0]);
+    let c5 = render(source, &[72.0]);
+    assert_eq!(c4.len(), 24000);
+    assert_eq!(c5.len(), 24000);
+    // Half a second of C4 is about 131 cycles.
+    let (c4_cycles, c5_cycles) = (count_cycles(&c4), count_cycles(&c5));
+    assert!((130..=131).contains(&c4_cycles), "C4: {} cycles", c4_cycles);
+    assert!(
+        (c5_cycles as i32 - 2 * c4_cycles as i32).abs() <= 1,
+        "C4: {} cycles, C5: {} cycles",
+        c4_cycles,
+        c5_cycles
+    );
+    // Notes are played in sequence.
+    let sequence = render(source, &[60.0, 72.0]);
+    assert_eq!(&sequence[..24000], &c4[..]);
+    assert_eq!(&sequence[24000..], &c5[..]);
+    // A patch without a note does not depend on the notes.
+    let source = "(* (sine (oscillator 200Hz)) (envelope (set 1.0) (gate) (stop)))";
+    assert_eq!(render(source, &[60.0]), render(source, &[72.0]));
+}
+
+#[test]
+fn velocity() {
+    let render = |source: &str, velocity: u8| {
+        let params = RenderParams {
+            notes: vec![vec![NoteEvent::with_velocity(60.0, velocity)]],
+            gate: 4800,
+            ..Default::default()
+        };
+        let mut errors = Errors(Vec::new());
+        match render_program(&mut errors, source, &params) {
+            Ok(samples) => samples,
+            Err(_) => panic!("render failed: {:?}", errors.0),
+        }
+    };
+    let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |acc, &x| acc.max(x.abs()));
+    let source = "(* (velocity) (sine (oscillator (note 0))) (envelope (set 1.0) (gate) (stop)))";
+    let soft = peak(&render(source, 32));
+    let loud = peak(&render(source, 127));
+    assert!(soft > 0.0);
+    assert!(loud > soft, "soft {}, loud {}", soft, loud);
+    assert!((loud - 1.0).abs() < 1e-3, "loud {}", loud);
+    assert!((soft - 32.0 / 127.0).abs() < 1e-3, "soft {}", soft);
+    // The default velocity is in the middle of the range.
+    let params = RenderParams {
+        max_length: 100,
+        ..Default::default()
+    };
+    let mut errors = Errors(Vec::new());
+    let samples = render_program(&mut errors, "(* 1V (velocity))", &params).unwrap();
+    assert_eq!(samples[0], 64.0 / 127.0);
+}
+
+#[test]
+fn chords() {
+    let render = |chord: &[f32], max_voices: usize| {
+        let params = RenderParams {
+            notes: vec![chord.iter().map(|&note| NoteEvent::new(note)).collect()],
+            gate: 4800,
+            max_voices,
+            ..Default::default()
+        };
+        let mut errors = Errors(Vec::new());
+        let source = "(* (sine (oscillator (note 0))) (envelope (set 0.25) (gate) (stop)))";
+        match render_program(&mut errors, source, &params) {
+            Ok(samples) => samples,
+            Err(_) => panic!("render failed: {:?}", errors.0),
+        }
+    };
+    let c4 = render(&[60.0], 8);
+    let e4 = render(&[64.0], 8);
+    let g4 = render(&[67.0], 8);
+    let chord = render(&[60.0, 64.0, 67.0], 8);
+    assert_eq!(chord.len(), c4.len());
+    for (n, &x) in chord.iter().enumerate() {
+        assert_eq!(x, c4[n] + e4[n] + g4[n], "sample {}", n);
+    }
+    // Notes past the voice limit are dropped from the end of the chord.
+    let limited = render(&[60.0, 64.0, 67.0], 2);
+    for (n, &x) in limited.iter().enumerate() {
+        assert_eq!(x, c4[n] + e4[n], "sample {}", n);
+    }
+}
+
+#[test]
+fn wave() {
+    let mut errors = Errors(Vec::new());
+    let mut params = RenderParams {
+        max_length: 1000,
+        ..Default::default()
+    };
+    params.parameters.sample_rate = 44100.0;
+    let source = "(* 1V (envelope (set 0.25)))";
+    let data = match render_wave(&mut errors, source, &params) {
+        Ok(data) => data,
+        Err(_) => panic!("render failed: {:?}", errors.0),
+    };
+    let u16_at = |n: usize| u16::from_le_bytes([data[n], data[n + 1]]);
+    let u32_at = |n: usize| u32::from_le_bytes([data[n], data[n + 1], data[n + 2], data[n + 3]]);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[8..12], b"WAVE");
+    assert_eq!(u32_at(4) as usize, data.len() - 8);
+    assert_eq!(u16_at(22), 1); // Channels.
+    assert_eq!(u32_at(24), 44100);
+    assert_eq!(u16_at(34), 16); // Bits per sample.
+    assert_eq!(&data[36..40], b"data");
+    assert_eq!(u32_at(40), 2000);
+    assert_eq!(data.len(), 44 + 2000);
+    for n in 0..1000 {
+        let x = u16_at(44 + n * 2) as i16;
+        assert!((x as i32 - 8192).abs() <= 1, "sample {}", x);
+    }
+    assert!(render_wave(&mut errors, "(sine", &params).is_err());
+}